@@ -0,0 +1,6 @@
+fn main() {
+    // The coordinator protocol is only generated for the grpc feature.
+    #[cfg(feature = "grpc")]
+    tonic_build::compile_protos("proto/coordinator.proto")
+        .expect("failed to compile proto/coordinator.proto");
+}
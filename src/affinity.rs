@@ -0,0 +1,131 @@
+//! Binding workers to CPU cores.
+//!
+//! Threads started by a pinned thread inherit its mask, so a worker's own
+//! rayon pool (`--pin-rayon`) stays on the worker's cores. The global rayon
+//! pool and bellperson's pools are shared and stay unpinned.
+
+#[cfg(target_os = "linux")]
+use std::mem;
+
+use anyhow::{bail, Context, Result};
+use rayon::{ThreadPool, ThreadPoolBuilder};
+
+#[derive(Clone, Debug)]
+pub struct CoreSets {
+    sets: Vec<Vec<usize>>,
+}
+
+impl CoreSets {
+    /// Parses `auto`, which splits the cores this process may run on evenly
+    /// between `workers`, or core lists such as `0-3,8/4-7,9` separated by
+    /// `/`, which are handed out to the workers round-robin.
+    pub fn parse(spec: &str, workers: usize) -> Result<Self> {
+        let sets = if spec == "auto" {
+            let cores = available()?;
+            let workers = workers.max(1);
+            if cores.len() < workers {
+                bail!(
+                    "{} cores are not enough to pin {} threads",
+                    cores.len(),
+                    workers
+                );
+            }
+            let per_worker = cores.len() / workers;
+            cores
+                .chunks(per_worker)
+                .take(workers)
+                .map(|chunk| chunk.to_vec())
+                .collect()
+        } else {
+            spec.split('/')
+                .map(parse_list)
+                .collect::<Result<Vec<_>>>()?
+        };
+        Ok(CoreSets { sets })
+    }
+
+    /// Worker `i` gets `sets[i % sets.len()]`.
+    pub(crate) fn from_sets(sets: Vec<Vec<usize>>) -> Self {
+        CoreSets { sets }
+    }
+
+    /// The cores worker `index` is bound to.
+    pub fn for_worker(&self, index: usize) -> &[usize] {
+        &self.sets[index % self.sets.len()]
+    }
+}
+
+/// Parses a `taskset` style core list like `0-3,8`.
+pub(crate) fn parse_list(list: &str) -> Result<Vec<usize>> {
+    let mut cores = Vec::new();
+    for range in list.split(',') {
+        let range = range.trim();
+        let (first, last) = match range.find('-') {
+            Some(pos) => (&range[..pos], &range[pos + 1..]),
+            None => (range, range),
+        };
+        let first: usize = first
+            .parse()
+            .with_context(|| format!("invalid core {:?}", range))?;
+        let last: usize = last
+            .parse()
+            .with_context(|| format!("invalid core {:?}", range))?;
+        if first > last {
+            bail!("invalid core range {:?}", range);
+        }
+        cores.extend(first..=last);
+    }
+    Ok(cores)
+}
+
+/// The cores the calling thread may currently run on.
+#[cfg(target_os = "linux")]
+fn available() -> Result<Vec<usize>> {
+    unsafe {
+        let mut set: libc::cpu_set_t = mem::zeroed();
+        if libc::sched_getaffinity(0, mem::size_of::<libc::cpu_set_t>(), &mut set) != 0 {
+            return Err(std::io::Error::last_os_error()).context("sched_getaffinity failed");
+        }
+        Ok((0..libc::CPU_SETSIZE as usize)
+            .filter(|core| libc::CPU_ISSET(*core, &set))
+            .collect())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn available() -> Result<Vec<usize>> {
+    bail!("core affinity needs Linux")
+}
+
+/// Binds the calling thread to `cores`.
+#[cfg(target_os = "linux")]
+pub fn pin_current(cores: &[usize]) -> Result<()> {
+    unsafe {
+        let mut set: libc::cpu_set_t = mem::zeroed();
+        for core in cores {
+            if *core >= libc::CPU_SETSIZE as usize {
+                bail!("core {} out of range", core);
+            }
+            libc::CPU_SET(*core, &mut set);
+        }
+        if libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(std::io::Error::last_os_error())
+                .with_context(|| format!("failed to pin to cores {:?}", cores));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pin_current(cores: &[usize]) -> Result<()> {
+    bail!("pinning to cores {:?} needs Linux", cores)
+}
+
+/// A rayon pool of `threads` threads for the calling thread to run its
+/// lifecycles in; they inherit the caller's core mask.
+pub fn pool(threads: usize) -> Result<ThreadPool> {
+    ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .context("failed to build rayon pool")
+}
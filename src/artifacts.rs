@@ -0,0 +1,546 @@
+//! On-disk layout of the files a sector lifecycle reads and writes.
+//!
+//! A lifecycle either works in a throwaway temporary directory or, with
+//! `--cache-root`, in a stable per-worker directory whose contents later runs
+//! can pick up again.
+//!
+//! Temporary directories live in a directory of the run's own under
+//! `--workdir`, so the [`Cleanup`] policy can be applied to all of them at
+//! the end, including after the watchdog ended a hung run without unwinding.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use lazy_static::lazy_static;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tempfile::{Builder, NamedTempFile};
+
+use crate::codec::{self, Versioned};
+
+/// Serialized `SectorMeta` of the sector.
+pub const SECTOR_META: &str = "sector";
+/// Versioned `SealPreCommitPhase1Output`, see `codec`.
+pub const PC1_OUTPUT: &str = "pc1";
+/// Serialized `SealPreCommitOutput`.
+pub const PC2_OUTPUT: &str = "pc2";
+/// Versioned `SealCommitPhase1Output`, see `codec`.
+pub const C1_OUTPUT: &str = "c1";
+/// Serialized `SealCommitOutput`, the finished proof.
+pub const C2_OUTPUT: &str = "c2";
+
+/// Prefix of the directory each run keeps its temporary sectors in.
+const RUN_DIR_PREFIX: &str = "hang-run-";
+
+/// What happens to the temporary sector directories of a run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cleanup {
+    /// Remove each as soon as its lifecycle is done.
+    Always,
+    /// Keep those of failed lifecycles, and of the whole run if it hung.
+    OnSuccess,
+    /// Keep all of them.
+    Never,
+}
+
+impl FromStr for Cleanup {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "always" => Ok(Cleanup::Always),
+            "on-success" => Ok(Cleanup::OnSuccess),
+            "never" => Ok(Cleanup::Never),
+            _ => bail!("unknown cleanup policy: {}", s),
+        }
+    }
+}
+
+/// What `--shared-cache` has the workers of a run share, reproducing the
+/// misconfiguration of several workers pointed at the same path.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SharedCache {
+    /// A single cache directory; staged and sealed files stay apart.
+    Cache,
+    /// A single sector directory, the parent of the cache, so the staged and
+    /// sealed files are shared as well.
+    Sector,
+}
+
+impl FromStr for SharedCache {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "cache" => Ok(SharedCache::Cache),
+            "sector" => Ok(SharedCache::Sector),
+            _ => bail!("unknown shared cache mode: {}", s),
+        }
+    }
+}
+
+/// Where sectors keep the files the proofs library reads and writes itself:
+/// the staged file, the sealed replica and the cache.
+pub trait SealedStorage: Send + Sync {
+    /// The directory holding those files of the sector in `dir`.
+    fn sector_dir(&self, dir: &Path) -> PathBuf;
+}
+
+/// Next to the sector's saved artifacts, the default.
+pub struct LocalStorage;
+
+impl SealedStorage for LocalStorage {
+    fn sector_dir(&self, dir: &Path) -> PathBuf {
+        dir.to_path_buf()
+    }
+}
+
+/// Under another directory, such as the mount of a network filesystem or a
+/// [`crate::storage::DelayedDisk`], at the path of the sector directory.
+pub struct RemoteStorage {
+    root: PathBuf,
+}
+
+impl RemoteStorage {
+    pub fn new(root: PathBuf) -> Self {
+        RemoteStorage { root }
+    }
+}
+
+impl SealedStorage for RemoteStorage {
+    fn sector_dir(&self, dir: &Path) -> PathBuf {
+        self.root.join(dir.strip_prefix("/").unwrap_or(dir))
+    }
+}
+
+struct RunDir {
+    dir: PathBuf,
+    cleanup: Cleanup,
+}
+
+lazy_static! {
+    static ref RUN_DIR: Mutex<Option<RunDir>> = Mutex::new(None);
+    static ref SEALED_STORAGE: Mutex<Arc<dyn SealedStorage>> = Mutex::new(Arc::new(LocalStorage));
+}
+
+/// Has every sector opened from now on keep its staged and sealed files and
+/// its cache in `storage`.
+pub fn set_sealed_storage(storage: impl SealedStorage + 'static) {
+    *SEALED_STORAGE.lock().expect("sealed storage poisoned") = Arc::new(storage);
+}
+
+fn sealed_storage() -> Arc<dyn SealedStorage> {
+    SEALED_STORAGE
+        .lock()
+        .expect("sealed storage poisoned")
+        .clone()
+}
+
+/// Creates the directory this process keeps its temporary sectors in under
+/// `workdir` and returns it. With [`Cleanup::Always`], the directories left
+/// behind by runs that were killed are removed first.
+pub fn set_workdir(workdir: &Path, cleanup: Cleanup) -> Result<PathBuf> {
+    fs::create_dir_all(workdir)
+        .with_context(|| format!("failed to create {}", workdir.display()))?;
+    if cleanup == Cleanup::Always {
+        remove_stale_run_dirs(workdir)?;
+    }
+    let dir = workdir.join(format!("{}{}", RUN_DIR_PREFIX, std::process::id()));
+    fs::create_dir_all(&dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    *RUN_DIR.lock().expect("run dir poisoned") = Some(RunDir {
+        dir: dir.clone(),
+        cleanup,
+    });
+    Ok(dir)
+}
+
+fn remove_stale_run_dirs(workdir: &Path) -> Result<()> {
+    for entry in fs::read_dir(workdir)? {
+        let path = entry?.path();
+        let pid = match path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix(RUN_DIR_PREFIX))
+            .and_then(|pid| pid.parse::<libc::pid_t>().ok())
+        {
+            Some(pid) => pid,
+            None => continue,
+        };
+        if !is_alive(pid) {
+            println!("Removing {} left behind by an earlier run", path.display());
+            fs::remove_dir_all(&path)
+                .with_context(|| format!("failed to remove {}", path.display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether process `pid` still runs; signal 0 only checks that it exists.
+/// Unlike looking in `/proc`, this works off Linux too.
+pub(crate) fn is_alive(pid: libc::pid_t) -> bool {
+    unsafe { libc::kill(pid, 0) == 0 }
+    || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Applies the cleanup policy to the run directory once the run is over.
+pub fn finish_run(success: bool) {
+    let run_dir = match RUN_DIR.lock().expect("run dir poisoned").take() {
+        Some(run_dir) => run_dir,
+        None => return,
+    };
+    let remove = match run_dir.cleanup {
+        Cleanup::Always => true,
+        Cleanup::OnSuccess => success,
+        Cleanup::Never => false,
+    };
+    if remove {
+        let _ = fs::remove_dir_all(&run_dir.dir);
+        let _ = fs::remove_dir_all(sealed_storage().sector_dir(&run_dir.dir));
+    } else if fs::read_dir(&run_dir.dir).map_or(false, |mut dir| dir.next().is_some()) {
+        println!("Kept sector files in {}", run_dir.dir.display());
+    } else {
+        let _ = fs::remove_dir(&run_dir.dir);
+    }
+}
+
+/// The files of one sector: the outputs saved between phases and, placed by
+/// the run's [`SealedStorage`], the staged file, the replica and the cache.
+pub struct SectorFiles {
+    dir: PathBuf,
+    /// A cache directory outside of `dir`.
+    cache: Option<PathBuf>,
+    remove_on_drop: bool,
+}
+
+impl SectorFiles {
+    /// A fresh temporary directory in the run directory, or the system's
+    /// temporary directory without one. It is removed again on drop unless
+    /// the cleanup policy keeps it.
+    pub fn temporary() -> Result<Self> {
+        let run_dir = RUN_DIR.lock().expect("run dir poisoned");
+        let (parent, keep) = match run_dir.as_ref() {
+            Some(run_dir) => (run_dir.dir.clone(), run_dir.cleanup == Cleanup::Never),
+            None => (std::env::temp_dir(), false),
+        };
+        let dir = Builder::new()
+            .tempdir_in(parent)
+            .context("failed to create temp dir")?
+            .into_path();
+        Self::open(dir, keep)
+    }
+
+    /// A temporary directory that shares its cache, or itself entirely, with
+    /// every other worker of the run. Shared directories are left to the
+    /// cleanup policy of the run directory.
+    pub fn shared(mode: SharedCache) -> Result<Self> {
+        let shared = match RUN_DIR.lock().expect("run dir poisoned").as_ref() {
+            Some(run_dir) => run_dir.dir.join("shared"),
+            None => bail!("shared sector files need a run directory"),
+        };
+        match mode {
+            SharedCache::Cache => {
+                let mut files = Self::temporary()?;
+                files.cache = Some(shared.join("cache"));
+                fs::create_dir_all(files.cache())
+                    .with_context(|| format!("failed to create {}", files.cache().display()))?;
+                Ok(files)
+            }
+            SharedCache::Sector => Self::open(shared, true),
+        }
+    }
+
+    /// Keeps a temporary directory whose lifecycle failed if the cleanup
+    /// policy asks for that.
+    pub fn failed(&mut self) {
+        if keeps_failures(&self.dir) {
+            self.remove_on_drop = false;
+        }
+    }
+
+    /// A stable directory under a cache root, kept on drop if `keep` is set.
+    pub fn persistent(dir: PathBuf, keep: bool) -> Result<Self> {
+        Self::open(dir, keep)
+    }
+
+    /// An existing directory to read saved artifacts from, left as it is.
+    pub fn inspect(dir: PathBuf) -> Self {
+        SectorFiles {
+            dir,
+            cache: None,
+            remove_on_drop: false,
+        }
+    }
+
+    /// Moves the sector to a directory of its own next to this one, for
+    /// another worker to take over. Not for shared directories.
+    pub(crate) fn hand_over(&self) -> Result<Self> {
+        let mut name = self.dir.file_name().unwrap_or_default().to_os_string();
+        name.push("-reassigned");
+        let dir = self.dir.with_file_name(name);
+        let storage = sealed_storage();
+        let sealed_dir = storage.sector_dir(&self.dir);
+        if sealed_dir != self.dir {
+            fs::rename(&sealed_dir, storage.sector_dir(&dir))
+                .with_context(|| format!("failed to move {}", sealed_dir.display()))?;
+        }
+        fs::rename(&self.dir, &dir)
+            .with_context(|| format!("failed to move {}", self.dir.display()))?;
+        Ok(SectorFiles {
+            dir,
+            cache: None,
+            remove_on_drop: self.remove_on_drop,
+        })
+    }
+
+    fn open(dir: PathBuf, keep: bool) -> Result<Self> {
+        let files = SectorFiles {
+            dir,
+            cache: None,
+            remove_on_drop: !keep,
+        };
+        // The cache may be kept elsewhere.
+        fs::create_dir_all(&files.dir)
+            .with_context(|| format!("failed to create {}", files.dir.display()))?;
+        fs::create_dir_all(files.cache())
+            .with_context(|| format!("failed to create {}", files.cache().display()))?;
+        Ok(files)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The unpadded piece data, removed once it was added. Sector
+    /// directories of older runs still have it.
+    pub fn piece(&self) -> PathBuf {
+        self.dir.join("piece")
+    }
+
+    /// The staged (unsealed) sector produced by `add_piece`.
+    pub fn staged(&self) -> PathBuf {
+        sealed_storage().sector_dir(&self.dir).join("staged")
+    }
+
+    /// The sealed replica.
+    pub fn sealed(&self) -> PathBuf {
+        sealed_storage().sector_dir(&self.dir).join("sealed")
+    }
+
+    /// The cache directory holding layers and trees.
+    pub fn cache(&self) -> PathBuf {
+        match &self.cache {
+            Some(cache) => cache.clone(),
+            None => sealed_storage().sector_dir(&self.dir).join("cache"),
+        }
+    }
+
+    fn artifact(&self, name: &str) -> PathBuf {
+        artifact_path(&self.dir, name)
+    }
+
+    pub fn save<T: Serialize>(&self, name: &str, value: &T) -> Result<()> {
+        let path = self.artifact(name);
+        let file = BufWriter::new(
+            File::create(&path).with_context(|| format!("failed to create {}", path.display()))?,
+        );
+        serde_json::to_writer(file, value)
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    /// Loads a previously saved artifact, `None` if there is none.
+    pub fn load<T: DeserializeOwned>(&self, name: &str) -> Result<Option<T>> {
+        let path = self.artifact(name);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let file = BufReader::new(File::open(&path)?);
+        let value = serde_json::from_reader(file)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        Ok(Some(value))
+    }
+
+    /// Saves a phase output in its versioned format, see [`codec`].
+    pub fn save_versioned<T: Versioned>(&self, name: &str, value: &T) -> Result<()> {
+        let path = self.artifact(name);
+        let file = BufWriter::new(
+            File::create(&path).with_context(|| format!("failed to create {}", path.display()))?,
+        );
+        codec::write(file, value).with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    /// Loads a phase output [`save_versioned`](Self::save_versioned) saved,
+    /// `None` if there is none.
+    pub fn load_versioned<T: Versioned>(&self, name: &str) -> Result<Option<T>> {
+        let path = self.artifact(name);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let file = BufReader::new(File::open(&path)?);
+        let value =
+            codec::read(file).with_context(|| format!("failed to read {}", path.display()))?;
+        Ok(Some(value))
+    }
+
+    /// Copies a saved artifact to `other`.
+    pub fn copy_to(&self, name: &str, other: &SectorFiles) -> Result<()> {
+        let (from, to) = (self.artifact(name), other.artifact(name));
+        fs::copy(&from, &to)
+            .with_context(|| format!("failed to copy {} to {}", from.display(), to.display()))?;
+        Ok(())
+    }
+
+    /// Drops a saved artifact that no longer matches the sector's state.
+    pub fn remove(&self, name: &str) -> Result<()> {
+        let path = self.artifact(name);
+        if path.exists() {
+            fs::remove_file(&path)
+                .with_context(|| format!("failed to remove {}", path.display()))?;
+        }
+        Ok(())
+    }
+}
+
+/// Hands seal lifecycles the directories of their sectors and creates the
+/// files they write themselves: the piece data, the staged sector, the
+/// replica PC1 fills and unsealed ranges. The proofs library opens the rest
+/// by path, wherever the run's [`SealedStorage`] puts it.
+pub trait ArtifactStore {
+    /// The files of the sector called `name`; stores that hand out fresh
+    /// directories ignore it.
+    fn sector(&self, name: &str) -> Result<SectorFiles>;
+
+    /// Creates the file at `path`, or empties it, for reading and writing.
+    fn create(&self, path: &Path) -> Result<File> {
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .with_context(|| format!("failed to create {}", path.display()))
+    }
+
+    /// Opens the file at `path` for reading.
+    fn open(&self, path: &Path) -> Result<File> {
+        File::open(path).with_context(|| format!("failed to open {}", path.display()))
+    }
+
+    /// A file next to the sector in `files`, so it stays under `--workdir`
+    /// and its cleanup, that is removed again on drop.
+    fn temp_file(&self, files: &SectorFiles) -> Result<NamedTempFile> {
+        NamedTempFile::new_in(files.dir())
+            .with_context(|| format!("failed to create a file in {}", files.dir().display()))
+    }
+}
+
+/// Every sector in a temporary directory of its own, see
+/// [`SectorFiles::temporary`].
+pub struct TempStore;
+
+impl ArtifactStore for TempStore {
+    fn sector(&self, _name: &str) -> Result<SectorFiles> {
+        SectorFiles::temporary()
+    }
+}
+
+/// Every sector in the directory of its name under `root`, where later runs
+/// find it again, kept on drop if `keep` is set.
+pub struct DirStore {
+    root: PathBuf,
+    keep: bool,
+}
+
+impl DirStore {
+    pub fn new(root: PathBuf, keep: bool) -> Self {
+        DirStore { root, keep }
+    }
+}
+
+impl ArtifactStore for DirStore {
+    fn sector(&self, name: &str) -> Result<SectorFiles> {
+        SectorFiles::persistent(self.root.join(name), self.keep)
+    }
+}
+
+/// Sectors sharing their cache, or everything, with every other worker, see
+/// [`SectorFiles::shared`].
+pub struct SharedStore(pub SharedCache);
+
+impl ArtifactStore for SharedStore {
+    fn sector(&self, _name: &str) -> Result<SectorFiles> {
+        SectorFiles::shared(self.0)
+    }
+}
+
+/// Holds every operation of `inner` back by `latency` first, as a slow
+/// network filesystem would. Only what goes through the store is delayed;
+/// `--storage-latency-ms` delays the proofs library's own I/O.
+pub struct LatencyStore<S> {
+    inner: S,
+    latency: Duration,
+}
+
+impl<S: ArtifactStore> LatencyStore<S> {
+    pub fn new(inner: S, latency: Duration) -> Self {
+        LatencyStore { inner, latency }
+    }
+}
+
+impl<S: ArtifactStore> ArtifactStore for LatencyStore<S> {
+    fn sector(&self, name: &str) -> Result<SectorFiles> {
+        thread::sleep(self.latency);
+        self.inner.sector(name)
+    }
+
+    fn create(&self, path: &Path) -> Result<File> {
+        thread::sleep(self.latency);
+        self.inner.create(path)
+    }
+
+    fn open(&self, path: &Path) -> Result<File> {
+        thread::sleep(self.latency);
+        self.inner.open(path)
+    }
+
+    fn temp_file(&self, files: &SectorFiles) -> Result<NamedTempFile> {
+        thread::sleep(self.latency);
+        self.inner.temp_file(files)
+    }
+}
+
+/// Whether the sector directory `dir` holds a saved `name` artifact.
+pub fn is_saved(dir: &Path, name: &str) -> bool {
+    artifact_path(dir, name).exists()
+}
+
+fn artifact_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{}.json", name))
+}
+
+/// Whether `dir` is a temporary directory the cleanup policy keeps when its
+/// lifecycle fails.
+fn keeps_failures(dir: &Path) -> bool {
+    match RUN_DIR.lock().expect("run dir poisoned").as_ref() {
+        Some(run_dir) => run_dir.cleanup == Cleanup::OnSuccess && dir.starts_with(&run_dir.dir),
+        None => false,
+    }
+}
+
+impl Drop for SectorFiles {
+    fn drop(&mut self) {
+        // A lifecycle that panicked failed too.
+        if self.remove_on_drop && !(std::thread::panicking() && keeps_failures(&self.dir)) {
+            let _ = fs::remove_dir_all(&self.dir);
+            let sealed_dir = sealed_storage().sector_dir(&self.dir);
+            if sealed_dir != self.dir {
+                let _ = fs::remove_dir_all(sealed_dir);
+            }
+        }
+    }
+}
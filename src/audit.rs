@@ -0,0 +1,237 @@
+//! `hang audit`: checks the sector directories a hung or crashed run left
+//! behind, to tell which of them are still worth resuming.
+//!
+//! Every directory below the audited one that holds sector metadata is
+//! checked: the comm_d recomputed from its piece infos must match the one
+//! PC2 saved, and a saved proof must verify. The sector size follows from
+//! the piece infos, which always fill the sector. The API version comes from
+//! a `V1_1_0` or `V1_0_0` directory above the sector, as under a
+//! `--cache-root`, and is otherwise whichever one the proof verifies with.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, ensure, Result};
+use filecoin_proofs::{
+    compute_comm_d, verify_seal, MerkleTreeTrait, PaddedBytesAmount, PoRepConfig, SealCommitOutput,
+    SealPreCommitOutput, SectorSize, POREP_PARTITIONS,
+};
+use storage_proofs_core::api_version::ApiVersion;
+
+use crate::artifacts::{
+    self, SectorFiles, C1_OUTPUT, C2_OUTPUT, PC1_OUTPUT, PC2_OUTPUT, SECTOR_META,
+};
+use crate::lifecycle::{init_logger, parse_api_version, porep_config, SectorMeta};
+use crate::metrics::Phase;
+
+/// The saved outputs looked for, latest phase first.
+const OUTPUTS: [(&str, Phase); 4] = [
+    (C2_OUTPUT, Phase::Commit2),
+    (C1_OUTPUT, Phase::Commit1),
+    (PC2_OUTPUT, Phase::PreCommit2),
+    (PC1_OUTPUT, Phase::PreCommit1),
+];
+
+/// What the audit found in one sector directory.
+#[derive(Debug)]
+pub struct SectorAudit {
+    pub dir: PathBuf,
+    pub sector_id: Option<u64>,
+    /// The latest phase whose output was saved.
+    pub last_phase: Option<Phase>,
+    /// Whether the recomputed comm_d matches the saved one, `None` before
+    /// PC2 finished.
+    pub comm_d: Option<bool>,
+    /// Whether the saved proof verifies, `None` without a proof.
+    pub proof: Option<bool>,
+    /// The API version the proof verified with.
+    pub api_version: Option<ApiVersion>,
+    /// Why the sector could not be checked completely.
+    pub error: Option<String>,
+}
+
+impl SectorAudit {
+    pub fn mismatched(&self) -> bool {
+        self.comm_d == Some(false) || self.proof == Some(false)
+    }
+}
+
+/// Audits every sector directory below `root`. Proofs are verified against
+/// `porep_id`, or the default PoRep ID of their API version.
+pub fn audit(root: &Path, porep_id: Option<[u8; 32]>) -> Result<Vec<SectorAudit>> {
+    init_logger();
+    let mut dirs = Vec::new();
+    find_sectors(root, &mut dirs)?;
+    dirs.sort();
+    Ok(dirs
+        .into_iter()
+        .map(|dir| audit_sector(dir, porep_id))
+        .collect())
+}
+
+fn find_sectors(dir: &Path, sectors: &mut Vec<PathBuf>) -> Result<()> {
+    if artifacts::is_saved(dir, SECTOR_META) {
+        sectors.push(dir.to_path_buf());
+        return Ok(());
+    }
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_sectors(&path, sectors)?;
+        }
+    }
+    Ok(())
+}
+
+fn audit_sector(dir: PathBuf, porep_id: Option<[u8; 32]>) -> SectorAudit {
+    let mut audit = SectorAudit {
+        last_phase: OUTPUTS
+            .iter()
+            .find(|(name, _)| artifacts::is_saved(&dir, name))
+            .map(|(_, phase)| *phase),
+        dir,
+        sector_id: None,
+        comm_d: None,
+        proof: None,
+        api_version: None,
+        error: None,
+    };
+    if let Err(err) = check_sector(&mut audit, porep_id) {
+        audit.error = Some(format!("{:#}", err));
+    }
+    audit
+}
+
+fn check_sector(audit: &mut SectorAudit, porep_id: Option<[u8; 32]>) -> Result<()> {
+    let files = SectorFiles::inspect(audit.dir.clone());
+    let meta = files
+        .load::<SectorMeta>(SECTOR_META)?
+        .ok_or_else(|| anyhow!("no sector metadata"))?;
+    audit.sector_id = Some(meta.sector_id.into());
+    let sector_size = meta
+        .piece_infos
+        .iter()
+        .map(|piece| u64::from(PaddedBytesAmount::from(piece.size)))
+        .sum::<u64>();
+    ensure!(
+        POREP_PARTITIONS
+            .read()
+            .expect("POREP_PARTITIONS poisoned")
+            .contains_key(&sector_size),
+        "the pieces add up to {} bytes, which is no sector size",
+        sector_size
+    );
+
+    let pre_commit_output = match files.load::<SealPreCommitOutput>(PC2_OUTPUT)? {
+        Some(output) => output,
+        None => return Ok(()),
+    };
+    let comm_d = compute_comm_d(SectorSize(sector_size), &meta.piece_infos)?;
+    audit.comm_d = Some(comm_d == pre_commit_output.comm_d);
+
+    let commit_output = match files.load::<SealCommitOutput>(C2_OUTPUT)? {
+        Some(output) => output,
+        None => return Ok(()),
+    };
+    let api_versions = match api_version_of(&audit.dir) {
+        Some(api_version) => vec![api_version],
+        None => vec![parse_api_version("1.1.0")?, parse_api_version("1.0.0")?],
+    };
+    audit.proof = Some(false);
+    for (api_version, default_porep_id) in api_versions {
+        let config = porep_config(
+            sector_size,
+            porep_id.unwrap_or(default_porep_id),
+            api_version,
+        );
+        let verified = with_shape!(
+            sector_size,
+            verify_proof,
+            config,
+            &meta,
+            &pre_commit_output,
+            &commit_output,
+        )?;
+        if verified {
+            audit.proof = Some(true);
+            audit.api_version = Some(api_version);
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn verify_proof<Tree: 'static + MerkleTreeTrait>(
+    config: PoRepConfig,
+    meta: &SectorMeta,
+    pre_commit_output: &SealPreCommitOutput,
+    commit_output: &SealCommitOutput,
+) -> Result<bool> {
+    verify_seal::<Tree>(
+        config,
+        pre_commit_output.comm_r,
+        pre_commit_output.comm_d,
+        meta.prover_id,
+        meta.sector_id,
+        meta.ticket,
+        meta.seed,
+        &commit_output.proof,
+    )
+}
+
+/// The API version named by a directory above `dir`, as in the layout of a
+/// cache root.
+fn api_version_of(dir: &Path) -> Option<(ApiVersion, [u8; 32])> {
+    dir.ancestors()
+        .filter_map(|path| path.file_name()?.to_str())
+        .filter(|name| name.starts_with('V'))
+        .find_map(|name| parse_api_version(name).ok())
+}
+
+/// Prints a line per sector followed by how many can be picked up again.
+pub fn print_report(audits: &[SectorAudit]) {
+    println!(
+        "{:<20} {:<6} {:<8} {:<16} {}",
+        "sector id", "saved", "comm_d", "proof", "directory"
+    );
+    for audit in audits {
+        let check = |result: Option<bool>| match result {
+            Some(true) => "ok",
+            Some(false) => "MISMATCH",
+            None => "-",
+        };
+        let proof = match (audit.proof, audit.api_version) {
+            (Some(true), Some(api_version)) => format!("ok ({:?})", api_version),
+            (proof, _) => check(proof).to_string(),
+        };
+        println!(
+            "{:<20} {:<6} {:<8} {:<16} {}",
+            audit.sector_id.map_or("-".to_string(), |id| id.to_string()),
+            audit
+                .last_phase
+                .map_or("-".to_string(), |phase| phase.to_string()),
+            check(audit.comm_d),
+            proof,
+            audit.dir.display()
+        );
+        if let Some(err) = &audit.error {
+            println!("    {}", err);
+        }
+    }
+
+    let proven = audits.iter().filter(|a| a.proof == Some(true)).count();
+    let mismatched = audits.iter().filter(|a| a.mismatched()).count();
+    let broken = audits
+        .iter()
+        .filter(|a| a.error.is_some() && !a.mismatched())
+        .count();
+    println!();
+    println!(
+        "{} sectors: {} proven, {} resumable, {} mismatched, {} unreadable",
+        audits.len(),
+        proven,
+        audits.len() - proven - mismatched - broken,
+        mismatched,
+        broken
+    );
+}
@@ -0,0 +1,108 @@
+//! Starts every phase of every worker at the same moment.
+//!
+//! With `--sync-phases` a worker about to start a phase waits until every
+//! other worker still running is about to start one too, whichever it is,
+//! and then all of them start together. Phase entry is where the workers
+//! contend for the GPU lock, the scheduler and memory, so lining them up
+//! there makes a hang that needs them to collide far more likely than free
+//! running threads do. A worker that ran all its lifecycles no longer
+//! counts.
+//!
+//! Only thread workers meet at the barrier.
+
+use std::sync::Condvar;
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+
+use crate::lock_order::{OrderedGuard, OrderedMutex};
+use crate::shutdown;
+use crate::watchdog;
+
+/// How often waiting workers check for a shutdown.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Default)]
+struct Barrier {
+    enabled: bool,
+    /// Whether each worker is done, and no longer waited for.
+    done: Vec<bool>,
+    /// Workers waiting for the others.
+    arrived: usize,
+    /// Number of times the workers were released together.
+    released: usize,
+}
+
+impl Barrier {
+    fn active(&self) -> usize {
+        self.done.iter().filter(|done| !**done).count()
+    }
+
+    /// Lets the waiting workers go once every active one arrived.
+    fn release_if_complete(&mut self) -> bool {
+        if self.arrived == 0 || self.arrived < self.active() {
+            return false;
+        }
+        self.arrived = 0;
+        self.released += 1;
+        RELEASED.notify_all();
+        true
+    }
+}
+
+lazy_static! {
+    static ref BARRIER: OrderedMutex<Barrier> =
+        OrderedMutex::new("phase barrier", Barrier::default());
+    static ref RELEASED: Condvar = Condvar::new();
+}
+
+/// Holds each of `workers` workers before every phase until all of them are
+/// about to start one.
+pub fn enable(workers: usize) {
+    let mut barrier = lock();
+    barrier.enabled = true;
+    barrier.done = vec![false; workers];
+}
+
+/// Waits until every active worker is about to start a phase.
+pub(crate) fn enter() {
+    let mut barrier = lock();
+    if !barrier.enabled {
+        return;
+    }
+    barrier.arrived += 1;
+    if barrier.release_if_complete() {
+        return;
+    }
+    let released = barrier.released;
+    while barrier.released == released && !shutdown::requested() {
+        barrier = barrier.wait_timeout(&RELEASED, POLL_INTERVAL).0;
+        // Waiting for a slower worker is not a hang.
+        watchdog::waiting();
+    }
+}
+
+/// Stops waiting for `worker` once it runs no more phases.
+pub(crate) fn finished(worker: usize) {
+    let mut barrier = lock();
+    if let Some(done) = barrier.done.get_mut(worker) {
+        *done = true;
+        barrier.release_if_complete();
+    }
+}
+
+/// Prints how often the workers started their phases together.
+pub fn print_report() {
+    let barrier = lock();
+    if barrier.enabled {
+        println!();
+        println!(
+            "Started the phases of all workers together {} times",
+            barrier.released
+        );
+    }
+}
+
+fn lock() -> OrderedGuard<'static, Barrier> {
+    BARRIER.lock()
+}
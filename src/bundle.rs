@@ -0,0 +1,149 @@
+//! Self-contained proof bundles, to verify a proof on another machine.
+//!
+//! With `--export-bundles` every successful C2 also writes a JSON bundle with
+//! the proof and everything `verify_seal` needs besides the parameters:
+//! commitments, prover and sector ids, ticket, seed and the PoRep config.
+//! `hang import-verify` checks bundles without the sector files they came
+//! from. Byte strings are hex encoded.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use filecoin_proofs::{verify_seal, Commitment, MerkleTreeTrait, PoRepConfig};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+
+use crate::lifecycle::{
+    init_logger, parse_api_version, parse_porep_id, porep_config, porep_id_hex, SectorMeta,
+};
+
+/// Bumped whenever the fields of [`ProofBundle`] change meaning.
+pub const BUNDLE_VERSION: u32 = 1;
+
+lazy_static! {
+    static ref EXPORT_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProofBundle {
+    pub version: u32,
+    pub sector_size: u64,
+    /// As in `--api-versions`, e.g. `V1_1_0`.
+    pub api_version: String,
+    pub porep_id: String,
+    pub comm_r: String,
+    pub comm_d: String,
+    pub prover_id: String,
+    pub sector_id: u64,
+    pub ticket: String,
+    pub seed: String,
+    pub proof: String,
+}
+
+/// Writes a bundle to `dir` for every proof from now on.
+pub fn enable(dir: PathBuf) -> Result<()> {
+    fs::create_dir_all(&dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    *EXPORT_DIR.lock().expect("bundle dir poisoned") = Some(dir);
+    Ok(())
+}
+
+/// Writes the bundle of a freshly proven sector, if bundles are exported.
+pub(crate) fn export(
+    config: PoRepConfig,
+    meta: &SectorMeta,
+    comm_r: Commitment,
+    comm_d: Commitment,
+    proof: &[u8],
+) -> Result<()> {
+    let dir = match EXPORT_DIR.lock().expect("bundle dir poisoned").clone() {
+        Some(dir) => dir,
+        None => return Ok(()),
+    };
+    let sector_id = u64::from(meta.sector_id);
+    let bundle = ProofBundle {
+        version: BUNDLE_VERSION,
+        sector_size: u64::from(config.sector_size),
+        api_version: format!("{:?}", config.api_version),
+        porep_id: porep_id_hex(&config.porep_id),
+        comm_r: hex(&comm_r),
+        comm_d: hex(&comm_d),
+        prover_id: hex(&meta.prover_id),
+        sector_id,
+        ticket: hex(&meta.ticket),
+        seed: hex(&meta.seed),
+        proof: hex(proof),
+    };
+    let path = dir.join(format!("sector-{}.json", sector_id));
+    let json = serde_json::to_string_pretty(&bundle)?;
+    fs::write(&path, json).with_context(|| format!("failed to write {}", path.display()))
+}
+
+/// Verifies the proof in the bundle at `path`.
+pub fn verify(path: &Path) -> Result<bool> {
+    init_logger();
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let bundle: ProofBundle = serde_json::from_str(&contents)
+        .with_context(|| format!("invalid bundle {}", path.display()))?;
+    ensure!(
+        bundle.version == BUNDLE_VERSION,
+        "bundle version {} is not the supported {}",
+        bundle.version,
+        BUNDLE_VERSION
+    );
+    let (api_version, _) = parse_api_version(&bundle.api_version)?;
+    let config = porep_config(
+        bundle.sector_size,
+        parse_porep_id(&bundle.porep_id)?,
+        api_version,
+    );
+    with_shape!(bundle.sector_size, verify_bundle, config, &bundle)
+}
+
+fn verify_bundle<Tree: 'static + MerkleTreeTrait>(
+    config: PoRepConfig,
+    bundle: &ProofBundle,
+) -> Result<bool> {
+    verify_seal::<Tree>(
+        config,
+        bytes32(&bundle.comm_r, "comm_r")?,
+        bytes32(&bundle.comm_d, "comm_d")?,
+        bytes32(&bundle.prover_id, "prover_id")?,
+        bundle.sector_id.into(),
+        bytes32(&bundle.ticket, "ticket")?,
+        bytes32(&bundle.seed, "seed")?,
+        &unhex(&bundle.proof).context("invalid proof")?,
+    )
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn unhex(digits: &str) -> Result<Vec<u8>> {
+    if digits.len() % 2 != 0 || !digits.is_ascii() {
+        bail!("not an even number of hex digits");
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&digits[i..i + 2], 16)
+                .map_err(|_| anyhow!("invalid hex digits {:?}", &digits[i..i + 2]))
+        })
+        .collect()
+}
+
+fn bytes32(digits: &str, name: &str) -> Result<[u8; 32]> {
+    let bytes = unhex(digits).with_context(|| format!("invalid {}", name))?;
+    ensure!(
+        bytes.len() == 32,
+        "{} is {} bytes, not 32",
+        name,
+        bytes.len()
+    );
+    let mut array = [0u8; 32];
+    array.copy_from_slice(&bytes);
+    Ok(array)
+}
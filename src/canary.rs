@@ -0,0 +1,144 @@
+//! Starvation of the global rayon pool.
+//!
+//! Workers stuck inside their phases look the same whether the phases wait
+//! for the GPU lock or for rayon threads that are all busy or blocked. While
+//! any worker is in a phase, a canary task is put on the global pool every
+//! second; one that does not get to run within the threshold flags pool
+//! starvation, which is logged, shown in the watchdog's dump and counted in
+//! the report. Workers with a rayon pool of their own are not covered.
+
+use std::sync::mpsc;
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use lazy_static::lazy_static;
+use tracing::{info, warn};
+
+/// How often the canary is sent while workers are in a phase.
+const INTERVAL: Duration = Duration::from_secs(1);
+
+struct Canary {
+    threshold: Duration,
+    /// Threads currently in a phase.
+    active: usize,
+    /// When the canary that has not run yet was sent, once it is overdue.
+    overdue_since: Option<Instant>,
+    starvations: usize,
+    longest: Duration,
+}
+
+lazy_static! {
+    static ref CANARY: Mutex<Option<Canary>> = Mutex::new(None);
+}
+
+/// Starts sending the canary, flagging starvation once it waited longer
+/// than `threshold`.
+pub fn start(threshold: Duration) {
+    *lock() = Some(Canary {
+        threshold,
+        active: 0,
+        overdue_since: None,
+        starvations: 0,
+        longest: Duration::default(),
+    });
+    thread::Builder::new()
+        .name("rayon-canary".into())
+        .spawn(run)
+        .expect("failed to spawn rayon canary");
+}
+
+/// Called when the current thread starts a phase.
+pub(crate) fn enter() {
+    if let Some(canary) = lock().as_mut() {
+        canary.active += 1;
+    }
+}
+
+/// Called when the current thread's phase returned.
+pub(crate) fn leave() {
+    if let Some(canary) = lock().as_mut() {
+        canary.active = canary.active.saturating_sub(1);
+    }
+}
+
+fn run() {
+    loop {
+        thread::sleep(INTERVAL);
+        let threshold = match lock().as_ref() {
+            Some(canary) if canary.active > 0 => canary.threshold,
+            _ => continue,
+        };
+        let sent = Instant::now();
+        let (tx, rx) = mpsc::channel();
+        rayon::spawn(move || {
+            let _ = tx.send(());
+        });
+        if rx.recv_timeout(threshold).is_ok() {
+            continue;
+        }
+
+        warn!(
+            "the global rayon pool did not run the canary within {:?}, pool starvation",
+            threshold
+        );
+        if let Some(canary) = lock().as_mut() {
+            canary.overdue_since = Some(sent);
+            canary.starvations += 1;
+        }
+        let _ = rx.recv();
+        let waited = sent.elapsed();
+        info!(
+            waited_secs = waited.as_secs_f64(),
+            "the global rayon pool ran the canary"
+        );
+        if let Some(canary) = lock().as_mut() {
+            canary.overdue_since = None;
+            canary.longest = canary.longest.max(waited);
+        }
+    }
+}
+
+/// How long the overdue canary has been waiting, if one is.
+pub(crate) fn starved_for() -> Option<Duration> {
+    lock()
+        .as_ref()
+        .and_then(|canary| canary.overdue_since)
+        .map(|since| since.elapsed())
+}
+
+/// Whether the pool is starved right now, for the watchdog's dump.
+pub(crate) fn describe() -> String {
+    match starved_for() {
+        Some(waited) => format!(
+            "rayon canary: waiting for the global pool for {:.1}s, pool starvation\n",
+            waited.as_secs_f64()
+        ),
+        None => String::new(),
+    }
+}
+
+/// Prints how often the global pool was starved, if it ever was.
+pub fn print_report() {
+    let canary = lock();
+    let canary = match canary.as_ref() {
+        Some(canary) if canary.starvations > 0 => canary,
+        _ => return,
+    };
+    println!();
+    println!(
+        "Pool starvation: the global rayon pool kept the canary waiting {} times, longest {:.1}s",
+        canary.starvations,
+        canary.longest.as_secs_f64()
+    );
+    if let Some(since) = canary.overdue_since {
+        println!(
+            "  still starved at the end, for {:.1}s",
+            since.elapsed().as_secs_f64()
+        );
+    }
+}
+
+fn lock() -> MutexGuard<'static, Option<Canary>> {
+    CANARY.lock().expect("rayon canary poisoned")
+}
@@ -0,0 +1,119 @@
+//! A cgroup of its own for every process worker.
+//!
+//! Miners' orchestrators run each lotus-worker with a CPU quota and a hard
+//! memory limit, and the stalls they report happen close to that limit.
+//! `--worker-cpus` and `--worker-memory` give every process worker a cgroup
+//! v2 with `cpu.max` and `memory.max` set accordingly, and swap turned off
+//! as on a Kubernetes node. Children move themselves into theirs before
+//! exec, so everything they allocate is charged to it. Needs root.
+
+use std::ffi::CString;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use tracing::warn;
+
+use crate::storage::CGROUP_ROOT;
+
+/// `cpu.max` quotas are given per period of this many microseconds.
+const CPU_PERIOD_US: u64 = 100_000;
+
+/// The limits every worker gets, `None` leaving one out.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct WorkerLimits {
+    /// Cores worth of CPU time.
+    pub cpus: Option<f64>,
+    /// Bytes of memory.
+    pub memory: Option<u64>,
+}
+
+/// The cgroups of the workers, removed again on drop.
+#[derive(Debug)]
+pub struct WorkerCgroups {
+    dirs: Vec<PathBuf>,
+}
+
+impl WorkerCgroups {
+    /// Creates a cgroup with `limits` for each of `workers` workers.
+    pub fn create(workers: usize, limits: WorkerLimits) -> Result<Self> {
+        let root = Path::new(CGROUP_ROOT);
+        fs::write(root.join("cgroup.subtree_control"), "+cpu +memory")
+            .context("failed to enable the cpu and memory controllers")?;
+        let mut cgroups = WorkerCgroups {
+            dirs: Vec::with_capacity(workers),
+        };
+        for index in 0..workers {
+            let dir = root.join(format!("hang-{}-worker-{}", std::process::id(), index));
+            fs::create_dir(&dir).with_context(|| format!("failed to create {}", dir.display()))?;
+            cgroups.dirs.push(dir.clone());
+            if let Some(cpus) = limits.cpus {
+                let quota = (cpus * CPU_PERIOD_US as f64).round() as u64;
+                fs::write(dir.join("cpu.max"), format!("{} {}", quota, CPU_PERIOD_US))
+                    .context("failed to set cpu.max")?;
+            }
+            if let Some(memory) = limits.memory {
+                fs::write(dir.join("memory.max"), memory.to_string())
+                    .context("failed to set memory.max")?;
+                fs::write(dir.join("memory.swap.max"), "0")
+                    .context("failed to set memory.swap.max")?;
+            }
+        }
+        Ok(cgroups)
+    }
+
+    /// The `cgroup.procs` worker `index` writes itself to, as the C string
+    /// a child can still use between fork and exec.
+    pub fn procs(&self, index: usize) -> CString {
+        let path = self.dirs[index % self.dirs.len()].join("cgroup.procs");
+        CString::new(path.as_os_str().as_bytes()).expect("cgroup paths have no NUL")
+    }
+
+    /// Warns about every worker the kernel OOM-killed a process of.
+    pub fn log_oom_kills(&self) {
+        for (index, dir) in self.dirs.iter().enumerate() {
+            let events = match fs::read_to_string(dir.join("memory.events")) {
+                Ok(events) => events,
+                Err(_) => continue,
+            };
+            let kills = events
+                .lines()
+                .filter_map(|line| line.strip_prefix("oom_kill "))
+                .filter_map(|count| count.trim().parse::<u64>().ok())
+                .sum::<u64>();
+            if kills > 0 {
+                warn!(worker = index, "hit its memory limit, {} OOM kills", kills);
+            }
+        }
+    }
+}
+
+impl Drop for WorkerCgroups {
+    fn drop(&mut self) {
+        // Only empty cgroups can be removed, which they are once the
+        // workers exited.
+        for dir in &self.dirs {
+            let _ = fs::remove_dir(dir);
+        }
+    }
+}
+
+/// Moves the calling process into the cgroup of `procs`. Only makes
+/// async-signal-safe calls, for use in `pre_exec`.
+pub fn join(procs: &CString) -> std::io::Result<()> {
+    unsafe {
+        let fd = libc::open(procs.as_ptr(), libc::O_WRONLY);
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        // Writing 0 moves the writer itself.
+        let written = libc::write(fd, b"0".as_ptr() as *const libc::c_void, 1);
+        let err = std::io::Error::last_os_error();
+        libc::close(fd);
+        if written != 1 {
+            return Err(err);
+        }
+    }
+    Ok(())
+}
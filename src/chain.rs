@@ -0,0 +1,96 @@
+//! A simulated chain that seal lifecycles take their tickets and seeds from.
+//!
+//! Without it a lifecycle draws its ticket and seed up front and runs C1
+//! right after PC2. On a miner the seed only comes from the chain once the
+//! pre-commit landed and the challenge delay passed, so a sector sits idle
+//! between PC2 and C1 while its worker's resources go to other sectors.
+//! With `--epoch-secs` epochs tick at that interval, counted from the Unix
+//! epoch so worker processes see the same chain. A lifecycle takes the
+//! randomness of the epoch PC1 starts in as its ticket, and waits from the
+//! epoch PC2 finished in for `--seed-delay` more epochs before drawing its
+//! seed. Every reader of an epoch gets the same randomness, derived from the
+//! run seed.
+
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use lazy_static::lazy_static;
+use rand::{Rng, SeedableRng};
+use rand_xorshift::XorShiftRng;
+use tracing::info;
+
+use crate::shutdown;
+use crate::watchdog;
+
+/// Mixes the epoch into the run seed.
+const GOLDEN: u64 = 0x9e37_79b9_7f4a_7c15;
+
+#[derive(Clone, Copy)]
+struct Chain {
+    seed: u64,
+    epoch: Duration,
+    seed_delay: u64,
+}
+
+lazy_static! {
+    static ref CHAIN: Mutex<Option<Chain>> = Mutex::new(None);
+}
+
+/// Draws tickets and seeds from epochs of length `epoch`, each seed
+/// `seed_delay` epochs after PC2, with randomness derived from `seed`.
+pub fn enable(seed: u64, epoch: Duration, seed_delay: u64) {
+    *CHAIN.lock().expect("chain poisoned") = Some(Chain {
+        seed,
+        epoch,
+        seed_delay,
+    });
+}
+
+/// The current epoch, `None` without a simulated chain.
+pub(crate) fn epoch() -> Option<u64> {
+    chain().map(|chain| chain.current())
+}
+
+/// The randomness of the current epoch, `None` without a simulated chain.
+pub(crate) fn ticket() -> Option<[u8; 32]> {
+    chain().map(|chain| chain.randomness(chain.current()))
+}
+
+/// Waits until the seed of a sector pre-committed in `precommit_epoch` is
+/// on the chain and returns it, or the randomness of the epoch reached once
+/// shutdown is requested; `None` without a simulated chain.
+pub(crate) fn wait_for_seed(precommit_epoch: u64) -> Option<[u8; 32]> {
+    let chain = chain()?;
+    let seed_epoch = precommit_epoch + chain.seed_delay;
+    if chain.current() < seed_epoch {
+        info!(
+            "waiting {} epochs for the seed of epoch {}",
+            seed_epoch - chain.current(),
+            seed_epoch
+        );
+    }
+    while chain.current() < seed_epoch && !shutdown::requested() {
+        thread::sleep(Duration::from_secs(1).min(chain.epoch));
+        // Waiting for the chain is not a hang.
+        watchdog::waiting();
+    }
+    Some(chain.randomness(chain.current().min(seed_epoch)))
+}
+
+impl Chain {
+    fn current(&self) -> u64 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        (now.as_millis() / self.epoch.as_millis().max(1)) as u64
+    }
+
+    fn randomness(&self, epoch: u64) -> [u8; 32] {
+        XorShiftRng::seed_from_u64(self.seed ^ epoch.wrapping_mul(GOLDEN)).gen()
+    }
+}
+
+fn chain() -> Option<Chain> {
+    *CHAIN.lock().expect("chain poisoned")
+}
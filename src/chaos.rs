@@ -0,0 +1,332 @@
+//! Fault injection.
+//!
+//! Faults given with `--chaos` are checked whenever a worker starts a phase.
+//! A killed worker dies without cleaning up after itself, so the remaining
+//! workers show whether it left the GPU lock or the scheduler wedged; the
+//! watchdog reports them if they stop making progress.
+//!
+//! Cache corruption is applied by the seal lifecycle itself, right before it
+//! validates the cache for PC2 or C1.
+//!
+//! In pipeline mode a worker can also be killed as it starts PC2 after
+//! handing its sector to the pipeline, which has the next worker starting a
+//! seal lifecycle finish it from the PC1 checkpoint and verify its proof.
+
+use std::cell::Cell;
+use std::fs::{self, OpenOptions};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::mpsc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use lazy_static::lazy_static;
+use rand::{Rng, SeedableRng};
+use rand_xorshift::XorShiftRng;
+use tracing::{error, warn, Span};
+
+use crate::metrics::Phase;
+
+/// A process worker is killed at a random point this far into the phase at
+/// the latest, or at its end if it finishes sooner.
+const KILL_DELAY_MAX: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fault {
+    /// Kill the worker during `phase` with the given probability.
+    KillWorker { phase: Phase, probability: f64 },
+    /// Damage a file in the cache directory right before `before`, which is
+    /// either PC2 or C1, with the given probability.
+    CorruptCache {
+        before: Phase,
+        corruption: Corruption,
+        probability: f64,
+    },
+    /// Kill the worker as it starts PC2 with the given probability, leaving
+    /// its sector to another worker, see [`crate::pipeline`].
+    ReassignPc2 { probability: f64 },
+}
+
+/// What is done to the chosen cache file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Corruption {
+    /// Cut the file to half its size.
+    Truncate,
+    Delete,
+}
+
+impl FromStr for Corruption {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "truncate" => Ok(Corruption::Truncate),
+            "delete" => Ok(Corruption::Delete),
+            _ => bail!("unknown cache corruption: {}", s),
+        }
+    }
+}
+
+impl FromStr for Fault {
+    type Err = anyhow::Error;
+
+    /// Parses `kill-worker:<phase>:<probability>`, e.g. `kill-worker:pc2:0.1`,
+    /// `corrupt-cache:<pc2|c1>:<truncate|delete>:<probability>` or
+    /// `reassign-pc2:<probability>`.
+    fn from_str(s: &str) -> Result<Self> {
+        let parts = s.split(':').collect::<Vec<_>>();
+        match parts.as_slice() {
+            ["kill-worker", phase, probability] => Ok(Fault::KillWorker {
+                phase: parse_phase(phase)?,
+                probability: parse_probability(probability)?,
+            }),
+            ["corrupt-cache", before, corruption, probability] => {
+                let before = parse_phase(before)?;
+                if before != Phase::PreCommit2 && before != Phase::Commit1 {
+                    bail!("the cache can only be corrupted before pc2 or c1");
+                }
+                Ok(Fault::CorruptCache {
+                    before,
+                    corruption: corruption.parse()?,
+                    probability: parse_probability(probability)?,
+                })
+            }
+            ["reassign-pc2", probability] => Ok(Fault::ReassignPc2 {
+                probability: parse_probability(probability)?,
+            }),
+            _ => bail!("unknown fault: {}", s),
+        }
+    }
+}
+
+fn parse_phase(name: &str) -> Result<Phase> {
+    match Phase::ALL.iter().find(|phase| phase.name() == name) {
+        Some(phase) => Ok(*phase),
+        None => bail!("unknown phase: {}", name),
+    }
+}
+
+fn parse_probability(value: &str) -> Result<f64> {
+    let probability: f64 = value
+        .parse()
+        .with_context(|| format!("invalid probability {:?}", value))?;
+    if !(0.0..=1.0).contains(&probability) {
+        bail!("probability {} is not between 0 and 1", probability);
+    }
+    Ok(probability)
+}
+
+/// How a worker is killed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KillMode {
+    /// Panic the worker thread once the phase returns; a thread cannot be
+    /// stopped from the outside in the middle of a call.
+    Panic,
+    /// Abort the whole process mid-phase, for process workers.
+    Abort,
+}
+
+struct State {
+    faults: Vec<Fault>,
+    mode: KillMode,
+    rng: XorShiftRng,
+}
+
+lazy_static! {
+    static ref STATE: Mutex<Option<State>> = Mutex::new(None);
+}
+
+/// Enables `faults` for every phase started from now on, drawing from `seed`.
+pub fn install(faults: Vec<Fault>, seed: u64, mode: KillMode) {
+    if faults.is_empty() {
+        return;
+    }
+    *STATE.lock().expect("chaos state poisoned") = Some(State {
+        faults,
+        mode,
+        rng: XorShiftRng::seed_from_u64(seed),
+    });
+}
+
+/// Called by the seal lifecycle right before validating `cache` for
+/// `before`; returns what was damaged, if anything.
+pub fn corrupt_cache(before: Phase, cache: &Path) -> Result<Option<String>> {
+    let mut state = STATE.lock().expect("chaos state poisoned");
+    let state = match state.as_mut() {
+        Some(state) => state,
+        None => return Ok(None),
+    };
+    let fault = state.faults.iter().find_map(|fault| match fault {
+        Fault::CorruptCache {
+            before: target,
+            corruption,
+            probability,
+        } if *target == before => Some((*corruption, *probability)),
+        _ => None,
+    });
+    let corruption = match fault {
+        Some((corruption, probability)) if state.rng.gen_bool(probability) => corruption,
+        _ => return Ok(None),
+    };
+
+    let mut files = fs::read_dir(cache)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    files.retain(|path| path.is_file());
+    files.sort();
+    if files.is_empty() {
+        return Ok(None);
+    }
+    let path = &files[state.rng.gen_range(0, files.len())];
+    match corruption {
+        Corruption::Truncate => {
+            let file = OpenOptions::new().write(true).open(path)?;
+            file.set_len(file.metadata()?.len() / 2)?;
+        }
+        Corruption::Delete => fs::remove_file(path)?,
+    }
+    let damage = format!("{:?} {}", corruption, path.display()).to_lowercase();
+    warn!("chaos: {} before {}", damage, before);
+    Ok(Some(damage))
+}
+
+/// Called by the seal lifecycle before PC2; decides whether the worker dies
+/// there, leaving its sector to another.
+pub(crate) fn reassign_pc2() -> bool {
+    let mut state = STATE.lock().expect("chaos state poisoned");
+    let state = match state.as_mut() {
+        Some(state) => state,
+        None => return false,
+    };
+    let probability = state
+        .faults
+        .iter()
+        .map(|fault| match fault {
+            Fault::ReassignPc2 { probability } => *probability,
+            _ => 0.0,
+        })
+        .fold(0.0, f64::max);
+    probability > 0.0 && state.rng.gen_bool(probability)
+}
+
+/// A pending kill of the current worker.
+pub(crate) enum Kill {
+    Panic(Phase),
+    Abort(mpsc::Sender<()>),
+}
+
+/// Called when the current worker starts `phase`; decides whether it dies.
+pub(crate) fn enter(phase: Phase) -> Option<Kill> {
+    let mut state = STATE.lock().expect("chaos state poisoned");
+    let state = state.as_mut()?;
+    let probability = state
+        .faults
+        .iter()
+        .map(|fault| match fault {
+            Fault::KillWorker {
+                phase: target,
+                probability,
+            } if *target == phase => *probability,
+            _ => 0.0,
+        })
+        .fold(0.0, f64::max);
+    if probability == 0.0 || !state.rng.gen_bool(probability) {
+        return None;
+    }
+
+    match state.mode {
+        KillMode::Panic => Some(Kill::Panic(phase)),
+        KillMode::Abort => {
+            let delay = state
+                .rng
+                .gen_range(0, KILL_DELAY_MAX.as_millis() as u64 + 1);
+            let (done, rx) = mpsc::channel();
+            let span = Span::current();
+            thread::Builder::new()
+                .name("chaos".into())
+                .spawn(move || {
+                    let _ = rx.recv_timeout(Duration::from_millis(delay));
+                    span.in_scope(|| error!("chaos: killing worker during {}", phase));
+                    std::process::abort();
+                })
+                .expect("failed to spawn chaos thread");
+            Some(Kill::Abort(done))
+        }
+    }
+}
+
+thread_local! {
+    /// Set once a kill fired on this thread, so the panic is not caught.
+    static KILLING: Cell<bool> = Cell::new(false);
+}
+
+/// Whether the current thread is being killed on purpose.
+pub(crate) fn killing() -> bool {
+    KILLING.with(Cell::get)
+}
+
+impl Kill {
+    /// Called when the phase returned before the kill fired.
+    pub(crate) fn fire(self) -> ! {
+        match self {
+            Kill::Panic(phase) => {
+                crate::watchdog::detach();
+                KILLING.with(|killing| killing.set(true));
+                panic!("chaos: killed worker during {}", phase);
+            }
+            Kill::Abort(done) => {
+                let _ = done.send(());
+                loop {
+                    thread::park();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn faults_parse() {
+        assert_eq!(
+            "kill-worker:pc2:0.1".parse::<Fault>().unwrap(),
+            Fault::KillWorker {
+                phase: Phase::PreCommit2,
+                probability: 0.1,
+            }
+        );
+        assert_eq!(
+            "corrupt-cache:c1:delete:1".parse::<Fault>().unwrap(),
+            Fault::CorruptCache {
+                before: Phase::Commit1,
+                corruption: Corruption::Delete,
+                probability: 1.0,
+            }
+        );
+        assert_eq!(
+            "reassign-pc2:0".parse::<Fault>().unwrap(),
+            Fault::ReassignPc2 { probability: 0.0 }
+        );
+    }
+
+    #[test]
+    fn bad_faults_are_rejected() {
+        for fault in &[
+            "kill-worker:pc2",
+            "kill-worker:pc3:0.1",
+            "kill-worker:pc2:1.5",
+            "kill-worker:pc2:-0.1",
+            "kill-worker:pc2:often",
+            "corrupt-cache:pc1:truncate:0.1",
+            "corrupt-cache:pc2:shred:0.1",
+            "reassign-c2:0.1",
+            "",
+        ] {
+            assert!(fault.parse::<Fault>().is_err(), "{:?} parsed", fault);
+        }
+    }
+}
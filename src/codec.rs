@@ -0,0 +1,141 @@
+//! Versioned on-disk format of the phase outputs that outlive a lifecycle.
+//!
+//! The PC1 output a resumed lifecycle picks up and the C1 output handed to
+//! a C2 worker may come from another build of the harness, on another
+//! machine. Both are written as JSON with a header naming what they are,
+//! the format version, the proofs release and the tree shape they were
+//! produced with, and reading one checks the header before the output, so a
+//! mismatch is reported as such instead of as a parse error deep inside the
+//! output or a proof that does not verify.
+
+use std::any;
+use std::io::{Read, Write};
+
+use anyhow::{bail, Context, Result};
+use filecoin_proofs::{MerkleTreeTrait, SealCommitPhase1Output, SealPreCommitPhase1Output};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::compat::PROOFS_RELEASE;
+
+/// Bumped whenever the header or the way outputs are written changes.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// A phase output with an on-disk format of its own.
+pub trait Versioned: Serialize + DeserializeOwned {
+    /// What the output is, e.g. `pc1`.
+    const KIND: &'static str;
+    /// The tree shape the output belongs to.
+    fn shape() -> &'static str;
+}
+
+impl<Tree: 'static + MerkleTreeTrait> Versioned for SealPreCommitPhase1Output<Tree> {
+    const KIND: &'static str = "pc1";
+
+    fn shape() -> &'static str {
+        any::type_name::<Tree>()
+    }
+}
+
+impl<Tree: 'static + MerkleTreeTrait> Versioned for SealCommitPhase1Output<Tree> {
+    const KIND: &'static str = "c1";
+
+    fn shape() -> &'static str {
+        any::type_name::<Tree>()
+    }
+}
+
+/// What a versioned output says about itself.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Header {
+    pub kind: String,
+    pub version: u32,
+    pub proofs_release: String,
+    pub shape: String,
+}
+
+impl Header {
+    fn of<T: Versioned>() -> Self {
+        Header {
+            kind: T::KIND.to_string(),
+            version: FORMAT_VERSION,
+            proofs_release: PROOFS_RELEASE.to_string(),
+            shape: T::shape().to_string(),
+        }
+    }
+
+    /// Fails unless an output with this header can be read as a `T`.
+    fn check<T: Versioned>(&self) -> Result<()> {
+        let expected = Header::of::<T>();
+        if self.kind != expected.kind {
+            bail!(
+                "expected a {} output, found a {} output",
+                expected.kind,
+                self.kind
+            );
+        }
+        if self.version != expected.version {
+            bail!(
+                "{} output is in format version {}, this build reads version {}",
+                self.kind,
+                self.version,
+                expected.version
+            );
+        }
+        if self.proofs_release != expected.proofs_release {
+            bail!(
+                "{} output was produced by {}, this build uses {}",
+                self.kind,
+                self.proofs_release,
+                expected.proofs_release
+            );
+        }
+        if self.shape != expected.shape {
+            bail!(
+                "{} output is of shape {}, expected {}",
+                self.kind,
+                self.shape,
+                expected.shape
+            );
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+struct Envelope<'a, T> {
+    header: Header,
+    output: &'a T,
+}
+
+#[derive(Deserialize)]
+struct OwnedEnvelope {
+    header: Header,
+    output: serde_json::Value,
+}
+
+/// Writes `output` with its header to `writer`.
+pub fn write<T: Versioned, W: Write>(writer: W, output: &T) -> Result<()> {
+    serde_json::to_writer(
+        writer,
+        &Envelope {
+            header: Header::of::<T>(),
+            output,
+        },
+    )?;
+    Ok(())
+}
+
+/// Reads an output [`write`] wrote, checking its header first.
+pub fn read<T: Versioned, R: Read>(reader: R) -> Result<T> {
+    let envelope: OwnedEnvelope = serde_json::from_reader(reader)
+        .with_context(|| format!("not a versioned {} output", T::KIND))?;
+    envelope.header.check::<T>()?;
+    serde_json::from_value(envelope.output).with_context(|| format!("invalid {} output", T::KIND))
+}
+
+/// Reads the header of an output [`write`] wrote, without checking it.
+pub fn read_header<R: Read>(reader: R) -> Result<Header> {
+    let envelope: OwnedEnvelope = serde_json::from_reader(reader)?;
+    Ok(envelope.header)
+}
@@ -0,0 +1,228 @@
+//! `hang compare`: diffs the `--output-json` reports of two runs.
+//!
+//! Meant for running the harness before and after a proofs dependency bump:
+//! the throughput, failure and timeout rates and the p50 and p95 of every
+//! phase of the second run are set against the first, and every change for
+//! the worse by more than the threshold is flagged as a regression, as is
+//! every phase the second run never completed. Rates are compared in
+//! percentage points, everything else in percent.
+
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+use crate::metrics::{self, phase_stats, LifecycleRecord};
+
+/// One number both runs have.
+#[derive(Clone, Debug)]
+pub struct Metric {
+    pub name: String,
+    pub before: f64,
+    pub after: f64,
+    /// Whether a higher value is better, as for throughput.
+    pub higher_is_better: bool,
+    /// Whether the metric is a rate in percent, compared in points.
+    pub rate: bool,
+    /// Whether the second run has no value at all, as for a phase it never
+    /// completed; `after` is meaningless then.
+    pub missing: bool,
+}
+
+impl Metric {
+    /// How much the metric got worse, in percent or, for rates, in
+    /// percentage points; negative when it got better.
+    pub fn worsening(&self) -> f64 {
+        if self.missing {
+            return f64::INFINITY;
+        }
+        let change = if self.rate {
+            self.after - self.before
+        } else if self.before == 0.0 {
+            // Counts and durations are never negative.
+            if self.after > 0.0 {
+                f64::INFINITY
+            } else {
+                0.0
+            }
+        } else {
+            100.0 * (self.after - self.before) / self.before
+        };
+        if self.higher_is_better {
+            -change
+        } else {
+            change
+        }
+    }
+
+    pub fn regressed(&self, threshold: f64) -> bool {
+        self.worsening() > threshold
+    }
+}
+
+/// Compares the report at `after` with the one at `before`.
+pub fn compare(before: &Path, after: &Path) -> Result<Vec<Metric>> {
+    let read = |path: &Path| {
+        metrics::read_report(path).with_context(|| format!("failed to read {}", path.display()))
+    };
+    let (before, after) = (read(before)?, read(after)?);
+
+    let mut metrics = Vec::new();
+    if let (Some(before_elapsed), Some(after_elapsed)) = (before.elapsed, after.elapsed) {
+        metrics.push(Metric {
+            name: "lifecycles per hour".to_string(),
+            before: per_hour(&before.records, before_elapsed),
+            after: per_hour(&after.records, after_elapsed),
+            higher_is_better: true,
+            rate: false,
+            missing: false,
+        });
+    }
+    metrics.push(Metric {
+        name: "failed lifecycles %".to_string(),
+        before: percent(&before.records, |r| !r.success),
+        after: percent(&after.records, |r| !r.success),
+        higher_is_better: false,
+        rate: true,
+        missing: false,
+    });
+    metrics.push(Metric {
+        name: "timed out lifecycles %".to_string(),
+        before: percent(&before.records, |r| r.timed_out.is_some()),
+        after: percent(&after.records, |r| r.timed_out.is_some()),
+        higher_is_better: false,
+        rate: true,
+        missing: false,
+    });
+    let after_stats = phase_stats(&after.records);
+    for before in phase_stats(&before.records) {
+        // A phase the second run never completed is the worst regression.
+        let after = after_stats
+            .iter()
+            .find(|s| s.group == before.group && s.phase == before.phase);
+        for &(name, before_secs, after_secs) in &[
+            ("p50", before.p50, after.map(|after| after.p50)),
+            ("p95", before.p95, after.map(|after| after.p95)),
+        ] {
+            metrics.push(Metric {
+                name: format!("{} {} {} (s)", before.group, before.phase, name),
+                before: before_secs,
+                after: after_secs.unwrap_or_default(),
+                higher_is_better: false,
+                rate: false,
+                missing: after_secs.is_none(),
+            });
+        }
+    }
+    Ok(metrics)
+}
+
+/// Prints every metric, marking those that regressed by more than
+/// `threshold`, and returns how many did.
+pub fn print_report(metrics: &[Metric], threshold: f64) -> usize {
+    println!(
+        "{:<28} {:>12} {:>12} {:>10}",
+        "metric", "before", "after", "change"
+    );
+    let mut regressions = 0;
+    for metric in metrics {
+        let regressed = metric.regressed(threshold);
+        if regressed {
+            regressions += 1;
+        }
+        let change = if metric.missing {
+            "missing".to_string()
+        } else if metric.rate {
+            format!("{:+.1}pt", metric.after - metric.before)
+        } else if metric.before == 0.0 {
+            "-".to_string()
+        } else {
+            format!(
+                "{:+.1}%",
+                100.0 * (metric.after - metric.before) / metric.before
+            )
+        };
+        println!(
+            "{:<28} {:>12.3} {:>12.3} {:>10}{}",
+            metric.name,
+            metric.before,
+            metric.after,
+            change,
+            if regressed { "  REGRESSION" } else { "" }
+        );
+    }
+    println!();
+    println!(
+        "{} of {} metrics regressed by more than {}%, or points for rates",
+        regressions,
+        metrics.len(),
+        threshold
+    );
+    regressions
+}
+
+fn per_hour(records: &[LifecycleRecord], elapsed: Duration) -> f64 {
+    let succeeded = records.iter().filter(|r| r.success).count();
+    match elapsed.as_secs_f64() {
+        secs if secs > 0.0 => succeeded as f64 * 3600.0 / secs,
+        _ => 0.0,
+    }
+}
+
+fn percent(records: &[LifecycleRecord], matches: fn(&LifecycleRecord) -> bool) -> f64 {
+    match records.len() {
+        0 => 0.0,
+        n => 100.0 * records.iter().filter(|r| matches(r)).count() as f64 / n as f64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metric(before: f64, after: f64, higher_is_better: bool, rate: bool) -> Metric {
+        Metric {
+            name: "metric".to_string(),
+            before,
+            after,
+            higher_is_better,
+            rate,
+            missing: false,
+        }
+    }
+
+    #[test]
+    fn durations_worsen_in_percent() {
+        assert_eq!(metric(10.0, 12.0, false, false).worsening(), 20.0);
+        assert_eq!(metric(10.0, 5.0, false, false).worsening(), -50.0);
+    }
+
+    #[test]
+    fn throughput_worsens_when_it_drops() {
+        assert_eq!(metric(10.0, 8.0, true, false).worsening(), 20.0);
+        assert_eq!(metric(10.0, 15.0, true, false).worsening(), -50.0);
+    }
+
+    #[test]
+    fn rates_worsen_in_points() {
+        assert_eq!(metric(2.0, 5.0, false, true).worsening(), 3.0);
+        assert_eq!(metric(90.0, 80.0, true, true).worsening(), 10.0);
+    }
+
+    #[test]
+    fn growing_from_zero_always_regresses() {
+        assert_eq!(metric(0.0, 1.0, false, false).worsening(), f64::INFINITY);
+        assert_eq!(metric(0.0, 0.0, false, false).worsening(), 0.0);
+        assert!(!metric(0.0, 0.0, false, false).regressed(0.0));
+    }
+
+    #[test]
+    fn missing_phase_regresses() {
+        let metric = Metric {
+            missing: true,
+            ..metric(10.0, 0.0, false, false)
+        };
+        assert_eq!(metric.worsening(), f64::INFINITY);
+        assert!(metric.regressed(1000.0));
+    }
+}
@@ -0,0 +1,43 @@
+//! The filecoin-proofs release the harness is built against.
+//!
+//! Exactly one `proofs-*` cargo feature picks it: `proofs-scheduler`, the
+//! default, is the Zondax `scheduler` branch, while `proofs-v9`, `proofs-v10`
+//! and `proofs-v11` are the upstream releases, to bisect which one brought
+//! the hang in. The crate root renames the chosen release's crates to
+//! `filecoin_proofs` and `storage_proofs_core`, so only what differs between
+//! the releases goes through this module.
+
+#[cfg(not(any(
+    feature = "proofs-scheduler",
+    feature = "proofs-v9",
+    feature = "proofs-v10",
+    feature = "proofs-v11"
+)))]
+compile_error!("enable one of the proofs-scheduler, proofs-v9, proofs-v10 or proofs-v11 features");
+
+#[cfg(any(
+    all(feature = "proofs-scheduler", feature = "proofs-v9"),
+    all(feature = "proofs-scheduler", feature = "proofs-v10"),
+    all(feature = "proofs-scheduler", feature = "proofs-v11"),
+    all(feature = "proofs-v9", feature = "proofs-v10"),
+    all(feature = "proofs-v9", feature = "proofs-v11"),
+    all(feature = "proofs-v10", feature = "proofs-v11"),
+))]
+compile_error!("only one proofs-* feature can be enabled, add --no-default-features");
+
+/// The release, as logged at startup.
+#[cfg(feature = "proofs-scheduler")]
+pub const PROOFS_RELEASE: &str = "filecoin-proofs scheduler branch";
+#[cfg(feature = "proofs-v9")]
+pub const PROOFS_RELEASE: &str = "filecoin-proofs 9";
+#[cfg(feature = "proofs-v10")]
+pub const PROOFS_RELEASE: &str = "filecoin-proofs 10";
+#[cfg(feature = "proofs-v11")]
+pub const PROOFS_RELEASE: &str = "filecoin-proofs 11";
+
+/// The scalar field element of public inputs. Up to 9 bellperson has it,
+/// from 10 on the proofs only use blstrs.
+#[cfg(any(feature = "proofs-scheduler", feature = "proofs-v9"))]
+pub use bellperson::bls::Fr;
+#[cfg(any(feature = "proofs-v10", feature = "proofs-v11"))]
+pub use blstrs::Scalar as Fr;
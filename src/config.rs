@@ -0,0 +1,1401 @@
+//! TOML run configuration.
+//!
+//! Every key mirrors the command line flag of the same name; flags given on
+//! the command line take precedence over the file, which takes precedence over
+//! the built-in defaults.
+//!
+//! ```toml
+//! num-threads = 4
+//! sector-size = "32KiB"
+//! workload = "seal"
+//! iterations = 10
+//! api-versions = ["1.1.0", "1.0.0"]
+//! hang-timeout = 600
+//! output-json = "results.json"
+//!
+//! # Optional heterogeneous worker groups; when present they replace the
+//! # single group described by `num-threads`, `workload` and `phase`.
+//! [[group]]
+//! name = "pc1"
+//! threads = 4
+//! phase = "pc1"
+//! rayon-threads = 8
+//!
+//! [[group]]
+//! name = "window-post"
+//! threads = 1
+//! workload = "window-post"
+//! iterations = 20
+//!
+//! # Combinations run one after the other by `--sweep`.
+//! [sweep]
+//! threads = [1, 2, 4]
+//! sector-sizes = ["2KiB", "32KiB"]
+//! api-versions = ["1.1.0", "1.0.0"]
+//! workloads = ["seal", "window-post"]
+//! duration = 300
+//! ```
+//!
+//! [`Settings`] and [`WorkerSettings`] are what a run makes of both, checked
+//! against each other before anything is set up.
+
+use std::env;
+use std::fmt::Display;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use clap::ArgMatches;
+use filecoin_proofs::ProverId;
+use rand::{random, Rng, SeedableRng};
+use rand_xorshift::XorShiftRng;
+use serde::Deserialize;
+use storage_proofs_core::api_version::ApiVersion;
+use tracing::info;
+
+use crate::affinity::CoreSets;
+use crate::artifacts::{Cleanup, SharedCache};
+use crate::cgroup::WorkerLimits;
+use crate::chaos::Fault;
+use crate::env_preset::{EnvPreset, Toggles};
+use crate::gpu_devices::{GpuDevices, GpuPolicy};
+use crate::lifecycle::{
+    parse_api_version, parse_phase, parse_porep_id, parse_prover_id, parse_sector_size,
+    piece_sizes, porep_id_hex, random_prover_id, UnsealRanges,
+};
+use crate::metrics::Phase;
+use crate::numa::{NumaPlacement, NumaPolicy};
+use crate::overlap::Overlap;
+use crate::piece_gen::PieceData;
+use crate::process::{FIRST_LIFECYCLE_ARG, WORKER_INDEX_ARG};
+use crate::soak;
+use crate::storage::parse_bytes;
+use crate::sweep::SWEEP_ARG;
+use crate::workloads::{ApiVersionMix, WorkerConfig, Workload};
+
+const NUM_THREADS_DEFAULT: &str = "1";
+const SECTOR_SIZE_DEFAULT: &str = "32KiB";
+/// The smallest test sector, whose whole lifecycle takes seconds.
+const FAST_SECTOR_SIZE: &str = "2KiB";
+const PHASE_DEFAULT: &str = "all";
+const WORKLOAD_DEFAULT: &str = "seal";
+const POST_SECTORS_DEFAULT: &str = "2";
+const AGGREGATE_SECTORS_DEFAULT: &str = "4";
+const PIECES_DEFAULT: &str = "1";
+const PIECE_DATA_DEFAULT: &str = "random";
+const SECTORS_PER_WORKER_DEFAULT: &str = "1";
+const UNSEAL_RANGES_DEFAULT: &str = "fixed";
+const HANG_TIMEOUT_DEFAULT: &str = "1800";
+const ITERATIONS_DEFAULT: &str = "1";
+const API_VERSIONS_DEFAULT: [&str; 2] = ["1.1.0", "1.0.0"];
+const SHUTDOWN_TIMEOUT_DEFAULT: &str = "120";
+const WORKER_MODE_DEFAULT: &str = "thread";
+const STAGGER_MS_DEFAULT: &str = "0";
+const PHASE_TIMEOUT_DEFAULT: &str = "0";
+const RETRIES_DEFAULT: &str = "0";
+const TRANSIENT_RETRIES_DEFAULT: &str = "0";
+const RETRY_BACKOFF_MS_DEFAULT: &str = "1000";
+const GPU_POLICY_DEFAULT: &str = "free-for-all";
+const JITTER_MS_DEFAULT: &str = "0";
+const API_VERSION_MIX_DEFAULT: &str = "within";
+const CLEANUP_DEFAULT: &str = "always";
+const HEARTBEAT_INTERVAL_DEFAULT: &str = "10";
+const CACHE_STALL_WINDOW_DEFAULT: &str = "0";
+const STARVATION_THRESHOLD_DEFAULT: &str = "5";
+const BACKGROUND_CPU_DEFAULT: &str = "0";
+const BACKGROUND_IO_DEFAULT: &str = "0";
+const FUZZ_MAX_DELAY_MS_DEFAULT: &str = "2000";
+const EPOCH_SECS_DEFAULT: &str = "0";
+const SEED_DELAY_DEFAULT: &str = "150";
+const C2_WORKERS_DEFAULT: &str = "0";
+const STORAGE_SIZE_DEFAULT: &str = "64G";
+/// Keeps the prover ids derived from --seed apart from the PoRep ids.
+const PROVER_SEED_SALT: u64 = 0x7072_6f76_6572;
+/// Keeps the PoRep ids derived from --seed apart from every other use of it.
+const POREP_SEED_SALT: u64 = 0x706f_7265_70;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    pub num_threads: Option<usize>,
+    pub sector_size: Option<String>,
+    pub fast: bool,
+    pub workload: Option<String>,
+    pub phase: Option<String>,
+    pub post_sectors: Option<usize>,
+    pub aggregate_sectors: Option<usize>,
+    pub pieces: Option<usize>,
+    pub sectors_per_worker: Option<usize>,
+    pub cc: bool,
+    pub piece_data: Option<String>,
+    pub env_preset: Option<String>,
+    pub skip_proofs: bool,
+    pub shared_cache: Option<String>,
+    pub unseal_ranges: Option<String>,
+    pub verify_full_unseal: bool,
+    pub seed: Option<u64>,
+    pub stagger_ms: Option<u64>,
+    pub jitter_ms: Option<u64>,
+    pub iterations: Option<usize>,
+    pub duration: Option<String>,
+    pub api_versions: Option<Vec<String>>,
+    pub api_version_mix: Option<String>,
+    pub porep_id: Option<String>,
+    pub random_porep_id: bool,
+    pub prover_id: Option<String>,
+    pub provers: Option<usize>,
+    pub hang_timeout: Option<u64>,
+    pub phase_timeout: Option<u64>,
+    pub retries: Option<usize>,
+    pub transient_retries: Option<usize>,
+    pub retry_backoff_ms: Option<u64>,
+    pub churn: bool,
+    pub overlap: Option<String>,
+    pub sync_phases: bool,
+    pub fuzz: bool,
+    pub fuzz_replay: Option<u64>,
+    pub fuzz_max_delay_ms: Option<u64>,
+    pub epoch_secs: Option<f64>,
+    pub seed_delay: Option<u64>,
+    pub max_pc1: Option<usize>,
+    pub max_pc2: Option<usize>,
+    pub max_c2: Option<usize>,
+    pub post_priority: bool,
+    pub shutdown_timeout: Option<u64>,
+    pub output_json: Option<PathBuf>,
+    pub export_bundles: Option<PathBuf>,
+    pub trace: Option<PathBuf>,
+    pub timeline: Option<PathBuf>,
+    pub record_schedule: Option<PathBuf>,
+    pub replay_schedule: Option<PathBuf>,
+    pub cache_root: Option<PathBuf>,
+    pub c2_queue: Option<PathBuf>,
+    pub c2_workers: Option<usize>,
+    pub workdir: Option<PathBuf>,
+    pub worker_logs: bool,
+    pub cleanup: Option<String>,
+    pub keep_artifacts: bool,
+    pub resume_from: Option<String>,
+    pub param_cache: Option<PathBuf>,
+    pub fetch_params: bool,
+    pub skip_param_check: bool,
+    pub tmpfs_size: Option<String>,
+    pub worker_cpus: Option<f64>,
+    pub worker_memory: Option<String>,
+    pub io_read_bps: Option<String>,
+    pub io_write_bps: Option<String>,
+    pub remote_storage: Option<PathBuf>,
+    pub storage_latency_ms: Option<u64>,
+    pub storage_size: Option<String>,
+    pub metrics_listen: Option<String>,
+    pub heartbeat_file: Option<PathBuf>,
+    pub heartbeat_interval: Option<u64>,
+    pub profile_on_hang: Option<PathBuf>,
+    pub postmortem_dir: Option<PathBuf>,
+    pub cache_stall_window: Option<u64>,
+    pub starvation_threshold: Option<u64>,
+    pub coordinate: Option<String>,
+    pub log_format: Option<String>,
+    pub tui: bool,
+    pub worker_mode: Option<String>,
+    pub pin_cores: Option<String>,
+    pub pin_rayon: bool,
+    pub rayon_threads: Option<usize>,
+    pub background_cpu: Option<usize>,
+    pub background_io: Option<u64>,
+    pub multicore_sdr: Option<bool>,
+    pub gpu_column_builder: Option<bool>,
+    pub gpu_tree_builder: Option<bool>,
+    pub numa_node: Option<String>,
+    pub gpu_devices: Option<String>,
+    pub gpu_policy: Option<String>,
+    pub gpu_lock_test: bool,
+    pub dry_run: bool,
+    pub chaos: Vec<String>,
+    #[serde(rename = "group")]
+    pub groups: Vec<GroupConfig>,
+    pub sweep: Option<SweepConfig>,
+}
+
+/// A set of identical workers; unset keys fall back to the top-level values.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct GroupConfig {
+    pub name: Option<String>,
+    pub threads: usize,
+    pub workload: Option<String>,
+    pub phase: Option<String>,
+    pub iterations: Option<usize>,
+    pub rayon_threads: Option<usize>,
+    pub multicore_sdr: Option<bool>,
+    pub gpu_column_builder: Option<bool>,
+    pub gpu_tree_builder: Option<bool>,
+}
+
+/// The matrix `--sweep` runs; an empty list keeps the run's own setting.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct SweepConfig {
+    #[serde(default)]
+    pub threads: Vec<usize>,
+    #[serde(default)]
+    pub sector_sizes: Vec<String>,
+    /// Each entry is a comma separated list, e.g. `"1.1.0,1.0.0"`.
+    #[serde(default)]
+    pub api_versions: Vec<String>,
+    #[serde(default)]
+    pub workloads: Vec<String>,
+    /// How long each combination runs, in seconds.
+    pub duration: u64,
+}
+
+impl Config {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("failed to read config {}", path.display()))?;
+        toml::from_str(&contents).with_context(|| format!("invalid config {}", path.display()))
+    }
+}
+
+/// The settings of a run, a sweep included, resolved from the command line
+/// and the config file.
+#[derive(Debug)]
+pub struct Settings {
+    pub num_threads: usize,
+    /// The sector size as given, which sweep cells pass on.
+    pub sector_size_name: String,
+    pub sector_size: u64,
+    pub env_preset: Option<EnvPreset>,
+    /// Every flag that only works on Linux, with whether it was given.
+    pub linux_only: Vec<(&'static str, bool)>,
+    pub phase: Option<Phase>,
+    /// The workload as given, which sweep cells pass on.
+    pub workload_name: String,
+    pub workload: Workload,
+    pub post_sectors: usize,
+    pub hang_timeout: Duration,
+    pub aggregate_sectors: usize,
+    pub iterations: usize,
+    /// How long a soak run loops.
+    pub duration: Option<Duration>,
+    /// Every process seals a single sector so they all meet at the GPU lock
+    /// in C2.
+    pub gpu_lock_test: bool,
+    pub overlap: Option<Overlap>,
+    pub stage_limits: Vec<(Phase, usize)>,
+    pub pieces: usize,
+    pub sectors_per_worker: usize,
+    pub piece_data: PieceData,
+    pub skip_proofs: bool,
+    pub shared_cache: Option<SharedCache>,
+    pub unseal_ranges: UnsealRanges,
+    pub seed: u64,
+    /// Whether `--seed` was given; child processes are handed it otherwise.
+    pub seed_given: bool,
+    pub stagger: Duration,
+    pub jitter: Duration,
+    pub shutdown_timeout: Duration,
+    /// The API versions as given, which sweep cells pass on.
+    pub api_version_names: Vec<String>,
+    pub api_versions: Vec<(ApiVersion, [u8; 32])>,
+    pub api_version_mix: ApiVersionMix,
+    pub provers: Vec<ProverId>,
+    pub output_json: Option<PathBuf>,
+    pub dry_run: bool,
+    pub coordinate: Option<SocketAddr>,
+    /// The matrix to run instead, given `--sweep`.
+    pub sweep: Option<SweepConfig>,
+}
+
+impl Settings {
+    pub fn from_args(matches: &ArgMatches, file: &Config) -> Result<Self> {
+        let num_threads = resolve(
+            matches,
+            "num-threads",
+            file.num_threads,
+            NUM_THREADS_DEFAULT,
+        )?;
+        let sector_size_name =
+            if matches.is_present("fast") || (file.fast && !matches.is_present("sector-size")) {
+                FAST_SECTOR_SIZE.to_string()
+            } else {
+                resolve::<String>(
+                    matches,
+                    "sector-size",
+                    file.sector_size.clone(),
+                    SECTOR_SIZE_DEFAULT,
+                )?
+            };
+        let sector_size = parse_sector_size(&sector_size_name)?;
+        let env_preset = match matches
+            .value_of("env-preset")
+            .or(file.env_preset.as_deref())
+        {
+            Some(preset) => Some(preset.parse::<EnvPreset>()?),
+            None => None,
+        };
+        let linux_only = vec![
+            ("env-preset", env_preset.map_or(false, EnvPreset::uses_gpu)),
+            (
+                "pin-cores",
+                matches.is_present("pin-cores") || file.pin_cores.is_some(),
+            ),
+            (
+                "numa-node",
+                matches.is_present("numa-node") || file.numa_node.is_some(),
+            ),
+            (
+                "tmpfs-size",
+                matches.is_present("tmpfs-size") || file.tmpfs_size.is_some(),
+            ),
+            (
+                "storage-latency-ms",
+                matches.is_present("storage-latency-ms") || file.storage_latency_ms.is_some(),
+            ),
+            (
+                "worker-cpus",
+                matches.is_present("worker-cpus") || file.worker_cpus.is_some(),
+            ),
+            (
+                "worker-memory",
+                matches.is_present("worker-memory") || file.worker_memory.is_some(),
+            ),
+            (
+                "io-read-bps",
+                matches.is_present("io-read-bps") || file.io_read_bps.is_some(),
+            ),
+            (
+                "io-write-bps",
+                matches.is_present("io-write-bps") || file.io_write_bps.is_some(),
+            ),
+            (
+                "gpu-devices",
+                matches.is_present("gpu-devices") || file.gpu_devices.is_some(),
+            ),
+            (
+                "gpu-lock-test",
+                matches.is_present("gpu-lock-test") || file.gpu_lock_test,
+            ),
+            (
+                "profile-on-hang",
+                matches.is_present("profile-on-hang") || file.profile_on_hang.is_some(),
+            ),
+        ];
+        let phase = parse_phase(&resolve::<String>(
+            matches,
+            "phase",
+            file.phase.clone(),
+            PHASE_DEFAULT,
+        )?)?;
+        let workload_name =
+            resolve::<String>(matches, "workload", file.workload.clone(), WORKLOAD_DEFAULT)?;
+        let workload = workload_name.parse::<Workload>()?;
+        let post_sectors = resolve(
+            matches,
+            "post-sectors",
+            file.post_sectors,
+            POST_SECTORS_DEFAULT,
+        )?;
+        if post_sectors == 0 {
+            bail!("--post-sectors must be at least 1");
+        }
+        let hang_timeout = resolve(
+            matches,
+            "hang-timeout",
+            file.hang_timeout,
+            HANG_TIMEOUT_DEFAULT,
+        )?;
+        let aggregate_sectors = resolve(
+            matches,
+            "aggregate-sectors",
+            file.aggregate_sectors,
+            AGGREGATE_SECTORS_DEFAULT,
+        )?;
+        if aggregate_sectors < 2 {
+            bail!("aggregation needs at least 2 sectors");
+        }
+        let iterations = resolve(matches, "iterations", file.iterations, ITERATIONS_DEFAULT)?;
+        // A soak run loops until its deadline.
+        let duration = match matches.value_of("duration").or(file.duration.as_deref()) {
+            Some(duration) => Some(soak::parse_duration(duration)?),
+            None => None,
+        };
+        let iterations = if duration.is_some() { 0 } else { iterations };
+        let gpu_lock_test = matches.is_present("gpu-lock-test") || file.gpu_lock_test;
+        let overlap = match matches.value_of("overlap").or(file.overlap.as_deref()) {
+            Some(overlap) => Some(overlap.parse::<Overlap>()?),
+            None => None,
+        };
+        let mut stage_limits = Vec::new();
+        for (name, from_file, phase) in [
+            ("max-pc1", file.max_pc1, Phase::PreCommit1),
+            ("max-pc2", file.max_pc2, Phase::PreCommit2),
+            ("max-c2", file.max_c2, Phase::Commit2),
+        ]
+        .iter()
+        {
+            let limit = match matches.value_of(name) {
+                Some(limit) => Some(limit.parse::<usize>()?),
+                None => *from_file,
+            };
+            match limit {
+                Some(0) => bail!("--{} must be at least 1", name),
+                Some(limit) => stage_limits.push((*phase, limit)),
+                None => {}
+            }
+        }
+        let (phase, workload, iterations) = if gpu_lock_test {
+            if !file.groups.is_empty() {
+                bail!("--gpu-lock-test cannot run [[group]] tables");
+            }
+            (Some(Phase::Commit2), Workload::Seal, 1)
+        } else {
+            (phase, workload, iterations)
+        };
+        let pieces = resolve(matches, "pieces", file.pieces, PIECES_DEFAULT)?;
+        piece_sizes(sector_size, pieces)?;
+        let sectors_per_worker = resolve(
+            matches,
+            "sectors-per-worker",
+            file.sectors_per_worker,
+            SECTORS_PER_WORKER_DEFAULT,
+        )?;
+        if sectors_per_worker == 0 {
+            bail!("--sectors-per-worker must be at least 1");
+        }
+        let piece_data = if matches.is_present("cc") || file.cc {
+            PieceData::Zeros
+        } else {
+            resolve::<String>(
+                matches,
+                "piece-data",
+                file.piece_data.clone(),
+                PIECE_DATA_DEFAULT,
+            )?
+            .parse::<PieceData>()?
+        };
+        let skip_proofs = matches.is_present("skip-proofs") || file.skip_proofs;
+        if skip_proofs && (phase.is_some() || gpu_lock_test) {
+            bail!("--skip-proofs runs PC1 and PC2 only, it takes no --phase");
+        }
+        let shared_cache = match matches
+            .value_of("shared-cache")
+            .or(file.shared_cache.as_deref())
+        {
+            Some(mode) => Some(mode.parse::<SharedCache>()?),
+            None => None,
+        };
+        let unseal_ranges = if matches.is_present("verify-full-unseal") || file.verify_full_unseal {
+            UnsealRanges::Full
+        } else {
+            resolve::<String>(
+                matches,
+                "unseal-ranges",
+                file.unseal_ranges.clone(),
+                UNSEAL_RANGES_DEFAULT,
+            )?
+            .parse::<UnsealRanges>()?
+        };
+        if unseal_ranges == UnsealRanges::Skip
+            && (phase == Some(Phase::Unseal) || workload == Workload::Unseal)
+        {
+            bail!("unsealing needs an unseal range other than none");
+        }
+        let seed = match matches.value_of("seed") {
+            Some(seed) => seed.parse()?,
+            None => file.seed.unwrap_or_else(random),
+        };
+        let stagger_ms = resolve(matches, "stagger-ms", file.stagger_ms, STAGGER_MS_DEFAULT)?;
+        let jitter_ms = resolve(matches, "jitter-ms", file.jitter_ms, JITTER_MS_DEFAULT)?;
+        let shutdown_timeout = resolve(
+            matches,
+            "shutdown-timeout",
+            file.shutdown_timeout,
+            SHUTDOWN_TIMEOUT_DEFAULT,
+        )?;
+        let api_version_names = match matches.values_of("api-versions") {
+            Some(versions) => versions.map(str::to_string).collect(),
+            None => file.api_versions.clone().unwrap_or_else(|| {
+                API_VERSIONS_DEFAULT
+                    .iter()
+                    .map(|version| version.to_string())
+                    .collect()
+            }),
+        };
+        let mut api_versions = api_version_names
+            .iter()
+            .map(|version| parse_api_version(version))
+            .collect::<Result<Vec<_>>>()?;
+        let random_porep_id = matches.is_present("random-porep-id") || file.random_porep_id;
+        match matches.value_of("porep-id").or(file.porep_id.as_deref()) {
+            Some(_) if random_porep_id => {
+                bail!("--porep-id and --random-porep-id exclude each other")
+            }
+            Some(porep_id) => {
+                let porep_id = parse_porep_id(porep_id)?;
+                for (_, id) in api_versions.iter_mut() {
+                    *id = porep_id;
+                }
+            }
+            // Derived from the seed so process workers pick the same ids.
+            None if random_porep_id => {
+                let mut rng = XorShiftRng::seed_from_u64(seed ^ POREP_SEED_SALT);
+                for (_, id) in api_versions.iter_mut() {
+                    *id = rng.gen();
+                }
+            }
+            None => {}
+        }
+        let prover_id = match matches.value_of("prover-id").or(file.prover_id.as_deref()) {
+            Some(prover_id) => Some(parse_prover_id(prover_id)?),
+            None => None,
+        };
+        let provers = match matches.value_of("provers") {
+            Some(provers) => Some(provers.parse::<usize>()?),
+            None => file.provers,
+        };
+        // A given --prover-id comes first, the rest are drawn from the salted
+        // seed, so every child process and rerun hands worker `i` the same one.
+        let provers = match (provers, prover_id) {
+            (Some(0), _) => bail!("--provers must be at least 1"),
+            (Some(count), first) => {
+                let mut rng = XorShiftRng::seed_from_u64(seed ^ PROVER_SEED_SALT);
+                first
+                    .into_iter()
+                    .chain(std::iter::repeat_with(|| random_prover_id(&mut rng)))
+                    .take(count)
+                    .collect()
+            }
+            (None, Some(prover_id)) => vec![prover_id],
+            (None, None) => vec![],
+        };
+        let api_version_mix = resolve::<String>(
+            matches,
+            "api-version-mix",
+            file.api_version_mix.clone(),
+            API_VERSION_MIX_DEFAULT,
+        )?
+        .parse::<ApiVersionMix>()?;
+        let output_json = matches
+            .value_of("output-json")
+            .map(PathBuf::from)
+            .or_else(|| file.output_json.clone());
+        let dry_run = matches.is_present("dry-run") || file.dry_run;
+        // Children of a coordinated run read the same config file.
+        let coordinate = match matches
+            .value_of("coordinate")
+            .or(file.coordinate.as_deref())
+        {
+            Some(_) if matches.is_present(WORKER_INDEX_ARG) => None,
+            Some(addr) => Some(addr.parse::<SocketAddr>()?),
+            None => None,
+        };
+        if coordinate.is_some() {
+            if matches.is_present(SWEEP_ARG) || gpu_lock_test || overlap.is_some() {
+                bail!("--coordinate runs neither --sweep, --gpu-lock-test nor --overlap");
+            }
+            if !stage_limits.is_empty() {
+                bail!("--coordinate cannot limit phases across instances");
+            }
+            #[cfg(not(feature = "grpc"))]
+            bail!("--coordinate needs a build with --features grpc");
+        }
+        let sweep = if matches.is_present(SWEEP_ARG) {
+            let sweep = match &file.sweep {
+                Some(sweep) => sweep.clone(),
+                None => bail!("--sweep needs a [sweep] table in the config file"),
+            };
+            if !file.groups.is_empty() && !sweep.threads.is_empty() {
+                bail!("sweep threads replace num-threads, which [[group]] tables ignore");
+            }
+            Some(sweep)
+        } else {
+            None
+        };
+
+        Ok(Settings {
+            num_threads,
+            sector_size_name,
+            sector_size,
+            env_preset,
+            linux_only,
+            phase,
+            workload_name,
+            workload,
+            post_sectors,
+            hang_timeout: Duration::from_secs(hang_timeout),
+            aggregate_sectors,
+            iterations,
+            duration,
+            gpu_lock_test,
+            overlap,
+            stage_limits,
+            pieces,
+            sectors_per_worker,
+            piece_data,
+            skip_proofs,
+            shared_cache,
+            unseal_ranges,
+            seed,
+            seed_given: matches.is_present("seed"),
+            stagger: Duration::from_millis(stagger_ms),
+            jitter: Duration::from_millis(jitter_ms),
+            shutdown_timeout: Duration::from_secs(shutdown_timeout),
+            api_version_names,
+            api_versions,
+            api_version_mix,
+            provers,
+            output_json,
+            dry_run,
+            coordinate,
+            sweep,
+        })
+    }
+
+    /// Logs the seed and the ids derived from it.
+    pub fn log(&self) {
+        info!("Using seed {}", self.seed);
+        for (api_version, porep_id) in &self.api_versions {
+            info!(
+                "API version {:?} uses porep id {}",
+                api_version,
+                porep_id_hex(porep_id)
+            );
+        }
+        for (i, prover_id) in self.provers.iter().enumerate() {
+            info!("Prover {} has id {}", i, porep_id_hex(prover_id));
+        }
+    }
+}
+
+/// The settings of the workers of a run, which the cells of a sweep resolve
+/// for themselves.
+#[derive(Debug)]
+pub struct WorkerSettings {
+    /// Every group with its number of workers; their cache roots and C2
+    /// queues are left to the storage the run sets up.
+    pub groups: Vec<(usize, WorkerConfig)>,
+    /// The number of workers over all groups.
+    pub num_threads: usize,
+    pub cache_root: Option<PathBuf>,
+    pub c2_queue: Option<PathBuf>,
+    pub tmpfs_size: Option<u64>,
+    pub io_read_bps: Option<u64>,
+    pub io_write_bps: Option<u64>,
+    pub remote_storage: Option<PathBuf>,
+    pub storage_latency: Option<Duration>,
+    pub storage_size: u64,
+    pub metrics_listen: Option<String>,
+    /// Whether every worker runs in a child process instead of a thread.
+    pub process_workers: bool,
+    /// The worker a child process runs.
+    pub worker_index: Option<usize>,
+    pub first_lifecycle: usize,
+    pub heartbeat_file: Option<PathBuf>,
+    pub heartbeat_interval: Duration,
+    pub profile_on_hang: Option<PathBuf>,
+    pub export_bundles: Option<PathBuf>,
+    pub postmortem_dir: Option<PathBuf>,
+    pub cache_stall_window: Option<Duration>,
+    pub starvation_threshold: Option<Duration>,
+    pub trace: Option<PathBuf>,
+    pub timeline: Option<PathBuf>,
+    pub record_schedule: Option<PathBuf>,
+    pub replay_schedule: Option<PathBuf>,
+    /// Whether workers wait for each other between phases; never in a dry
+    /// run.
+    pub sync_phases: bool,
+    pub epoch: Option<Duration>,
+    pub seed_delay: u64,
+    pub fuzz: bool,
+    pub fuzz_max_delay: Duration,
+    pub fuzz_replay: Option<u64>,
+    pub post_priority: bool,
+    pub tui: bool,
+    pub pin_cores: Option<CoreSets>,
+    pub numa: Option<NumaPlacement>,
+    pub gpu_devices: Option<GpuDevices>,
+    pub worker_limits: WorkerLimits,
+    pub faults: Vec<Fault>,
+    /// Whether any group aggregates, which needs the SRS parameters.
+    pub aggregating: bool,
+    /// The number of sectors sealed up front.
+    pub prepared_count: usize,
+    pub param_cache: Option<PathBuf>,
+    pub skip_param_check: bool,
+    pub fetch_params: bool,
+    pub cleanup: Cleanup,
+    pub workdir: PathBuf,
+    pub worker_logs: bool,
+    pub background_cpu: usize,
+    pub background_io: u64,
+    pub churn: bool,
+}
+
+impl WorkerSettings {
+    pub fn from_args(matches: &ArgMatches, file: Config, settings: &Settings) -> Result<Self> {
+        let dry_run = settings.dry_run;
+        let worker_index = match matches.value_of(WORKER_INDEX_ARG) {
+            Some(index) => Some(index.parse::<usize>()?),
+            None => None,
+        };
+        let cache_root = matches
+            .value_of("cache-root")
+            .map(PathBuf::from)
+            .or(file.cache_root);
+        let c2_queue = matches
+            .value_of("c2-queue")
+            .map(PathBuf::from)
+            .or(file.c2_queue);
+        let c2_workers = resolve(matches, "c2-workers", file.c2_workers, C2_WORKERS_DEFAULT)?;
+        if c2_workers > 0 && (c2_queue.is_none() || !file.groups.is_empty()) {
+            bail!("--c2-workers needs a --c2-queue and cannot run [[group]] tables");
+        }
+        let tmpfs_size = match matches
+            .value_of("tmpfs-size")
+            .or(file.tmpfs_size.as_deref())
+        {
+            Some(size) => Some(parse_bytes(size)?),
+            None => None,
+        };
+        let io_read_bps = match matches
+            .value_of("io-read-bps")
+            .or(file.io_read_bps.as_deref())
+        {
+            Some(bps) => Some(parse_bytes(bps)?),
+            None => None,
+        };
+        let io_write_bps = match matches
+            .value_of("io-write-bps")
+            .or(file.io_write_bps.as_deref())
+        {
+            Some(bps) => Some(parse_bytes(bps)?),
+            None => None,
+        };
+        // Children inherit the parent's storage, set up for real runs only.
+        let sets_up_storage = worker_index.is_none() && !dry_run;
+        if tmpfs_size.is_some() && cache_root.is_some() && worker_index.is_none() {
+            bail!("--tmpfs-size replaces --cache-root");
+        }
+        // The tmpfs becomes the cache root.
+        let mounts_tmpfs = tmpfs_size.is_some() && sets_up_storage;
+        let has_cache_root = cache_root.is_some() || mounts_tmpfs;
+        let limits_io = (io_read_bps.is_some() || io_write_bps.is_some()) && sets_up_storage;
+        if limits_io && mounts_tmpfs {
+            bail!("a tmpfs has no disk for --io-read-bps or --io-write-bps to throttle");
+        }
+        let remote_storage = matches
+            .value_of("remote-storage")
+            .map(PathBuf::from)
+            .or(file.remote_storage);
+        let storage_latency_ms = match matches.value_of("storage-latency-ms") {
+            Some(latency) => Some(latency.parse::<u64>()?),
+            None => file.storage_latency_ms,
+        };
+        if storage_latency_ms.is_some() && remote_storage.is_some() && worker_index.is_none() {
+            bail!("--storage-latency-ms replaces --remote-storage");
+        }
+        let storage_size = parse_bytes(&resolve::<String>(
+            matches,
+            "storage-size",
+            file.storage_size,
+            STORAGE_SIZE_DEFAULT,
+        )?)?;
+        let keep_artifacts = matches.is_present("keep-artifacts") || file.keep_artifacts;
+        if keep_artifacts && !has_cache_root {
+            bail!("--keep-artifacts requires --cache-root");
+        }
+        let resume_from = match matches
+            .value_of("resume-from")
+            .map(str::to_string)
+            .or(file.resume_from)
+        {
+            Some(value) => match parse_phase(&value)? {
+                Some(phase) if phase != Phase::Unseal => Some(phase),
+                _ => bail!("cannot resume from {}", value),
+            },
+            None => None,
+        };
+        if resume_from.is_some() && !has_cache_root {
+            bail!("--resume-from requires --cache-root");
+        }
+        if let (Some(from), Some(phase)) = (resume_from, settings.phase) {
+            if from > phase {
+                bail!("cannot resume from {} when only running {}", from, phase);
+            }
+        }
+        let metrics_listen = matches
+            .value_of("metrics-listen")
+            .map(str::to_string)
+            .or(file.metrics_listen);
+
+        let worker_mode = if settings.gpu_lock_test {
+            "process".to_string()
+        } else {
+            resolve::<String>(
+                matches,
+                "worker-mode",
+                file.worker_mode,
+                WORKER_MODE_DEFAULT,
+            )?
+        };
+        let process_workers = match worker_mode.as_str() {
+            "thread" => false,
+            "process" => true,
+            other => bail!("unknown worker mode: {}", other),
+        };
+        let heartbeat_interval = resolve(
+            matches,
+            "heartbeat-interval",
+            file.heartbeat_interval,
+            HEARTBEAT_INTERVAL_DEFAULT,
+        )?;
+        let heartbeat_file = matches
+            .value_of("heartbeat-file")
+            .map(PathBuf::from)
+            .or(file.heartbeat_file);
+        let profile_on_hang = matches
+            .value_of("profile-on-hang")
+            .map(PathBuf::from)
+            .or(file.profile_on_hang);
+        let export_bundles = matches
+            .value_of("export-bundles")
+            .map(PathBuf::from)
+            .or(file.export_bundles);
+        let postmortem_dir = matches
+            .value_of("postmortem-dir")
+            .map(PathBuf::from)
+            .or(file.postmortem_dir);
+        let cache_stall_window = resolve(
+            matches,
+            "cache-stall-window",
+            file.cache_stall_window,
+            CACHE_STALL_WINDOW_DEFAULT,
+        )?;
+        let starvation_threshold = resolve(
+            matches,
+            "starvation-threshold",
+            file.starvation_threshold,
+            STARVATION_THRESHOLD_DEFAULT,
+        )?;
+        let trace = matches.value_of("trace").map(PathBuf::from).or(file.trace);
+        let timeline = matches
+            .value_of("timeline")
+            .map(PathBuf::from)
+            .or(file.timeline);
+        let record_schedule = matches
+            .value_of("record-schedule")
+            .map(PathBuf::from)
+            .or(file.record_schedule);
+        let replay_schedule = matches
+            .value_of("replay-schedule")
+            .map(PathBuf::from)
+            .or(file.replay_schedule);
+        let tui = matches.is_present("tui") || file.tui;
+        if settings.coordinate.is_some()
+            && (record_schedule.is_some()
+                || replay_schedule.is_some()
+                || tui
+                || mounts_tmpfs
+                || limits_io
+                || (storage_latency_ms.is_some() && sets_up_storage))
+        {
+            bail!(
+                "--coordinate cannot record schedules, show --tui or set up storage on other machines"
+            );
+        }
+        if (record_schedule.is_some() || replay_schedule.is_some()) && !dry_run && process_workers {
+            bail!("schedules are only recorded and replayed for thread workers");
+        }
+        if settings.shared_cache.is_some() {
+            if process_workers {
+                bail!("--shared-cache needs thread workers");
+            }
+            if has_cache_root {
+                bail!(
+                    "--shared-cache shares directories of the run, not under --cache-root or a tmpfs"
+                );
+            }
+        }
+        if settings.overlap.is_some() && !dry_run && process_workers {
+            bail!("--overlap needs thread workers");
+        }
+        let sync_phases = (matches.is_present("sync-phases") || file.sync_phases) && !dry_run;
+        if sync_phases && process_workers {
+            bail!("--sync-phases needs thread workers");
+        }
+        let epoch_secs: f64 = resolve(matches, "epoch-secs", file.epoch_secs, EPOCH_SECS_DEFAULT)?;
+        let seed_delay = resolve(matches, "seed-delay", file.seed_delay, SEED_DELAY_DEFAULT)?;
+        let fuzz_replay = match matches.value_of("fuzz-replay") {
+            Some(seed) => Some(seed.parse()?),
+            None => file.fuzz_replay,
+        };
+        let fuzz = matches.is_present("fuzz") || file.fuzz || fuzz_replay.is_some();
+        if fuzz && !dry_run && process_workers {
+            bail!("--fuzz and --fuzz-replay need thread workers");
+        }
+        let fuzz_max_delay_ms = resolve(
+            matches,
+            "fuzz-max-delay-ms",
+            file.fuzz_max_delay_ms,
+            FUZZ_MAX_DELAY_MS_DEFAULT,
+        )?;
+        if !settings.stage_limits.is_empty() && !dry_run && process_workers {
+            bail!("--max-pc1, --max-pc2 and --max-c2 need thread workers");
+        }
+        let post_priority = matches.is_present("post-priority") || file.post_priority;
+        if post_priority
+            && !dry_run
+            && !settings
+                .stage_limits
+                .iter()
+                .any(|(phase, _)| *phase == Phase::Commit2)
+        {
+            bail!("--post-priority needs --max-c2");
+        }
+        #[cfg(not(feature = "tui"))]
+        if tui && worker_index.is_none() {
+            bail!("--tui needs a build with --features tui");
+        }
+
+        let phase_timeout = resolve(
+            matches,
+            "phase-timeout",
+            file.phase_timeout,
+            PHASE_TIMEOUT_DEFAULT,
+        )?;
+        let retries = resolve(matches, "retries", file.retries, RETRIES_DEFAULT)?;
+        if retries > 0 && phase_timeout == 0 {
+            bail!("--retries needs a --phase-timeout");
+        }
+        let transient_retries = resolve(
+            matches,
+            "transient-retries",
+            file.transient_retries,
+            TRANSIENT_RETRIES_DEFAULT,
+        )?;
+        let retry_backoff_ms = resolve(
+            matches,
+            "retry-backoff-ms",
+            file.retry_backoff_ms,
+            RETRY_BACKOFF_MS_DEFAULT,
+        )?;
+        let pin_rayon = matches.is_present("pin-rayon") || file.pin_rayon;
+        let rayon_threads = match matches.value_of("rayon-threads") {
+            Some(threads) => Some(threads.parse::<usize>()?),
+            None => file.rayon_threads,
+        };
+        let toggles = Toggles {
+            multicore_sdr: on_off(matches, "multicore-sdr", file.multicore_sdr)?,
+            gpu_column_builder: on_off(matches, "gpu-column-builder", file.gpu_column_builder)?,
+            gpu_tree_builder: on_off(matches, "gpu-tree-builder", file.gpu_tree_builder)?,
+        };
+        let churn = matches.is_present("churn") || file.churn;
+        let base = WorkerConfig {
+            group: "default".to_string(),
+            workload: settings.workload,
+            sector_size: settings.sector_size,
+            phase: settings.phase,
+            api_versions: settings.api_versions.clone(),
+            api_version_mix: settings.api_version_mix,
+            iterations: settings.iterations,
+            pieces: settings.pieces,
+            sectors_per_worker: settings.sectors_per_worker,
+            aggregate_sectors: settings.aggregate_sectors,
+            piece_data: settings.piece_data,
+            skip_proofs: settings.skip_proofs,
+            shared_cache: settings.shared_cache,
+            unseal_ranges: settings.unseal_ranges,
+            stagger: settings.stagger,
+            jitter: settings.jitter,
+            seed: settings.seed,
+            cache_root: cache_root.clone(),
+            keep_artifacts,
+            resume_from,
+            pin_cores: None,
+            pin_rayon,
+            rayon_threads,
+            toggles,
+            phase_timeout: match phase_timeout {
+                0 => None,
+                secs => Some(Duration::from_secs(secs)),
+            },
+            retries,
+            transient_retries,
+            retry_backoff: Duration::from_millis(retry_backoff_ms),
+            numa: None,
+            churn,
+            first_lifecycle: 0,
+            provers: settings.provers.clone(),
+            c2_queue: None,
+        };
+        let num_threads = settings.num_threads;
+        let mut groups = if let Some(overlap) = settings.overlap {
+            if !file.groups.is_empty() {
+                bail!("--overlap cannot run [[group]] tables");
+            }
+            overlap
+                .sides
+                .iter()
+                .map(|side| {
+                    let config = WorkerConfig {
+                        group: side.phase.to_string(),
+                        workload: side.workload,
+                        phase: side.stop_after,
+                        ..base.clone()
+                    };
+                    (1, config)
+                })
+                .collect()
+        } else if file.groups.is_empty() {
+            match settings.workload {
+                // Every other thread keeps sealing so winning PoSt competes with PC1/PC2.
+                Workload::WinningPost => vec![
+                    (
+                        (num_threads + 1) / 2,
+                        WorkerConfig {
+                            group: "winning-post".to_string(),
+                            ..base.clone()
+                        },
+                    ),
+                    (
+                        num_threads / 2,
+                        WorkerConfig {
+                            group: "seal".to_string(),
+                            workload: Workload::Seal,
+                            ..base
+                        },
+                    ),
+                ],
+                // The C2 threads take C1 outputs until every seal thread finished.
+                Workload::Seal if c2_workers > 0 => vec![
+                    (
+                        num_threads,
+                        WorkerConfig {
+                            group: "seal".to_string(),
+                            ..base.clone()
+                        },
+                    ),
+                    (
+                        c2_workers,
+                        WorkerConfig {
+                            group: "c2".to_string(),
+                            workload: Workload::C2,
+                            iterations: 0,
+                            ..base
+                        },
+                    ),
+                ],
+                _ => vec![(num_threads, base)],
+            }
+        } else {
+            file.groups
+                .into_iter()
+                .enumerate()
+                .map(|(i, group)| -> Result<(usize, WorkerConfig)> {
+                    let config = WorkerConfig {
+                        group: group.name.unwrap_or_else(|| format!("group{}", i)),
+                        workload: match group.workload {
+                            Some(workload) => workload.parse()?,
+                            None => base.workload,
+                        },
+                        phase: match group.phase {
+                            Some(phase) => parse_phase(&phase)?,
+                            None => base.phase,
+                        },
+                        iterations: group.iterations.unwrap_or(base.iterations),
+                        rayon_threads: group.rayon_threads.or(base.rayon_threads),
+                        toggles: Toggles {
+                            multicore_sdr: group.multicore_sdr,
+                            gpu_column_builder: group.gpu_column_builder,
+                            gpu_tree_builder: group.gpu_tree_builder,
+                        }
+                        .or(base.toggles),
+                        ..base.clone()
+                    };
+                    Ok((group.threads, config))
+                })
+                .collect::<Result<Vec<_>>>()?
+        };
+        let num_threads: usize = groups.iter().map(|(threads, _)| threads).sum();
+        if !has_cache_root
+            && groups
+                .iter()
+                .any(|(_, config)| config.workload == Workload::Verify)
+        {
+            bail!(
+                "--workload verify needs the --cache-root earlier seal runs kept their proofs in"
+            );
+        }
+        if c2_queue.is_none()
+            && groups
+                .iter()
+                .any(|(_, config)| config.workload == Workload::C2)
+        {
+            bail!("--workload c2 needs the --c2-queue seal workers hand their C1 outputs to");
+        }
+        if settings.api_version_mix == ApiVersionMix::Across
+            && num_threads < settings.api_versions.len()
+        {
+            bail!(
+                "--api-version-mix across needs a thread per API version, got {} for {}",
+                num_threads,
+                settings.api_versions.len()
+            );
+        }
+        if groups
+            .iter()
+            .any(|(_, config)| config.rayon_threads == Some(0))
+        {
+            bail!("rayon-threads must be at least 1");
+        }
+
+        let numa = match matches
+            .value_of("numa-node")
+            .map(str::to_string)
+            .or(file.numa_node)
+        {
+            Some(policy) => Some(NumaPlacement::new(
+                policy.parse::<NumaPolicy>()?,
+                num_threads,
+            )?),
+            None => None,
+        };
+        let pin_cores = match (
+            matches
+                .value_of("pin-cores")
+                .map(str::to_string)
+                .or(file.pin_cores),
+            &numa,
+        ) {
+            (Some(_), Some(_)) => bail!("--pin-cores and --numa-node both pin threads, pick one"),
+            (Some(spec), None) => Some(CoreSets::parse(&spec, num_threads)?),
+            (None, Some(numa)) => Some(numa.core_sets()),
+            (None, None) => None,
+        };
+        if pin_cores.is_none() && pin_rayon {
+            bail!("--pin-rayon needs --pin-cores or --numa-node");
+        }
+        for (_, config) in groups.iter_mut() {
+            config.pin_cores = pin_cores.clone();
+            config.numa = numa.clone();
+        }
+
+        let gpu_policy =
+            resolve::<String>(matches, "gpu-policy", file.gpu_policy, GPU_POLICY_DEFAULT)?
+                .parse::<GpuPolicy>()?;
+        let gpu_devices = match matches
+            .value_of("gpu-devices")
+            .map(str::to_string)
+            .or(file.gpu_devices)
+        {
+            Some(devices) => Some(GpuDevices::parse(&devices, gpu_policy, num_threads)?),
+            None => None,
+        };
+        if let (false, Some(devices)) = (process_workers, &gpu_devices) {
+            if devices.policy() != GpuPolicy::FreeForAll {
+                bail!(
+                    "--gpu-policy {:?} needs --worker-mode process",
+                    devices.policy()
+                );
+            }
+        }
+        let worker_limits = WorkerLimits {
+            cpus: match matches.value_of("worker-cpus") {
+                Some(cpus) => Some(cpus.parse::<f64>()?),
+                None => file.worker_cpus,
+            },
+            memory: match matches
+                .value_of("worker-memory")
+                .or(file.worker_memory.as_deref())
+            {
+                Some(memory) => Some(parse_bytes(memory)?),
+                None => None,
+            },
+        };
+        if worker_limits.cpus.map_or(false, |cpus| cpus <= 0.0) {
+            bail!("--worker-cpus must be positive");
+        }
+        if worker_limits != WorkerLimits::default() {
+            if !process_workers {
+                bail!("--worker-cpus and --worker-memory need --worker-mode process");
+            }
+            if io_read_bps.is_some() || io_write_bps.is_some() {
+                bail!("--worker-cpus and --worker-memory cannot be combined with --io-read-bps or --io-write-bps");
+            }
+        }
+        if !process_workers {
+            let mut toggles = groups.iter().map(|(_, config)| config.toggles);
+            let first = toggles.next().unwrap_or_default();
+            if toggles.any(|toggles| toggles != first) {
+                bail!("groups with different multicore-sdr, gpu-column-builder or gpu-tree-builder need --worker-mode process");
+            }
+        }
+
+        let faults = match matches.values_of("chaos") {
+            Some(faults) => faults.map(str::parse).collect::<Result<Vec<Fault>>>()?,
+            None => file
+                .chaos
+                .iter()
+                .map(|fault| fault.parse())
+                .collect::<Result<Vec<Fault>>>()?,
+        };
+        if (settings.skip_proofs || settings.shared_cache.is_some())
+            && groups
+                .iter()
+                .any(|(_, config)| config.workload != Workload::Seal)
+        {
+            bail!("--skip-proofs and --shared-cache only apply to the seal workload");
+        }
+        if faults
+            .iter()
+            .any(|fault| matches!(fault, Fault::ReassignPc2 { .. }))
+        {
+            if settings.stage_limits.is_empty() {
+                bail!("--chaos reassign-pc2 needs --max-pc1, --max-pc2 or --max-c2");
+            }
+            if settings.skip_proofs || settings.shared_cache.is_some() {
+                bail!("--chaos reassign-pc2 needs proofs and sectors of their own");
+            }
+        }
+        let aggregating = groups
+            .iter()
+            .any(|(_, config)| config.workload == Workload::Aggregate);
+        let prepared_count = if aggregating {
+            settings.post_sectors.max(settings.aggregate_sectors)
+        } else {
+            settings.post_sectors
+        };
+
+        let cleanup = resolve::<String>(matches, "cleanup", file.cleanup, CLEANUP_DEFAULT)?
+            .parse::<Cleanup>()?;
+        let workdir = matches
+            .value_of("workdir")
+            .map(PathBuf::from)
+            .or(file.workdir)
+            .unwrap_or_else(env::temp_dir);
+        let first_lifecycle = match matches.value_of(FIRST_LIFECYCLE_ARG) {
+            Some(lifecycle) => lifecycle.parse()?,
+            None => 0,
+        };
+
+        Ok(WorkerSettings {
+            groups,
+            num_threads,
+            cache_root,
+            c2_queue,
+            tmpfs_size,
+            io_read_bps,
+            io_write_bps,
+            remote_storage,
+            storage_latency: storage_latency_ms.map(Duration::from_millis),
+            storage_size,
+            metrics_listen,
+            process_workers,
+            worker_index,
+            first_lifecycle,
+            heartbeat_file,
+            heartbeat_interval: Duration::from_secs(heartbeat_interval),
+            profile_on_hang,
+            export_bundles,
+            postmortem_dir,
+            cache_stall_window: match cache_stall_window {
+                0 => None,
+                secs => Some(Duration::from_secs(secs)),
+            },
+            starvation_threshold: match starvation_threshold {
+                0 => None,
+                secs => Some(Duration::from_secs(secs)),
+            },
+            trace,
+            timeline,
+            record_schedule,
+            replay_schedule,
+            sync_phases,
+            epoch: if epoch_secs > 0.0 {
+                Some(Duration::from_secs_f64(epoch_secs))
+            } else {
+                None
+            },
+            seed_delay,
+            fuzz,
+            fuzz_max_delay: Duration::from_millis(fuzz_max_delay_ms),
+            fuzz_replay,
+            post_priority,
+            tui,
+            pin_cores,
+            numa,
+            gpu_devices,
+            worker_limits,
+            faults,
+            aggregating,
+            prepared_count,
+            param_cache: matches
+                .value_of("param-cache")
+                .map(PathBuf::from)
+                .or(file.param_cache),
+            skip_param_check: matches.is_present("skip-param-check") || file.skip_param_check,
+            fetch_params: matches.is_present("fetch-params") || file.fetch_params,
+            cleanup,
+            workdir,
+            worker_logs: matches.is_present("worker-logs") || file.worker_logs,
+            background_cpu: resolve(
+                matches,
+                "background-cpu",
+                file.background_cpu,
+                BACKGROUND_CPU_DEFAULT,
+            )?,
+            background_io: resolve(
+                matches,
+                "background-io",
+                file.background_io,
+                BACKGROUND_IO_DEFAULT,
+            )?,
+            churn,
+        })
+    }
+}
+
+/// Resolves a setting from the command line flag `name`, falling back to the
+/// value from the config file and then to `default`.
+pub fn resolve<T>(
+    matches: &ArgMatches,
+    name: &str,
+    from_file: Option<T>,
+    default: &str,
+) -> Result<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    let parse = |value: &str| {
+        value
+            .parse::<T>()
+            .map_err(|err| anyhow!("invalid value {:?} for {}: {}", value, name, err))
+    };
+
+    match (matches.value_of(name), from_file) {
+        (Some(value), _) => parse(value),
+        (None, Some(value)) => Ok(value),
+        (None, None) => parse(default),
+    }
+}
+
+/// Resolves a switch given as `on` or `off` by the command line flag `name`,
+/// falling back to the config file; `None` if neither sets it.
+pub fn on_off(matches: &ArgMatches, name: &str, from_file: Option<bool>) -> Result<Option<bool>> {
+    match matches.value_of(name) {
+        Some("on") => Ok(Some(true)),
+        Some("off") => Ok(Some(false)),
+        Some(value) => Err(anyhow!(
+            "invalid value {:?} for {}: not on or off",
+            value,
+            name
+        )),
+        None => Ok(from_file),
+    }
+}
@@ -0,0 +1,152 @@
+//! CPU use while workers are in a phase, from the utime and stime in procfs.
+//!
+//! Each phase records how busy the worker's own thread was and, since the
+//! proofs library does most of its work on rayon threads, the average and
+//! peak use of the whole process. Like RSS, the process figures include
+//! whatever concurrent workers did meanwhile. Percentages are of one core,
+//! as in `top`.
+
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard, Once};
+use std::thread::{self, ThreadId};
+use std::time::{Duration, Instant};
+
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+
+use crate::gpu_lock::current_tid;
+
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// CPU use during one phase, in percent of a core.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct CpuUsage {
+    /// The worker thread itself.
+    pub thread_pct: f64,
+    pub process_avg_pct: f64,
+    /// The busiest [`SAMPLE_INTERVAL`] of the process.
+    pub process_peak_pct: f64,
+}
+
+struct Tracked {
+    tid: i64,
+    started: Instant,
+    thread_start: Duration,
+    process_start: Duration,
+    process_peak: f64,
+}
+
+lazy_static! {
+    /// Every thread that is currently in a phase.
+    static ref TRACKED: Mutex<HashMap<ThreadId, Tracked>> = Mutex::new(HashMap::new());
+}
+
+static SAMPLER: Once = Once::new();
+
+/// Starts measuring the current thread's phase.
+pub fn enter() {
+    SAMPLER.call_once(|| {
+        thread::Builder::new()
+            .name("cpu-sampler".into())
+            .spawn(sample)
+            .expect("failed to spawn cpu sampler");
+    });
+    let tid = current_tid();
+    let (thread_start, process_start) = match (thread_time(tid), process_time()) {
+        (Some(thread), Some(process)) => (thread, process),
+        _ => return,
+    };
+    lock().insert(
+        thread::current().id(),
+        Tracked {
+            tid,
+            started: Instant::now(),
+            thread_start,
+            process_start,
+            process_peak: 0.0,
+        },
+    );
+}
+
+/// Stops measuring the current thread and returns its phase's CPU use.
+pub fn leave() -> Option<CpuUsage> {
+    let tracked = lock().remove(&thread::current().id())?;
+    let elapsed = tracked.started.elapsed();
+    let thread = thread_time(tracked.tid)?.checked_sub(tracked.thread_start)?;
+    let process = process_time()?.checked_sub(tracked.process_start)?;
+    let process_avg_pct = percent(process, elapsed);
+    Some(CpuUsage {
+        thread_pct: percent(thread, elapsed),
+        process_avg_pct,
+        // Phases shorter than a sample have no peak of their own.
+        process_peak_pct: tracked.process_peak.max(process_avg_pct),
+    })
+}
+
+/// How busy each of `tids` is, measured over `window`.
+pub fn threads_pct(tids: &[i64], window: Duration) -> Vec<Option<f64>> {
+    let before = tids.iter().map(|tid| thread_time(*tid)).collect::<Vec<_>>();
+    thread::sleep(window);
+    tids.iter()
+        .zip(before)
+        .map(|(tid, before)| {
+            let busy = thread_time(*tid)?.checked_sub(before?)?;
+            Some(percent(busy, window))
+        })
+        .collect()
+}
+
+fn percent(busy: Duration, elapsed: Duration) -> f64 {
+    if elapsed == Duration::default() {
+        0.0
+    } else {
+        100.0 * busy.as_secs_f64() / elapsed.as_secs_f64()
+    }
+}
+
+fn lock() -> MutexGuard<'static, HashMap<ThreadId, Tracked>> {
+    TRACKED.lock().expect("cpu samples poisoned")
+}
+
+fn sample() {
+    let mut last = process_time().map(|time| (time, Instant::now()));
+    loop {
+        thread::sleep(SAMPLE_INTERVAL);
+        let now = process_time().map(|time| (time, Instant::now()));
+        if let (Some((before, then)), Some((time, at))) = (last, now) {
+            let pct = percent(time.checked_sub(before).unwrap_or_default(), at - then);
+            for tracked in lock().values_mut() {
+                tracked.process_peak = tracked.process_peak.max(pct);
+            }
+        }
+        last = now;
+    }
+}
+
+fn process_time() -> Option<Duration> {
+    cpu_time("/proc/self/stat")
+}
+
+fn thread_time(tid: i64) -> Option<Duration> {
+    cpu_time(&format!("/proc/self/task/{}/stat", tid))
+}
+
+/// utime plus stime of a procfs `stat` file.
+#[cfg(target_os = "linux")]
+fn cpu_time(path: &str) -> Option<Duration> {
+    let stat = std::fs::read_to_string(path).ok()?;
+    // The command name may contain spaces, the fields after it do not;
+    // utime and stime are the 14th and 15th field.
+    let mut fields = stat.rsplit_once(')')?.1.split_whitespace().skip(11);
+    let utime = fields.next()?.parse::<u64>().ok()?;
+    let stime = fields.next()?.parse::<u64>().ok()?;
+    let ticks_per_sec = unsafe { libc::sysconf(libc::_SC_CLK_TCK) } as f64;
+    Some(Duration::from_secs_f64(
+        (utime + stime) as f64 / ticks_per_sec,
+    ))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn cpu_time(_path: &str) -> Option<Duration> {
+    None
+}
@@ -0,0 +1,291 @@
+//! `hang serve`: a small HTTP API to drive the harness from lab automation.
+//!
+//! Every run is this binary started again with the arguments given in the
+//! request plus an `--output-json` in a directory of its own, which also
+//! holds the run's output. The daemon only starts, stops and watches runs,
+//! so a hung or crashed run never takes it down.
+//!
+//! - `POST /runs` with `{"args": ["-t", "4", "--workload", "seal"]}` starts a
+//!   run and answers with its status, including its `id`
+//! - `GET /runs` lists all runs, `GET /runs/<id>` shows one
+//! - `POST /runs/<id>/stop` asks a run to stop as SIGTERM does
+//! - `GET /runs/<id>/report` returns its JSON report once it finished
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::PathBuf;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::{info, warn};
+
+use crate::outcome::Classification;
+use crate::process;
+
+const REPORT_FILE: &str = "report.json";
+const LOG_FILE: &str = "output.log";
+
+#[derive(Deserialize)]
+struct StartRequest {
+    args: Vec<String>,
+}
+
+struct Run {
+    id: usize,
+    args: Vec<String>,
+    pid: u32,
+    dir: PathBuf,
+    started_unix_secs: u64,
+    started: Instant,
+    ended: Option<Instant>,
+    stop_requested: bool,
+    status: Option<ExitStatus>,
+}
+
+#[derive(Serialize)]
+struct RunStatus<'a> {
+    id: usize,
+    args: &'a [String],
+    pid: u32,
+    /// `running`, `stopping` or `finished`.
+    state: &'static str,
+    started_unix_secs: u64,
+    elapsed_secs: f64,
+    exit_code: Option<i32>,
+    signal: Option<i32>,
+    classification: Option<Classification>,
+    log: PathBuf,
+}
+
+impl Run {
+    fn status(&self) -> RunStatus<'_> {
+        let exit_code = self.status.and_then(|status| status.code());
+        RunStatus {
+            id: self.id,
+            args: &self.args,
+            pid: self.pid,
+            state: match (self.status, self.stop_requested) {
+                (Some(_), _) => "finished",
+                (None, true) => "stopping",
+                (None, false) => "running",
+            },
+            started_unix_secs: self.started_unix_secs,
+            elapsed_secs: self
+                .ended
+                .unwrap_or_else(Instant::now)
+                .duration_since(self.started)
+                .as_secs_f64(),
+            exit_code,
+            signal: self.status.and_then(|status| status.signal()),
+            classification: exit_code.and_then(Classification::from_exit_code),
+            log: self.dir.join(LOG_FILE),
+        }
+    }
+}
+
+struct Daemon {
+    dir: PathBuf,
+    runs: Mutex<Vec<Run>>,
+}
+
+/// Serves the API on `addr` until the process is killed.
+pub fn serve<A: ToSocketAddrs>(addr: A) -> Result<()> {
+    let dir = env::temp_dir().join(format!("hang-serve-{}", std::process::id()));
+    fs::create_dir_all(&dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    let daemon = Arc::new(Daemon {
+        dir,
+        runs: Mutex::new(Vec::new()),
+    });
+
+    let listener = TcpListener::bind(addr)?;
+    info!(
+        "Serving the control API on {}, runs go to {}",
+        listener.local_addr()?,
+        daemon.dir.display()
+    );
+    for stream in listener.incoming() {
+        if let Err(err) = stream
+            .map_err(Into::into)
+            .and_then(|stream| handle(&daemon, stream))
+        {
+            warn!("control API: {:#}", err);
+        }
+    }
+    Ok(())
+}
+
+fn handle(daemon: &Arc<Daemon>, mut stream: TcpStream) -> Result<()> {
+    let (method, path, body) = read_request(&mut stream)?;
+    let segments = path
+        .trim_matches('/')
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect::<Vec<_>>();
+    let id = segments.get(1).and_then(|id| id.parse::<usize>().ok());
+
+    let (code, body) = match (method.as_str(), segments.as_slice(), id) {
+        ("POST", ["runs"], _) => match serde_json::from_slice::<StartRequest>(&body) {
+            Ok(request) => match start(daemon, request.args) {
+                Ok(status) => (201, status),
+                Err(err) => (500, error(err)),
+            },
+            Err(err) => (400, error(err)),
+        },
+        ("GET", ["runs"], _) => {
+            let runs = daemon.runs.lock().expect("runs poisoned");
+            (200, json!(runs.iter().map(Run::status).collect::<Vec<_>>()))
+        }
+        ("GET", ["runs", _], Some(id)) => with_run(daemon, id, |run| (200, json!(run.status()))),
+        ("POST", ["runs", _, "stop"], Some(id)) => with_run(daemon, id, |run| {
+            if run.status.is_none() {
+                process::terminate(&[run.pid]);
+                run.stop_requested = true;
+            }
+            (202, json!(run.status()))
+        }),
+        ("GET", ["runs", _, "report"], Some(id)) => with_run(daemon, id, |run| {
+            if run.status.is_none() {
+                return (409, error("the run has not finished yet"));
+            }
+            match fs::read(run.dir.join(REPORT_FILE)) {
+                Ok(report) => match serde_json::from_slice::<Value>(&report) {
+                    Ok(report) => (200, report),
+                    Err(err) => (500, error(err)),
+                },
+                Err(_) => (404, error("the run wrote no report")),
+            }
+        }),
+        _ => (404, error(format!("no route for {} {}", method, path))),
+    };
+    write_response(&mut stream, code, &body)
+}
+
+fn start(daemon: &Arc<Daemon>, args: Vec<String>) -> Result<Value> {
+    let mut runs = daemon.runs.lock().expect("runs poisoned");
+    let id = runs.len() + 1;
+    let dir = daemon.dir.join(format!("run-{}", id));
+    fs::create_dir_all(&dir)?;
+    let log = File::create(dir.join(LOG_FILE))?;
+
+    let mut command = Command::new(env::current_exe()?);
+    command
+        .args(&args)
+        .arg(format!("--output-json={}", dir.join(REPORT_FILE).display()))
+        .stdin(Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log);
+    // Signals sent to the daemon's process group stay away from the runs.
+    unsafe {
+        command.pre_exec(|| {
+            libc::setpgid(0, 0);
+            Ok(())
+        });
+    }
+    let mut child = command.spawn()?;
+    let pid = child.id();
+    info!(run = id, pid, "Started run {} with {:?}", id, args);
+
+    let waiter = daemon.clone();
+    thread::Builder::new()
+        .name(format!("run-{}", id))
+        .spawn(move || {
+            let status = child.wait();
+            let mut runs = waiter.runs.lock().expect("runs poisoned");
+            if let Some(run) = runs.iter_mut().find(|run| run.id == id) {
+                match status {
+                    Ok(status) => {
+                        info!(run = id, "Run {} finished: {}", id, status);
+                        run.status = Some(status);
+                        run.ended = Some(Instant::now());
+                    }
+                    Err(err) => warn!(run = id, "failed to wait for run {}: {}", id, err),
+                }
+            }
+        })?;
+
+    runs.push(Run {
+        id,
+        args,
+        pid,
+        dir,
+        started_unix_secs: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs()),
+        started: Instant::now(),
+        ended: None,
+        stop_requested: false,
+        status: None,
+    });
+    Ok(json!(runs[runs.len() - 1].status()))
+}
+
+fn with_run<F: FnOnce(&mut Run) -> (u16, Value)>(daemon: &Daemon, id: usize, f: F) -> (u16, Value) {
+    let mut runs = daemon.runs.lock().expect("runs poisoned");
+    match runs.iter_mut().find(|run| run.id == id) {
+        Some(run) => f(run),
+        None => (404, error(format!("there is no run {}", id))),
+    }
+}
+
+fn error<E: std::fmt::Display>(err: E) -> Value {
+    json!({ "error": err.to_string() })
+}
+
+/// Reads the method, path and body of an HTTP/1.1 request.
+fn read_request(stream: &mut TcpStream) -> Result<(String, String, Vec<u8>)> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next().ok_or_else(|| anyhow!("empty request"))?;
+    let path = parts
+        .next()
+        .ok_or_else(|| anyhow!("request without a path"))?;
+    let (method, path) = (method.to_string(), path.to_string());
+
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse()?;
+            }
+        }
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+    Ok((method, path, body))
+}
+
+fn write_response(stream: &mut TcpStream, code: u16, body: &Value) -> Result<()> {
+    let reason = match code {
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        400 => "Bad Request",
+        404 => "Not Found",
+        409 => "Conflict",
+        _ => "Internal Server Error",
+    };
+    let body = serde_json::to_string_pretty(body)?;
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        code,
+        reason,
+        body.len(),
+        body
+    )?;
+    Ok(())
+}
@@ -0,0 +1,241 @@
+//! Live terminal dashboard, built with the `tui` feature.
+//!
+//! Shows every worker's current phase, how long it has been in it and how
+//! many lifecycles it completed, so a stuck worker stands out instead of
+//! going quiet among the log lines. Those lines go to a log file while the
+//! dashboard is up; stderr, where the watchdog reports hangs, is left alone.
+//!
+//! Phases are read from the watchdog, which only thread workers report to;
+//! process workers show their completed lifecycles only.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use tui::backend::CrosstermBackend;
+use tui::layout::Constraint;
+use tui::style::{Color, Modifier, Style};
+use tui::widgets::{Block, Borders, Cell, Row, Table};
+use tui::Terminal;
+
+use crate::metrics::LifecycleRecord;
+use crate::watchdog::Watchdog;
+use crate::workloads::WorkerConfig;
+
+const REDRAW_INTERVAL: Duration = Duration::from_millis(500);
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Shows the dashboard for the next [`crate::workloads::run_groups`].
+pub fn enable() {
+    ENABLED.store(true, Ordering::SeqCst);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+#[derive(Default)]
+struct WorkerRow {
+    group: String,
+    completed: usize,
+    failed: usize,
+    finished: bool,
+}
+
+struct State {
+    rows: Vec<WorkerRow>,
+    watchdog: Option<Arc<Watchdog>>,
+    started: Instant,
+}
+
+/// The running dashboard; dropping it restores stdout.
+pub struct Dashboard {
+    state: Arc<Mutex<State>>,
+    stop: Option<Sender<()>>,
+    renderer: Option<JoinHandle<()>>,
+    stdout: RawFd,
+    log: PathBuf,
+}
+
+impl Dashboard {
+    /// Takes over the terminal for the workers of `groups`.
+    pub fn start(
+        groups: &[(usize, WorkerConfig)],
+        watchdog: Option<Arc<Watchdog>>,
+    ) -> Result<Self> {
+        let rows = groups
+            .iter()
+            .flat_map(|(threads, config)| std::iter::repeat(config).take(*threads))
+            .map(|config| WorkerRow {
+                group: config.group.clone(),
+                ..WorkerRow::default()
+            })
+            .collect();
+        let state = Arc::new(Mutex::new(State {
+            rows,
+            watchdog,
+            started: Instant::now(),
+        }));
+
+        let log = std::env::temp_dir().join(format!("hang-{}.log", std::process::id()));
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&log)
+            .with_context(|| format!("failed to create {}", log.display()))?;
+        io::stdout().flush()?;
+        let (stdout, terminal_fd) = unsafe {
+            let stdout = libc::dup(libc::STDOUT_FILENO);
+            let terminal_fd = libc::dup(stdout);
+            libc::dup2(file.as_raw_fd(), libc::STDOUT_FILENO);
+            (stdout, terminal_fd)
+        };
+        let mut terminal = Terminal::new(CrosstermBackend::new(unsafe {
+            File::from_raw_fd(terminal_fd)
+        }))?;
+        terminal.clear()?;
+        terminal.hide_cursor()?;
+
+        let (stop, rx) = mpsc::channel();
+        let renderer = {
+            let state = state.clone();
+            thread::Builder::new()
+                .name("dashboard".into())
+                .spawn(move || loop {
+                    let done = !matches!(
+                        rx.recv_timeout(REDRAW_INTERVAL),
+                        Err(RecvTimeoutError::Timeout)
+                    );
+                    let state = state.lock().expect("dashboard state poisoned");
+                    let _ = terminal.draw(|f| f.render_widget(table(&state), f.size()));
+                    if done {
+                        let _ = terminal.show_cursor();
+                        return;
+                    }
+                })?
+        };
+
+        Ok(Dashboard {
+            state,
+            stop: Some(stop),
+            renderer: Some(renderer),
+            stdout,
+            log,
+        })
+    }
+
+    /// Counts a finished lifecycle of `record.thread`.
+    pub fn lifecycle(&self, record: &LifecycleRecord) {
+        let mut state = self.state.lock().expect("dashboard state poisoned");
+        if let Some(row) = state.rows.get_mut(record.thread) {
+            row.completed += 1;
+            if !record.success {
+                row.failed += 1;
+            }
+        }
+    }
+
+    /// Marks worker `index` as done.
+    pub fn finished(&self, index: usize) {
+        let mut state = self.state.lock().expect("dashboard state poisoned");
+        if let Some(row) = state.rows.get_mut(index) {
+            row.finished = true;
+        }
+    }
+}
+
+impl Drop for Dashboard {
+    fn drop(&mut self) {
+        // The last frame stays on screen above whatever is printed next.
+        drop(self.stop.take());
+        if let Some(renderer) = self.renderer.take() {
+            let _ = renderer.join();
+        }
+        let _ = io::stdout().flush();
+        unsafe {
+            libc::dup2(self.stdout, libc::STDOUT_FILENO);
+            libc::close(self.stdout);
+        }
+        println!();
+        println!("Worker output was written to {}", self.log.display());
+    }
+}
+
+fn table(state: &State) -> Table<'static> {
+    let header = Row::new(vec![
+        "thread",
+        "group",
+        "phase",
+        "in phase (s)",
+        "completed",
+        "failed",
+    ])
+    .style(Style::default().add_modifier(Modifier::BOLD));
+
+    let rows = state
+        .rows
+        .iter()
+        .enumerate()
+        .map(|(index, row)| {
+            let phase = state
+                .watchdog
+                .as_ref()
+                .and_then(|watchdog| watchdog.phase(index));
+            let (phase, elapsed) = match (row.finished, phase) {
+                (true, _) => ("finished".to_string(), String::new()),
+                (false, Some((phase, elapsed))) => {
+                    let layer = state
+                        .watchdog
+                        .as_ref()
+                        .and_then(|watchdog| watchdog.layer(index));
+                    let phase = match layer {
+                        Some((layer, layers)) => format!("{} {}/{}", phase, layer, layers),
+                        None => phase.to_string(),
+                    };
+                    (phase, format!("{:.0}", elapsed.as_secs_f64()))
+                }
+                (false, None) if state.watchdog.is_some() => ("idle".to_string(), String::new()),
+                (false, None) => ("-".to_string(), String::new()),
+            };
+            let style = match (row.finished, row.failed) {
+                (true, _) => Style::default().fg(Color::DarkGray),
+                (false, 0) => Style::default(),
+                (false, _) => Style::default().fg(Color::Red),
+            };
+            Row::new(vec![
+                Cell::from(index.to_string()),
+                Cell::from(row.group.clone()),
+                Cell::from(phase),
+                Cell::from(elapsed),
+                Cell::from(row.completed.to_string()),
+                Cell::from(row.failed.to_string()),
+            ])
+            .style(style)
+        })
+        .collect::<Vec<_>>();
+
+    let title = format!(
+        " {} workers, {}s elapsed ",
+        state.rows.len(),
+        state.started.elapsed().as_secs()
+    );
+    Table::new(rows)
+        .header(header)
+        .block(Block::default().borders(Borders::ALL).title(title))
+        .widths(&[
+            Constraint::Length(8),
+            Constraint::Length(16),
+            Constraint::Length(14),
+            Constraint::Length(14),
+            Constraint::Length(10),
+            Constraint::Length(8),
+        ])
+}
@@ -0,0 +1,439 @@
+//! Distributed runs: a coordinator hands lifecycles to harness instances on
+//! other machines over gRPC and aggregates their records.
+//!
+//! `hang --coordinate 0.0.0.0:8701 ...` resolves its workers as usual but
+//! runs none of them itself. Every `hang join http://coordinator:8701` asks
+//! it for lifecycles and runs each in a child process started with the
+//! coordinator's arguments, exactly like a churning process worker. The
+//! lifecycles of one worker run one after the other, on whichever instance
+//! asks first, so the number of workers still bounds how many lifecycles run
+//! at once across the cluster.
+//!
+//! Parameters, a `--cache-root` and any other path on the command line must
+//! exist under the same names on every machine. A task whose instance
+//! disappears is not handed out again; the coordinator waits for it until
+//! it is interrupted.
+
+use std::collections::HashMap;
+use std::mem;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+use tracing::{error, info, warn};
+
+use crate::metrics::LifecycleRecord;
+use crate::outcome::Classification;
+use crate::platform;
+use crate::process::{self, ChildFailed, ProcessWorkers, PHASE_TIMEOUT_EXIT_CODE};
+use crate::shutdown;
+use crate::workloads::{RunOutcome, WorkerConfig, WorkerEvent};
+
+use proto::coordinator_client::CoordinatorClient;
+use proto::coordinator_server::{Coordinator, CoordinatorServer};
+use proto::{Ack, Task, TaskRequest, TaskResult};
+
+/// Types generated from `proto/coordinator.proto`.
+pub mod proto {
+    tonic::include_proto!("hang");
+}
+
+/// Flags of the coordinator that tasks do not inherit, and whether they take
+/// a value.
+const COORDINATOR_ONLY_ARGS: [(&str, bool); 7] = [
+    ("coordinate", true),
+    ("churn", false),
+    ("output-json", true),
+    ("metrics-listen", true),
+    ("trace", true),
+    ("duration", true),
+    ("tui", false),
+];
+
+/// How often idle instances ask for work and the coordinator checks
+/// whether the run is over.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long the coordinator keeps answering after the run, so that idle
+/// instances learn that it ended.
+const LINGER: Duration = Duration::from_secs(3);
+
+struct Worker {
+    config: WorkerConfig,
+    next_lifecycle: usize,
+    restarts: usize,
+    running: bool,
+    done: bool,
+}
+
+struct State {
+    args: Vec<String>,
+    workers: Vec<Worker>,
+    /// Worker and instance of every task handed out and not yet reported.
+    leases: HashMap<u64, (usize, String)>,
+    next_id: u64,
+    records: Vec<LifecycleRecord>,
+    classification: Classification,
+    failed_workers: Vec<(usize, Classification)>,
+}
+
+impl State {
+    fn next_task(&mut self, host: String) -> Task {
+        self.stop_if_requested();
+        let i = match self.workers.iter().position(|w| !w.done && !w.running) {
+            Some(i) => i,
+            None if self.finished() => {
+                return Task {
+                    done: true,
+                    ..Task::default()
+                }
+            }
+            None => {
+                return Task {
+                    wait: true,
+                    ..Task::default()
+                }
+            }
+        };
+        let worker = &mut self.workers[i];
+        worker.running = true;
+        self.next_id += 1;
+        info!(
+            thread = i,
+            "Lifecycle {} of worker {} goes to {}", worker.next_lifecycle, i, host
+        );
+        self.leases.insert(self.next_id, (i, host));
+        Task {
+            done: false,
+            wait: false,
+            id: self.next_id,
+            args: self.args.clone(),
+            worker: i as u64,
+            lifecycle: worker.next_lifecycle as u64,
+        }
+    }
+
+    fn report(&mut self, result: TaskResult) -> Result<(), Status> {
+        let (i, host) = self
+            .leases
+            .remove(&result.id)
+            .ok_or_else(|| Status::not_found(format!("there is no task {}", result.id)))?;
+        for json in &result.records {
+            match serde_json::from_str::<LifecycleRecord>(json) {
+                Ok(record) => {
+                    self.classification =
+                        self.classification.max(Classification::of_record(&record));
+                    self.records.push(record);
+                }
+                Err(err) => error!(thread = i, "invalid record from {}: {}", host, err),
+            }
+        }
+
+        let worker = &mut self.workers[i];
+        worker.running = false;
+        match (result.exited, result.exit_code) {
+            (true, 0) => {
+                worker.next_lifecycle += 1;
+                worker.done = worker.next_lifecycle >= worker.config.lifecycles(i);
+            }
+            (true, PHASE_TIMEOUT_EXIT_CODE)
+                if worker.restarts < worker.config.retries && !shutdown::requested() =>
+            {
+                worker.restarts += 1;
+                warn!(
+                    thread = i,
+                    "hit the phase timeout on {}, restarting it ({}/{})",
+                    host,
+                    worker.restarts,
+                    worker.config.retries
+                );
+            }
+            (true, PHASE_TIMEOUT_EXIT_CODE) => {
+                info!(thread = i, "got result from {}: phase timeout", host);
+                worker.done = true;
+                self.classification = Classification::Hang;
+            }
+            (exited, code) => {
+                let classification = if exited {
+                    Classification::from_exit_code(code).unwrap_or(Classification::PhaseFailure)
+                } else {
+                    Classification::PhaseFailure
+                };
+                error!(thread = i, "failed on {}: {}", host, result.error);
+                worker.done = true;
+                self.failed_workers.push((i, classification));
+                self.classification = self.classification.max(classification);
+            }
+        }
+        Ok(())
+    }
+
+    /// Hands out no more tasks once shutdown was requested.
+    fn stop_if_requested(&mut self) {
+        if shutdown::requested() {
+            for worker in &mut self.workers {
+                worker.done = true;
+            }
+        }
+    }
+
+    fn finished(&self) -> bool {
+        self.workers.iter().all(|w| w.done) && self.leases.is_empty()
+    }
+}
+
+struct Service {
+    state: Arc<Mutex<State>>,
+}
+
+#[tonic::async_trait]
+impl Coordinator for Service {
+    async fn next_task(&self, request: Request<TaskRequest>) -> Result<Response<Task>, Status> {
+        let host = request.into_inner().host;
+        Ok(Response::new(lock(&self.state).next_task(host)))
+    }
+
+    async fn report(&self, request: Request<TaskResult>) -> Result<Response<Ack>, Status> {
+        lock(&self.state).report(request.into_inner())?;
+        Ok(Response::new(Ack {}))
+    }
+}
+
+/// Serves the lifecycles of `groups` on `addr` until every one of them ran
+/// on a joined instance, or shutdown was requested and the tasks in flight
+/// reported back or `shutdown_timeout` passed.
+///
+/// Tasks run with the arguments of this process followed by `extra_args`.
+pub fn coordinate(
+    addr: SocketAddr,
+    groups: &[(usize, WorkerConfig)],
+    extra_args: Vec<String>,
+    shutdown_timeout: Duration,
+) -> Result<RunOutcome> {
+    let workers = groups
+        .iter()
+        .flat_map(|(threads, config)| std::iter::repeat(config).take(*threads))
+        .enumerate()
+        .map(|(i, config)| Worker {
+            config: WorkerConfig {
+                churn: true,
+                ..config.clone()
+            },
+            next_lifecycle: 0,
+            restarts: 0,
+            running: false,
+            done: config.lifecycles(i) == 0,
+        })
+        .collect::<Vec<_>>();
+    let mut args = task_args(std::env::args().skip(1));
+    args.push("--churn".to_string());
+    args.extend(extra_args);
+    let state = Arc::new(Mutex::new(State {
+        args,
+        workers,
+        leases: HashMap::new(),
+        next_id: 0,
+        records: Vec::new(),
+        classification: Classification::Success,
+        failed_workers: Vec::new(),
+    }));
+
+    // The server task only reports a busy address to itself.
+    drop(TcpListener::bind(addr).with_context(|| format!("failed to listen on {}", addr))?);
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    let service = CoordinatorServer::new(Service {
+        state: state.clone(),
+    });
+    runtime.spawn(async move {
+        if let Err(err) = Server::builder().add_service(service).serve(addr).await {
+            error!("coordinator: {}", err);
+        }
+    });
+    info!(
+        "Coordinating {} workers on {}",
+        lock(&state).workers.len(),
+        addr
+    );
+
+    let mut shutdown_deadline = None;
+    loop {
+        thread::sleep(POLL_INTERVAL);
+        let mut state = lock(&state);
+        state.stop_if_requested();
+        if state.finished() {
+            break;
+        }
+        if shutdown::requested() {
+            let deadline = *shutdown_deadline.get_or_insert_with(|| {
+                info!(
+                    "Shutdown requested, waiting up to {}s for {} tasks in flight",
+                    shutdown_timeout.as_secs(),
+                    state.leases.len()
+                );
+                Instant::now() + shutdown_timeout
+            });
+            if Instant::now() >= deadline {
+                warn!(
+                    "Gave up waiting for {} tasks, reporting partial results",
+                    state.leases.len()
+                );
+                break;
+            }
+        }
+    }
+    thread::sleep(LINGER);
+    runtime.shutdown_background();
+
+    let mut state = lock(&state);
+    Ok(RunOutcome {
+        records: mem::take(&mut state.records),
+        classification: state.classification,
+        failed_workers: mem::take(&mut state.failed_workers),
+    })
+}
+
+/// Runs lifecycles from the coordinator at `url` in up to `slots` child
+/// processes at a time, until it has no more or shutdown was requested.
+pub fn join(url: &str, slots: usize) -> Result<()> {
+    let host = platform::hostname();
+    info!("Joining {} as {} with {} slots", url, host, slots);
+    let handles = (0..slots)
+        .map(|slot| {
+            let url = url.to_string();
+            let host = format!("{}/{}", host, slot);
+            thread::Builder::new()
+                .name(format!("slot-{}", slot))
+                .spawn(move || run_slot(&url, &host))
+        })
+        .collect::<std::io::Result<Vec<_>>>()?;
+    for handle in handles {
+        handle.join().expect("slot panicked")?;
+    }
+    Ok(())
+}
+
+fn run_slot(url: &str, host: &str) -> Result<()> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let mut client = runtime
+        .block_on(CoordinatorClient::connect(url.to_string()))
+        .with_context(|| format!("failed to connect to {}", url))?;
+    while !shutdown::requested() {
+        let task = runtime
+            .block_on(client.next_task(TaskRequest {
+                host: host.to_string(),
+            }))
+            .context("coordinator unreachable")?
+            .into_inner();
+        if task.done {
+            info!("{}: the coordinator has no more tasks", host);
+            break;
+        }
+        if task.wait {
+            thread::sleep(POLL_INTERVAL);
+            continue;
+        }
+        let result = run_task(host, task);
+        runtime
+            .block_on(client.report(result))
+            .context("coordinator unreachable")?;
+    }
+    Ok(())
+}
+
+/// Runs the lifecycle of `task` in a child process, forwarding a shutdown
+/// request to it.
+fn run_task(host: &str, task: Task) -> TaskResult {
+    let (worker, lifecycle) = (task.worker as usize, task.lifecycle as usize);
+    let mut result = TaskResult {
+        host: host.to_string(),
+        id: task.id,
+        ..TaskResult::default()
+    };
+    let (events, rx) = mpsc::channel();
+    let (pid, handle) = match ProcessWorkers::with_args(task.args).spawn(worker, lifecycle, events)
+    {
+        Ok(spawned) => spawned,
+        Err(err) => {
+            result.error = format!("{:#}", err);
+            return result;
+        }
+    };
+    info!(
+        thread = worker,
+        "Running lifecycle {} of worker {}", lifecycle, worker
+    );
+
+    let mut terminated = false;
+    loop {
+        match rx.recv_timeout(POLL_INTERVAL) {
+            Ok(WorkerEvent::Lifecycle(record)) => result
+                .records
+                .push(serde_json::to_string(&record).expect("failed to encode record")),
+            Ok(WorkerEvent::Finished(_, Ok(()))) => {
+                result.exited = true;
+                break;
+            }
+            Ok(WorkerEvent::Finished(_, Err(err))) => {
+                if let Some(code) = err
+                    .downcast_ref::<ChildFailed>()
+                    .and_then(|child| child.status.code())
+                {
+                    result.exited = true;
+                    result.exit_code = code;
+                }
+                result.error = format!("{:#}", err);
+                break;
+            }
+            Ok(WorkerEvent::TimedOut(_)) => {
+                result.exited = true;
+                result.exit_code = PHASE_TIMEOUT_EXIT_CODE;
+                break;
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                if shutdown::requested() && !terminated {
+                    process::terminate(&[pid]);
+                    terminated = true;
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                result.error = "the worker process reported no result".to_string();
+                break;
+            }
+        }
+    }
+    let _ = handle.join();
+    result
+}
+
+/// Drops the flags in [`COORDINATOR_ONLY_ARGS`] from `args`.
+fn task_args<I: Iterator<Item = String>>(args: I) -> Vec<String> {
+    let mut kept = Vec::new();
+    let mut skip_value = false;
+    for arg in args {
+        if mem::replace(&mut skip_value, false) {
+            continue;
+        }
+        let flag = arg.strip_prefix("--").unwrap_or("");
+        match COORDINATOR_ONLY_ARGS
+            .iter()
+            .find(|(name, _)| flag == *name || flag.starts_with(&format!("{}=", name)))
+        {
+            Some((name, takes_value)) => skip_value = *takes_value && flag == *name,
+            None => kept.push(arg),
+        }
+    }
+    kept
+}
+
+fn lock(state: &Mutex<State>) -> MutexGuard<'_, State> {
+    state.lock().expect("coordinator state poisoned")
+}
@@ -0,0 +1,139 @@
+//! Named sets of the proofs' environment variables.
+//!
+//! Reproduction instructions used to start with a block of `export`s;
+//! `--env-preset` sets the same variables before the proofs library reads
+//! them and before any worker process inherits them. A preset overrides
+//! what the shell set, with a warning. [`Toggles`] turn single settings on
+//! or off per worker group.
+
+use std::env;
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
+use tracing::{info, warn};
+
+/// Turns the multicore SDR for PC1 on with `1`.
+pub const MULTICORE_SDR_ENV: &str = "FIL_PROOFS_USE_MULTICORE_SDR";
+/// Builds PC2's column tree on the GPU with `1`.
+pub const GPU_COLUMN_BUILDER_ENV: &str = "FIL_PROOFS_USE_GPU_COLUMN_BUILDER";
+/// Builds PC2's replica tree on the GPU with `1`.
+pub const GPU_TREE_BUILDER_ENV: &str = "FIL_PROOFS_USE_GPU_TREE_BUILDER";
+
+/// A named set of environment variables.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EnvPreset {
+    /// Only C2 on the GPU, the column and tree builders on the CPU.
+    GpuC2,
+    /// C2 and the column and tree builders on the GPU.
+    GpuAll,
+    /// Nothing on the GPU.
+    CpuOnly,
+    /// PC1 labelling with the multicore SDR implementation.
+    MulticoreSdr,
+}
+
+impl FromStr for EnvPreset {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "gpu-c2" => Ok(EnvPreset::GpuC2),
+            "gpu-all" => Ok(EnvPreset::GpuAll),
+            "cpu-only" => Ok(EnvPreset::CpuOnly),
+            "multicore-sdr" => Ok(EnvPreset::MulticoreSdr),
+            _ => bail!("unknown env preset: {}", s),
+        }
+    }
+}
+
+impl EnvPreset {
+    pub fn name(self) -> &'static str {
+        match self {
+            EnvPreset::GpuC2 => "gpu-c2",
+            EnvPreset::GpuAll => "gpu-all",
+            EnvPreset::CpuOnly => "cpu-only",
+            EnvPreset::MulticoreSdr => "multicore-sdr",
+        }
+    }
+
+    /// The variables the preset sets, `None` for those it removes.
+    pub fn vars(self) -> &'static [(&'static str, Option<&'static str>)] {
+        match self {
+            EnvPreset::GpuC2 => &[
+                ("BELLMAN_NO_GPU", None),
+                (GPU_COLUMN_BUILDER_ENV, Some("0")),
+                (GPU_TREE_BUILDER_ENV, Some("0")),
+            ],
+            EnvPreset::GpuAll => &[
+                ("BELLMAN_NO_GPU", None),
+                (GPU_COLUMN_BUILDER_ENV, Some("1")),
+                (GPU_TREE_BUILDER_ENV, Some("1")),
+            ],
+            EnvPreset::CpuOnly => &[
+                ("BELLMAN_NO_GPU", Some("1")),
+                (GPU_COLUMN_BUILDER_ENV, Some("0")),
+                (GPU_TREE_BUILDER_ENV, Some("0")),
+            ],
+            EnvPreset::MulticoreSdr => &[(MULTICORE_SDR_ENV, Some("1"))],
+        }
+    }
+
+    /// Whether the preset puts work on the GPU.
+    pub fn uses_gpu(self) -> bool {
+        matches!(self, EnvPreset::GpuC2 | EnvPreset::GpuAll)
+    }
+
+    /// Sets the preset's variables. Has to run before the proofs library
+    /// reads its settings.
+    pub fn apply(self) {
+        for (var, value) in self.vars() {
+            let previous = env::var(var).ok();
+            if previous.is_some() && previous.as_deref() != *value {
+                warn!(
+                    "env preset {} overrides {}={}",
+                    self.name(),
+                    var,
+                    previous.unwrap_or_default()
+                );
+            }
+            match value {
+                Some(value) => env::set_var(var, value),
+                None => env::remove_var(var),
+            }
+        }
+        info!("Using env preset {}", self.name());
+    }
+}
+
+/// Proofs settings a worker group turns on or off, `None` leaving one to the
+/// environment. The proofs read them once per process, so only process
+/// workers can differ in them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Toggles {
+    pub multicore_sdr: Option<bool>,
+    pub gpu_column_builder: Option<bool>,
+    pub gpu_tree_builder: Option<bool>,
+}
+
+impl Toggles {
+    /// These toggles, with the settings they leave open taken from `base`.
+    pub fn or(self, base: Toggles) -> Toggles {
+        Toggles {
+            multicore_sdr: self.multicore_sdr.or(base.multicore_sdr),
+            gpu_column_builder: self.gpu_column_builder.or(base.gpu_column_builder),
+            gpu_tree_builder: self.gpu_tree_builder.or(base.gpu_tree_builder),
+        }
+    }
+
+    /// The environment variables that apply the toggles.
+    pub fn env(self) -> Vec<(&'static str, &'static str)> {
+        [
+            (MULTICORE_SDR_ENV, self.multicore_sdr),
+            (GPU_COLUMN_BUILDER_ENV, self.gpu_column_builder),
+            (GPU_TREE_BUILDER_ENV, self.gpu_tree_builder),
+        ]
+        .iter()
+        .filter_map(|(var, on)| on.map(|on| (*var, if on { "1" } else { "0" })))
+        .collect()
+    }
+}
@@ -0,0 +1,179 @@
+//! What the run's outcome may depend on besides its arguments.
+//!
+//! Hangs come and go with the proofs' environment variables, the thread
+//! pool size, the CPU and GPU models, the kernel and the crate versions, so
+//! they are logged when a run starts and written into its JSON report.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::CStr;
+use std::fs;
+use std::mem;
+use std::process::Command;
+
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::compat;
+
+/// Prefixes of the environment variables that are captured.
+const VAR_PREFIXES: [&str; 3] = ["BELLMAN_", "FIL_PROOFS_", "NEPTUNE_"];
+/// Environment variables captured besides the prefixed ones.
+const VARS: [&str; 2] = ["RAYON_NUM_THREADS", "CUDA_VISIBLE_DEVICES"];
+/// The crates whose locked versions are captured.
+const LOCKED_CRATES: [&str; 6] = [
+    "filecoin-proofs",
+    "storage-proofs-core",
+    "storage-proofs-porep",
+    "bellperson",
+    "neptune",
+    "rust-gpu-tools",
+];
+/// The lock file the harness was built with.
+const CARGO_LOCK: &str = include_str!("../Cargo.lock");
+
+/// A snapshot of the environment of a run.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Environment {
+    pub vars: BTreeMap<String, String>,
+    pub cpu: Option<String>,
+    pub gpus: Vec<String>,
+    /// The kernel's name, release and machine, as `uname -srm` prints them.
+    pub kernel: Option<String>,
+    /// The harness's and [`LOCKED_CRATES`]' versions. A crate locked at more
+    /// than one version has all of them; `proofs_release` says which
+    /// filecoin-proofs was built.
+    pub crates: BTreeMap<String, String>,
+    pub proofs_release: String,
+}
+
+impl Environment {
+    pub fn capture() -> Self {
+        let vars = env::vars()
+            .filter(|(var, _)| {
+                VAR_PREFIXES.iter().any(|prefix| var.starts_with(prefix))
+                    || VARS.contains(&var.as_str())
+            })
+            .collect();
+        let mut crates = locked_versions(CARGO_LOCK);
+        crates.insert(
+            env!("CARGO_PKG_NAME").to_string(),
+            env!("CARGO_PKG_VERSION").to_string(),
+        );
+        Environment {
+            vars,
+            cpu: cpu_model(),
+            gpus: gpu_models(),
+            kernel: kernel(),
+            crates,
+            proofs_release: compat::PROOFS_RELEASE.to_string(),
+        }
+    }
+
+    /// Logs the snapshot a line per item.
+    pub fn log(&self) {
+        let unknown = || "unknown".to_string();
+        info!("Built against {}", self.proofs_release);
+        info!("CPU: {}", self.cpu.clone().unwrap_or_else(unknown));
+        if self.gpus.is_empty() {
+            info!("GPU: none found");
+        }
+        for gpu in &self.gpus {
+            info!("GPU: {}", gpu);
+        }
+        info!("Kernel: {}", self.kernel.clone().unwrap_or_else(unknown));
+        for (name, version) in &self.crates {
+            info!("Crate {} {}", name, version);
+        }
+        for (var, value) in &self.vars {
+            info!("{}={}", var, value);
+        }
+    }
+}
+
+fn cpu_model() -> Option<String> {
+    let cpuinfo = fs::read_to_string("/proc/cpuinfo").ok()?;
+    cpuinfo
+        .lines()
+        .find(|line| line.starts_with("model name"))
+        .and_then(|line| line.splitn(2, ':').nth(1))
+        .map(|model| model.trim().to_string())
+}
+
+/// The GPU models `nvidia-smi` lists, none without it.
+fn gpu_models() -> Vec<String> {
+    Command::new("nvidia-smi")
+        .args(&["--query-gpu=name", "--format=csv,noheader"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| {
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn kernel() -> Option<String> {
+    // Safety: uname only fills in the zeroed struct.
+    let mut name: libc::utsname = unsafe { mem::zeroed() };
+    if unsafe { libc::uname(&mut name) } != 0 {
+        return None;
+    }
+    let field = |field: &[libc::c_char]| {
+        // Safety: uname NUL-terminates every field.
+        unsafe { CStr::from_ptr(field.as_ptr()) }
+            .to_string_lossy()
+            .into_owned()
+    };
+    Some(format!(
+        "{} {} {}",
+        field(&name.sysname),
+        field(&name.release),
+        field(&name.machine)
+    ))
+}
+
+/// The versions `lock` pins [`LOCKED_CRATES`] to, with the git commit of
+/// those not from crates.io.
+fn locked_versions(lock: &str) -> BTreeMap<String, String> {
+    #[derive(Deserialize)]
+    struct Lock {
+        #[serde(default)]
+        package: Vec<Package>,
+    }
+    #[derive(Deserialize)]
+    struct Package {
+        name: String,
+        version: String,
+        source: Option<String>,
+    }
+
+    let lock: Lock = match toml::from_str(lock) {
+        Ok(lock) => lock,
+        Err(_) => return BTreeMap::new(),
+    };
+    let mut crates = BTreeMap::<String, Vec<String>>::new();
+    for package in lock.package {
+        if !LOCKED_CRATES.contains(&package.name.as_str()) {
+            continue;
+        }
+        let version = match package.source.as_deref().and_then(|source| {
+            source
+                .strip_prefix("git+")
+                .map(|git| git.rsplitn(2, '#').next().unwrap_or(git))
+        }) {
+            Some(commit) => format!("{} ({})", package.version, commit),
+            None => package.version,
+        };
+        crates.entry(package.name).or_default().push(version);
+    }
+    crates
+        .into_iter()
+        .map(|(name, versions)| (name, versions.join(", ")))
+        .collect()
+}
@@ -0,0 +1,213 @@
+//! Prometheus text-format endpoint for watching long runs.
+//!
+//! The counters are always updated from [`crate::metrics`]; they are only
+//! exposed over HTTP when [`serve`] has been started.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Result;
+use lazy_static::lazy_static;
+use tracing::{info, warn};
+
+use crate::metrics::Phase;
+
+/// Upper bounds, in seconds, of the phase latency histogram buckets.
+const LATENCY_BUCKETS: [f64; 10] = [
+    1.0,
+    5.0,
+    15.0,
+    60.0,
+    300.0,
+    900.0,
+    1800.0,
+    3600.0,
+    4.0 * 3600.0,
+    12.0 * 3600.0,
+];
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        for (bucket, bound) in self.buckets.iter_mut().zip(LATENCY_BUCKETS.iter()) {
+            if value <= *bound {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += value;
+    }
+}
+
+#[derive(Default)]
+struct Registry {
+    in_flight: BTreeMap<Phase, i64>,
+    latency: BTreeMap<Phase, Histogram>,
+    /// Seconds spent waiting for the GPU lock.
+    gpu_wait: BTreeMap<Phase, f64>,
+    /// Finished lifecycles keyed by worker group and success.
+    lifecycles: BTreeMap<(String, bool), u64>,
+}
+
+lazy_static! {
+    static ref REGISTRY: Mutex<Registry> = Mutex::new(Registry::default());
+}
+
+fn with_registry<F: FnOnce(&mut Registry)>(f: F) {
+    f(&mut REGISTRY.lock().expect("metrics registry poisoned"));
+}
+
+pub fn phase_started(phase: Phase) {
+    with_registry(|registry| *registry.in_flight.entry(phase).or_default() += 1);
+}
+
+pub fn phase_finished(phase: Phase, duration: Duration) {
+    with_registry(|registry| {
+        *registry.in_flight.entry(phase).or_default() -= 1;
+        registry
+            .latency
+            .entry(phase)
+            .or_default()
+            .observe(duration.as_secs_f64());
+    });
+}
+
+pub fn gpu_waited(phase: Phase, waited: Duration) {
+    with_registry(|registry| *registry.gpu_wait.entry(phase).or_default() += waited.as_secs_f64());
+}
+
+pub fn lifecycle_finished(group: &str, success: bool) {
+    with_registry(|registry| {
+        *registry
+            .lifecycles
+            .entry((group.to_string(), success))
+            .or_default() += 1
+    });
+}
+
+fn render() -> String {
+    let registry = REGISTRY.lock().expect("metrics registry poisoned");
+    let mut out = String::new();
+
+    writeln!(
+        out,
+        "# HELP hang_phases_in_flight Phases currently running."
+    )
+    .unwrap();
+    writeln!(out, "# TYPE hang_phases_in_flight gauge").unwrap();
+    for (phase, count) in &registry.in_flight {
+        writeln!(
+            out,
+            "hang_phases_in_flight{{phase=\"{}\"}} {}",
+            phase, count
+        )
+        .unwrap();
+    }
+
+    writeln!(
+        out,
+        "# HELP hang_gpu_lock_wait_seconds_total Time GPU phases spent waiting for the GPU lock."
+    )
+    .unwrap();
+    writeln!(out, "# TYPE hang_gpu_lock_wait_seconds_total counter").unwrap();
+    for (phase, waited) in &registry.gpu_wait {
+        writeln!(
+            out,
+            "hang_gpu_lock_wait_seconds_total{{phase=\"{}\"}} {}",
+            phase, waited
+        )
+        .unwrap();
+    }
+
+    writeln!(out, "# HELP hang_lifecycles_total Finished lifecycles.").unwrap();
+    writeln!(out, "# TYPE hang_lifecycles_total counter").unwrap();
+    for ((group, success), count) in &registry.lifecycles {
+        let result = if *success { "success" } else { "failure" };
+        writeln!(
+            out,
+            "hang_lifecycles_total{{group=\"{}\",result=\"{}\"}} {}",
+            group, result, count
+        )
+        .unwrap();
+    }
+
+    writeln!(
+        out,
+        "# HELP hang_phase_duration_seconds Wall-clock duration of phases."
+    )
+    .unwrap();
+    writeln!(out, "# TYPE hang_phase_duration_seconds histogram").unwrap();
+    for (phase, histogram) in &registry.latency {
+        for (bucket, bound) in histogram.buckets.iter().zip(LATENCY_BUCKETS.iter()) {
+            writeln!(
+                out,
+                "hang_phase_duration_seconds_bucket{{phase=\"{}\",le=\"{}\"}} {}",
+                phase, bound, bucket
+            )
+            .unwrap();
+        }
+        writeln!(
+            out,
+            "hang_phase_duration_seconds_bucket{{phase=\"{}\",le=\"+Inf\"}} {}",
+            phase, histogram.count
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "hang_phase_duration_seconds_sum{{phase=\"{}\"}} {}",
+            phase, histogram.sum
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "hang_phase_duration_seconds_count{{phase=\"{}\"}} {}",
+            phase, histogram.count
+        )
+        .unwrap();
+    }
+
+    out
+}
+
+fn respond(mut stream: TcpStream) -> Result<()> {
+    // Every request gets the metrics, so the request itself is only drained.
+    let mut request = [0u8; 1024];
+    let _ = stream.read(&mut request)?;
+
+    let body = render();
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    )?;
+    Ok(())
+}
+
+/// Serves the metrics on `addr` from a background thread.
+pub fn serve<A: ToSocketAddrs>(addr: A) -> Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    info!("Serving Prometheus metrics on {}", listener.local_addr()?);
+
+    thread::Builder::new()
+        .name("metrics-exporter".into())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                if let Err(err) = stream.map_err(Into::into).and_then(respond) {
+                    warn!("metrics exporter: {}", err);
+                }
+            }
+        })?;
+    Ok(())
+}
@@ -0,0 +1,93 @@
+//! Open file descriptors, counted as every lifecycle ends.
+//!
+//! Long soaks stall late more often than early, and descriptors leaked by
+//! temporary files or cache directories are one suspect. Every lifecycle
+//! record carries the count of its process when it ended, and a worker whose
+//! counts went up after each of enough lifecycles is reported as possibly
+//! leaking. Thread workers share the count of the process, process workers
+//! count their own.
+
+use std::fs;
+
+use tracing::warn;
+
+use crate::metrics::LifecycleRecord;
+
+/// Fewest counts of a worker before they are judged.
+const MIN_LIFECYCLES: usize = 5;
+
+#[cfg(target_os = "linux")]
+const FD_DIR: &str = "/proc/self/fd";
+#[cfg(not(target_os = "linux"))]
+const FD_DIR: &str = "/dev/fd";
+
+/// The number of file descriptors this process has open.
+pub fn count() -> Option<usize> {
+    // Listing the directory takes a descriptor of its own.
+    let entries = fs::read_dir(FD_DIR).ok()?.count();
+    Some(entries.saturating_sub(1))
+}
+
+/// A worker whose open descriptors never went down.
+#[derive(Clone, Debug)]
+pub struct Growth {
+    pub thread: usize,
+    pub group: String,
+    pub lifecycles: usize,
+    pub first: usize,
+    pub last: usize,
+}
+
+/// The workers whose counts grew over at least [`MIN_LIFECYCLES`]
+/// lifecycles without ever dropping, in the order the records came in.
+pub fn growth(records: &[LifecycleRecord]) -> Vec<Growth> {
+    let mut threads = records.iter().map(|r| r.thread).collect::<Vec<_>>();
+    threads.sort_unstable();
+    threads.dedup();
+    threads
+        .into_iter()
+        .filter_map(|thread| {
+            let counts = records
+                .iter()
+                .filter(|r| r.thread == thread)
+                .filter_map(|r| r.open_fds)
+                .collect::<Vec<_>>();
+            let (first, last) = (*counts.first()?, *counts.last()?);
+            let never_dropped = counts.windows(2).all(|pair| pair[0] <= pair[1]);
+            if counts.len() < MIN_LIFECYCLES || !never_dropped || last <= first {
+                return None;
+            }
+            Some(Growth {
+                thread,
+                group: records
+                    .iter()
+                    .find(|r| r.thread == thread)
+                    .map(|r| r.group.clone())
+                    .unwrap_or_default(),
+                lifecycles: counts.len(),
+                first,
+                last,
+            })
+        })
+        .collect()
+}
+
+/// Prints the workers whose open descriptors only grew, if any.
+pub fn print_report(records: &[LifecycleRecord]) {
+    let growth = growth(records);
+    if growth.is_empty() {
+        return;
+    }
+    warn!(
+        "open file descriptors of {} workers only grew, possibly leaking",
+        growth.len()
+    );
+    println!();
+    println!("Open file descriptors never dropped between lifecycles of:");
+    for worker in growth {
+        println!(
+            "  thread {} ({}): {} -> {} over {} lifecycles",
+            worker.thread, worker.group, worker.first, worker.last, worker.lifecycles
+        );
+    }
+}
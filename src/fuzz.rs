@@ -0,0 +1,159 @@
+//! Schedule fuzzing: a random perturbation of every iteration.
+//!
+//! With `--fuzz` each iteration draws a schedule from a seed derived from
+//! the run seed. The schedule gives every worker a delay before its
+//! lifecycle, a rayon pool of random size and a delay before each of its
+//! phases, so the workers reach their phases and the GPU lock in a different
+//! order every iteration. The seed of the schedule goes into the lifecycle's
+//! record; `--fuzz-replay <schedule>` runs every iteration with that one
+//! schedule again, so a schedule that hung can be tried until it hangs
+//! again.
+//!
+//! Only thread workers are fuzzed.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use lazy_static::lazy_static;
+use rand::{Rng, SeedableRng};
+use rand_xorshift::XorShiftRng;
+
+use crate::metrics::{LifecycleRecord, Phase};
+use crate::shutdown;
+use crate::watchdog;
+
+/// Mixes the parts a schedule's values are derived from.
+const GOLDEN: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// What the schedule of an iteration gives one worker.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Schedule {
+    /// The seed the schedule was drawn from.
+    pub seed: u64,
+    /// Wait before the lifecycle starts.
+    pub start_delay: Duration,
+    /// Size of the worker's rayon pool for the lifecycle.
+    pub rayon_threads: usize,
+}
+
+struct Fuzzer {
+    max_delay: Duration,
+    replay: Option<u64>,
+    /// The schedule of the lifecycle each worker runs.
+    current: HashMap<usize, Schedule>,
+}
+
+lazy_static! {
+    static ref FUZZER: Mutex<Option<Fuzzer>> = Mutex::new(None);
+}
+
+/// Fuzzes every iteration with delays of up to `max_delay`, or runs them all
+/// with the schedule drawn from `replay`.
+pub fn enable(max_delay: Duration, replay: Option<u64>) {
+    *lock() = Some(Fuzzer {
+        max_delay,
+        replay,
+        current: HashMap::new(),
+    });
+}
+
+/// Draws the schedule of `worker` for `iteration` of the run with `seed`
+/// and makes it the one its phases are delayed by; `None` unless fuzzing.
+pub(crate) fn begin(seed: u64, worker: usize, iteration: usize) -> Option<Schedule> {
+    let mut fuzzer = lock();
+    let fuzzer = fuzzer.as_mut()?;
+    let schedule_seed = fuzzer.replay.unwrap_or_else(|| {
+        XorShiftRng::seed_from_u64(seed ^ (iteration as u64 + 1).wrapping_mul(GOLDEN)).gen()
+    });
+    let mut rng = worker_rng(schedule_seed, worker, None);
+    let schedule = Schedule {
+        seed: schedule_seed,
+        start_delay: random_delay(&mut rng, fuzzer.max_delay),
+        rayon_threads: rng.gen_range(1, cpus() + 1),
+    };
+    fuzzer.current.insert(worker, schedule);
+    Some(schedule)
+}
+
+/// Holds `worker` for the delay its schedule gives `phase`.
+pub(crate) fn enter(worker: usize, phase: Phase) {
+    let delay = match &*lock() {
+        Some(fuzzer) => match fuzzer.current.get(&worker) {
+            Some(schedule) => {
+                let mut rng = worker_rng(schedule.seed, worker, Some(phase));
+                random_delay(&mut rng, fuzzer.max_delay)
+            }
+            None => return,
+        },
+        None => return,
+    };
+    sleep(delay);
+}
+
+/// Waits `delay`, or until shutdown is requested.
+fn sleep(delay: Duration) {
+    let deadline = Instant::now() + delay;
+    while !shutdown::requested() {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left == Duration::default() {
+            break;
+        }
+        thread::sleep(left.min(Duration::from_secs(1)));
+        // A fuzzed delay is not a hang.
+        watchdog::waiting();
+    }
+}
+
+/// Prints how many schedules were tried and how to replay those that hung.
+pub fn print_report(records: &[LifecycleRecord]) {
+    if lock().is_none() {
+        return;
+    }
+    let mut schedules = BTreeMap::<u64, Vec<&LifecycleRecord>>::new();
+    for record in records {
+        if let Some(seed) = record.fuzz_schedule {
+            schedules.entry(seed).or_default().push(record);
+        }
+    }
+    println!();
+    println!("Fuzzing: {} schedules tried", schedules.len());
+    for (seed, records) in &schedules {
+        for record in records {
+            if let Some(phase) = record.timed_out {
+                println!(
+                    "  schedule {} hung thread {} in {} of iteration {}",
+                    seed, record.thread, phase, record.iteration
+                );
+                println!("    replay it with --fuzz-replay {}", seed);
+            }
+        }
+    }
+}
+
+/// The RNG of `worker`'s values in the schedule drawn from `seed`, those of
+/// its lifecycle for `None` and those of `phase` otherwise.
+fn worker_rng(seed: u64, worker: usize, phase: Option<Phase>) -> XorShiftRng {
+    let phase = match phase {
+        Some(phase) => Phase::ALL.iter().position(|p| *p == phase).unwrap_or(0) as u64 + 1,
+        None => 0,
+    };
+    let worker = (worker as u64 + 1).wrapping_mul(GOLDEN);
+    XorShiftRng::seed_from_u64(seed ^ worker ^ phase.wrapping_mul(GOLDEN).rotate_left(32))
+}
+
+fn random_delay(rng: &mut XorShiftRng, max: Duration) -> Duration {
+    Duration::from_millis(rng.gen_range(0, max.as_millis() as u64 + 1))
+}
+
+/// Online CPUs, the largest pool a schedule draws.
+fn cpus() -> usize {
+    // Safety: sysconf only reads a system setting.
+    let cpus = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) };
+    cpus.max(1) as usize
+}
+
+fn lock() -> MutexGuard<'static, Option<Fuzzer>> {
+    FUZZER.lock().expect("fuzzer poisoned")
+}
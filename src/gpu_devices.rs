@@ -0,0 +1,89 @@
+//! Which GPUs each worker may use.
+//!
+//! Neither bellperson nor neptune take a device per call, they use every
+//! device the driver exposes. Workers are therefore restricted through the
+//! driver's visibility variables, which only works per process: anything but
+//! [`GpuPolicy::FreeForAll`] needs process workers.
+
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
+
+/// Environment variables restricting the devices CUDA and ROCm expose.
+const VISIBILITY_VARS: [&str; 2] = ["CUDA_VISIBLE_DEVICES", "GPU_DEVICE_ORDINAL"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GpuPolicy {
+    /// Worker `i` gets device `i % n`.
+    RoundRobin,
+    /// Workers are split into `n` consecutive blocks, one per device.
+    Pin,
+    /// Every worker sees every listed device.
+    FreeForAll,
+}
+
+impl FromStr for GpuPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "round-robin" => Ok(GpuPolicy::RoundRobin),
+            "pin" => Ok(GpuPolicy::Pin),
+            "free-for-all" => Ok(GpuPolicy::FreeForAll),
+            _ => bail!("unknown gpu policy: {}", s),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct GpuDevices {
+    devices: Vec<usize>,
+    policy: GpuPolicy,
+    workers: usize,
+}
+
+impl GpuDevices {
+    /// Parses a comma separated list of device ordinals such as `0,1,2`.
+    pub fn parse(devices: &str, policy: GpuPolicy, workers: usize) -> Result<Self> {
+        let devices = devices
+            .split(',')
+            .map(|device| device.trim().parse::<usize>())
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        if devices.is_empty() {
+            bail!("no gpu devices given");
+        }
+        Ok(GpuDevices {
+            devices,
+            policy,
+            workers,
+        })
+    }
+
+    pub fn policy(&self) -> GpuPolicy {
+        self.policy
+    }
+
+    /// The devices worker `index` may use.
+    pub fn for_worker(&self, index: usize) -> Vec<usize> {
+        let n = self.devices.len();
+        match self.policy {
+            GpuPolicy::RoundRobin => vec![self.devices[index % n]],
+            GpuPolicy::Pin => vec![self.devices[(index * n / self.workers.max(1)).min(n - 1)]],
+            GpuPolicy::FreeForAll => self.devices.clone(),
+        }
+    }
+
+    /// Environment of a process running worker `index`.
+    pub fn env(&self, index: usize) -> Vec<(&'static str, String)> {
+        let visible = self
+            .for_worker(index)
+            .iter()
+            .map(|device| device.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+        VISIBILITY_VARS
+            .iter()
+            .map(|var| (*var, visible.clone()))
+            .collect()
+    }
+}
@@ -0,0 +1,138 @@
+//! Estimates how long workers wait for the GPU lock.
+//!
+//! bellperson serializes GPU work with a `flock` on a lock file, taken on the
+//! thread that calls into the prover. While a worker is in a phase that may
+//! take that lock, a sampler thread checks every [`SAMPLE_INTERVAL`] whether
+//! the worker's thread is blocked in `flock`. The blocked samples add up to
+//! the wait time; the rest of the phase counts as holding the lock. With
+//! `--trace` the blocked stretches also show up on the worker's track, and
+//! the acquisitions that follow them go to [`crate::schedule`].
+
+use std::collections::HashMap;
+use std::sync::{Mutex, Once};
+use std::thread;
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+
+use crate::metrics::Phase;
+use crate::schedule;
+use crate::trace;
+
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(20);
+const TRACE_CATEGORY: &str = "gpu_lock";
+const TRACE_NAME: &str = "gpu lock wait";
+
+#[derive(Default)]
+struct Sampled {
+    /// Time spent blocked so far.
+    waited: Duration,
+    /// Whether the last sample found the thread blocked.
+    blocked: bool,
+    /// The trace track of the thread.
+    worker: Option<usize>,
+    phase: Option<Phase>,
+}
+
+lazy_static! {
+    /// Samples keyed by the kernel id of each thread that is currently in a
+    /// GPU phase.
+    static ref WAITING: Mutex<HashMap<i64, Sampled>> = Mutex::new(HashMap::new());
+}
+
+static SAMPLER: Once = Once::new();
+
+/// Whether `phase` runs GPU work that contends for the lock.
+pub fn uses_gpu(phase: Phase) -> bool {
+    matches!(
+        phase,
+        Phase::PreCommit2
+            | Phase::Commit2
+            | Phase::WindowPost
+            | Phase::WinningPost
+            | Phase::Aggregate
+            | Phase::SnapEncode
+            | Phase::SnapProve
+    )
+}
+
+/// Starts sampling the current thread, which is in `phase`.
+pub fn enter(phase: Phase) {
+    SAMPLER.call_once(|| {
+        thread::Builder::new()
+            .name("gpu-lock-sampler".into())
+            .spawn(sample)
+            .expect("failed to spawn gpu lock sampler");
+    });
+    let sampled = Sampled {
+        worker: trace::worker(),
+        phase: Some(phase),
+        ..Sampled::default()
+    };
+    lock().insert(current_tid(), sampled);
+}
+
+/// Stops sampling the current thread and returns how long it was blocked.
+pub fn leave() -> Duration {
+    let sampled = lock().remove(&current_tid()).unwrap_or_default();
+    if let (true, Some(worker)) = (sampled.blocked, sampled.worker) {
+        trace::end(worker, TRACE_CATEGORY, TRACE_NAME);
+    }
+    sampled.waited
+}
+
+/// Number of threads in a GPU phase that are blocked on the lock right now.
+pub fn blocked_now() -> usize {
+    lock().keys().filter(|tid| blocked_in_flock(**tid)).count()
+}
+
+fn lock() -> std::sync::MutexGuard<'static, HashMap<i64, Sampled>> {
+    WAITING.lock().expect("gpu lock samples poisoned")
+}
+
+fn sample() {
+    loop {
+        thread::sleep(SAMPLE_INTERVAL);
+        for (tid, sampled) in lock().iter_mut() {
+            let blocked = blocked_in_flock(*tid);
+            if blocked {
+                sampled.waited += SAMPLE_INTERVAL;
+            }
+            if let (true, Some(worker)) = (blocked != sampled.blocked, sampled.worker) {
+                if blocked {
+                    trace::begin(worker, TRACE_CATEGORY, TRACE_NAME);
+                } else {
+                    trace::end(worker, TRACE_CATEGORY, TRACE_NAME);
+                    if let Some(phase) = sampled.phase {
+                        schedule::gpu_lock_acquired(worker, phase);
+                    }
+                }
+            }
+            sampled.blocked = blocked;
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) fn current_tid() -> i64 {
+    unsafe { libc::syscall(libc::SYS_gettid) }
+}
+
+/// Reads the system call `tid` is blocked in from procfs.
+#[cfg(target_os = "linux")]
+fn blocked_in_flock(tid: i64) -> bool {
+    std::fs::read_to_string(format!("/proc/self/task/{}/syscall", tid))
+        .ok()
+        .and_then(|syscall| syscall.split_whitespace().next()?.parse::<i64>().ok())
+        == Some(libc::SYS_flock)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn current_tid() -> i64 {
+    0
+}
+
+#[cfg(not(target_os = "linux"))]
+fn blocked_in_flock(_tid: i64) -> bool {
+    false
+}
@@ -0,0 +1,348 @@
+//! Remote C2: seal workers hand their C1 outputs to separate C2 workers.
+//!
+//! Miners often run C2 on a few GPU machines that prove for many sealing
+//! workers. With `--c2-queue <dir>` seal lifecycles stop after C1 and queue
+//! the sector's metadata, PC2 and C1 outputs as a job directory; workers of
+//! the `c2` workload claim the oldest job, run C2 and verify the proof. Jobs
+//! are claimed by renaming them, so any number of worker processes, and
+//! `hang` runs on other machines sharing the directory, take each job once.
+//! Seal workers register as producers while they run, and C2 workers stop
+//! once every producer finished and no job is left.
+//!
+//! Producers and claims name the process behind them, which refreshes them
+//! while it runs. One that died without cleaning up, killed or ended by the
+//! watchdog, is noticed by its pid on the same machine and by the age of its
+//! file on others: its producers no longer count and its claimed jobs are
+//! queued again.
+
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Once};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Context, Result};
+use filecoin_proofs::PoRepConfig;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use tempfile::Builder;
+use tracing::{info, warn};
+
+use crate::artifacts::{self, SectorFiles, C1_OUTPUT, PC2_OUTPUT, SECTOR_META};
+use crate::lifecycle::{parse_api_version, parse_porep_id, porep_config, porep_id_hex, SectorMeta};
+use crate::platform;
+use crate::shutdown;
+use crate::watchdog;
+
+/// The artifact describing the proofs configuration of a job.
+pub const JOB: &str = "job";
+
+/// Jobs waiting for a C2 worker.
+const PENDING: &str = "pending";
+/// Jobs a C2 worker took.
+const CLAIMED: &str = "claimed";
+/// One file per seal worker that may still queue jobs.
+const PRODUCERS: &str = "producers";
+/// Inside a claimed job: the process that claimed it.
+const OWNER: &str = "owner";
+/// How often C2 workers look for new jobs.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How often a process refreshes its producers and claims.
+const OWNER_HEARTBEAT: Duration = Duration::from_secs(10);
+/// How long the producer or claim of a process on another machine may go
+/// without a refresh before the process is taken for dead.
+const OWNER_TTL: Duration = Duration::from_secs(120);
+
+lazy_static! {
+    /// The producer and claim files of this process, refreshed every
+    /// [`OWNER_HEARTBEAT`] until they are removed.
+    static ref OWNED: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+}
+
+static HEARTBEAT: Once = Once::new();
+
+/// The process behind a producer or a claim.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct Owner {
+    host: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    boot_id: Option<String>,
+    pid: u32,
+}
+
+impl Owner {
+    fn current() -> Self {
+        Owner {
+            host: platform::hostname(),
+            boot_id: platform::boot_id(),
+            pid: process::id(),
+        }
+    }
+
+    /// Whether the process that wrote `path` may still run. Files that are
+    /// missing or not written yet count as alive.
+    fn alive(path: &Path) -> bool {
+        let owner = match fs::read(path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<Owner>(&bytes).ok())
+        {
+            Some(owner) => owner,
+            None => return true,
+        };
+        let current = Owner::current();
+        if owner.host == current.host && owner.boot_id.is_some() {
+            return owner.boot_id == current.boot_id
+                && artifacts::is_alive(owner.pid as libc::pid_t);
+        }
+        // Another machine, or one that cannot tell its boots apart.
+        fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .map_or(true, |age| age < OWNER_TTL)
+    }
+}
+
+/// Writes the owner file at `path` for this process and keeps refreshing
+/// it until it is removed.
+fn own(path: PathBuf) -> Result<()> {
+    let owner = serde_json::to_vec(&Owner::current())?;
+    fs::write(&path, &owner).with_context(|| format!("failed to create {}", path.display()))?;
+    OWNED.lock().expect("owned files poisoned").push(path);
+    HEARTBEAT.call_once(|| {
+        thread::Builder::new()
+            .name("c2-queue-heartbeat".into())
+            .spawn(move || loop {
+                thread::sleep(OWNER_HEARTBEAT);
+                // Rewritten rather than created, so files removed in the
+                // meantime are dropped instead of coming back.
+                OWNED.lock().expect("owned files poisoned").retain(|path| {
+                    OpenOptions::new()
+                        .write(true)
+                        .truncate(true)
+                        .open(path)
+                        .and_then(|mut file| file.write_all(&owner))
+                        .is_ok()
+                });
+            })
+            .expect("failed to spawn C2 queue heartbeat thread");
+    });
+    Ok(())
+}
+
+/// The configuration C2 needs that a C2 worker may not share.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Job {
+    pub sector_size: u64,
+    /// As in `--api-versions`, e.g. `V1_1_0`.
+    pub api_version: String,
+    pub porep_id: String,
+}
+
+impl Job {
+    pub fn config(&self) -> Result<PoRepConfig> {
+        let (api_version, _) = parse_api_version(&self.api_version)?;
+        Ok(porep_config(
+            self.sector_size,
+            parse_porep_id(&self.porep_id)?,
+            api_version,
+        ))
+    }
+}
+
+/// A C2 lifecycle found no job left to prove: every producer finished and
+/// the queue is empty, or shutdown was requested.
+#[derive(Clone, Copy, Debug)]
+pub struct QueueDrained;
+
+impl fmt::Display for QueueDrained {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "no C1 outputs left to prove")
+    }
+}
+
+impl std::error::Error for QueueDrained {}
+
+/// A seal worker queueing jobs; unregistered when dropped.
+#[derive(Debug)]
+pub struct Producer {
+    path: PathBuf,
+}
+
+impl Drop for Producer {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// A directory of C1 outputs waiting for C2.
+#[derive(Clone, Debug)]
+pub struct C2Queue {
+    dir: PathBuf,
+    /// Whether a producer was registered or a job claimed yet, so C2 workers
+    /// started ahead of the seal workers wait for them.
+    producers_seen: Arc<AtomicBool>,
+}
+
+impl C2Queue {
+    pub fn open(dir: PathBuf) -> Result<Self> {
+        for sub in &[PENDING, CLAIMED, PRODUCERS] {
+            let path = dir.join(sub);
+            fs::create_dir_all(&path)
+                .with_context(|| format!("failed to create {}", path.display()))?;
+        }
+        Ok(C2Queue {
+            dir,
+            producers_seen: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Registers seal worker `index` of this process as queueing jobs until
+    /// the returned producer is dropped.
+    pub(crate) fn producer(&self, index: usize) -> Result<Producer> {
+        let path = self.dir.join(PRODUCERS).join(format!(
+            "{}-{}-{}",
+            platform::hostname(),
+            process::id(),
+            index
+        ));
+        own(path.clone())?;
+        Ok(Producer { path })
+    }
+
+    /// Whether any seal worker, of this or another run, may still queue
+    /// jobs. Producers of processes that died are removed.
+    fn producing(&self) -> Result<bool> {
+        let producers = self.dir.join(PRODUCERS);
+        let mut producing = false;
+        for entry in fs::read_dir(&producers)
+            .with_context(|| format!("failed to list {}", producers.display()))?
+        {
+            let path = entry?.path();
+            if Owner::alive(&path) {
+                producing = true;
+            } else {
+                warn!("removing {}, its process is gone", path.display());
+                let _ = fs::remove_file(&path);
+            }
+        }
+        if producing {
+            self.producers_seen.store(true, Ordering::SeqCst);
+        }
+        Ok(producing)
+    }
+
+    /// Queues the jobs again that processes claimed and died with.
+    fn requeue_orphans(&self) -> Result<()> {
+        let claimed = self.dir.join(CLAIMED);
+        for entry in fs::read_dir(&claimed)
+            .with_context(|| format!("failed to list {}", claimed.display()))?
+        {
+            let entry = entry?;
+            let job = entry.path();
+            if Owner::alive(&job.join(OWNER)) {
+                continue;
+            }
+            let pending = self.dir.join(PENDING).join(entry.file_name());
+            // Another worker got there first.
+            if fs::rename(&job, &pending).is_err() {
+                continue;
+            }
+            let _ = fs::remove_file(pending.join(OWNER));
+            warn!("queued {} again, its C2 worker is gone", job.display());
+        }
+        Ok(())
+    }
+
+    /// Keeps the job of a failed C2 lifecycle where it is for inspection,
+    /// if failures are kept, rather than having it queued again.
+    pub(crate) fn keep_failed(&self, files: &mut SectorFiles) {
+        files.failed();
+        let _ = fs::remove_file(files.dir().join(OWNER));
+    }
+
+    /// Queues the C1 output a lifecycle with `config` left in `files`.
+    pub(crate) fn push(&self, files: &SectorFiles, config: PoRepConfig) -> Result<()> {
+        let meta = files
+            .load::<SectorMeta>(SECTOR_META)?
+            .ok_or_else(|| anyhow!("no sector meta in {}", files.dir().display()))?;
+        // Built next to the queue and moved in whole, so no worker claims a
+        // half written job.
+        let staging = Builder::new()
+            .prefix(".staging-")
+            .tempdir_in(&self.dir)
+            .context("failed to create a job directory")?;
+        let job = SectorFiles::inspect(staging.path().to_path_buf());
+        for name in &[SECTOR_META, PC2_OUTPUT, C1_OUTPUT] {
+            files.copy_to(name, &job)?;
+        }
+        job.save(
+            JOB,
+            &Job {
+                sector_size: u64::from(config.sector_size),
+                api_version: format!("{:?}", config.api_version),
+                porep_id: porep_id_hex(&config.porep_id),
+            },
+        )?;
+        // Named by the time they were queued, so the oldest is taken first.
+        let queued = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let name = format!("{:020}-{}", queued.as_nanos(), u64::from(meta.sector_id));
+        fs::rename(staging.into_path(), self.dir.join(PENDING).join(&name))
+            .with_context(|| format!("failed to queue {}", name))?;
+        info!(
+            sector_id = u64::from(meta.sector_id),
+            "queued C1 output for C2"
+        );
+        Ok(())
+    }
+
+    /// Claims the oldest job, waiting for one to be queued. `None` once
+    /// shutdown was requested, or once producers were seen and all of them
+    /// finished without leaving a job. The job's directory is removed when
+    /// the returned files are dropped.
+    pub(crate) fn pop(&self) -> Result<Option<(SectorFiles, Job)>> {
+        let pending = self.dir.join(PENDING);
+        while !shutdown::requested() {
+            // Checked before listing the jobs, so a job queued by the last
+            // producer is still seen.
+            let producing = self.producing()?;
+            self.requeue_orphans()?;
+            let mut names = fs::read_dir(&pending)
+                .with_context(|| format!("failed to list {}", pending.display()))?
+                .filter_map(|entry| entry.ok().map(|entry| entry.file_name()))
+                .collect::<Vec<_>>();
+            names.sort();
+            for name in names {
+                let claimed = self.dir.join(CLAIMED).join(&name);
+                // Another worker got there first.
+                if fs::rename(pending.join(&name), &claimed).is_err() {
+                    continue;
+                }
+                own(claimed.join(OWNER))?;
+                let files = SectorFiles::persistent(claimed, false)?;
+                let job = files
+                    .load::<Job>(JOB)?
+                    .ok_or_else(|| anyhow!("job {} has no {}", files.dir().display(), JOB))?;
+                self.producers_seen.store(true, Ordering::SeqCst);
+                return Ok(Some((files, job)));
+            }
+            if !producing && self.producers_seen.load(Ordering::SeqCst) {
+                return Ok(None);
+            }
+            thread::sleep(POLL_INTERVAL);
+            // Waiting for C1 outputs is not a hang.
+            watchdog::waiting();
+        }
+        Ok(None)
+    }
+}
@@ -1,308 +0,0 @@
-use anyhow::Result;
-use std::io::{Read, Seek, SeekFrom, Write};
-use std::path::Path;
-use std::sync::Once;
-
-use bellperson::bls::Fr;
-use ff::Field;
-use filecoin_proofs::{
-    add_piece, clear_cache, compute_comm_d, generate_piece_commitment, get_unsealed_range,
-    seal_commit_phase1, seal_commit_phase2, seal_pre_commit_phase1, seal_pre_commit_phase2,
-    validate_cache_for_commit, validate_cache_for_precommit_phase2, verify_seal, Commitment,
-    DefaultTreeDomain, MerkleTreeTrait, PaddedBytesAmount, PieceInfo, PoRepConfig,
-    PoRepProofPartitions, ProverId, SealPreCommitOutput, SealPreCommitPhase1Output,
-    SectorShape32KiB, SectorSize, UnpaddedByteIndex, UnpaddedBytesAmount, POREP_PARTITIONS,
-    SECTOR_SIZE_32_KIB,
-};
-use rand::{random, Rng, SeedableRng};
-use rand_xorshift::XorShiftRng;
-use storage_proofs_core::{api_version::ApiVersion, sector::SectorId};
-use tempfile::{tempdir, NamedTempFile, TempDir};
-const ARBITRARY_POREP_ID_V1_0_0: [u8; 32] = [127; 32];
-const ARBITRARY_POREP_ID_V1_1_0: [u8; 32] = [128; 32];
-
-const TEST_SEED: [u8; 16] = [
-    0x59, 0x62, 0xbe, 0x5d, 0x76, 0x3d, 0x31, 0x8d, 0x17, 0xdb, 0x37, 0x32, 0x54, 0x06, 0xbc, 0xe5,
-];
-
-const NUM_THREADS_DEFAULT: &str = "1";
-
-static INIT_LOGGER: Once = Once::new();
-fn init_logger() {
-    INIT_LOGGER.call_once(|| {
-        fil_logger::init();
-    });
-}
-
-fn generate_piece_file(sector_size: u64) -> Result<(NamedTempFile, Vec<u8>)> {
-    let number_of_bytes_in_piece = UnpaddedBytesAmount::from(PaddedBytesAmount(sector_size));
-
-    let piece_bytes: Vec<u8> = (0..number_of_bytes_in_piece.0)
-        .map(|_| random::<u8>())
-        .collect();
-
-    let mut piece_file = NamedTempFile::new()?;
-    piece_file.write_all(&piece_bytes)?;
-    piece_file.as_file_mut().sync_all()?;
-    piece_file.as_file_mut().seek(SeekFrom::Start(0))?;
-
-    Ok((piece_file, piece_bytes))
-}
-
-fn porep_config(sector_size: u64, porep_id: [u8; 32], api_version: ApiVersion) -> PoRepConfig {
-    PoRepConfig {
-        sector_size: SectorSize(sector_size),
-        partitions: PoRepProofPartitions(
-            *POREP_PARTITIONS
-                .read()
-                .expect("POREP_PARTITIONS poisoned")
-                .get(&sector_size)
-                .expect("unknown sector size"),
-        ),
-        porep_id,
-        api_version,
-    }
-}
-
-fn seal_lifecycle<Tree: 'static + MerkleTreeTrait>(
-    sector_size: u64,
-    porep_id: &[u8; 32],
-    api_version: ApiVersion,
-) -> Result<()> {
-    let rng = &mut XorShiftRng::from_seed(TEST_SEED);
-    let prover_fr: DefaultTreeDomain = Fr::random(rng).into();
-    let mut prover_id = [0u8; 32];
-    prover_id.copy_from_slice(AsRef::<[u8]>::as_ref(&prover_fr));
-
-    create_seal::<_, Tree>(rng, sector_size, prover_id, false, porep_id, api_version)?;
-    Ok(())
-}
-
-fn create_seal<R: Rng, Tree: 'static + MerkleTreeTrait>(
-    rng: &mut R,
-    sector_size: u64,
-    prover_id: ProverId,
-    skip_proof: bool,
-    porep_id: &[u8; 32],
-    api_version: ApiVersion,
-) -> Result<(SectorId, NamedTempFile, Commitment, TempDir)> {
-    init_logger();
-
-    let (mut piece_file, piece_bytes) = generate_piece_file(sector_size)?;
-    let sealed_sector_file = NamedTempFile::new()?;
-    let cache_dir = tempdir().expect("failed to create temp dir");
-
-    let config = porep_config(sector_size, *porep_id, api_version);
-    let ticket = rng.gen();
-    let seed = rng.gen();
-    let sector_id = rng.gen::<u64>().into();
-
-    let (piece_infos, phase1_output) = run_seal_pre_commit_phase1::<Tree>(
-        config,
-        prover_id,
-        sector_id,
-        ticket,
-        &cache_dir,
-        &mut piece_file,
-        &sealed_sector_file,
-    )?;
-
-    let pre_commit_output = seal_pre_commit_phase2(
-        config,
-        phase1_output,
-        cache_dir.path(),
-        sealed_sector_file.path(),
-    )?;
-
-    let comm_r = pre_commit_output.comm_r;
-
-    validate_cache_for_commit::<_, _, Tree>(cache_dir.path(), sealed_sector_file.path())?;
-
-    if skip_proof {
-        clear_cache::<Tree>(cache_dir.path())?;
-    } else {
-        proof_and_unseal::<Tree>(
-            config,
-            cache_dir.path(),
-            &sealed_sector_file,
-            prover_id,
-            sector_id,
-            ticket,
-            seed,
-            pre_commit_output,
-            &piece_infos,
-            &piece_bytes,
-        )
-        .expect("failed to proof");
-    }
-
-    Ok((sector_id, sealed_sector_file, comm_r, cache_dir))
-}
-
-fn proof_and_unseal<Tree: 'static + MerkleTreeTrait>(
-    config: PoRepConfig,
-    cache_dir_path: &Path,
-    sealed_sector_file: &NamedTempFile,
-    prover_id: ProverId,
-    sector_id: SectorId,
-    ticket: [u8; 32],
-    seed: [u8; 32],
-    pre_commit_output: SealPreCommitOutput,
-    piece_infos: &[PieceInfo],
-    piece_bytes: &[u8],
-) -> Result<()> {
-    let comm_d = pre_commit_output.comm_d;
-    let comm_r = pre_commit_output.comm_r;
-
-    let mut unseal_file = NamedTempFile::new()?;
-    let phase1_output = seal_commit_phase1::<_, Tree>(
-        config,
-        cache_dir_path,
-        sealed_sector_file.path(),
-        prover_id,
-        sector_id,
-        ticket,
-        seed,
-        pre_commit_output,
-        piece_infos,
-    )?;
-
-    clear_cache::<Tree>(cache_dir_path)?;
-
-    let commit_output = seal_commit_phase2(config, phase1_output, prover_id, sector_id)?;
-
-    let _ = get_unsealed_range::<_, Tree>(
-        config,
-        cache_dir_path,
-        sealed_sector_file.path(),
-        unseal_file.path(),
-        prover_id,
-        sector_id,
-        comm_d,
-        ticket,
-        UnpaddedByteIndex(508),
-        UnpaddedBytesAmount(508),
-    )?;
-
-    unseal_file.seek(SeekFrom::Start(0))?;
-
-    let mut contents = vec![];
-    assert!(
-        unseal_file.read_to_end(&mut contents).is_ok(),
-        "failed to populate buffer with unsealed bytes"
-    );
-    assert_eq!(contents.len(), 508);
-    assert_eq!(&piece_bytes[508..508 + 508], &contents[..]);
-
-    let computed_comm_d = compute_comm_d(config.sector_size, piece_infos)?;
-
-    assert_eq!(
-        comm_d, computed_comm_d,
-        "Computed and expected comm_d don't match."
-    );
-
-    let verified = verify_seal::<Tree>(
-        config,
-        comm_r,
-        comm_d,
-        prover_id,
-        sector_id,
-        ticket,
-        seed,
-        &commit_output.proof,
-    )?;
-    assert!(verified, "failed to verify valid seal");
-    Ok(())
-}
-
-fn run_seal_pre_commit_phase1<Tree: 'static + MerkleTreeTrait>(
-    config: PoRepConfig,
-    prover_id: ProverId,
-    sector_id: SectorId,
-    ticket: [u8; 32],
-    cache_dir: &TempDir,
-    mut piece_file: &mut NamedTempFile,
-    sealed_sector_file: &NamedTempFile,
-) -> Result<(Vec<PieceInfo>, SealPreCommitPhase1Output<Tree>)> {
-    let number_of_bytes_in_piece =
-        UnpaddedBytesAmount::from(PaddedBytesAmount(config.sector_size.into()));
-
-    let piece_info = generate_piece_commitment(piece_file.as_file_mut(), number_of_bytes_in_piece)?;
-    piece_file.as_file_mut().seek(SeekFrom::Start(0))?;
-
-    let mut staged_sector_file = NamedTempFile::new()?;
-    add_piece(
-        &mut piece_file,
-        &mut staged_sector_file,
-        number_of_bytes_in_piece,
-        &[],
-    )?;
-
-    let piece_infos = vec![piece_info];
-
-    let phase1_output = seal_pre_commit_phase1::<_, _, _, Tree>(
-        config,
-        cache_dir.path(),
-        staged_sector_file.path(),
-        sealed_sector_file.path(),
-        prover_id,
-        sector_id,
-        ticket,
-        &piece_infos,
-    )?;
-
-    validate_cache_for_precommit_phase2(
-        cache_dir.path(),
-        staged_sector_file.path(),
-        &phase1_output,
-    )?;
-
-    Ok((piece_infos, phase1_output))
-}
-
-fn main() -> Result<()> {
-    use clap::{App, Arg};
-
-    let matches = App::new("test")
-        .arg(
-            Arg::with_name("num-threads")
-                .short("t")
-                .long("num-threads")
-                .value_name("num of threads")
-                .help("The number of threads to use - default: 1")
-                .required(false)
-                .takes_value(true),
-        )
-        .get_matches();
-
-    let num_threads = matches
-        .value_of("num-threads")
-        .unwrap_or(NUM_THREADS_DEFAULT)
-        .parse::<usize>()
-        .expect("Expected an integer value");
-
-    println!("Spawning {} threads", num_threads);
-    let handlers = (0..num_threads)
-        .map(|_| {
-            std::thread::spawn(move || {
-                seal_lifecycle::<SectorShape32KiB>(
-                    SECTOR_SIZE_32_KIB,
-                    &ARBITRARY_POREP_ID_V1_1_0,
-                    ApiVersion::V1_1_0,
-                )?;
-                seal_lifecycle::<SectorShape32KiB>(
-                    SECTOR_SIZE_32_KIB,
-                    &ARBITRARY_POREP_ID_V1_0_0,
-                    ApiVersion::V1_0_0,
-                )
-            })
-        })
-        .collect::<Vec<_>>();
-
-    for h in handlers {
-        let thread_id = h.thread().id();
-        let res = h.join().unwrap();
-        println!("{:?} got result: {:?}", thread_id, res);
-    }
-    Ok(())
-}
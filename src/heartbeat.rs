@@ -0,0 +1,268 @@
+//! A heartbeat file for external monitors.
+//!
+//! While workers run, a JSON file with every worker's phase and the time of
+//! its last progress is rewritten at a fixed interval, so systemd, a
+//! Kubernetes liveness probe or a cron job can tell a hang from a slow phase
+//! without parsing logs. The file is replaced atomically; an
+//! `updated_unix_secs` that stops moving means the harness itself is stuck.
+//!
+//! Phases come from the watchdog, which only thread workers report to; a
+//! process worker's last progress is the end of its last lifecycle.
+
+use std::collections::VecDeque;
+use std::ffi::OsString;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use tracing::warn;
+
+use crate::metrics::{LifecycleRecord, Phase};
+use crate::watchdog::Watchdog;
+use crate::workloads::WorkerConfig;
+
+/// How many past heartbeats a postmortem gets.
+const HISTORY_LEN: usize = 100;
+
+lazy_static! {
+    static ref CONFIG: Mutex<Option<(PathBuf, Duration)>> = Mutex::new(None);
+    /// The latest heartbeats as JSON lines, oldest first.
+    static ref HISTORY: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+}
+
+/// Writes the heartbeat of the next [`crate::workloads::run_groups`] to
+/// `path` every `interval`.
+pub fn enable(path: PathBuf, interval: Duration) {
+    *CONFIG.lock().expect("heartbeat config poisoned") = Some((path, interval));
+}
+
+pub(crate) fn config() -> Option<(PathBuf, Duration)> {
+    CONFIG.lock().expect("heartbeat config poisoned").clone()
+}
+
+/// The last heartbeats written, as JSON lines.
+pub(crate) fn history() -> Vec<String> {
+    HISTORY
+        .lock()
+        .expect("heartbeat history poisoned")
+        .iter()
+        .cloned()
+        .collect()
+}
+
+#[derive(Serialize)]
+struct Report {
+    pid: u32,
+    started_unix_secs: f64,
+    updated_unix_secs: f64,
+    workers: Vec<WorkerReport>,
+}
+
+#[derive(Serialize)]
+struct WorkerReport {
+    worker: usize,
+    group: String,
+    /// `running`, `idle` between phases, or `finished`.
+    state: &'static str,
+    phase: Option<Phase>,
+    in_phase_secs: Option<f64>,
+    /// The SDR layer being labelled during PC1, counting from 1.
+    layer: Option<usize>,
+    layers: Option<usize>,
+    /// How long PC1 or PC2 has written nothing to the cache directory, once
+    /// that exceeded `--cache-stall-window`.
+    cache_stalled_secs: Option<f64>,
+    last_progress_unix_secs: f64,
+    lifecycles: usize,
+    failed: usize,
+}
+
+struct Worker {
+    group: String,
+    lifecycles: usize,
+    failed: usize,
+    last_lifecycle: SystemTime,
+    finished: bool,
+}
+
+struct State {
+    path: PathBuf,
+    workers: Vec<Worker>,
+    watchdog: Option<Arc<Watchdog>>,
+    started: SystemTime,
+}
+
+/// The running heartbeat writer; dropping it writes the file a last time.
+pub struct Heartbeat {
+    state: Arc<Mutex<State>>,
+    stop: Option<Sender<()>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl Heartbeat {
+    /// Starts rewriting `path` every `interval` for the workers of `groups`.
+    pub fn start(
+        path: PathBuf,
+        interval: Duration,
+        groups: &[(usize, WorkerConfig)],
+        watchdog: Option<Arc<Watchdog>>,
+    ) -> Result<Self> {
+        let started = SystemTime::now();
+        let workers = groups
+            .iter()
+            .flat_map(|(threads, config)| std::iter::repeat(config).take(*threads))
+            .map(|config| Worker {
+                group: config.group.clone(),
+                lifecycles: 0,
+                failed: 0,
+                last_lifecycle: started,
+                finished: false,
+            })
+            .collect();
+        let state = Arc::new(Mutex::new(State {
+            path,
+            workers,
+            watchdog,
+            started,
+        }));
+        lock(&state).write()?;
+
+        let (stop, rx) = mpsc::channel();
+        let writer = {
+            let state = state.clone();
+            thread::Builder::new()
+                .name("heartbeat".into())
+                .spawn(move || loop {
+                    let done = !matches!(rx.recv_timeout(interval), Err(RecvTimeoutError::Timeout));
+                    if let Err(err) = lock(&state).write() {
+                        warn!("heartbeat: {:#}", err);
+                    }
+                    if done {
+                        return;
+                    }
+                })?
+        };
+
+        Ok(Heartbeat {
+            state,
+            stop: Some(stop),
+            writer: Some(writer),
+        })
+    }
+
+    /// Counts a finished lifecycle of `record.thread` as progress.
+    pub fn lifecycle(&self, record: &LifecycleRecord) {
+        let mut state = lock(&self.state);
+        if let Some(worker) = state.workers.get_mut(record.thread) {
+            worker.lifecycles += 1;
+            if !record.success {
+                worker.failed += 1;
+            }
+            worker.last_lifecycle = SystemTime::now();
+        }
+    }
+
+    /// Marks worker `index` as done.
+    pub fn finished(&self, index: usize) {
+        let mut state = lock(&self.state);
+        if let Some(worker) = state.workers.get_mut(index) {
+            worker.finished = true;
+            worker.last_lifecycle = SystemTime::now();
+        }
+    }
+}
+
+impl Drop for Heartbeat {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+impl State {
+    /// Replaces the heartbeat file through a rename, so readers never see
+    /// half of it.
+    fn write(&self) -> Result<()> {
+        let now = SystemTime::now();
+        let workers = self
+            .workers
+            .iter()
+            .enumerate()
+            .map(|(index, worker)| {
+                let watched = self.watchdog.as_ref().map(|watchdog| {
+                    (
+                        watchdog.phase(index),
+                        watchdog.since_progress(index).unwrap_or_default(),
+                    )
+                });
+                let layer = self
+                    .watchdog
+                    .as_ref()
+                    .filter(|_| !worker.finished)
+                    .and_then(|watchdog| watchdog.layer(index));
+                let cache_stalled = self
+                    .watchdog
+                    .as_ref()
+                    .filter(|_| !worker.finished)
+                    .and_then(|watchdog| watchdog.cache_stalled(index));
+                let (state, phase, last_progress) = match watched {
+                    _ if worker.finished => ("finished", None, worker.last_lifecycle),
+                    Some((Some(phase), since)) => ("running", Some(phase), now - since),
+                    Some((None, since)) => ("idle", None, now - since),
+                    None => ("running", None, worker.last_lifecycle),
+                };
+                WorkerReport {
+                    worker: index,
+                    group: worker.group.clone(),
+                    state,
+                    phase: phase.map(|(phase, _)| phase),
+                    in_phase_secs: phase.map(|(_, elapsed)| elapsed.as_secs_f64()),
+                    layer: layer.map(|(layer, _)| layer),
+                    layers: layer.map(|(_, layers)| layers),
+                    cache_stalled_secs: cache_stalled.map(|quiet| quiet.as_secs_f64()),
+                    last_progress_unix_secs: unix_secs(last_progress),
+                    lifecycles: worker.lifecycles,
+                    failed: worker.failed,
+                }
+            })
+            .collect();
+        let report = Report {
+            pid: std::process::id(),
+            started_unix_secs: unix_secs(self.started),
+            updated_unix_secs: unix_secs(now),
+            workers,
+        };
+
+        let mut tmp = OsString::from(self.path.as_os_str());
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        {
+            let mut history = HISTORY.lock().expect("heartbeat history poisoned");
+            if history.len() == HISTORY_LEN {
+                history.pop_front();
+            }
+            history.push_back(serde_json::to_string(&report)?);
+        }
+        let json = serde_json::to_string_pretty(&report)?;
+        fs::write(&tmp, json).with_context(|| format!("failed to write {}", tmp.display()))?;
+        fs::rename(&tmp, &self.path)
+            .with_context(|| format!("failed to replace {}", self.path.display()))
+    }
+}
+
+fn unix_secs(time: SystemTime) -> f64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0.0, |since| since.as_secs_f64())
+}
+
+fn lock(state: &Mutex<State>) -> MutexGuard<'_, State> {
+    state.lock().expect("heartbeat state poisoned")
+}
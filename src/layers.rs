@@ -0,0 +1,101 @@
+//! PC1 progress, layer by layer.
+//!
+//! `seal_pre_commit_phase1` reports nothing until it returns, which for a
+//! 32GiB sector can take hours. It does write every SDR layer to the cache
+//! directory as `sc-02-data-layer-<n>.dat` once the layer is done, so the
+//! layer being labelled is one more than the number of those files.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use filecoin_proofs::LAYERS;
+use tracing::{info, Span};
+
+const LAYER_PREFIX: &str = "sc-02-data-layer-";
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The number of SDR layers of a sector of `sector_size` bytes.
+pub fn total(sector_size: u64) -> usize {
+    LAYERS
+        .read()
+        .expect("LAYERS poisoned")
+        .get(&sector_size)
+        .copied()
+        .unwrap_or(11)
+}
+
+/// The layer PC1 is labelling in `cache`, counting from 1, out of `total`.
+pub fn current(cache: &Path, total: usize) -> usize {
+    let done = fs::read_dir(cache).map_or(0, |entries| {
+        entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| {
+                entry
+                    .file_name()
+                    .to_str()
+                    .map_or(false, |name| name.starts_with(LAYER_PREFIX))
+            })
+            .count()
+    });
+    (done + 1).min(total)
+}
+
+/// Follows a running PC1: the current worker reports its layer to the
+/// watchdog, every new layer counts as progress there and is logged.
+/// Dropping it stops all of them.
+pub struct LayerWatch {
+    stop: Option<Sender<()>>,
+    poller: Option<JoinHandle<()>>,
+}
+
+impl LayerWatch {
+    pub fn start(cache: PathBuf, sector_size: u64) -> Self {
+        let total = total(sector_size);
+        crate::watchdog::watch_layers(cache.clone(), total);
+
+        let (stop, rx) = mpsc::channel();
+        let worker = crate::watchdog::current();
+        // Logged in the worker's span, so the lines carry its fields.
+        let span = Span::current();
+        let poller = thread::Builder::new()
+            .name("pc1-layers".into())
+            .spawn(move || {
+                let _span = span.entered();
+                let mut logged = 0;
+                loop {
+                    let layer = current(&cache, total);
+                    if layer != logged {
+                        info!(layer, layers = total, "pc1 layer {}/{}", layer, total);
+                        if let Some((watchdog, index)) = &worker {
+                            watchdog.progressed(*index);
+                        }
+                        logged = layer;
+                    }
+                    if !matches!(
+                        rx.recv_timeout(POLL_INTERVAL),
+                        Err(RecvTimeoutError::Timeout)
+                    ) {
+                        return;
+                    }
+                }
+            })
+            .ok();
+
+        LayerWatch {
+            stop: Some(stop),
+            poller,
+        }
+    }
+}
+
+impl Drop for LayerWatch {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(poller) = self.poller.take() {
+            let _ = poller.join();
+        }
+    }
+}
@@ -0,0 +1,113 @@
+//! Sector lifecycle drivers for reproducing GPU scheduler hangs.
+//!
+//! The `hang` binary is a thin command line front end over
+//! [`workloads::run_groups`]; integration tests can drive the same lifecycles
+//! directly instead of copying them.
+//!
+//! Linux gets every feature, other Unixes the reduced set of [`platform`].
+
+#[cfg(not(unix))]
+compile_error!("the harness needs a Unix, on Windows run it in WSL");
+
+// The upstream releases under the names the `scheduler` branch has; see
+// `compat`.
+#[cfg(feature = "proofs-v10")]
+extern crate filecoin_proofs_v10 as filecoin_proofs;
+#[cfg(feature = "proofs-v11")]
+extern crate filecoin_proofs_v11 as filecoin_proofs;
+#[cfg(feature = "proofs-v9")]
+extern crate filecoin_proofs_v9 as filecoin_proofs;
+#[cfg(feature = "proofs-v10")]
+extern crate storage_proofs_core_v10 as storage_proofs_core;
+#[cfg(feature = "proofs-v11")]
+extern crate storage_proofs_core_v11 as storage_proofs_core;
+#[cfg(feature = "proofs-v9")]
+extern crate storage_proofs_core_v9 as storage_proofs_core;
+
+/// Calls the generic function `$f` with the `SectorShape*` tree type that
+/// matches the runtime sector size `$size`. Written as `$f::<_, ...>`, the
+/// generic arguments after the tree type are passed on.
+macro_rules! with_shape {
+    ($size:expr, $f:ident) => {
+        with_shape!($size, $f::<_>,)
+    };
+    ($size:expr, $f:ident, $($args:expr),* $(,)?) => {
+        with_shape!($size, $f::<_>, $($args),*)
+    };
+    ($size:expr, $f:ident::<_ $(, $g:ty)*>, $($args:expr),* $(,)?) => {
+        match $size {
+            filecoin_proofs::SECTOR_SIZE_2_KIB => $f::<filecoin_proofs::SectorShape2KiB $(, $g)*>($($args),*),
+            filecoin_proofs::SECTOR_SIZE_4_KIB => $f::<filecoin_proofs::SectorShape4KiB $(, $g)*>($($args),*),
+            filecoin_proofs::SECTOR_SIZE_16_KIB => $f::<filecoin_proofs::SectorShape16KiB $(, $g)*>($($args),*),
+            filecoin_proofs::SECTOR_SIZE_32_KIB => $f::<filecoin_proofs::SectorShape32KiB $(, $g)*>($($args),*),
+            filecoin_proofs::SECTOR_SIZE_8_MIB => $f::<filecoin_proofs::SectorShape8MiB $(, $g)*>($($args),*),
+            filecoin_proofs::SECTOR_SIZE_16_MIB => $f::<filecoin_proofs::SectorShape16MiB $(, $g)*>($($args),*),
+            filecoin_proofs::SECTOR_SIZE_512_MIB => $f::<filecoin_proofs::SectorShape512MiB $(, $g)*>($($args),*),
+            filecoin_proofs::SECTOR_SIZE_1_GIB => $f::<filecoin_proofs::SectorShape1GiB $(, $g)*>($($args),*),
+            filecoin_proofs::SECTOR_SIZE_32_GIB => $f::<filecoin_proofs::SectorShape32GiB $(, $g)*>($($args),*),
+            filecoin_proofs::SECTOR_SIZE_64_GIB => $f::<filecoin_proofs::SectorShape64GiB $(, $g)*>($($args),*),
+            other => panic!("unsupported sector size: {}", other),
+        }
+    };
+}
+
+pub mod affinity;
+pub mod artifacts;
+pub mod audit;
+pub mod barrier;
+pub mod bundle;
+pub mod canary;
+pub mod cgroup;
+pub mod chain;
+pub mod chaos;
+pub mod codec;
+pub mod compare;
+pub mod compat;
+pub mod config;
+pub mod cpu;
+pub mod daemon;
+#[cfg(feature = "tui")]
+pub mod dashboard;
+#[cfg(feature = "grpc")]
+pub mod distributed;
+pub mod env_preset;
+pub mod environment;
+pub mod exporter;
+pub mod fds;
+pub mod fuzz;
+pub mod gpu_devices;
+pub mod gpu_lock;
+pub mod handoff;
+pub mod heartbeat;
+pub mod layers;
+pub mod lifecycle;
+pub mod load;
+pub mod lock_monitor;
+pub mod lock_order;
+pub mod logging;
+pub mod memory;
+pub mod metrics;
+pub mod numa;
+pub mod outcome;
+pub mod overlap;
+pub mod panics;
+pub mod params;
+pub mod piece_gen;
+pub mod pipeline;
+pub mod plan;
+pub mod platform;
+pub mod postmortem;
+pub mod process;
+pub mod profile;
+pub mod schedule;
+pub mod shutdown;
+#[cfg(feature = "proofs-v11")]
+pub mod snap;
+pub mod soak;
+pub mod stall;
+pub mod storage;
+pub mod sweep;
+pub mod timeline;
+pub mod trace;
+pub mod watchdog;
+pub mod workloads;
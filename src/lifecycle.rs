@@ -0,0 +1,906 @@
+//! A single sector's seal lifecycle, from the piece data up to a verified
+//! proof, with every phase output checkpointed through [`SectorFiles`].
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use filecoin_proofs::{
+    add_piece, clear_cache, compute_comm_d, generate_piece_commitment, get_unsealed_range,
+    seal_commit_phase1, seal_commit_phase2, seal_pre_commit_phase1, seal_pre_commit_phase2,
+    validate_cache_for_commit, validate_cache_for_precommit_phase2, verify_seal, Commitment,
+    MerkleTreeTrait, PaddedBytesAmount, PieceInfo, PoRepConfig, PoRepProofPartitions, ProverId,
+    SealCommitOutput, SealCommitPhase1Output, SealPreCommitOutput, SealPreCommitPhase1Output,
+    SectorSize, UnpaddedByteIndex, UnpaddedBytesAmount, POREP_PARTITIONS, SECTOR_SIZE_16_KIB,
+    SECTOR_SIZE_16_MIB, SECTOR_SIZE_1_GIB, SECTOR_SIZE_2_KIB, SECTOR_SIZE_32_GIB,
+    SECTOR_SIZE_32_KIB, SECTOR_SIZE_4_KIB, SECTOR_SIZE_512_MIB, SECTOR_SIZE_64_GIB,
+    SECTOR_SIZE_8_MIB,
+};
+use memmap::Mmap;
+use rand::Rng;
+use rand_xorshift::XorShiftRng;
+use serde::{Deserialize, Serialize};
+use storage_proofs_core::{api_version::ApiVersion, fr32::bytes_into_fr, sector::SectorId};
+use tracing::info;
+
+use crate::artifacts::{
+    ArtifactStore, SectorFiles, C1_OUTPUT, C2_OUTPUT, PC1_OUTPUT, PC2_OUTPUT, SECTOR_META,
+};
+use crate::chain;
+use crate::chaos;
+use crate::layers::LayerWatch;
+use crate::logging::{self, LogFormat};
+use crate::metrics::{self, Phase};
+use crate::outcome::{CacheInvalid, VerificationMismatch};
+use crate::piece_gen::{PieceData, PieceReader};
+use crate::pipeline;
+use crate::stall::StallWatch;
+
+pub const ARBITRARY_POREP_ID_V1_0_0: [u8; 32] = [127; 32];
+pub const ARBITRARY_POREP_ID_V1_1_0: [u8; 32] = [128; 32];
+
+/// Smallest padded piece `add_piece` accepts.
+const MIN_PIECE_SIZE: u64 = 128;
+/// Piece data is written through a buffer of this size.
+const PIECE_WRITE_BUFFER: usize = 1 << 20;
+/// Unsealed data is checked in chunks of this size.
+const COMPARE_CHUNK: usize = 1 << 20;
+
+/// Which part of a sealed sector gets unsealed and checked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnsealRanges {
+    /// The 508 bytes at offset 508.
+    Fixed,
+    /// A random offset and length, different for every lifecycle.
+    Random,
+    /// The whole sector.
+    Full,
+    /// No unsealing at all.
+    Skip,
+}
+
+impl FromStr for UnsealRanges {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "fixed" => Ok(UnsealRanges::Fixed),
+            "random" => Ok(UnsealRanges::Random),
+            "full" => Ok(UnsealRanges::Full),
+            "none" => Ok(UnsealRanges::Skip),
+            _ => bail!("unknown unseal ranges: {}", s),
+        }
+    }
+}
+
+/// Everything besides the phase outputs needed to pick a sector up again.
+#[derive(Serialize, Deserialize)]
+pub struct SectorMeta {
+    pub prover_id: ProverId,
+    pub sector_id: SectorId,
+    pub ticket: [u8; 32],
+    pub seed: [u8; 32],
+    pub piece_infos: Vec<PieceInfo>,
+    /// What the piece data was and the seed it is generated from, so unseal
+    /// checks can generate it again. Sectors sealed before it was recorded
+    /// kept their piece file instead.
+    #[serde(default)]
+    pub piece_source: Option<(PieceData, [u8; 16])>,
+    /// The epoch of the simulated chain PC2 finished in, which the seed is
+    /// drawn after.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub precommit_epoch: Option<u64>,
+}
+
+/// Parses an API version and pairs it with the PoRep id used for it.
+pub fn parse_api_version(value: &str) -> Result<(ApiVersion, [u8; 32])> {
+    match value.to_lowercase().trim_start_matches('v') {
+        "1.0.0" | "1.0" | "1_0" | "1_0_0" => Ok((ApiVersion::V1_0_0, ARBITRARY_POREP_ID_V1_0_0)),
+        "1.1.0" | "1.1" | "1_1" | "1_1_0" => Ok((ApiVersion::V1_1_0, ARBITRARY_POREP_ID_V1_1_0)),
+        _ => bail!("unsupported api version: {}", value),
+    }
+}
+
+/// Parses a PoRep ID given as 64 hex digits, optionally prefixed with `0x`.
+pub fn parse_porep_id(value: &str) -> Result<[u8; 32]> {
+    parse_id(value, "porep id")
+}
+
+/// Parses a prover ID like [`parse_porep_id`]. PoSt takes it as a field
+/// element, so it has to be one.
+pub fn parse_prover_id(value: &str) -> Result<ProverId> {
+    let prover_id = parse_id(value, "prover id")?;
+    bytes_into_fr(&prover_id)
+        .map_err(|_| anyhow!("prover id {:?} is not a field element", value))?;
+    Ok(prover_id)
+}
+
+fn parse_id(value: &str, name: &str) -> Result<[u8; 32]> {
+    let digits = value.trim_start_matches("0x");
+    if digits.len() != 64 || !digits.is_ascii() {
+        bail!("a {} is 32 bytes in 64 hex digits, got {:?}", name, value);
+    }
+    let mut id = [0u8; 32];
+    for (i, byte) in id.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&digits[2 * i..2 * i + 2], 16)
+            .map_err(|_| anyhow!("invalid {} {:?}", name, value))?;
+    }
+    Ok(id)
+}
+
+/// Formats a PoRep ID the way [`parse_porep_id`] reads it.
+pub fn porep_id_hex(porep_id: &[u8; 32]) -> String {
+    porep_id
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Parses the `--phase` selection, `None` meaning the whole lifecycle.
+pub fn parse_phase(value: &str) -> Result<Option<Phase>> {
+    let phase = match value.to_lowercase().as_str() {
+        "pc1" => Phase::PreCommit1,
+        "pc2" => Phase::PreCommit2,
+        "c1" => Phase::Commit1,
+        "c2" => Phase::Commit2,
+        "unseal" => Phase::Unseal,
+        "all" => return Ok(None),
+        _ => bail!("unknown phase: {}", value),
+    };
+    Ok(Some(phase))
+}
+
+/// Parses a human readable sector size such as `32KiB` or `64GiB`.
+pub fn parse_sector_size(value: &str) -> Result<u64> {
+    let sector_size = match value.to_lowercase().as_str() {
+        "2kib" => SECTOR_SIZE_2_KIB,
+        "4kib" => SECTOR_SIZE_4_KIB,
+        "16kib" => SECTOR_SIZE_16_KIB,
+        "32kib" => SECTOR_SIZE_32_KIB,
+        "8mib" => SECTOR_SIZE_8_MIB,
+        "16mib" => SECTOR_SIZE_16_MIB,
+        "512mib" => SECTOR_SIZE_512_MIB,
+        "1gib" => SECTOR_SIZE_1_GIB,
+        "32gib" => SECTOR_SIZE_32_GIB,
+        "64gib" => SECTOR_SIZE_64_GIB,
+        _ => bail!("unsupported sector size: {}", value),
+    };
+    Ok(sector_size)
+}
+
+/// Installs the default log output unless the binary already picked one.
+pub fn init_logger() {
+    let _ = logging::init(LogFormat::Pretty);
+}
+
+/// Writes the unpadded piece data of a whole sector, generated from
+/// `piece_seed` as it is written. Zeros, for a committed capacity sector,
+/// only cost a sparse file.
+pub(crate) fn generate_piece_file<S: ArtifactStore + ?Sized>(
+    store: &S,
+    path: &Path,
+    sector_size: u64,
+    piece_data: PieceData,
+    piece_seed: [u8; 16],
+) -> Result<File> {
+    let number_of_bytes_in_piece = UnpaddedBytesAmount::from(PaddedBytesAmount(sector_size));
+
+    let mut piece_file = store.create(path)?;
+    if piece_data == PieceData::Zeros {
+        piece_file.set_len(number_of_bytes_in_piece.into())?;
+    } else {
+        let mut reader = PieceReader::new(piece_data, piece_seed, number_of_bytes_in_piece.into());
+        let mut writer = BufWriter::with_capacity(PIECE_WRITE_BUFFER, &mut piece_file);
+        io::copy(&mut reader, &mut writer)?;
+        writer.flush()?;
+    }
+    piece_file.sync_all()?;
+    piece_file.seek(SeekFrom::Start(0))?;
+
+    Ok(piece_file)
+}
+
+/// Splits a sector into `count` pieces of halving size, largest first, so
+/// every piece is aligned without padding and together they fill the sector.
+pub fn piece_sizes(sector_size: u64, count: usize) -> Result<Vec<UnpaddedBytesAmount>> {
+    if count == 0 {
+        bail!("a sector needs at least one piece");
+    }
+    if count > 64 || sector_size >> (count - 1) < MIN_PIECE_SIZE {
+        bail!(
+            "{} pieces do not fit into a {} byte sector",
+            count,
+            sector_size
+        );
+    }
+
+    let mut sizes = (1..count).map(|i| sector_size >> i).collect::<Vec<_>>();
+    sizes.push(sector_size >> (count - 1));
+    Ok(sizes
+        .into_iter()
+        .map(|size| UnpaddedBytesAmount::from(PaddedBytesAmount(size)))
+        .collect())
+}
+
+pub fn porep_config(sector_size: u64, porep_id: [u8; 32], api_version: ApiVersion) -> PoRepConfig {
+    PoRepConfig {
+        sector_size: SectorSize(sector_size),
+        partitions: PoRepProofPartitions(
+            *POREP_PARTITIONS
+                .read()
+                .expect("POREP_PARTITIONS poisoned")
+                .get(&sector_size)
+                .expect("unknown sector size"),
+        ),
+        porep_id,
+        api_version,
+    }
+}
+
+/// A random valid prover id. The bytes are drawn directly rather than
+/// through `Fr::random`, whose RNG trait differs between proofs releases,
+/// and clearing the top two bits keeps them below the field modulus.
+pub fn random_prover_id<R: Rng>(rng: &mut R) -> ProverId {
+    let mut prover_id: ProverId = rng.gen();
+    prover_id[31] &= 0b0011_1111;
+    prover_id
+}
+
+/// Where a seal lifecycle starts and stops.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PhaseRange {
+    /// Outputs of the phases before this one are loaded from disk instead of
+    /// being computed again.
+    pub resume_from: Option<Phase>,
+    /// Return right after this phase; `None` runs through verification.
+    pub stop_after: Option<Phase>,
+}
+
+impl PhaseRange {
+    fn reuses(&self, phase: Phase) -> bool {
+        self.resume_from.map_or(false, |from| phase < from)
+    }
+
+    fn stops_after(&self, phase: Phase) -> bool {
+        self.stop_after == Some(phase)
+    }
+}
+
+/// Seals a sector in `files`, whose own files `store` creates,
+/// checkpointing the output of every phase so a later lifecycle can resume
+/// from it.
+#[allow(clippy::too_many_arguments)]
+pub fn seal_lifecycle<Tree: 'static + MerkleTreeTrait, S: ArtifactStore + ?Sized>(
+    store: &S,
+    rng: &mut XorShiftRng,
+    prover_id: ProverId,
+    files: &SectorFiles,
+    sector_size: u64,
+    porep_id: &[u8; 32],
+    api_version: ApiVersion,
+    pieces: usize,
+    piece_data: PieceData,
+    unseal_ranges: UnsealRanges,
+    range: PhaseRange,
+    keep_artifacts: bool,
+) -> Result<()> {
+    batch_seal_lifecycle::<Tree, S>(
+        store,
+        rng,
+        prover_id,
+        std::slice::from_ref(files),
+        sector_size,
+        porep_id,
+        api_version,
+        pieces,
+        piece_data,
+        unseal_ranges,
+        range,
+        keep_artifacts,
+    )
+}
+
+/// Seals a sector in each of `batch`, running PC1 and PC2 of all of them
+/// before committing any, the way pipelined sealing does. Their pre-commit
+/// outputs are held in memory in between.
+#[allow(clippy::too_many_arguments)]
+pub fn batch_seal_lifecycle<Tree: 'static + MerkleTreeTrait, S: ArtifactStore + ?Sized>(
+    store: &S,
+    rng: &mut XorShiftRng,
+    prover_id: ProverId,
+    batch: &[SectorFiles],
+    sector_size: u64,
+    porep_id: &[u8; 32],
+    api_version: ApiVersion,
+    pieces: usize,
+    piece_data: PieceData,
+    unseal_ranges: UnsealRanges,
+    range: PhaseRange,
+    keep_artifacts: bool,
+) -> Result<()> {
+    init_logger();
+
+    let config = porep_config(sector_size, *porep_id, api_version);
+    let mut pre_committed = Vec::with_capacity(batch.len());
+    for files in batch {
+        if let Some((meta, pre_commit_output)) = pre_commit::<Tree, S>(
+            store, config, rng, prover_id, files, pieces, piece_data, range,
+        )? {
+            pre_committed.push((files, meta, pre_commit_output));
+        }
+    }
+    for (files, meta, pre_commit_output) in pre_committed {
+        commit::<Tree, S>(
+            store,
+            config,
+            rng,
+            files,
+            meta,
+            pre_commit_output,
+            unseal_ranges,
+            range,
+            keep_artifacts,
+        )?;
+    }
+    Ok(())
+}
+
+/// Runs PC1 and PC2, returning what the commit phases need unless the
+/// lifecycle ends before them.
+#[allow(clippy::too_many_arguments)]
+fn pre_commit<Tree: 'static + MerkleTreeTrait, S: ArtifactStore + ?Sized>(
+    store: &S,
+    config: PoRepConfig,
+    rng: &mut XorShiftRng,
+    prover_id: ProverId,
+    files: &SectorFiles,
+    pieces: usize,
+    piece_data: PieceData,
+    range: PhaseRange,
+) -> Result<Option<(SectorMeta, SealPreCommitOutput)>> {
+    let sector_size = u64::from(config.sector_size);
+
+    // Missing checkpoints are recomputed, so a single phase can also be run
+    // against a fresh directory.
+    let stored = if range.reuses(Phase::PreCommit1) {
+        match (
+            files.load::<SectorMeta>(SECTOR_META)?,
+            files.load_versioned::<SealPreCommitPhase1Output<Tree>>(PC1_OUTPUT)?,
+        ) {
+            (Some(meta), Some(phase1_output)) => Some((meta, phase1_output)),
+            _ => None,
+        }
+    } else {
+        None
+    };
+    let reused_phase1 = stored.is_some();
+    let (mut meta, phase1_output) = match stored {
+        Some(stored) => stored,
+        None => {
+            let piece_seed = rng.gen();
+            let mut piece_file =
+                generate_piece_file(store, &files.piece(), sector_size, piece_data, piece_seed)?;
+            let ticket = rng.gen();
+            let seed = rng.gen();
+            let sector_id = rng.gen::<u64>().into();
+            let ticket = chain::ticket().unwrap_or(ticket);
+
+            let (piece_infos, phase1_output) = run_seal_pre_commit_phase1::<Tree, S>(
+                store,
+                config,
+                prover_id,
+                sector_id,
+                ticket,
+                files,
+                &mut piece_file,
+                &piece_sizes(sector_size, pieces)?,
+                piece_data,
+            )?;
+            // Unseal checks generate the data again, the file would only
+            // take up a sector's worth of disk.
+            drop(piece_file);
+            fs::remove_file(files.piece())?;
+            let meta = SectorMeta {
+                prover_id,
+                sector_id,
+                ticket,
+                seed,
+                piece_infos,
+                piece_source: Some((piece_data, piece_seed)),
+                precommit_epoch: None,
+            };
+            files.save(SECTOR_META, &meta)?;
+            files.save_versioned(PC1_OUTPUT, &phase1_output)?;
+            files.remove(PC2_OUTPUT)?;
+            files.remove(C1_OUTPUT)?;
+            files.remove(C2_OUTPUT)?;
+            (meta, phase1_output)
+        }
+    };
+    metrics::set_sector_id(meta.sector_id);
+    if range.stops_after(Phase::PreCommit1) {
+        return Ok(None);
+    }
+
+    let stored = if range.reuses(Phase::PreCommit2) {
+        files.load::<SealPreCommitOutput>(PC2_OUTPUT)?
+    } else {
+        None
+    };
+    let pre_commit_output = if let Some(pre_commit_output) = stored {
+        pre_commit_output
+    } else {
+        let damage = chaos::corrupt_cache(Phase::PreCommit2, &files.cache())?;
+        let validated =
+            validate_cache_for_precommit_phase2(files.cache(), files.staged(), &phase1_output);
+        if let Some(damage) = damage {
+            return expect_rejected(&damage, validated).map(|()| None);
+        }
+        validated.context(CacheInvalid)?;
+
+        // PC2 encodes the replica in place, so it needs the unsealed copy PC1
+        // would have left behind.
+        if reused_phase1 {
+            fs::copy(files.staged(), files.sealed())?;
+        }
+        // A sector resumed from its PC1 checkpoint, maybe one taken over,
+        // is not handed over again.
+        if !reused_phase1 && chaos::reassign_pc2() {
+            pipeline::hand_over(files, config)?;
+            chaos::Kill::Panic(Phase::PreCommit2).fire();
+        }
+        let pre_commit_output = metrics::time(Phase::PreCommit2, || {
+            let _stall = StallWatch::start(files.cache(), Phase::PreCommit2);
+            seal_pre_commit_phase2(config, phase1_output, files.cache(), files.sealed())
+        })?;
+        files.save(PC2_OUTPUT, &pre_commit_output)?;
+        files.remove(C1_OUTPUT)?;
+        files.remove(C2_OUTPUT)?;
+        if let Some(epoch) = chain::epoch() {
+            meta.precommit_epoch = Some(epoch);
+            files.save(SECTOR_META, &meta)?;
+        }
+        pre_commit_output
+    };
+    if range.stops_after(Phase::PreCommit2) {
+        return Ok(None);
+    }
+    Ok(Some((meta, pre_commit_output)))
+}
+
+/// Runs the phases after PC2 through verification.
+#[allow(clippy::too_many_arguments)]
+fn commit<Tree: 'static + MerkleTreeTrait, S: ArtifactStore + ?Sized>(
+    store: &S,
+    config: PoRepConfig,
+    rng: &mut XorShiftRng,
+    files: &SectorFiles,
+    mut meta: SectorMeta,
+    pre_commit_output: SealPreCommitOutput,
+    unseal_ranges: UnsealRanges,
+    range: PhaseRange,
+    keep_artifacts: bool,
+) -> Result<()> {
+    let comm_d = pre_commit_output.comm_d;
+    let comm_r = pre_commit_output.comm_r;
+
+    if range.stops_after(Phase::Unseal) {
+        if !keep_artifacts {
+            clear_cache::<Tree>(&files.cache())?;
+        }
+        return unseal_and_check::<Tree, S>(
+            store,
+            config,
+            files,
+            &meta,
+            comm_d,
+            unseal_ranges,
+            rng,
+        );
+    }
+
+    let stored = if range.reuses(Phase::Commit1) {
+        files.load_versioned::<SealCommitPhase1Output<Tree>>(C1_OUTPUT)?
+    } else {
+        None
+    };
+    let phase1_output = if let Some(phase1_output) = stored {
+        phase1_output
+    } else {
+        if let Some(epoch) = meta.precommit_epoch.or_else(chain::epoch) {
+            if let Some(seed) = chain::wait_for_seed(epoch) {
+                meta.seed = seed;
+                files.save(SECTOR_META, &meta)?;
+            }
+        }
+        let damage = chaos::corrupt_cache(Phase::Commit1, &files.cache())?;
+        let validated = validate_cache_for_commit::<_, _, Tree>(files.cache(), files.sealed());
+        if let Some(damage) = damage {
+            return expect_rejected(&damage, validated);
+        }
+        validated.context(CacheInvalid)?;
+        let phase1_output = metrics::time(Phase::Commit1, || {
+            seal_commit_phase1::<_, Tree>(
+                config,
+                files.cache(),
+                files.sealed(),
+                meta.prover_id,
+                meta.sector_id,
+                meta.ticket,
+                meta.seed,
+                pre_commit_output,
+                &meta.piece_infos,
+            )
+        })?;
+        files.save_versioned(C1_OUTPUT, &phase1_output)?;
+        files.remove(C2_OUTPUT)?;
+        phase1_output
+    };
+    if range.stops_after(Phase::Commit1) {
+        return Ok(());
+    }
+
+    // Kept artifacts must stay usable for re-running PC2 and C1.
+    if !keep_artifacts {
+        clear_cache::<Tree>(&files.cache())?;
+    }
+
+    let commit_output = metrics::time(Phase::Commit2, || {
+        seal_commit_phase2(config, phase1_output, meta.prover_id, meta.sector_id)
+    })?;
+    files.save(C2_OUTPUT, &commit_output)?;
+    crate::bundle::export(config, &meta, comm_r, comm_d, &commit_output.proof)?;
+    if range.stops_after(Phase::Commit2) {
+        return Ok(());
+    }
+
+    unseal_and_check::<Tree, S>(store, config, files, &meta, comm_d, unseal_ranges, rng)?;
+
+    let computed_comm_d = compute_comm_d(config.sector_size, &meta.piece_infos)?;
+
+    ensure!(
+        comm_d == computed_comm_d,
+        VerificationMismatch("computed and expected comm_d don't match".to_string())
+    );
+
+    let verified = metrics::time(Phase::Verify, || {
+        verify_seal::<Tree>(
+            config,
+            comm_r,
+            comm_d,
+            meta.prover_id,
+            meta.sector_id,
+            meta.ticket,
+            meta.seed,
+            &commit_output.proof,
+        )
+    })?;
+    ensure!(
+        verified,
+        VerificationMismatch("failed to verify valid seal".to_string())
+    );
+    Ok(())
+}
+
+/// Verifies the proof a finished seal lifecycle left in `files`.
+pub fn verify_lifecycle<Tree: 'static + MerkleTreeTrait>(
+    files: &SectorFiles,
+    sector_size: u64,
+    porep_id: &[u8; 32],
+    api_version: ApiVersion,
+) -> Result<()> {
+    init_logger();
+
+    let config = porep_config(sector_size, *porep_id, api_version);
+    let (meta, pre_commit_output, commit_output) = match (
+        files.load::<SectorMeta>(SECTOR_META)?,
+        files.load::<SealPreCommitOutput>(PC2_OUTPUT)?,
+        files.load::<SealCommitOutput>(C2_OUTPUT)?,
+    ) {
+        (Some(meta), Some(pre_commit_output), Some(commit_output)) => {
+            (meta, pre_commit_output, commit_output)
+        }
+        _ => bail!("no finished proof in {}", files.dir().display()),
+    };
+    metrics::set_sector_id(meta.sector_id);
+
+    let verified = metrics::time(Phase::Verify, || {
+        verify_seal::<Tree>(
+            config,
+            pre_commit_output.comm_r,
+            pre_commit_output.comm_d,
+            meta.prover_id,
+            meta.sector_id,
+            meta.ticket,
+            meta.seed,
+            &commit_output.proof,
+        )
+    })?;
+    ensure!(
+        verified,
+        VerificationMismatch("failed to verify stored seal proof".to_string())
+    );
+    Ok(())
+}
+
+/// Runs C2 on the C1 output another worker left in `files` and verifies
+/// the proof, as a remote C2 worker does.
+pub fn c2_lifecycle<Tree: 'static + MerkleTreeTrait>(
+    files: &SectorFiles,
+    config: PoRepConfig,
+) -> Result<()> {
+    init_logger();
+
+    let (meta, pre_commit_output, phase1_output) = match (
+        files.load::<SectorMeta>(SECTOR_META)?,
+        files.load::<SealPreCommitOutput>(PC2_OUTPUT)?,
+        files.load_versioned::<SealCommitPhase1Output<Tree>>(C1_OUTPUT)?,
+    ) {
+        (Some(meta), Some(pre_commit_output), Some(phase1_output)) => {
+            (meta, pre_commit_output, phase1_output)
+        }
+        _ => bail!("no C1 output in {}", files.dir().display()),
+    };
+    metrics::set_sector_id(meta.sector_id);
+
+    let commit_output = metrics::time(Phase::Commit2, || {
+        seal_commit_phase2(config, phase1_output, meta.prover_id, meta.sector_id)
+    })?;
+    crate::bundle::export(
+        config,
+        &meta,
+        pre_commit_output.comm_r,
+        pre_commit_output.comm_d,
+        &commit_output.proof,
+    )?;
+
+    let verified = metrics::time(Phase::Verify, || {
+        verify_seal::<Tree>(
+            config,
+            pre_commit_output.comm_r,
+            pre_commit_output.comm_d,
+            meta.prover_id,
+            meta.sector_id,
+            meta.ticket,
+            meta.seed,
+            &commit_output.proof,
+        )
+    })?;
+    ensure!(
+        verified,
+        VerificationMismatch("failed to verify handed off seal proof".to_string())
+    );
+    Ok(())
+}
+
+/// Checks that cache validation failed on a cache damaged by `--chaos`. The
+/// sector cannot be completed either way, so the lifecycle ends there.
+fn expect_rejected(damage: &str, validated: Result<()>) -> Result<()> {
+    match validated {
+        Ok(()) => bail!("cache validation accepted a cache with {}", damage),
+        Err(err) => {
+            info!("chaos: cache validation rejected {}: {:#}", damage, err);
+            Ok(())
+        }
+    }
+}
+
+/// Unseals the range selected by `ranges` and checks it against the piece
+/// data the sector was sealed with.
+pub fn unseal_and_check<Tree: 'static + MerkleTreeTrait, S: ArtifactStore + ?Sized>(
+    store: &S,
+    config: PoRepConfig,
+    files: &SectorFiles,
+    meta: &SectorMeta,
+    comm_d: Commitment,
+    ranges: UnsealRanges,
+    rng: &mut XorShiftRng,
+) -> Result<()> {
+    let sector_bytes = u64::from(UnpaddedBytesAmount::from(PaddedBytesAmount(
+        config.sector_size.into(),
+    )));
+    let (offset, length) = match ranges {
+        UnsealRanges::Fixed => (508, 508),
+        UnsealRanges::Full => (0, sector_bytes),
+        UnsealRanges::Random => {
+            let offset = rng.gen_range(0, sector_bytes);
+            (offset, rng.gen_range(1, sector_bytes - offset + 1))
+        }
+        UnsealRanges::Skip => return Ok(()),
+    };
+
+    let unseal_file = store.temp_file(files)?;
+    let _ = metrics::time(Phase::Unseal, || {
+        get_unsealed_range::<_, Tree>(
+            config,
+            files.cache(),
+            files.sealed(),
+            unseal_file.path(),
+            meta.prover_id,
+            meta.sector_id,
+            comm_d,
+            meta.ticket,
+            UnpaddedByteIndex(offset),
+            UnpaddedBytesAmount(length),
+        )
+    })?;
+
+    let (start, end) = (offset, offset + length);
+    ensure!(
+        unseal_file.as_file().metadata()?.len() == length,
+        VerificationMismatch(format!(
+            "unsealed length mismatch for range {}..{}",
+            start, end
+        ))
+    );
+
+    // Neither side is read into memory as a whole, a full unseal of a large
+    // sector would not fit: the unsealed output is mapped and the expected
+    // bytes are generated again from the range's first block on.
+    let expected: Box<dyn Read> = match meta.piece_source {
+        Some((piece_data, piece_seed)) => {
+            Box::new(PieceReader::at(piece_data, piece_seed, offset, length))
+        }
+        None => {
+            let mut piece_file = store.open(&files.piece())?;
+            piece_file.seek(SeekFrom::Start(offset))?;
+            Box::new(piece_file)
+        }
+    };
+    // Safety: the temporary file is only written by the unseal above, which
+    // has returned.
+    let actual = unsafe { Mmap::map(unseal_file.as_file())? };
+    if let Some((first, last)) = differences(expected, &actual)? {
+        // Corruption tends to cluster, so where it starts and ends says more
+        // than the range that was checked.
+        bail!(VerificationMismatch(format!(
+            "unsealed bytes mismatch for range {}..{}, first at {}, last at {}",
+            start,
+            end,
+            start + first,
+            start + last
+        )));
+    }
+    Ok(())
+}
+
+/// The first and last offset at which `expected` and `actual` differ,
+/// compared a chunk at a time.
+fn differences<E: Read>(mut expected: E, actual: &[u8]) -> io::Result<Option<(u64, u64)>> {
+    let mut expected_chunk = vec![0u8; COMPARE_CHUNK];
+    let mut found = None;
+    let mut pos = 0;
+    for actual_chunk in actual.chunks(COMPARE_CHUNK) {
+        let len = actual_chunk.len();
+        expected.read_exact(&mut expected_chunk[..len])?;
+        if expected_chunk[..len] != *actual_chunk {
+            for i in (0..len).filter(|i| expected_chunk[*i] != actual_chunk[*i]) {
+                let at = pos + i as u64;
+                found = Some(found.map_or((at, at), |(first, _)| (first, at)));
+            }
+        }
+        pos += len as u64;
+    }
+    Ok(found)
+}
+
+/// Adds the pieces of `piece_file` to a new staged sector at `staged`,
+/// returning their infos.
+pub(crate) fn add_pieces<S: ArtifactStore + ?Sized>(
+    store: &S,
+    staged: &Path,
+    piece_file: &mut File,
+    piece_sizes: &[UnpaddedBytesAmount],
+    piece_data: PieceData,
+) -> Result<Vec<PieceInfo>> {
+    let mut staged_sector_file = store.create(staged)?;
+
+    let mut piece_infos = Vec::with_capacity(piece_sizes.len());
+    let mut piece_lengths = Vec::with_capacity(piece_sizes.len());
+    for &piece_size in piece_sizes {
+        // Zero pieces have a well known commitment, which add_piece returns
+        // anyway, so CC sectors skip the separate commitment pass.
+        let expected = if piece_data == PieceData::Zeros {
+            None
+        } else {
+            let start = piece_file.seek(SeekFrom::Current(0))?;
+            let piece_info =
+                generate_piece_commitment((&mut *piece_file).take(piece_size.into()), piece_size)?;
+            piece_file.seek(SeekFrom::Start(start))?;
+            Some(piece_info)
+        };
+
+        let (piece_info, _) = metrics::time(Phase::AddPiece, || {
+            add_piece(
+                (&mut *piece_file).take(piece_size.into()),
+                &mut staged_sector_file,
+                piece_size,
+                &piece_lengths,
+            )
+        })?;
+        piece_lengths.push(piece_size);
+        piece_infos.push(expected.unwrap_or(piece_info));
+    }
+    Ok(piece_infos)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run_seal_pre_commit_phase1<Tree: 'static + MerkleTreeTrait, S: ArtifactStore + ?Sized>(
+    store: &S,
+    config: PoRepConfig,
+    prover_id: ProverId,
+    sector_id: SectorId,
+    ticket: [u8; 32],
+    files: &SectorFiles,
+    piece_file: &mut File,
+    piece_sizes: &[UnpaddedBytesAmount],
+    piece_data: PieceData,
+) -> Result<(Vec<PieceInfo>, SealPreCommitPhase1Output<Tree>)> {
+    let piece_infos = add_pieces(store, &files.staged(), piece_file, piece_sizes, piece_data)?;
+    store.create(&files.sealed())?;
+
+    let sector_size = u64::from(config.sector_size);
+    let phase1_output = metrics::time(Phase::PreCommit1, || {
+        let _layers = LayerWatch::start(files.cache(), sector_size);
+        let _stall = StallWatch::start(files.cache(), Phase::PreCommit1);
+        seal_pre_commit_phase1::<_, _, _, Tree>(
+            config,
+            files.cache(),
+            files.staged(),
+            files.sealed(),
+            prover_id,
+            sector_id,
+            ticket,
+            &piece_infos,
+        )
+    })?;
+
+    Ok((piece_infos, phase1_output))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID_HEX: &str = "0102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f20";
+
+    #[test]
+    fn porep_id_parses() {
+        let mut id = [0u8; 32];
+        for (i, byte) in id.iter_mut().enumerate() {
+            *byte = i as u8 + 1;
+        }
+        assert_eq!(parse_porep_id(ID_HEX).unwrap(), id);
+        assert_eq!(parse_porep_id(&format!("0x{}", ID_HEX)).unwrap(), id);
+        assert_eq!(porep_id_hex(&id), ID_HEX);
+    }
+
+    #[test]
+    fn bad_porep_ids_are_rejected() {
+        assert!(parse_porep_id(&ID_HEX[2..]).is_err());
+        assert!(parse_porep_id(&format!("{}00", ID_HEX)).is_err());
+        assert!(parse_porep_id(&ID_HEX.replace('a', "g")).is_err());
+        // 64 bytes, but not 64 ASCII hex digits.
+        assert!(parse_porep_id(&format!("{}é", &ID_HEX[2..])).is_err());
+    }
+
+    #[test]
+    fn api_versions_parse() {
+        for value in &["1.0.0", "v1.0", "1_0", "V1_0_0"] {
+            assert_eq!(
+                parse_api_version(value).unwrap(),
+                (ApiVersion::V1_0_0, ARBITRARY_POREP_ID_V1_0_0)
+            );
+        }
+        for value in &["1.1.0", "v1.1", "1_1", "V1_1_0"] {
+            assert_eq!(
+                parse_api_version(value).unwrap(),
+                (ApiVersion::V1_1_0, ARBITRARY_POREP_ID_V1_1_0)
+            );
+        }
+        assert!(parse_api_version("1.2.0").is_err());
+        assert!(parse_api_version("").is_err());
+    }
+}
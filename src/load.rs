@@ -0,0 +1,129 @@
+//! Background load next to the workers.
+//!
+//! The hang shows up more often on busy hosts. `--background-cpu N` keeps N
+//! threads spinning and `--background-io <MB/s>` keeps rewriting a file in
+//! the run directory at that rate, syncing every chunk so the writes reach
+//! the disk the sectors are on. Both run until the workers are done.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use tracing::{info, warn};
+
+/// Name of the file the I/O load rewrites.
+const IO_LOAD_FILE: &str = "background-io.dat";
+/// The I/O load writes and syncs chunks of this size.
+const IO_CHUNK: usize = 1 << 20;
+/// The I/O load file wraps around at this size.
+const IO_FILE_SIZE: u64 = 256 << 20;
+
+/// Keeps the spinning threads' arithmetic from being optimized away.
+static CPU_SINK: AtomicU64 = AtomicU64::new(0);
+
+/// The running load threads; dropping them stops them.
+pub struct BackgroundLoad {
+    stop: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<()>>,
+    io_file: Option<PathBuf>,
+}
+
+impl BackgroundLoad {
+    /// Starts `cpu_threads` spinning threads and, unless `io_mb_per_sec` is
+    /// zero, a writer doing that many MB/s under `dir`.
+    pub fn start(cpu_threads: usize, io_mb_per_sec: u64, dir: &Path) -> Result<Self> {
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut threads = Vec::with_capacity(cpu_threads + 1);
+        for i in 0..cpu_threads {
+            let stop = stop.clone();
+            threads.push(
+                thread::Builder::new()
+                    .name(format!("background-cpu-{}", i))
+                    .spawn(move || burn_cpu(&stop))?,
+            );
+        }
+        let io_file =
+            if io_mb_per_sec > 0 {
+                let path = dir.join(IO_LOAD_FILE);
+                let file = OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .truncate(true)
+                    .open(&path)?;
+                let stop = stop.clone();
+                threads.push(thread::Builder::new().name("background-io".into()).spawn(
+                    move || {
+                        if let Err(err) = burn_io(file, io_mb_per_sec, &stop) {
+                            warn!("background io load stopped: {:#}", err);
+                        }
+                    },
+                )?);
+                Some(path)
+            } else {
+                None
+            };
+        info!(
+            "Background load: {} spinning threads, {} MB/s of writes",
+            cpu_threads, io_mb_per_sec
+        );
+        Ok(BackgroundLoad {
+            stop,
+            threads,
+            io_file,
+        })
+    }
+}
+
+impl Drop for BackgroundLoad {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+        if let Some(path) = &self.io_file {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+fn burn_cpu(stop: &AtomicBool) {
+    let mut x = 1u64;
+    while !stop.load(Ordering::Relaxed) {
+        for _ in 0..1_000_000 {
+            x = x.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+        }
+        CPU_SINK.store(x, Ordering::Relaxed);
+    }
+}
+
+/// Writes `mb_per_sec` MB/s to `file` a synced chunk at a time, sleeping
+/// whenever it is ahead of that rate.
+fn burn_io(mut file: File, mb_per_sec: u64, stop: &AtomicBool) -> Result<()> {
+    let chunk = (0..IO_CHUNK).map(|i| i as u8).collect::<Vec<_>>();
+    let bytes_per_sec = mb_per_sec * 1_000_000;
+    let started = Instant::now();
+    let mut written = 0u64;
+    let mut offset = 0u64;
+    while !stop.load(Ordering::Relaxed) {
+        let due = Duration::from_secs_f64(written as f64 / bytes_per_sec as f64);
+        let elapsed = started.elapsed();
+        if due > elapsed {
+            thread::sleep((due - elapsed).min(Duration::from_millis(100)));
+            continue;
+        }
+        if offset >= IO_FILE_SIZE {
+            offset = 0;
+            file.seek(SeekFrom::Start(0))?;
+        }
+        file.write_all(&chunk)?;
+        file.sync_data()?;
+        offset += IO_CHUNK as u64;
+        written += IO_CHUNK as u64;
+    }
+    Ok(())
+}
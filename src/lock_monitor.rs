@@ -0,0 +1,237 @@
+//! Watches the bellperson GPU lock across processes.
+//!
+//! bellperson serializes GPU work with a `flock` on a file in the temporary
+//! directory, which the kernel lists in `/proc/locks` together with the pid
+//! holding it and the pids blocked on it. Polling that list from the parent
+//! shows the order in which process workers got the lock and how long each
+//! waited, which the in-process sampler of [`crate::gpu_lock`] cannot see
+//! across processes. Workers are told apart by their `--worker-index`.
+
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::process::WORKER_INDEX_ARG;
+
+/// Name of bellperson's GPU lock file in the temporary directory.
+pub const GPU_LOCK_FILE: &str = "bellman.gpu.lock";
+
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// One time a process got the lock; times are relative to the monitor start.
+#[derive(Clone, Debug)]
+pub struct Acquisition {
+    pub pid: u32,
+    pub worker: Option<usize>,
+    /// When the process was first seen blocked on the lock, or when it got
+    /// it if it never had to wait.
+    pub requested: Duration,
+    pub acquired: Duration,
+    pub released: Option<Duration>,
+}
+
+impl Acquisition {
+    pub fn wait(&self) -> Duration {
+        self.acquired - self.requested
+    }
+}
+
+/// Polls `/proc/locks` until stopped.
+pub struct LockMonitor {
+    stop: Sender<()>,
+    handle: JoinHandle<Vec<Acquisition>>,
+}
+
+impl LockMonitor {
+    pub fn start() -> Self {
+        let path = std::env::temp_dir().join(GPU_LOCK_FILE);
+        let (stop, rx) = mpsc::channel();
+        let handle = thread::Builder::new()
+            .name("lock-monitor".into())
+            .spawn(move || {
+                let started = Instant::now();
+                let mut state = State::default();
+                loop {
+                    state.poll(&path, started.elapsed());
+                    match rx.recv_timeout(POLL_INTERVAL) {
+                        Err(RecvTimeoutError::Timeout) => continue,
+                        _ => return state.acquisitions,
+                    }
+                }
+            })
+            .expect("failed to spawn lock monitor");
+        LockMonitor { stop, handle }
+    }
+
+    /// Stops polling and returns the acquisitions in the order they happened.
+    pub fn stop(self) -> Vec<Acquisition> {
+        let _ = self.stop.send(());
+        self.handle.join().expect("lock monitor panicked")
+    }
+}
+
+#[derive(Default)]
+struct State {
+    /// When each process currently blocked on the lock was first seen there.
+    waiting: HashMap<u32, Duration>,
+    holder: Option<u32>,
+    workers: HashMap<u32, Option<usize>>,
+    acquisitions: Vec<Acquisition>,
+}
+
+impl State {
+    fn poll(&mut self, path: &Path, now: Duration) {
+        let inode = match fs::metadata(path) {
+            Ok(meta) => meta.ino(),
+            Err(_) => return,
+        };
+        let locks = match fs::read_to_string("/proc/locks") {
+            Ok(locks) => locks,
+            Err(_) => return,
+        };
+
+        let mut holder = None;
+        let mut waiters = Vec::new();
+        for line in locks.lines() {
+            match parse_lock(line) {
+                Some((pid, lock_inode, blocked)) if lock_inode == inode => {
+                    if blocked {
+                        waiters.push(pid);
+                    } else {
+                        holder = Some(pid);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        for pid in &waiters {
+            self.waiting.entry(*pid).or_insert(now);
+        }
+        if holder == self.holder {
+            return;
+        }
+        if let Some(acquisition) = self.acquisitions.last_mut() {
+            if acquisition.released.is_none() {
+                acquisition.released = Some(now);
+            }
+        }
+        if let Some(pid) = holder {
+            let worker = *self.workers.entry(pid).or_insert_with(|| worker_index(pid));
+            self.acquisitions.push(Acquisition {
+                pid,
+                worker,
+                requested: self.waiting.remove(&pid).unwrap_or(now),
+                acquired: now,
+                released: None,
+            });
+        }
+        self.holder = holder;
+    }
+}
+
+/// Parses a `/proc/locks` line such as
+/// `1: -> FLOCK  ADVISORY  WRITE 4242 08:01:1234 0 EOF` into the pid, the
+/// inode and whether the process is blocked rather than holding the lock.
+fn parse_lock(line: &str) -> Option<(u32, u64, bool)> {
+    let mut fields = line.split_whitespace().skip(1).peekable();
+    let blocked = fields.peek() == Some(&"->");
+    if blocked {
+        fields.next();
+    }
+    if fields.next()? != "FLOCK" {
+        return None;
+    }
+    let mut fields = fields.skip(2);
+    let pid = fields.next()?.parse().ok()?;
+    let inode = fields.next()?.rsplit(':').next()?.parse().ok()?;
+    Some((pid, inode, blocked))
+}
+
+/// The `--worker-index` process `pid` was started with.
+fn worker_index(pid: u32) -> Option<usize> {
+    let cmdline = fs::read(format!("/proc/{}/cmdline", pid)).ok()?;
+    let prefix = format!("--{}=", WORKER_INDEX_ARG);
+    cmdline
+        .split(|byte| *byte == 0)
+        .filter_map(|arg| std::str::from_utf8(arg).ok())
+        .find_map(|arg| arg.strip_prefix(prefix.as_str())?.parse().ok())
+}
+
+/// Prints the acquisitions in order, followed by how fairly the lock was
+/// handed out: how often a later request was served before an earlier one,
+/// and Jain's fairness index of the total wait per process, 1 meaning every
+/// process waited equally long.
+pub fn print_report(acquisitions: &[Acquisition]) {
+    if acquisitions.is_empty() {
+        println!("No process took the GPU lock");
+        return;
+    }
+
+    println!();
+    println!(
+        "{:>4} {:<10} {:>8} {:>14} {:>14} {:>10} {:>10}",
+        "#", "worker", "pid", "requested (s)", "acquired (s)", "wait (s)", "hold (s)"
+    );
+    for (i, acquisition) in acquisitions.iter().enumerate() {
+        let hold = match acquisition.released {
+            Some(released) => format!("{:.3}", (released - acquisition.acquired).as_secs_f64()),
+            None => "-".to_string(),
+        };
+        println!(
+            "{:>4} {:<10} {:>8} {:>14.3} {:>14.3} {:>10.3} {:>10}",
+            i + 1,
+            acquisition
+                .worker
+                .map_or("?".to_string(), |worker| worker.to_string()),
+            acquisition.pid,
+            acquisition.requested.as_secs_f64(),
+            acquisition.acquired.as_secs_f64(),
+            acquisition.wait().as_secs_f64(),
+            hold
+        );
+    }
+
+    let mut inversions = 0;
+    for (i, earlier) in acquisitions.iter().enumerate() {
+        inversions += acquisitions[i + 1..]
+            .iter()
+            .filter(|later| later.requested < earlier.requested)
+            .count();
+    }
+
+    let mut waits: HashMap<u32, f64> = HashMap::new();
+    for acquisition in acquisitions {
+        *waits.entry(acquisition.pid).or_default() += acquisition.wait().as_secs_f64();
+    }
+    let sum: f64 = waits.values().sum();
+    let squares: f64 = waits.values().map(|wait| wait * wait).sum();
+    let fairness = if squares == 0.0 {
+        1.0
+    } else {
+        sum * sum / (waits.len() as f64 * squares)
+    };
+    let max_wait = acquisitions
+        .iter()
+        .map(Acquisition::wait)
+        .max()
+        .unwrap_or_default();
+
+    println!();
+    println!(
+        "{} acquisitions by {} processes, {} served out of request order",
+        acquisitions.len(),
+        waits.len(),
+        inversions
+    );
+    println!(
+        "wait: mean {:.3}s, max {:.3}s, Jain's fairness index over processes {:.3}",
+        sum / acquisitions.len() as f64,
+        max_wait.as_secs_f64(),
+        fairness
+    );
+}
@@ -0,0 +1,220 @@
+//! Lock order tracking for the harness's own synchronization.
+//!
+//! The pipeline's slots and the mutexes behind its queues, the phase barrier,
+//! the overlap gate and schedule replay are taken through this module, which
+//! remembers which of them each thread holds. Taking one while holding
+//! another records that order; once two of them were taken in both orders,
+//! directly or over others, two threads can deadlock on them. The cycle is
+//! logged as a potential deadlock right away, shown in the watchdog's dump and
+//! listed in the report, so a wedge in the harness itself does not pass for
+//! one in the proofs library.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Condvar, Mutex, MutexGuard, WaitTimeoutResult};
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use tracing::error;
+
+type Name = Cow<'static, str>;
+
+#[derive(Default)]
+struct Order {
+    /// Every lock taken while holding another, by the one held.
+    after: BTreeMap<Name, BTreeSet<Name>>,
+    /// Cycles found so far, each starting at its smallest name.
+    cycles: BTreeSet<Vec<Name>>,
+}
+
+lazy_static! {
+    static ref ORDER: Mutex<Order> = Mutex::new(Order::default());
+}
+
+thread_local! {
+    /// What the current thread holds, in the order it took them.
+    static HELD: RefCell<Vec<Name>> = RefCell::new(Vec::new());
+}
+
+/// Something the current thread holds, released on drop.
+pub(crate) struct Held(Name);
+
+/// Records that the current thread takes `name` now, reporting a potential
+/// deadlock if that completes a cycle.
+pub(crate) fn acquire(name: impl Into<Name>) -> Held {
+    let name = name.into();
+    let held = HELD.with(|held| held.borrow().clone());
+    if !held.is_empty() {
+        let mut order = ORDER.lock().expect("lock order poisoned");
+        for before in held.iter().filter(|before| **before != name) {
+            let new = order
+                .after
+                .entry(before.clone())
+                .or_default()
+                .insert(name.clone());
+            if !new {
+                continue;
+            }
+            if let Some(mut path) = order.path(&name, before) {
+                // Rotated to a canonical start, so a cycle is reported once.
+                let start = (0..path.len()).min_by_key(|&i| &path[i]).unwrap_or(0);
+                path.rotate_left(start);
+                if order.cycles.insert(path.clone()) {
+                    error!(
+                        "potential deadlock: locks taken in a cycle, {}",
+                        describe_cycle(&path)
+                    );
+                }
+            }
+        }
+    }
+    HELD.with(|held| held.borrow_mut().push(name.clone()));
+    Held(name)
+}
+
+impl Drop for Held {
+    fn drop(&mut self) {
+        HELD.with(|held| {
+            let mut held = held.borrow_mut();
+            if let Some(i) = held.iter().rposition(|name| *name == self.0) {
+                held.remove(i);
+            }
+        });
+    }
+}
+
+impl Order {
+    /// The locks taken one after the other from `from` to `to`, if they
+    /// ever were, starting with `from`.
+    fn path(&self, from: &Name, to: &Name) -> Option<Vec<Name>> {
+        let mut stack = vec![vec![from.clone()]];
+        let mut seen = BTreeSet::new();
+        while let Some(path) = stack.pop() {
+            let last = path.last().expect("paths are never empty");
+            if last == to {
+                return Some(path);
+            }
+            if !seen.insert(last.clone()) {
+                continue;
+            }
+            for next in self.after.get(last).into_iter().flatten() {
+                let mut path = path.clone();
+                path.push(next.clone());
+                stack.push(path);
+            }
+        }
+        None
+    }
+}
+
+fn describe_cycle(cycle: &[Name]) -> String {
+    let mut names = cycle.iter().map(|name| name.as_ref()).collect::<Vec<_>>();
+    names.extend(cycle.first().map(|name| name.as_ref()));
+    names.join(" -> ")
+}
+
+/// A mutex whose locking is tracked.
+pub(crate) struct OrderedMutex<T> {
+    name: &'static str,
+    inner: Mutex<T>,
+}
+
+/// The guard of an [`OrderedMutex`].
+pub(crate) struct OrderedGuard<'a, T> {
+    guard: MutexGuard<'a, T>,
+    held: Held,
+}
+
+impl<T> OrderedMutex<T> {
+    pub(crate) fn new(name: &'static str, value: T) -> Self {
+        OrderedMutex {
+            name,
+            inner: Mutex::new(value),
+        }
+    }
+
+    pub(crate) fn lock(&self) -> OrderedGuard<'_, T> {
+        let held = acquire(self.name);
+        let guard = self
+            .inner
+            .lock()
+            .unwrap_or_else(|_| panic!("{} poisoned", self.name));
+        OrderedGuard { guard, held }
+    }
+}
+
+impl<'a, T> OrderedGuard<'a, T> {
+    /// Waits on `condvar`, not holding the lock meanwhile.
+    pub(crate) fn wait(self, condvar: &Condvar) -> Self {
+        let OrderedGuard { guard, held } = self;
+        let name = held.0.clone();
+        drop(held);
+        let guard = condvar
+            .wait(guard)
+            .unwrap_or_else(|_| panic!("{} poisoned", name));
+        OrderedGuard {
+            guard,
+            held: acquire(name),
+        }
+    }
+
+    /// Waits on `condvar` for up to `timeout`, not holding the lock
+    /// meanwhile.
+    pub(crate) fn wait_timeout(
+        self,
+        condvar: &Condvar,
+        timeout: Duration,
+    ) -> (Self, WaitTimeoutResult) {
+        let OrderedGuard { guard, held } = self;
+        let name = held.0.clone();
+        drop(held);
+        let (guard, result) = condvar
+            .wait_timeout(guard, timeout)
+            .unwrap_or_else(|_| panic!("{} poisoned", name));
+        let guard = OrderedGuard {
+            guard,
+            held: acquire(name),
+        };
+        (guard, result)
+    }
+}
+
+impl<T> Deref for OrderedGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for OrderedGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+/// The cycles found so far, for the watchdog's dump.
+pub(crate) fn describe() -> String {
+    let order = ORDER.lock().expect("lock order poisoned");
+    let mut lines = String::new();
+    for cycle in &order.cycles {
+        let _ = writeln!(lines, "lock order cycle: {}", describe_cycle(cycle));
+    }
+    lines
+}
+
+/// Prints every cycle found, if any.
+pub fn print_report() {
+    let order = ORDER.lock().expect("lock order poisoned");
+    if order.cycles.is_empty() {
+        return;
+    }
+    println!();
+    println!("Potential deadlocks, locks of the harness taken in a cycle:");
+    for cycle in &order.cycles {
+        println!("  {}", describe_cycle(cycle));
+    }
+}
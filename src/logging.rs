@@ -0,0 +1,191 @@
+//! Log output through `tracing`.
+//!
+//! Workers run inside `worker` and `iteration` spans and every timed phase
+//! inside a `phase` span, so each event says which thread, lifecycle and
+//! phase it came from. Records of the `log` crate, which the proofs library
+//! logs through, are forwarded and pick up the same spans. `RUST_LOG` picks
+//! the level as it did for fil_logger, defaulting to `info`.
+//!
+//! With [`enable_worker_logs`] every worker's lines also go to a file of its
+//! own, without colors. A thread worker's file only gets the events of its
+//! own thread, those of the rayon threads it hands work to stay on stdout; a
+//! process worker's gets everything its process logs.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, Once};
+
+use anyhow::{anyhow, bail, Context, Result};
+use lazy_static::lazy_static;
+use tracing_subscriber::EnvFilter;
+
+/// How many of the latest log lines [`keep_recent`] keeps.
+const RECENT_LEN: usize = 10_000;
+
+lazy_static! {
+    static ref RECENT: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+    static ref WORKER_LOG_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+    static ref PROCESS_LOG: Mutex<Option<File>> = Mutex::new(None);
+}
+
+thread_local! {
+    static THREAD_LOG: RefCell<Option<File>> = RefCell::new(None);
+}
+
+static KEEP_RECENT: AtomicBool = AtomicBool::new(false);
+
+/// How log events are written to stdout.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    /// One human readable line per event, prefixed with its spans.
+    Pretty,
+    /// One JSON object per event, with the fields of all its spans.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "pretty" => Ok(LogFormat::Pretty),
+            "json" => Ok(LogFormat::Json),
+            _ => bail!("unknown log format: {}", s),
+        }
+    }
+}
+
+static INIT: Once = Once::new();
+
+/// Installs the global subscriber; only the first call has an effect.
+pub fn init(format: LogFormat) -> Result<()> {
+    let mut res = Ok(());
+    INIT.call_once(|| {
+        let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+        let builder = tracing_subscriber::fmt()
+            .with_env_filter(filter)
+            .with_writer(|| Recorder);
+        res = match format {
+            LogFormat::Pretty => builder.try_init(),
+            LogFormat::Json => builder
+                .json()
+                .with_current_span(true)
+                .with_span_list(true)
+                .try_init(),
+        }
+        .map_err(|err| anyhow!("failed to install the log subscriber: {}", err));
+    });
+    res
+}
+
+/// Keeps the latest log lines in memory from now on, for a postmortem.
+pub fn keep_recent() {
+    KEEP_RECENT.store(true, Ordering::SeqCst);
+}
+
+/// The log lines kept since [`keep_recent`], oldest first.
+pub(crate) fn recent() -> Vec<String> {
+    RECENT
+        .lock()
+        .expect("recent logs poisoned")
+        .iter()
+        .cloned()
+        .collect()
+}
+
+/// Also writes the log lines of every worker to `worker-<index>.log` under
+/// `dir` from now on.
+pub fn enable_worker_logs(dir: PathBuf) -> Result<()> {
+    fs::create_dir_all(&dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    *WORKER_LOG_DIR.lock().expect("worker log dir poisoned") = Some(dir);
+    Ok(())
+}
+
+/// Sends the log lines of the calling thread, or with `whole_process` those
+/// of every thread, to the file of worker `index` as well, if worker logs
+/// are enabled. Restarted workers append to the file of their predecessor.
+pub(crate) fn open_worker_log(index: usize, whole_process: bool) -> Result<()> {
+    let dir = match WORKER_LOG_DIR
+        .lock()
+        .expect("worker log dir poisoned")
+        .clone()
+    {
+        Some(dir) => dir,
+        None => return Ok(()),
+    };
+    let path = dir.join(format!("worker-{}.log", index));
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("failed to open {}", path.display()))?;
+    if whole_process {
+        *PROCESS_LOG.lock().expect("process log poisoned") = Some(file);
+    } else {
+        THREAD_LOG.with(|log| *log.borrow_mut() = Some(file));
+    }
+    Ok(())
+}
+
+/// Appends `line` to the worker log of the calling thread or process, if
+/// there is one.
+fn write_worker_log(line: &[u8]) {
+    let line = strip_colors(line);
+    let written = THREAD_LOG
+        .try_with(|log| match log.borrow_mut().as_mut() {
+            Some(file) => {
+                let _ = file.write_all(&line);
+                true
+            }
+            None => false,
+        })
+        .unwrap_or(false);
+    if !written {
+        if let Some(file) = PROCESS_LOG.lock().expect("process log poisoned").as_mut() {
+            let _ = file.write_all(&line);
+        }
+    }
+}
+
+/// `line` without the ANSI escape sequences of the pretty format.
+fn strip_colors(line: &[u8]) -> Vec<u8> {
+    let mut stripped = Vec::with_capacity(line.len());
+    let mut bytes = line.iter();
+    while let Some(&byte) = bytes.next() {
+        if byte == 0x1b {
+            // A CSI sequence ends with its first letter.
+            bytes.by_ref().find(|byte| byte.is_ascii_alphabetic());
+        } else {
+            stripped.push(byte);
+        }
+    }
+    stripped
+}
+
+/// Writes to stdout and the worker log, and keeps what it wrote if asked to.
+/// Every event is formatted into a single write.
+struct Recorder;
+
+impl Write for Recorder {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = io::stdout().write(buf)?;
+        write_worker_log(&buf[..written]);
+        if KEEP_RECENT.load(Ordering::SeqCst) {
+            let mut recent = RECENT.lock().expect("recent logs poisoned");
+            if recent.len() == RECENT_LEN {
+                recent.pop_front();
+            }
+            recent.push_back(String::from_utf8_lossy(&buf[..written]).into_owned());
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+}
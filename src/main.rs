@@ -0,0 +1,1597 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use clap::{App, Arg, SubCommand};
+use tracing::{error, info, warn};
+
+use test_hang::artifacts::{self, RemoteStorage};
+use test_hang::cgroup::{WorkerCgroups, WorkerLimits};
+use test_hang::chaos::{self, KillMode};
+use test_hang::config::{resolve, Config, Settings, SweepConfig, WorkerSettings};
+use test_hang::environment::Environment;
+use test_hang::handoff::C2Queue;
+use test_hang::lifecycle::parse_porep_id;
+use test_hang::load::BackgroundLoad;
+use test_hang::lock_monitor::{self, LockMonitor};
+use test_hang::logging::{self, LogFormat};
+use test_hang::metrics;
+use test_hang::outcome::{Classification, REGRESSION_EXIT_CODE};
+use test_hang::overlap;
+use test_hang::params;
+use test_hang::piece_gen::PieceData;
+use test_hang::pipeline;
+use test_hang::process::{self, ProcessWorkers, FIRST_LIFECYCLE_ARG, WORKER_INDEX_ARG};
+use test_hang::storage::{DelayedDisk, IoLimit, Tmpfs};
+use test_hang::sweep::{self, Cell, Sweep, SWEEP_ARG};
+use test_hang::workloads::{
+    prepare_sectors, run_groups, ApiVersionMix, RunOutcome, WorkerConfig, WorkerMode, Workload,
+};
+use test_hang::{
+    audit, barrier, bundle, canary, chain, compare, daemon, exporter, fds, fuzz, heartbeat,
+    lock_order, plan, platform, postmortem, profile, schedule, shutdown, soak, stall, timeline,
+    trace,
+};
+
+const LOG_FORMAT_DEFAULT: &str = "pretty";
+const LISTEN_DEFAULT: &str = "127.0.0.1:8700";
+const SLOTS_DEFAULT: &str = "1";
+const COMPARE_THRESHOLD_DEFAULT: &str = "10";
+
+fn main() -> Result<()> {
+    let classification = run()?;
+    if classification != Classification::Success {
+        std::process::exit(classification.exit_code());
+    }
+    Ok(())
+}
+
+fn app() -> App<'static, 'static> {
+    App::new("test")
+        .arg(
+            Arg::with_name("config")
+                .short("c")
+                .long("config")
+                .value_name("file")
+                .help("TOML file providing defaults for any of the other options")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("num-threads")
+                .short("t")
+                .long("num-threads")
+                .value_name("num of threads")
+                .help("The number of threads to use - default: 1")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("sector-size")
+                .short("s")
+                .long("sector-size")
+                .value_name("sector size")
+                .help("The sector size to seal (2KiB, 4KiB, 16KiB, 32KiB, 8MiB, 16MiB, 512MiB, 1GiB, 32GiB, 64GiB) - default: 32KiB")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("fast")
+                .long("fast")
+                .help("Seal 2KiB sectors, so a full lifecycle including proofs takes seconds")
+                .conflicts_with("sector-size")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("phase")
+                .short("p")
+                .long("phase")
+                .value_name("phase")
+                .help("Run only this seal phase (pc1, pc2, c1, c2, unseal, all) - default: all")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("workload")
+                .short("w")
+                .long("workload")
+                .value_name("workload")
+                .help("The workload each thread runs (seal, window-post, winning-post, aggregate, verify, unseal, c2, snap) - default: seal")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("post-sectors")
+                .long("post-sectors")
+                .value_name("num of sectors")
+                .help("The number of sectors sealed up front for PoSt workloads - default: 2")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("aggregate-sectors")
+                .long("aggregate-sectors")
+                .value_name("num of sectors")
+                .help("The number of sectors each aggregate proof covers - default: 4")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("pieces")
+                .long("pieces")
+                .value_name("num of pieces")
+                .help("The number of pieces each sealed sector is filled with - default: 1")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("sectors-per-worker")
+                .long("sectors-per-worker")
+                .value_name("num of sectors")
+                .help("The number of sectors each seal lifecycle takes through PC2 before committing any, like pipelined sealing - default: 1")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("cc")
+                .long("cc")
+                .help("Seal committed capacity sectors filled with zeros instead of random data, same as --piece-data zeros")
+                .conflicts_with("piece-data")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("env-preset")
+                .long("env-preset")
+                .value_name("preset")
+                .help("Set the proofs' environment variables for a known setup: gpu-c2 (only C2 on the GPU), gpu-all, cpu-only or multicore-sdr")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("piece-data")
+                .long("piece-data")
+                .value_name("data")
+                .help("What sealed sectors are filled with, generated from --seed (random, zeros, pattern, compressible) - default: random")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("skip-proofs")
+                .long("skip-proofs")
+                .help("Only run PC1 and PC2 and clear the cache, which needs no Groth parameters")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("unseal-ranges")
+                .long("unseal-ranges")
+                .value_name("ranges")
+                .help("Which ranges to unseal and check (fixed, random, full, none) - default: fixed")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("verify-full-unseal")
+                .long("verify-full-unseal")
+                .help("Unseal the whole sector and compare it byte for byte with the piece data, same as --unseal-ranges full")
+                .conflicts_with("unseal-ranges")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("seed")
+                .long("seed")
+                .value_name("seed")
+                .help("Seed every thread and iteration derives its inputs from - default: random")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("api-versions")
+                .long("api-versions")
+                .value_name("versions")
+                .help("Comma separated API versions each thread cycles through - default: 1.1.0,1.0.0")
+                .required(false)
+                .use_delimiter(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("porep-id")
+                .long("porep-id")
+                .value_name("hex")
+                .help("PoRep ID as 64 hex digits, used for every API version instead of the built-in arbitrary ones")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("prover-id")
+                .long("prover-id")
+                .value_name("hex")
+                .help("Prover ID as 64 hex digits every sector is sealed for, instead of a random one per lifecycle")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("provers")
+                .long("provers")
+                .value_name("num of provers")
+                .help("Split the workers among this many prover IDs derived from --seed, the first being --prover-id if given")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("random-porep-id")
+                .long("random-porep-id")
+                .help("Use a PoRep ID per API version derived from --seed")
+                .conflicts_with("porep-id")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("api-version-mix")
+                .long("api-version-mix")
+                .value_name("mix")
+                .help("Run every API version in each thread, or spread them over the threads so versions only mix between threads (within, across) - default: within")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name(SWEEP_ARG)
+                .long(SWEEP_ARG)
+                .help("Run every combination of the [sweep] table of the config file for its duration, one summary row per combination")
+                .conflicts_with_all(&["num-threads", "sector-size", "workload", "api-versions", "iterations"])
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("output-json")
+                .long("output-json")
+                .value_name("path")
+                .help("Write a JSON record per thread and iteration to this file")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("postmortem-dir")
+                .long("postmortem-dir")
+                .value_name("dir")
+                .help("On a hang or panic, collect logs, stacks, heartbeats, memory maps, environment and open files into a timestamped directory under this one")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("export-bundles")
+                .long("export-bundles")
+                .value_name("dir")
+                .help("Write a self-contained proof bundle for every C2 to this directory, for hang import-verify")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("trace")
+                .long("trace")
+                .value_name("path")
+                .help("Write the timeline of every thread's phases and GPU lock waits to this file in Chrome trace format")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("timeline")
+                .long("timeline")
+                .value_name("path")
+                .help("Append a CSV row to this file for every phase that ends, with its time, thread, duration and result")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("record-schedule")
+                .long("record-schedule")
+                .value_name("path")
+                .help("Write the order in which threads start phases and get the GPU lock to this file")
+                .conflicts_with("replay-schedule")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("replay-schedule")
+                .long("replay-schedule")
+                .value_name("path")
+                .help("Hold every thread at the start of a phase until the schedule recorded in this file says it is its turn")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("iterations")
+                .short("i")
+                .long("iterations")
+                .value_name("count")
+                .help("Lifecycle passes per thread, 0 runs until killed - default: 1")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("cache-root")
+                .long("cache-root")
+                .value_name("dir")
+                .help("Keep sector files under this directory instead of a temporary one")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("c2-queue")
+                .long("c2-queue")
+                .value_name("dir")
+                .help("Stop seal lifecycles after C1 and queue their outputs in this directory for workers of the c2 workload")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("c2-workers")
+                .long("c2-workers")
+                .value_name("n")
+                .help("Add this many threads of the c2 workload, taking the C1 outputs the seal threads queue until all of them finished - default: 0")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("shared-cache")
+                .long("shared-cache")
+                .value_name("mode")
+                .help("Misconfigure every thread to share one cache directory, or one whole sector directory, and expect clean failures (cache, sector)")
+                .conflicts_with("cache-root")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("keep-artifacts")
+                .long("keep-artifacts")
+                .help("Keep sector files and phase outputs under --cache-root so later runs can reuse them")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("workdir")
+                .long("workdir")
+                .value_name("dir")
+                .help("Directory the temporary sector files of each run go to - default: the system temporary directory")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("worker-logs")
+                .long("worker-logs")
+                .help("Also write every worker's log lines to hang-logs-<pid>/worker-<n>.log under --workdir")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("cleanup")
+                .long("cleanup")
+                .value_name("policy")
+                .help("When temporary sector files are removed (always, on-success keeps those of failed lifecycles and hung runs, never) - default: always")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("resume-from")
+                .long("resume-from")
+                .value_name("phase")
+                .help("Restart seal lifecycles at this phase (pc1, pc2, c1, c2) from the checkpoints under --cache-root")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("param-cache")
+                .long("param-cache")
+                .value_name("dir")
+                .help("Directory holding the Groth parameters - default: $FIL_PROOFS_PARAMETER_CACHE or /var/tmp/filecoin-proof-parameters")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("fetch-params")
+                .long("fetch-params")
+                .help("Download missing parameters with paramfetch instead of failing")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("skip-param-check")
+                .long("skip-param-check")
+                .help("Let the proofs library find or generate parameters on first use")
+                .conflicts_with("fetch-params")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("tmpfs-size")
+                .long("tmpfs-size")
+                .value_name("bytes")
+                .help("Keep the sector files on a tmpfs of this size, e.g. 4G, to run on a nearly full disk (needs root)")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("worker-cpus")
+                .long("worker-cpus")
+                .value_name("cores")
+                .help("Give every process worker a cgroup with a CPU quota of this many cores, e.g. 2.5; needs root and --worker-mode process")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("worker-memory")
+                .long("worker-memory")
+                .value_name("bytes")
+                .help("Give every process worker a cgroup with this hard memory limit and no swap, e.g. 64G; needs root and --worker-mode process")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("io-read-bps")
+                .long("io-read-bps")
+                .value_name("bytes")
+                .help("Throttle reads from the disk holding the sector files, e.g. 50M per second (needs root and cgroup v2)")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("io-write-bps")
+                .long("io-write-bps")
+                .value_name("bytes")
+                .help("Throttle writes to the disk holding the sector files, e.g. 20M per second (needs root and cgroup v2)")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("remote-storage")
+                .long("remote-storage")
+                .value_name("dir")
+                .help("Keep the staged and sealed files and the cache of every sector under this directory, e.g. an NFS mount, apart from the rest of its files")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("storage-latency-ms")
+                .long("storage-latency-ms")
+                .value_name("ms")
+                .help("Keep the staged and sealed files and the cache on a disk that delays every read and write by this long, like NFS or ceph (needs root)")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("storage-size")
+                .long("storage-size")
+                .value_name("bytes")
+                .help("Size of the --storage-latency-ms disk, sparse until written - default: 64G")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("phase-timeout")
+                .long("phase-timeout")
+                .value_name("seconds")
+                .help("Abandon a lifecycle whose phase runs longer than this, 0 meaning never - default: 0")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("retries")
+                .long("retries")
+                .value_name("count")
+                .help("How often a lifecycle abandoned after --phase-timeout is started over; process workers are restarted instead - default: 0")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("transient-retries")
+                .long("transient-retries")
+                .value_name("count")
+                .help("How often a lifecycle that failed with a transient I/O error is started over - default: 0")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("retry-backoff-ms")
+                .long("retry-backoff-ms")
+                .value_name("milliseconds")
+                .help("Wait before the first transient retry, doubled for every further one - default: 1000")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("stagger-ms")
+                .long("stagger-ms")
+                .value_name("milliseconds")
+                .help("Delay between the starts of consecutive threads - default: 0")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("jitter-ms")
+                .long("jitter-ms")
+                .value_name("milliseconds")
+                .help("Random extra start delay per thread, derived from --seed - default: 0")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("pin-cores")
+                .long("pin-cores")
+                .value_name("cores")
+                .help("Bind threads to cores: auto splits the available cores evenly, or core lists like 0-3/4-7 are handed out round-robin")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("pin-rayon")
+                .long("pin-rayon")
+                .help("Give each pinned thread its own rayon pool on its cores")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("background-cpu")
+                .long("background-cpu")
+                .value_name("threads")
+                .help("Keep this many threads spinning next to the workers, to mimic a busy host - default: 0")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("background-io")
+                .long("background-io")
+                .value_name("MB/s")
+                .help("Keep writing and syncing a file in the run directory at this rate next to the workers - default: 0")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("rayon-threads")
+                .long("rayon-threads")
+                .value_name("threads")
+                .help("Run each thread's proving calls in a rayon pool of its own of this size - default: the shared global pool")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("multicore-sdr")
+                .long("multicore-sdr")
+                .value_name("on|off")
+                .help("Turn multicore SDR for PC1 on or off, per [[group]] with multicore-sdr there; groups that differ need --worker-mode process - default: $FIL_PROOFS_USE_MULTICORE_SDR")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("gpu-column-builder")
+                .long("gpu-column-builder")
+                .value_name("on|off")
+                .help("Build PC2's column tree on the GPU or the CPU, per [[group]] with gpu-column-builder there; groups that differ need --worker-mode process - default: $FIL_PROOFS_USE_GPU_COLUMN_BUILDER")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("gpu-tree-builder")
+                .long("gpu-tree-builder")
+                .value_name("on|off")
+                .help("Build PC2's replica tree on the GPU or the CPU, per [[group]] with gpu-tree-builder there; groups that differ need --worker-mode process - default: $FIL_PROOFS_USE_GPU_TREE_BUILDER")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("numa-node")
+                .long("numa-node")
+                .value_name("policy")
+                .help("Pin threads and their memory to NUMA nodes: round-robin, pack, or a node id for all of them")
+                .conflicts_with("pin-cores")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("gpu-devices")
+                .long("gpu-devices")
+                .value_name("ordinals")
+                .help("Comma separated GPU ordinals the threads may use, e.g. 0,1,2 - default: all")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("gpu-policy")
+                .long("gpu-policy")
+                .value_name("policy")
+                .help("How --gpu-devices are assigned to threads (round-robin, pin, free-for-all); anything but free-for-all needs --worker-mode process - default: free-for-all")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("chaos")
+                .long("chaos")
+                .value_name("fault")
+                .help("Inject a fault: kill-worker:<phase>:<probability> kills a thread during that phase, corrupt-cache:<pc2|c1>:<truncate|delete>:<probability> damages its cache before that phase, reassign-pc2:<probability> kills a pipeline thread as it starts PC2 and has another finish its sector; may be repeated")
+                .required(false)
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("gpu-lock-test")
+                .long("gpu-lock-test")
+                .help("Run a single C2 in each of --num-threads child processes and report the order they got the GPU lock in, with fairness statistics")
+                .conflicts_with_all(&["worker-mode", "phase", "workload", "iterations", SWEEP_ARG])
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("duration")
+                .long("duration")
+                .value_name("duration")
+                .help("Loop lifecycles until this much wall-clock time has passed, e.g. 6h, then summarize the throughput and hang events")
+                .conflicts_with_all(&["iterations", "gpu-lock-test", SWEEP_ARG])
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("overlap")
+                .long("overlap")
+                .value_name("pair")
+                .help("Run two threads that start these two phases together on every pass, e.g. pc2+c2 or c2+window-post")
+                .conflicts_with_all(&["num-threads", "phase", "workload", "gpu-lock-test", SWEEP_ARG])
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("sync-phases")
+                .long("sync-phases")
+                .help("Hold every thread before each phase until all threads are about to start one, then start them together")
+                .conflicts_with_all(&["overlap", "replay-schedule", "gpu-lock-test"])
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("epoch-secs")
+                .long("epoch-secs")
+                .value_name("secs")
+                .help("Take tickets and seeds from a simulated chain with epochs this long, waiting for the seed between PC2 and C1 - default: 0, off")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("seed-delay")
+                .long("seed-delay")
+                .value_name("epochs")
+                .help("Epochs after PC2 until the simulated chain has a sector's seed - default: 150")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("fuzz")
+                .long("fuzz")
+                .help("Give every iteration a random schedule of start delays, rayon pool sizes and phase delays, recording its seed")
+                .conflicts_with_all(&["replay-schedule", "sync-phases", "gpu-lock-test"])
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("fuzz-replay")
+                .long("fuzz-replay")
+                .value_name("schedule")
+                .help("Run every iteration with the --fuzz schedule of this seed")
+                .conflicts_with_all(&["replay-schedule", "sync-phases", "gpu-lock-test"])
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("fuzz-max-delay-ms")
+                .long("fuzz-max-delay-ms")
+                .value_name("ms")
+                .help("Longest start or phase delay a --fuzz schedule draws - default: 2000")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max-pc1")
+                .long("max-pc1")
+                .alias("max-concurrent-pc1")
+                .value_name("num of sectors")
+                .help("Queue sectors so at most this many threads run PC1 at a time, like lotus's sealing scheduler")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max-pc2")
+                .long("max-pc2")
+                .alias("max-concurrent-pc2")
+                .value_name("num of sectors")
+                .help("Queue sectors so at most this many threads run PC2 at a time")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max-c2")
+                .long("max-c2")
+                .alias("max-concurrent-c2")
+                .value_name("num of sectors")
+                .help("Queue sectors so at most this many threads run C2 at a time")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("post-priority")
+                .long("post-priority")
+                .help("Let WindowPoSt take --max-c2 slots ahead of the queued C2s, like lotus's PoSt priority")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("churn")
+                .long("churn")
+                .help("End every worker thread or process after each lifecycle and start a fresh one for the next, like a restarting lotus-worker")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("dry-run")
+                .long("dry-run")
+                .help("Print the workers, workloads and estimated disk and memory use the configuration resolves to, without sealing anything")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("worker-mode")
+                .long("worker-mode")
+                .value_name("mode")
+                .help("Run workers as threads or as child processes (thread, process) - default: thread")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name(WORKER_INDEX_ARG)
+                .long(WORKER_INDEX_ARG)
+                .hidden(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name(FIRST_LIFECYCLE_ARG)
+                .long(FIRST_LIFECYCLE_ARG)
+                .hidden(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("coordinate")
+                .long("coordinate")
+                .value_name("addr")
+                .help("Run no workers here but hand their lifecycles to `hang join` instances connecting to this address, e.g. 0.0.0.0:8701 (needs --features grpc)")
+                .conflicts_with_all(&[SWEEP_ARG, "gpu-lock-test", "overlap", "record-schedule", "replay-schedule", "tui", "tmpfs-size", "io-read-bps", "io-write-bps", "storage-latency-ms"])
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("metrics-listen")
+                .long("metrics-listen")
+                .value_name("addr")
+                .help("Serve Prometheus metrics on this address, e.g. 127.0.0.1:9100")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("heartbeat-file")
+                .long("heartbeat-file")
+                .value_name("path")
+                .help("Keep rewriting this JSON file with every worker's phase and last progress, for external watchdogs")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("heartbeat-interval")
+                .long("heartbeat-interval")
+                .value_name("seconds")
+                .help("How often the heartbeat file is rewritten - default: 10")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("profile-on-hang")
+                .long("profile-on-hang")
+                .value_name("svg")
+                .help("When the watchdog fires, profile the hung process with perf and write a flamegraph here")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("cache-stall-window")
+                .long("cache-stall-window")
+                .value_name("seconds")
+                .help("Flag a PC1 or PC2 whose cache directory saw no write for this long, 0 to disable - default: 0")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("starvation-threshold")
+                .long("starvation-threshold")
+                .value_name("seconds")
+                .help("Flag pool starvation when a canary task on the global rayon pool waits this long while workers are in a phase, 0 to disable - default: 5")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("log-format")
+                .long("log-format")
+                .value_name("format")
+                .help("Write log events as human readable lines or as JSON objects carrying the thread, iteration and phase (pretty, json) - default: pretty")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("tui")
+                .long("tui")
+                .help("Show a live dashboard of every thread's phase instead of its log lines, which go to a file (needs --features tui)")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("shutdown-timeout")
+                .long("shutdown-timeout")
+                .value_name("seconds")
+                .help("How long to wait for in-flight phases after SIGINT/SIGTERM - default: 120")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("hang-timeout")
+                .long("hang-timeout")
+                .value_name("seconds")
+                .help("Abort when a thread makes no progress for this long, 0 disables - default: 1800")
+                .required(false)
+                .takes_value(true),
+        )
+        .subcommand(
+            SubCommand::with_name("serve")
+                .about("Run as a daemon that starts, stops and reports runs through an HTTP API")
+                .arg(
+                    Arg::with_name("listen")
+                        .long("listen")
+                        .value_name("addr")
+                        .help("Address to serve the API on - default: 127.0.0.1:8700")
+                        .required(false)
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("audit")
+                .about("Check the comm_d and proofs of the sector directories a run left behind")
+                .arg(
+                    Arg::with_name("dir")
+                        .value_name("dir")
+                        .help("Directory to search for sectors, e.g. a --cache-root or kept run directory")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("porep-id")
+                        .long("porep-id")
+                        .value_name("hex")
+                        .help("PoRep ID the proofs were sealed with - default: the one of their API version")
+                        .required(false)
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("import-verify")
+                .about("Verify the proofs of bundles written by --export-bundles")
+                .arg(
+                    Arg::with_name("bundles")
+                        .value_name("bundle")
+                        .help("Bundle files to verify")
+                        .required(true)
+                        .multiple(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("compare")
+                .about("Diff the --output-json reports of two runs and flag regressions")
+                .arg(
+                    Arg::with_name("before")
+                        .value_name("report-a.json")
+                        .help("Report of the baseline run")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("after")
+                        .value_name("report-b.json")
+                        .help("Report of the run to check against it")
+                        .required(true)
+                        .index(2),
+                )
+                .arg(
+                    Arg::with_name("threshold")
+                        .long("threshold")
+                        .value_name("percent")
+                        .help("Change for the worse that counts as a regression, in percentage points for rates - default: 10")
+                        .required(false)
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("join")
+                .about("Run lifecycles handed out by a `hang --coordinate` run (needs --features grpc)")
+                .arg(
+                    Arg::with_name("coordinator")
+                        .value_name("url")
+                        .help("The coordinator to ask for work, e.g. http://10.0.0.1:8701")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("slots")
+                        .long("slots")
+                        .value_name("n")
+                        .help("Number of lifecycles to run at once - default: 1")
+                        .required(false)
+                        .takes_value(true),
+                ),
+        )
+}
+
+fn run() -> Result<Classification> {
+    let matches = app().get_matches();
+
+    let file = match matches.value_of("config") {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+    let log_format = resolve::<String>(
+        &matches,
+        "log-format",
+        file.log_format.clone(),
+        LOG_FORMAT_DEFAULT,
+    )?
+    .parse::<LogFormat>()?;
+    logging::init(log_format)?;
+
+    if let Some(serve) = matches.subcommand_matches("serve") {
+        daemon::serve(serve.value_of("listen").unwrap_or(LISTEN_DEFAULT))?;
+        return Ok(Classification::Success);
+    }
+    if let Some(args) = matches.subcommand_matches("audit") {
+        let porep_id = match args.value_of("porep-id") {
+            Some(porep_id) => Some(parse_porep_id(porep_id)?),
+            None => None,
+        };
+        let audits = audit::audit(
+            &PathBuf::from(args.value_of("dir").expect("the dir is required")),
+            porep_id,
+        )?;
+        audit::print_report(&audits);
+        return Ok(if audits.iter().any(|audit| audit.mismatched()) {
+            Classification::VerificationMismatch
+        } else {
+            Classification::Success
+        });
+    }
+    if let Some(args) = matches.subcommand_matches("import-verify") {
+        let mut classification = Classification::Success;
+        for path in args.values_of("bundles").expect("bundles are required") {
+            match bundle::verify(Path::new(path)) {
+                Ok(true) => println!("{:<10} {}", "ok", path),
+                Ok(false) => {
+                    classification = Classification::VerificationMismatch;
+                    println!("{:<10} {}", "MISMATCH", path);
+                }
+                Err(err) => {
+                    if classification == Classification::Success {
+                        classification = Classification::PhaseFailure;
+                    }
+                    println!("{:<10} {}", "error", path);
+                    println!("    {:#}", err);
+                }
+            }
+        }
+        return Ok(classification);
+    }
+    if let Some(args) = matches.subcommand_matches("compare") {
+        let threshold: f64 = args
+            .value_of("threshold")
+            .unwrap_or(COMPARE_THRESHOLD_DEFAULT)
+            .parse()?;
+        let metrics = compare::compare(
+            Path::new(
+                args.value_of("before")
+                    .expect("the before report is required"),
+            ),
+            Path::new(
+                args.value_of("after")
+                    .expect("the after report is required"),
+            ),
+        )?;
+        let regressions = compare::print_report(&metrics, threshold);
+        if regressions > 0 {
+            // Told apart from a usage error, which exits with 1.
+            eprintln!("Error: {} metrics regressed", regressions);
+            std::process::exit(REGRESSION_EXIT_CODE);
+        }
+        return Ok(Classification::Success);
+    }
+    #[cfg(feature = "grpc")]
+    if let Some(join) = matches.subcommand_matches("join") {
+        let url = join.value_of("coordinator").expect("the url is required");
+        let slots = join.value_of("slots").unwrap_or(SLOTS_DEFAULT).parse()?;
+        shutdown::install()?;
+        test_hang::distributed::join(url, slots)?;
+        return Ok(Classification::Success);
+    }
+    #[cfg(not(feature = "grpc"))]
+    if matches.subcommand_matches("join").is_some() {
+        return Err(anyhow!("hang join needs a build with --features grpc"));
+    }
+
+    let settings = Settings::from_args(&matches, &file)?;
+    if let Some(preset) = settings.env_preset {
+        preset.apply();
+    }
+    platform::restrict(settings.sector_size, &settings.linux_only)?;
+    settings.log();
+    let environment = Environment::capture();
+    environment.log();
+
+    if let Some(sweep) = &settings.sweep {
+        return run_sweep(&settings, sweep, &environment);
+    }
+    let workers = WorkerSettings::from_args(&matches, file, &settings)?;
+    run_workers(settings, workers, environment)
+}
+
+/// Runs every cell of `sweep` as a run of its own.
+fn run_sweep(
+    settings: &Settings,
+    sweep: &SweepConfig,
+    environment: &Environment,
+) -> Result<Classification> {
+    let cells = sweep::cells(
+        sweep,
+        &Cell {
+            threads: settings.num_threads,
+            sector_size: settings.sector_size_name.clone(),
+            api_versions: settings.api_version_names.join(","),
+            workload: settings.workload_name.clone(),
+        },
+    )?;
+    if settings.dry_run {
+        plan::print_sweep(&cells, sweep.duration);
+        return Ok(Classification::Success);
+    }
+    // Every cell derives its inputs from the same seed.
+    let mut extra_args = Vec::new();
+    if !settings.seed_given {
+        extra_args.push(format!("--seed={}", settings.seed));
+    }
+    let runner = Sweep::new(
+        extra_args,
+        Duration::from_secs(sweep.duration),
+        settings.shutdown_timeout,
+    );
+
+    shutdown::install()?;
+    let mut summaries = Vec::new();
+    for (i, cell) in cells.iter().enumerate() {
+        if shutdown::requested() {
+            break;
+        }
+        info!(
+            "Sweep cell {}/{}: {} threads, sector size {}, api versions {}, workload {}",
+            i + 1,
+            cells.len(),
+            cell.threads,
+            cell.sector_size,
+            cell.api_versions,
+            cell.workload
+        );
+        summaries.push(runner.run(cell)?);
+    }
+
+    sweep::print_summaries(&summaries);
+    if let Some(path) = &settings.output_json {
+        sweep::write_json(path, environment, &summaries)?;
+        println!("Wrote results to {}", path.display());
+    }
+    Ok(Classification::Success)
+}
+
+/// Sets up the storage, instrumentation and environment `workers` asks for,
+/// then runs the workers, or the one worker of a child process.
+fn run_workers(
+    settings: Settings,
+    workers: WorkerSettings,
+    environment: Environment,
+) -> Result<Classification> {
+    let dry_run = settings.dry_run;
+    let worker_index = workers.worker_index;
+    let mut groups = workers.groups;
+    let mut cache_root = workers.cache_root;
+    let c2_queue = match (&workers.c2_queue, dry_run) {
+        (Some(dir), false) => Some(C2Queue::open(dir.clone())?),
+        _ => None,
+    };
+    // Children inherit the parent's mount and cgroup, and get its tmpfs as
+    // their cache root.
+    let mut storage_args = Vec::new();
+    let _tmpfs = match workers.tmpfs_size {
+        Some(size) if worker_index.is_none() && !dry_run => {
+            let tmpfs = Tmpfs::mount(size)?;
+            info!(
+                "Sector files on a {} byte tmpfs at {}",
+                size,
+                tmpfs.dir().display()
+            );
+            cache_root = Some(tmpfs.dir().to_path_buf());
+            storage_args.push(format!("--cache-root={}", tmpfs.dir().display()));
+            Some(tmpfs)
+        }
+        _ => None,
+    };
+    // Held until the end of the run; dropping them undoes the setup.
+    let _io_limit = if (workers.io_read_bps.is_some() || workers.io_write_bps.is_some())
+        && worker_index.is_none()
+        && !dry_run
+    {
+        let path = cache_root.clone().unwrap_or_else(env::temp_dir);
+        fs::create_dir_all(&path)?;
+        let limit = IoLimit::apply(&path, workers.io_read_bps, workers.io_write_bps)?;
+        info!("Throttling I/O to device {}", limit.device()?);
+        Some(limit)
+    } else {
+        None
+    };
+    // Children keep their sectors on the parent's disk.
+    let delayed_disk = match workers.storage_latency {
+        Some(latency) if worker_index.is_none() && !dry_run => {
+            let disk = DelayedDisk::mount(workers.storage_size, latency)?;
+            info!(
+                "Sealed sector files on a {} byte disk delaying I/O by {}ms at {}",
+                workers.storage_size,
+                latency.as_millis(),
+                disk.dir().display()
+            );
+            storage_args.push(format!("--remote-storage={}", disk.dir().display()));
+            Some(disk)
+        }
+        _ => None,
+    };
+    match (&delayed_disk, &workers.remote_storage) {
+        (Some(disk), _) => {
+            artifacts::set_sealed_storage(RemoteStorage::new(disk.dir().to_path_buf()))
+        }
+        (None, Some(dir)) if !dry_run => {
+            fs::create_dir_all(dir)?;
+            artifacts::set_sealed_storage(RemoteStorage::new(dir.clone()));
+        }
+        _ => {}
+    }
+    for (_, config) in groups.iter_mut() {
+        config.cache_root = cache_root.clone();
+        config.c2_queue = c2_queue.clone();
+    }
+
+    // Children must derive the same inputs, even from a random seed.
+    let worker_mode = if workers.process_workers {
+        let mut extra_args = storage_args;
+        if !settings.seed_given {
+            extra_args.push(format!("--seed={}", settings.seed));
+        }
+        WorkerMode::Process(ProcessWorkers::new(extra_args))
+    } else {
+        WorkerMode::Thread
+    };
+
+    // Children leave the exporter to the parent, which already holds the port.
+    if let (Some(addr), None, false) = (workers.metrics_listen, worker_index, dry_run) {
+        exporter::serve(addr)?;
+    }
+    if let (Some(path), None, false) = (workers.heartbeat_file, worker_index, dry_run) {
+        heartbeat::enable(path, workers.heartbeat_interval);
+    }
+    if let (Some(path), false) = (workers.profile_on_hang, dry_run) {
+        // Worker processes have watchdogs of their own.
+        profile::enable(match worker_index {
+            Some(index) => path.with_file_name(format!(
+                "{}-worker-{}.svg",
+                path.file_stem().unwrap_or_default().to_string_lossy(),
+                index
+            )),
+            None => path,
+        });
+    }
+    if let (Some(dir), false) = (workers.export_bundles, dry_run) {
+        bundle::enable(dir)?;
+    }
+    if let (Some(dir), false) = (workers.postmortem_dir, dry_run) {
+        postmortem::enable(dir)?;
+    }
+    if let (Some(window), false) = (workers.cache_stall_window, dry_run) {
+        stall::enable(window);
+    }
+    if let (Some(threshold), false) = (workers.starvation_threshold, dry_run) {
+        canary::start(threshold);
+    }
+    if let (Some(path), false) = (workers.trace, dry_run) {
+        match worker_index {
+            Some(index) => trace::join(&path, index)?,
+            None => trace::start(&path)?,
+        }
+    }
+    if let (Some(path), false) = (workers.timeline, dry_run) {
+        match worker_index {
+            Some(_) => timeline::join(&path)?,
+            None => timeline::start(&path)?,
+        }
+    }
+    if !dry_run {
+        if let Some(path) = &workers.record_schedule {
+            schedule::record(path)?;
+        }
+        if let Some(path) = &workers.replay_schedule {
+            schedule::replay(path)?;
+        }
+    }
+    if let (Some(overlap), false) = (settings.overlap, dry_run) {
+        overlap::enable([overlap.sides[0].phase, overlap.sides[1].phase]);
+    }
+    if let (Some(epoch), false) = (workers.epoch, dry_run) {
+        chain::enable(settings.seed, epoch, workers.seed_delay);
+    }
+    if workers.fuzz && !dry_run {
+        fuzz::enable(workers.fuzz_max_delay, workers.fuzz_replay);
+    }
+    if !settings.stage_limits.is_empty() && !dry_run {
+        pipeline::enable(&settings.stage_limits);
+    }
+    if workers.post_priority && !dry_run {
+        pipeline::enable_post_priority();
+    }
+    #[cfg(feature = "tui")]
+    if workers.tui && worker_index.is_none() {
+        test_hang::dashboard::enable();
+    }
+
+    if workers.sync_phases {
+        barrier::enable(workers.num_threads);
+    }
+    if let Some(sets) = &workers.pin_cores {
+        for i in 0..workers.num_threads {
+            match &workers.numa {
+                Some(numa) => info!(
+                    "thread {} pinned to numa node {}, cores {:?}",
+                    i,
+                    numa.node_for(i),
+                    sets.for_worker(i)
+                ),
+                None => info!("thread {} pinned to cores {:?}", i, sets.for_worker(i)),
+            }
+        }
+    }
+    let worker_mode = match (worker_mode, workers.gpu_devices) {
+        (WorkerMode::Process(children), Some(devices)) => {
+            WorkerMode::Process(children.with_gpu_devices(devices))
+        }
+        (WorkerMode::Thread, Some(devices)) => {
+            // Must be set before the proofs library first looks for devices.
+            for (var, value) in devices.env(0) {
+                env::set_var(var, value);
+            }
+            WorkerMode::Thread
+        }
+        (worker_mode, None) => worker_mode,
+    };
+    let worker_cgroups = match &worker_mode {
+        WorkerMode::Process(_)
+            if workers.worker_limits != WorkerLimits::default()
+                && worker_index.is_none()
+                && !dry_run =>
+        {
+            let limits = workers.worker_limits;
+            let cgroups = Arc::new(WorkerCgroups::create(workers.num_threads, limits)?);
+            info!(
+                "Every worker runs in a cgroup of its own, cpus {}, memory {}",
+                limits
+                    .cpus
+                    .map_or("unlimited".to_string(), |cpus| cpus.to_string()),
+                limits
+                    .memory
+                    .map_or("unlimited".to_string(), |memory| memory.to_string())
+            );
+            Some(cgroups)
+        }
+        _ => None,
+    };
+    let worker_mode = match (worker_mode, &worker_cgroups) {
+        (WorkerMode::Process(children), Some(cgroups)) => {
+            WorkerMode::Process(children.with_cgroups(cgroups.clone()))
+        }
+        (worker_mode, _) => worker_mode,
+    };
+    if let WorkerMode::Thread = worker_mode {
+        // Must be set before the proofs library reads its settings; thread
+        // workers all have the same.
+        let toggles = groups
+            .first()
+            .map(|(_, config)| config.toggles)
+            .unwrap_or_default();
+        for (var, value) in toggles.env() {
+            env::set_var(var, value);
+        }
+    }
+
+    if dry_run {
+        plan::print_plan(
+            &groups,
+            settings.sector_size,
+            workers.prepared_count,
+            matches!(worker_mode, WorkerMode::Process(_)),
+        );
+        return Ok(Classification::Success);
+    }
+
+    if let Some(dir) = &workers.param_cache {
+        params::set_cache_dir(dir);
+    }
+    // Joined instances check their own parameters.
+    if worker_index.is_none()
+        && settings.coordinate.is_none()
+        && !workers.skip_param_check
+        && !settings.skip_proofs
+    {
+        let dir = params::cache_dir();
+        if let Err(err) = params::check(&dir, settings.sector_size, workers.aggregating) {
+            if !workers.fetch_params {
+                return Err(err.context(
+                    "run with --fetch-params, or with --skip-param-check to generate \
+                     small sector parameters on first use",
+                ));
+            }
+            info!("{:#}, fetching parameters", err);
+            params::fetch(settings.sector_size)?;
+            params::check(&dir, settings.sector_size, workers.aggregating)?;
+        }
+        info!(
+            "Parameters for sector size {} found in {}",
+            settings.sector_size,
+            dir.display()
+        );
+    }
+
+    let run_dir = artifacts::set_workdir(&workers.workdir, workers.cleanup)?;
+    if worker_index.is_none() {
+        info!("Temporary sector files go to {}", run_dir.display());
+    }
+    if workers.worker_logs {
+        // Process workers write next to the files of their parent.
+        let pid = match worker_index {
+            Some(_) => unsafe { libc::getppid() as u32 },
+            None => std::process::id(),
+        };
+        let dir = workers.workdir.join(format!("hang-logs-{}", pid));
+        logging::enable_worker_logs(dir.clone())?;
+        if worker_index.is_none() {
+            info!("Worker logs go to {}", dir.display());
+        }
+    }
+
+    if let Some(index) = worker_index {
+        let config = groups
+            .iter()
+            .flat_map(|(threads, config)| std::iter::repeat(config).take(*threads))
+            .nth(index)
+            .ok_or_else(|| anyhow!("there is no worker {}", index))?;
+        let config = &WorkerConfig {
+            first_lifecycle: workers.first_lifecycle,
+            ..config.clone()
+        };
+        let sealed_sectors = if config.workload.needs_sealed_sectors() {
+            prepare_sectors(
+                settings.sector_size,
+                &settings.api_versions,
+                workers.prepared_count,
+                config.workload == Workload::Aggregate,
+                settings.provers.first().copied(),
+                if config.workload.needs_cc_sectors() {
+                    PieceData::Zeros
+                } else {
+                    PieceData::Random
+                },
+            )?
+        } else {
+            vec![]
+        };
+        shutdown::install()?;
+        chaos::install(workers.faults, settings.seed, KillMode::Abort);
+        let res = process::run_child(
+            index,
+            config,
+            Arc::new(sealed_sectors),
+            settings.hang_timeout,
+        );
+        artifacts::finish_run(res.is_ok());
+        // The parent classifies the worker by the exit code.
+        return match res {
+            Ok(()) => Ok(Classification::Success),
+            Err(err) => {
+                error!(worker = index, "{:#}", err);
+                Ok(Classification::of_error(&err))
+            }
+        };
+    }
+
+    // Process workers seal their own sectors.
+    let sealed_sectors = if groups
+        .iter()
+        .any(|(_, config)| config.workload.needs_sealed_sectors())
+        && matches!(worker_mode, WorkerMode::Thread)
+        && settings.coordinate.is_none()
+    {
+        info!("Sealing {} sectors up front", workers.prepared_count);
+        // PoSt and aggregation work just as well on the CC sectors snap
+        // workers update.
+        let sealed_data = if groups
+            .iter()
+            .any(|(_, config)| config.workload.needs_cc_sectors())
+        {
+            PieceData::Zeros
+        } else {
+            PieceData::Random
+        };
+        prepare_sectors(
+            settings.sector_size,
+            &settings.api_versions,
+            workers.prepared_count,
+            workers.aggregating,
+            settings.provers.first().copied(),
+            sealed_data,
+        )?
+    } else {
+        vec![]
+    };
+
+    info!(
+        "Spawning {} threads, sector size {}",
+        workers.num_threads, settings.sector_size
+    );
+    if let Some(addr) = settings.coordinate {
+        info!("Each thread runs on instances joining {}", addr);
+    } else if let WorkerMode::Process(_) = worker_mode {
+        info!("Each thread runs in a child process");
+    }
+    if workers.churn {
+        info!("Workers are respawned after every lifecycle");
+    }
+    for (threads, config) in &groups {
+        info!(
+            "  {}: {} threads, workload {:?}, phase {:?}, iterations {}",
+            config.group, threads, config.workload, config.phase, config.iterations
+        );
+    }
+    if settings.api_versions.len() > 1 {
+        let mix = match settings.api_version_mix {
+            ApiVersionMix::Within => "within",
+            ApiVersionMix::Across => "across",
+        };
+        info!(
+            "API versions {} mixed {} threads",
+            settings.api_version_names.join(", "),
+            mix
+        );
+    }
+
+    shutdown::install()?;
+    // Process workers are killed by their own copy.
+    if let (WorkerMode::Thread, None) = (&worker_mode, settings.coordinate) {
+        chaos::install(workers.faults, settings.seed, KillMode::Panic);
+    }
+
+    let lock_monitor = if settings.gpu_lock_test {
+        Some(LockMonitor::start())
+    } else {
+        None
+    };
+    let background_load = if workers.background_cpu > 0 || workers.background_io > 0 {
+        Some(BackgroundLoad::start(
+            workers.background_cpu,
+            workers.background_io,
+            &run_dir,
+        )?)
+    } else {
+        None
+    };
+    if let Some(duration) = settings.duration {
+        info!("Soaking for {}s", duration.as_secs());
+        shutdown::request_after(duration);
+    }
+    let started = Instant::now();
+    #[cfg(feature = "grpc")]
+    let coordinated = match settings.coordinate {
+        // Joined instances must derive the same inputs, even from a random seed.
+        Some(addr) => {
+            let mut extra_args = Vec::new();
+            if !settings.seed_given {
+                extra_args.push(format!("--seed={}", settings.seed));
+            }
+            Some(test_hang::distributed::coordinate(
+                addr,
+                &groups,
+                extra_args,
+                settings.shutdown_timeout,
+            )?)
+        }
+        None => None,
+    };
+    #[cfg(not(feature = "grpc"))]
+    let coordinated = None;
+    let outcome = match coordinated {
+        Some(outcome) => outcome,
+        None => run_groups(
+            &groups,
+            Arc::new(sealed_sectors),
+            settings.hang_timeout,
+            settings.shutdown_timeout,
+            &worker_mode,
+        )?,
+    };
+    let elapsed = started.elapsed();
+    drop(background_load);
+    if let Some(cgroups) = &worker_cgroups {
+        cgroups.log_oom_kills();
+    }
+
+    metrics::print_summary(&outcome.records);
+    fds::print_report(&outcome.records);
+    if settings.duration.is_some() {
+        soak::print_summary(&outcome, elapsed);
+    }
+    let RunOutcome {
+        records,
+        classification,
+        ..
+    } = outcome;
+    artifacts::finish_run(records.iter().all(|record| record.success));
+    // Workers trampling on each other's files are expected to fail, but
+    // only with an error: a hang or a bad proof means the library let it
+    // through.
+    let classification = match (settings.shared_cache, classification) {
+        (Some(mode), classification) => {
+            let classification = match classification {
+                Classification::PhaseFailure => Classification::Success,
+                classification => classification,
+            };
+            let failed = records
+                .iter()
+                .filter(|record| Classification::of_record(record) == Classification::PhaseFailure)
+                .count();
+            println!();
+            println!(
+                "Shared {:?} directory: {} of {} lifecycles failed cleanly, the run counts as {}",
+                mode,
+                failed,
+                records.len(),
+                classification
+            );
+            classification
+        }
+        (None, classification) => classification,
+    };
+    if let Some(monitor) = lock_monitor {
+        lock_monitor::print_report(&monitor.stop());
+    }
+    schedule::print_report();
+    overlap::print_report();
+    barrier::print_report();
+    pipeline::print_report();
+    canary::print_report();
+    lock_order::print_report();
+    fuzz::print_report(&records);
+    let classification = match pipeline::unfinished_reassignments() {
+        0 => classification,
+        unfinished => {
+            warn!(
+                "{} sectors handed over in PC2 were never finished",
+                unfinished
+            );
+            classification.max(Classification::PhaseFailure)
+        }
+    };
+
+    if let Some(path) = settings.output_json {
+        metrics::write_json(&path, classification, &environment, elapsed, &records)?;
+        println!("Wrote results to {}", path.display());
+    }
+    if classification != Classification::Success {
+        warn!("Run classified as {}", classification);
+    }
+    Ok(classification)
+}
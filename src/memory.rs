@@ -0,0 +1,69 @@
+//! Peak resident memory of the process while workers are in a phase.
+//!
+//! RSS is only known per process, so every thread in a phase sees the peak of
+//! the whole process during that phase: with concurrent workers it includes
+//! whatever the others allocated meanwhile. Process workers measure their own.
+
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard, Once};
+use std::thread::{self, ThreadId};
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(100);
+
+lazy_static! {
+    /// Highest RSS seen so far by each thread that is currently in a phase.
+    static ref PEAKS: Mutex<HashMap<ThreadId, u64>> = Mutex::new(HashMap::new());
+}
+
+static SAMPLER: Once = Once::new();
+
+/// Starts tracking the peak RSS for the current thread's phase.
+pub fn enter() {
+    SAMPLER.call_once(|| {
+        thread::Builder::new()
+            .name("memory-sampler".into())
+            .spawn(sample)
+            .expect("failed to spawn memory sampler");
+    });
+    lock().insert(thread::current().id(), rss().unwrap_or_default());
+}
+
+/// Stops tracking the current thread and returns the peak RSS in bytes.
+pub fn leave() -> Option<u64> {
+    let peak = lock().remove(&thread::current().id())?;
+    Some(peak.max(rss().unwrap_or_default()))
+}
+
+fn lock() -> MutexGuard<'static, HashMap<ThreadId, u64>> {
+    PEAKS.lock().expect("memory samples poisoned")
+}
+
+fn sample() {
+    loop {
+        thread::sleep(SAMPLE_INTERVAL);
+        let rss = match rss() {
+            Some(rss) => rss,
+            None => continue,
+        };
+        for peak in lock().values_mut() {
+            *peak = (*peak).max(rss);
+        }
+    }
+}
+
+/// Current resident set size of this process in bytes.
+#[cfg(target_os = "linux")]
+pub fn rss() -> Option<u64> {
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages = statm.split_whitespace().nth(1)?.parse::<u64>().ok()?;
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    Some(pages * page_size as u64)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn rss() -> Option<u64> {
+    None
+}
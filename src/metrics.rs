@@ -0,0 +1,1034 @@
+//! Wall-clock timing of the individual lifecycle phases.
+//!
+//! Timings are collected in a thread local so the lifecycle code does not have
+//! to carry a recorder around; each worker turns them into a
+//! [`LifecycleRecord`] with [`finish`] once a lifecycle is done.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use storage_proofs_core::{api_version::ApiVersion, sector::SectorId};
+use tracing::{info, info_span};
+
+use crate::cpu::CpuUsage;
+use crate::environment::Environment;
+use crate::handoff::QueueDrained;
+use crate::outcome::{Classification, ErrorClass};
+
+/// A timed step of the seal or PoSt lifecycle.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Phase {
+    AddPiece,
+    PreCommit1,
+    PreCommit2,
+    Commit1,
+    Commit2,
+    Unseal,
+    Verify,
+    WindowPost,
+    WinningPost,
+    Aggregate,
+    SnapEncode,
+    SnapProve,
+}
+
+impl Phase {
+    pub const ALL: [Phase; 12] = [
+        Phase::AddPiece,
+        Phase::PreCommit1,
+        Phase::PreCommit2,
+        Phase::Commit1,
+        Phase::Commit2,
+        Phase::Unseal,
+        Phase::Verify,
+        Phase::WindowPost,
+        Phase::WinningPost,
+        Phase::Aggregate,
+        Phase::SnapEncode,
+        Phase::SnapProve,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Phase::AddPiece => "add_piece",
+            Phase::PreCommit1 => "pc1",
+            Phase::PreCommit2 => "pc2",
+            Phase::Commit1 => "c1",
+            Phase::Commit2 => "c2",
+            Phase::Unseal => "unseal",
+            Phase::Verify => "verify",
+            Phase::WindowPost => "window_post",
+            Phase::WinningPost => "winning_post",
+            Phase::Aggregate => "aggregate",
+            Phase::SnapEncode => "snap_encode",
+            Phase::SnapProve => "snap_prove",
+        }
+    }
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl Serialize for Phase {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+impl<'de> Deserialize<'de> for Phase {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Phase::ALL
+            .iter()
+            .copied()
+            .find(|phase| phase.name() == name)
+            .ok_or_else(|| de::Error::custom(format!("unknown phase: {}", name)))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct PhaseTiming {
+    pub phase: Phase,
+    #[serde(
+        rename = "duration_secs",
+        serialize_with = "serialize_secs",
+        deserialize_with = "deserialize_secs"
+    )]
+    pub duration: Duration,
+    /// For GPU phases, how much of `duration` was spent waiting for the GPU
+    /// lock rather than holding it.
+    #[serde(
+        default,
+        rename = "gpu_wait_secs",
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_opt_secs",
+        deserialize_with = "deserialize_opt_secs"
+    )]
+    pub gpu_wait: Option<Duration>,
+    /// Peak resident memory of the process during the phase, in bytes.
+    #[serde(
+        default,
+        rename = "peak_rss_bytes",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub peak_rss: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu: Option<CpuUsage>,
+}
+
+fn serialize_secs<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+fn deserialize_secs<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    f64::deserialize(deserializer).map(Duration::from_secs_f64)
+}
+
+fn serialize_opt_secs<S: Serializer>(
+    duration: &Option<Duration>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match duration {
+        Some(duration) => serialize_secs(duration, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+fn deserialize_opt_secs<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Duration>, D::Error> {
+    Option::<f64>::deserialize(deserializer).map(|secs| secs.map(Duration::from_secs_f64))
+}
+
+/// Outcome of a single lifecycle run by one thread.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LifecycleRecord {
+    pub group: String,
+    pub thread: usize,
+    pub iteration: usize,
+    pub api_version: String,
+    pub sector_id: Option<u64>,
+    pub phases: Vec<PhaseTiming>,
+    pub success: bool,
+    /// The error followed by its causes, outermost first.
+    pub errors: Vec<String>,
+    /// The phase that exceeded the phase timeout and was abandoned.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timed_out: Option<Phase>,
+    /// What kind of failure ended the lifecycle.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure: Option<Classification>,
+    /// What the error was about.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_class: Option<ErrorClass>,
+    /// The failure was transient and the lifecycle was started over.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub retried: bool,
+    /// The lifecycle's correlation id, as in the `iteration` span of its log
+    /// lines.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub correlation: String,
+    /// The seed of the `--fuzz` schedule the lifecycle ran with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fuzz_schedule: Option<u64>,
+    /// File descriptors the process had open when the lifecycle ended.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub open_fds: Option<usize>,
+    /// Where and when the phase that failed the lifecycle did, if one did.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub phase_error: Option<PhaseError>,
+}
+
+/// A phase ran longer than the phase timeout and was abandoned.
+#[derive(Clone, Copy, Debug)]
+pub struct PhaseTimeout {
+    pub phase: Phase,
+    pub timeout: Duration,
+}
+
+impl fmt::Display for PhaseTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} exceeded the phase timeout of {}s",
+            self.phase,
+            self.timeout.as_secs()
+        )
+    }
+}
+
+impl std::error::Error for PhaseTimeout {}
+
+/// Where and when a phase failed, attached to its error as context.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PhaseError {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub worker: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iteration: Option<usize>,
+    pub phase: Phase,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sector_id: Option<u64>,
+    #[serde(
+        rename = "elapsed_secs",
+        serialize_with = "serialize_secs",
+        deserialize_with = "deserialize_secs"
+    )]
+    pub elapsed: Duration,
+}
+
+impl fmt::Display for PhaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} failed after {:.1}s",
+            self.phase,
+            self.elapsed.as_secs_f64()
+        )?;
+        if let Some(sector_id) = self.sector_id {
+            write!(f, " on sector {}", sector_id)?;
+        }
+        match (self.worker, self.iteration) {
+            (Some(worker), Some(iteration)) => {
+                write!(f, " (worker {}, iteration {})", worker, iteration)
+            }
+            (Some(worker), None) => write!(f, " (worker {})", worker),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Current {
+    timings: Vec<PhaseTiming>,
+    sector_id: Option<u64>,
+    iteration: Option<usize>,
+    /// The innermost phase a panic unwound out of.
+    panicked_in: Option<PhaseError>,
+}
+
+thread_local! {
+    static CURRENT: RefCell<Current> = RefCell::new(Current::default());
+}
+
+/// What a timed phase returns, telling whether it succeeded.
+pub trait PhaseOutput {
+    fn succeeded(&self) -> bool;
+    /// Attaches where and when the phase failed to its error.
+    fn failed_in(self, context: PhaseError) -> Self;
+}
+
+impl<T> PhaseOutput for Result<T> {
+    fn succeeded(&self) -> bool {
+        self.is_ok()
+    }
+
+    fn failed_in(self, context: PhaseError) -> Self {
+        self.map_err(|err| err.context(context))
+    }
+}
+
+/// Runs `f` in a `phase` span and records how long it took as `phase` for
+/// the current thread.
+pub fn time<T: PhaseOutput, F: FnOnce() -> T>(phase: Phase, f: F) -> T {
+    let worker = crate::trace::worker();
+    if let Some(worker) = worker {
+        crate::fuzz::enter(worker, phase);
+        crate::schedule::enter_phase(worker, phase);
+        crate::overlap::enter(worker, phase);
+        crate::barrier::enter();
+    }
+    let _span = info_span!("phase", %phase).entered();
+    let _slot = crate::pipeline::enter(phase);
+    info!("phase started");
+    if let Some(worker) = worker {
+        crate::trace::begin(worker, "phase", phase.name());
+    }
+    let uses_gpu = crate::gpu_lock::uses_gpu(phase);
+    crate::watchdog::enter(phase);
+    crate::exporter::phase_started(phase);
+    if uses_gpu {
+        crate::gpu_lock::enter(phase);
+    }
+    crate::memory::enter();
+    crate::cpu::enter();
+    crate::canary::enter();
+    let kill = crate::chaos::enter(phase);
+    let mut guard = PhaseGuard {
+        phase,
+        worker,
+        uses_gpu,
+        start: Instant::now(),
+        closed: false,
+    };
+    let out = f();
+    let duration = guard.start.elapsed();
+    let out = if out.succeeded() {
+        out
+    } else {
+        out.failed_in(phase_error(worker, phase, duration))
+    };
+    guard.close(duration, out.succeeded());
+    // Only once the phase was closed, so a killed worker leaves nothing open.
+    if let Some(kill) = kill {
+        kill.fire();
+    }
+    out
+}
+
+/// Where and when `phase` of the current lifecycle failed.
+fn phase_error(worker: Option<usize>, phase: Phase, elapsed: Duration) -> PhaseError {
+    CURRENT.with(|current| {
+        let current = current.borrow();
+        PhaseError {
+            worker,
+            iteration: current.iteration,
+            phase,
+            sector_id: current.sector_id,
+            elapsed,
+        }
+    })
+}
+
+/// Closes the phase [`time`] opened, also when the phase panics: a caught
+/// panic leaves the worker running, and it must not find the GPU lock,
+/// memory, CPU, canary, exporter, watchdog and trace state of a phase that
+/// never ended.
+struct PhaseGuard {
+    phase: Phase,
+    worker: Option<usize>,
+    uses_gpu: bool,
+    start: Instant,
+    closed: bool,
+}
+
+impl PhaseGuard {
+    fn close(&mut self, duration: Duration, succeeded: bool) {
+        self.closed = true;
+        let phase = self.phase;
+        let gpu_wait = if self.uses_gpu {
+            let waited = crate::gpu_lock::leave().min(duration);
+            crate::exporter::gpu_waited(phase, waited);
+            Some(waited)
+        } else {
+            None
+        };
+        let peak_rss = crate::memory::leave();
+        let cpu = crate::cpu::leave();
+        crate::canary::leave();
+        crate::exporter::phase_finished(phase, duration);
+        crate::timeline::phase_finished(self.worker, phase, duration, succeeded);
+        crate::watchdog::leave();
+        if let Some(worker) = self.worker {
+            crate::trace::end(worker, "phase", phase.name());
+        }
+        info!(
+            duration_secs = duration.as_secs_f64(),
+            gpu_wait_secs = gpu_wait.map(|waited| waited.as_secs_f64()),
+            "phase finished"
+        );
+        CURRENT.with(|current| {
+            current.borrow_mut().timings.push(PhaseTiming {
+                phase,
+                duration,
+                gpu_wait,
+                peak_rss,
+                cpu,
+            })
+        });
+    }
+}
+
+impl Drop for PhaseGuard {
+    fn drop(&mut self) {
+        // Still open: the phase unwound out of `time`.
+        if !self.closed {
+            let elapsed = self.start.elapsed();
+            let context = phase_error(self.worker, self.phase, elapsed);
+            CURRENT.with(|current| {
+                current.borrow_mut().panicked_in.get_or_insert(context);
+            });
+            self.close(elapsed, false);
+        }
+    }
+}
+
+/// Takes the phase a panic on the current thread unwound out of, for the
+/// [`crate::panics::catch`] that caught it.
+pub(crate) fn take_panicked_phase() -> Option<PhaseError> {
+    CURRENT.with(|current| current.borrow_mut().panicked_in.take())
+}
+
+/// Tags the lifecycle running on the current thread with its iteration.
+pub fn set_iteration(iteration: usize) {
+    CURRENT.with(|current| current.borrow_mut().iteration = Some(iteration));
+}
+
+/// Tags the lifecycle running on the current thread with its sector.
+pub fn set_sector_id(sector_id: SectorId) {
+    CURRENT.with(|current| current.borrow_mut().sector_id = Some(u64::from(sector_id)));
+}
+
+/// Closes the lifecycle running on the current thread and returns its record.
+pub fn finish(
+    group: &str,
+    thread: usize,
+    iteration: usize,
+    api_version: ApiVersion,
+    res: &Result<()>,
+) -> LifecycleRecord {
+    let current = CURRENT.with(|current| std::mem::take(&mut *current.borrow_mut()));
+    let errors = match res {
+        Ok(()) => Vec::new(),
+        Err(err) => err.chain().map(|cause| cause.to_string()).collect(),
+    };
+    // A drained C2 queue ends the worker rather than a lifecycle.
+    if !matches!(res, Err(err) if err.is::<QueueDrained>()) {
+        crate::exporter::lifecycle_finished(group, res.is_ok());
+    }
+
+    LifecycleRecord {
+        group: group.to_string(),
+        thread,
+        iteration,
+        api_version: format!("{:?}", api_version),
+        sector_id: current.sector_id,
+        phases: current.timings,
+        success: res.is_ok(),
+        errors,
+        timed_out: res
+            .as_ref()
+            .err()
+            .and_then(|err| err.downcast_ref::<PhaseTimeout>())
+            .map(|timeout| timeout.phase),
+        failure: res.as_ref().err().map(Classification::of_error),
+        error_class: res.as_ref().err().map(ErrorClass::of_error),
+        retried: false,
+        correlation: String::new(),
+        fuzz_schedule: None,
+        open_fds: crate::fds::count(),
+        phase_error: res
+            .as_ref()
+            .err()
+            .and_then(|err| err.downcast_ref::<PhaseError>())
+            .cloned(),
+    }
+}
+
+/// Every thread of `records` with its group, ordered by thread; records
+/// come in the order lifecycles finished, interleaving threads.
+fn threads(records: &[LifecycleRecord]) -> Vec<(usize, &str)> {
+    let mut threads = records
+        .iter()
+        .map(|r| (r.thread, r.group.as_str()))
+        .collect::<Vec<_>>();
+    threads.sort_unstable();
+    threads.dedup();
+    threads
+}
+
+/// Prints one row per thread and phase with the call count, total, mean and
+/// slowest duration, followed by the GPU lock contention, peak memory,
+/// phase timeouts and retried transient failures of each thread.
+pub fn print_summary(records: &[LifecycleRecord]) {
+    println!(
+        "{:<12} {:<16} {:<14} {:>6} {:>12} {:>12} {:>12}",
+        "thread", "group", "phase", "count", "total (s)", "mean (s)", "max (s)"
+    );
+    for (thread, group) in threads(records) {
+        for phase in Phase::ALL.iter() {
+            let durations = records
+                .iter()
+                .filter(|r| r.thread == thread)
+                .flat_map(|r| r.phases.iter())
+                .filter(|t| t.phase == *phase)
+                .map(|t| t.duration)
+                .collect::<Vec<_>>();
+            if durations.is_empty() {
+                continue;
+            }
+
+            let total: Duration = durations.iter().sum();
+            let max = durations.iter().max().copied().unwrap_or_default();
+            let mean = total / durations.len() as u32;
+            println!(
+                "{:<12} {:<16} {:<14} {:>6} {:>12.3} {:>12.3} {:>12.3}",
+                format!("thread {}", thread),
+                group,
+                phase,
+                durations.len(),
+                total.as_secs_f64(),
+                mean.as_secs_f64(),
+                max.as_secs_f64()
+            );
+        }
+    }
+
+    print_statistics(records);
+    print_gpu_contention(records);
+    print_memory(records);
+    print_cpu(records);
+    print_timeouts(records);
+    print_retries(records);
+}
+
+/// How long one phase took over all threads and iterations of a group, in
+/// seconds.
+#[derive(Clone, Debug, Serialize)]
+pub struct PhaseStats {
+    pub group: String,
+    pub phase: Phase,
+    pub count: usize,
+    pub mean: f64,
+    pub std_dev: f64,
+    pub p50: f64,
+    pub p95: f64,
+    pub p99: f64,
+}
+
+/// A phase that took more than [`OUTLIER_SIGMAS`] standard deviations
+/// longer than the mean of the other runs of that phase in its group.
+#[derive(Clone, Copy, Debug)]
+pub struct Outlier<'a> {
+    pub record: &'a LifecycleRecord,
+    pub phase: Phase,
+    pub secs: f64,
+    /// How many standard deviations above the mean of the others it took.
+    pub sigmas: f64,
+}
+
+/// Phases this many standard deviations above the mean are outliers.
+pub const OUTLIER_SIGMAS: f64 = 3.0;
+
+/// The groups of `records` in the order they first show up.
+fn groups(records: &[LifecycleRecord]) -> Vec<&str> {
+    let mut groups = Vec::new();
+    for record in records {
+        if !groups.contains(&record.group.as_str()) {
+            groups.push(record.group.as_str());
+        }
+    }
+    groups
+}
+
+/// How long every run of `phase` in `group` took, with its record.
+fn phase_secs<'a>(
+    records: &'a [LifecycleRecord],
+    group: &str,
+    phase: Phase,
+) -> Vec<(&'a LifecycleRecord, f64)> {
+    records
+        .iter()
+        .filter(|r| r.group == group)
+        .flat_map(|r| r.phases.iter().map(move |t| (r, t)))
+        .filter(|(_, t)| t.phase == phase)
+        .map(|(r, t)| (r, t.duration.as_secs_f64()))
+        .collect()
+}
+
+/// The statistics of every phase that ran in each group; groups sealing
+/// other sector sizes or running other workloads do not mix. Groups come in
+/// the order they first show up, their phases in the order of
+/// [`Phase::ALL`].
+pub fn phase_stats(records: &[LifecycleRecord]) -> Vec<PhaseStats> {
+    let mut stats = Vec::new();
+    for group in groups(records) {
+        for phase in Phase::ALL.iter() {
+            let mut secs = phase_secs(records, group, *phase)
+                .into_iter()
+                .map(|(_, secs)| secs)
+                .collect::<Vec<_>>();
+            if secs.is_empty() {
+                continue;
+            }
+            secs.sort_by(|a, b| a.partial_cmp(b).expect("durations are not NaN"));
+            let count = secs.len();
+            let mean = secs.iter().sum::<f64>() / count as f64;
+            let variance = secs.iter().map(|s| (s - mean) * (s - mean)).sum::<f64>() / count as f64;
+            stats.push(PhaseStats {
+                group: group.to_string(),
+                phase: *phase,
+                count,
+                mean,
+                std_dev: variance.sqrt(),
+                p50: percentile(&secs, 50.0),
+                p95: percentile(&secs, 95.0),
+                p99: percentile(&secs, 99.0),
+            });
+        }
+    }
+    stats
+}
+
+/// Nearest rank: the smallest of the sorted `secs` that at least `p` percent
+/// of them do not exceed.
+fn percentile(secs: &[f64], p: f64) -> f64 {
+    secs[((p / 100.0 * secs.len() as f64).ceil() as usize).max(1) - 1]
+}
+
+/// The phases of `records` that took more than [`OUTLIER_SIGMAS`] standard
+/// deviations longer than the mean of the other runs of that phase in their
+/// group, the worst first. Each run is measured against the others alone, as
+/// one included in its own mean and deviation could never lie more than
+/// `(n - 1) / sqrt(n)` of them above it.
+pub fn outliers(records: &[LifecycleRecord]) -> Vec<Outlier<'_>> {
+    let mut outliers = Vec::new();
+    for group in groups(records) {
+        for phase in Phase::ALL.iter() {
+            let runs = phase_secs(records, group, *phase);
+            // The others need a spread to measure against.
+            if runs.len() < 3 {
+                continue;
+            }
+            let others = (runs.len() - 1) as f64;
+            let sum = runs.iter().map(|(_, secs)| secs).sum::<f64>();
+            let sum_sq = runs.iter().map(|(_, secs)| secs * secs).sum::<f64>();
+            for &(record, secs) in &runs {
+                let mean = (sum - secs) / others;
+                let variance = (sum_sq - secs * secs) / others - mean * mean;
+                if variance <= 0.0 {
+                    continue;
+                }
+                let sigmas = (secs - mean) / variance.sqrt();
+                if sigmas > OUTLIER_SIGMAS {
+                    outliers.push(Outlier {
+                        record,
+                        phase: *phase,
+                        secs,
+                        sigmas,
+                    });
+                }
+            }
+        }
+    }
+    outliers.sort_by(|a, b| b.sigmas.partial_cmp(&a.sigmas).expect("sigmas are not NaN"));
+    outliers
+}
+
+/// Prints the percentiles and spread of every phase over all threads of a
+/// group, and the iterations whose phases were outliers: a phase slowing
+/// down is often the first sign of the hang.
+fn print_statistics(records: &[LifecycleRecord]) {
+    let stats = phase_stats(records);
+    if stats.is_empty() {
+        return;
+    }
+
+    println!();
+    println!(
+        "{:<16} {:<14} {:>6} {:>10} {:>10} {:>10} {:>10} {:>10}",
+        "group", "phase", "count", "mean (s)", "stddev (s)", "p50 (s)", "p95 (s)", "p99 (s)"
+    );
+    for stats in &stats {
+        println!(
+            "{:<16} {:<14} {:>6} {:>10.3} {:>10.3} {:>10.3} {:>10.3} {:>10.3}",
+            stats.group,
+            stats.phase,
+            stats.count,
+            stats.mean,
+            stats.std_dev,
+            stats.p50,
+            stats.p95,
+            stats.p99
+        );
+    }
+
+    let outliers = outliers(records);
+    if outliers.is_empty() {
+        return;
+    }
+    println!();
+    println!(
+        "{} phases took more than {}σ longer than the mean:",
+        outliers.len(),
+        OUTLIER_SIGMAS
+    );
+    for outlier in outliers {
+        println!(
+            "  thread {} iteration {}: {} took {:.3}s, {:.1}σ above the mean of the others",
+            outlier.record.thread,
+            outlier.record.iteration,
+            outlier.phase,
+            outlier.secs,
+            outlier.sigmas
+        );
+    }
+}
+
+/// Prints, per thread, how much of its GPU phase time was spent waiting for
+/// the GPU lock versus holding it.
+fn print_gpu_contention(records: &[LifecycleRecord]) {
+    let mut header = false;
+    for (thread, group) in threads(records) {
+        let (total, waited) = records
+            .iter()
+            .filter(|r| r.thread == thread)
+            .flat_map(|r| r.phases.iter())
+            .filter_map(|t| t.gpu_wait.map(|wait| (t.duration, wait)))
+            .fold(
+                (Duration::default(), Duration::default()),
+                |(total, waited), (duration, wait)| (total + duration, waited + wait),
+            );
+        if total == Duration::default() {
+            continue;
+        }
+
+        if !header {
+            println!();
+            println!(
+                "{:<12} {:<16} {:>14} {:>12} {:>12} {:>8}",
+                "thread", "group", "gpu phases (s)", "waiting (s)", "holding (s)", "wait %"
+            );
+            header = true;
+        }
+        println!(
+            "{:<12} {:<16} {:>14.3} {:>12.3} {:>12.3} {:>7.1}%",
+            format!("thread {}", thread),
+            group,
+            total.as_secs_f64(),
+            waited.as_secs_f64(),
+            (total - waited).as_secs_f64(),
+            100.0 * waited.as_secs_f64() / total.as_secs_f64()
+        );
+    }
+}
+
+/// Prints, per thread and phase, the highest peak RSS seen.
+fn print_memory(records: &[LifecycleRecord]) {
+    let mut header = false;
+    for (thread, group) in threads(records) {
+        for phase in Phase::ALL.iter() {
+            let peak = records
+                .iter()
+                .filter(|r| r.thread == thread)
+                .flat_map(|r| r.phases.iter())
+                .filter(|t| t.phase == *phase)
+                .filter_map(|t| t.peak_rss)
+                .max();
+            let peak = match peak {
+                Some(peak) => peak,
+                None => continue,
+            };
+
+            if !header {
+                println!();
+                println!(
+                    "{:<12} {:<16} {:<14} {:>16}",
+                    "thread", "group", "phase", "peak rss (MiB)"
+                );
+                header = true;
+            }
+            println!(
+                "{:<12} {:<16} {:<14} {:>16.1}",
+                format!("thread {}", thread),
+                group,
+                phase,
+                peak as f64 / (1024.0 * 1024.0)
+            );
+        }
+    }
+}
+
+/// Prints, per thread and phase, the mean CPU use of the worker thread and
+/// of the process, and the process's peak.
+fn print_cpu(records: &[LifecycleRecord]) {
+    let mut header = false;
+    for (thread, group) in threads(records) {
+        for phase in Phase::ALL.iter() {
+            let usages = records
+                .iter()
+                .filter(|r| r.thread == thread)
+                .flat_map(|r| r.phases.iter())
+                .filter(|t| t.phase == *phase)
+                .filter_map(|t| t.cpu)
+                .collect::<Vec<_>>();
+            if usages.is_empty() {
+                continue;
+            }
+            let mean = |pct: fn(&CpuUsage) -> f64| {
+                usages.iter().map(pct).sum::<f64>() / usages.len() as f64
+            };
+            let peak = usages
+                .iter()
+                .map(|usage| usage.process_peak_pct)
+                .fold(0.0, f64::max);
+
+            if !header {
+                println!();
+                println!(
+                    "{:<12} {:<16} {:<14} {:>12} {:>14} {:>15}",
+                    "thread", "group", "phase", "thread cpu %", "process cpu %", "process peak %"
+                );
+                header = true;
+            }
+            println!(
+                "{:<12} {:<16} {:<14} {:>12.1} {:>14.1} {:>15.1}",
+                format!("thread {}", thread),
+                group,
+                phase,
+                mean(|usage| usage.thread_pct),
+                mean(|usage| usage.process_avg_pct),
+                peak
+            );
+        }
+    }
+}
+
+/// Prints, per thread and phase, how often the phase timeout fired.
+fn print_timeouts(records: &[LifecycleRecord]) {
+    let mut timeouts = BTreeMap::new();
+    for record in records {
+        if let Some(phase) = record.timed_out {
+            *timeouts
+                .entry((record.thread, record.group.as_str(), phase))
+                .or_insert(0) += 1;
+        }
+    }
+    if timeouts.is_empty() {
+        return;
+    }
+
+    println!();
+    println!(
+        "{:<12} {:<16} {:<14} {:>9}",
+        "thread", "group", "phase", "timeouts"
+    );
+    for ((thread, group, phase), count) in timeouts {
+        println!(
+            "{:<12} {:<16} {:<14} {:>9}",
+            format!("thread {}", thread),
+            group,
+            phase,
+            count
+        );
+    }
+}
+
+/// Prints, per thread and kind of error, how many lifecycles were retried.
+fn print_retries(records: &[LifecycleRecord]) {
+    let mut retries = BTreeMap::new();
+    for record in records.iter().filter(|record| record.retried) {
+        *retries
+            .entry((
+                record.thread,
+                record.group.as_str(),
+                record.error_class.unwrap_or(ErrorClass::Other),
+            ))
+            .or_insert(0) += 1;
+    }
+    if retries.is_empty() {
+        return;
+    }
+
+    println!();
+    println!(
+        "{:<12} {:<16} {:<18} {:>8}",
+        "thread", "group", "error", "retries"
+    );
+    for ((thread, group, class), count) in retries {
+        println!(
+            "{:<12} {:<16} {:<18} {:>8}",
+            format!("thread {}", thread),
+            group,
+            class.to_string(),
+            count
+        );
+    }
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    classification: Classification,
+    environment: &'a Environment,
+    #[serde(rename = "elapsed_secs", serialize_with = "serialize_secs")]
+    elapsed: Duration,
+    statistics: Vec<PhaseStats>,
+    records: &'a [LifecycleRecord],
+}
+
+/// Writes the run's classification, its environment, how long its workers
+/// ran, the statistics of every phase and all lifecycle records to `path`
+/// as a single JSON document.
+pub fn write_json<P: AsRef<Path>>(
+    path: P,
+    classification: Classification,
+    environment: &Environment,
+    elapsed: Duration,
+    records: &[LifecycleRecord],
+) -> Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    serde_json::to_writer_pretty(
+        file,
+        &Report {
+            classification,
+            environment,
+            elapsed,
+            statistics: phase_stats(records),
+            records,
+        },
+    )?;
+    Ok(())
+}
+
+/// The parts of a report [`write_json`] wrote that are read back.
+#[derive(Debug, Deserialize)]
+pub struct SavedReport {
+    /// How long the workers ran, missing from reports of older harnesses.
+    #[serde(
+        default,
+        rename = "elapsed_secs",
+        deserialize_with = "deserialize_opt_secs"
+    )]
+    pub elapsed: Option<Duration>,
+    pub records: Vec<LifecycleRecord>,
+}
+
+/// Reads back the report [`write_json`] wrote to `path`.
+pub fn read_report<P: AsRef<Path>>(path: P) -> Result<SavedReport> {
+    let file = BufReader::new(File::open(path)?);
+    Ok(serde_json::from_reader(file)?)
+}
+
+/// Reads back the records [`write_json`] wrote to `path`.
+pub fn read_json<P: AsRef<Path>>(path: P) -> Result<Vec<LifecycleRecord>> {
+    Ok(read_report(path)?.records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(group: &str, iteration: usize, phase: Phase, secs: f64) -> LifecycleRecord {
+        serde_json::from_value(serde_json::json!({
+            "group": group,
+            "thread": 0,
+            "iteration": iteration,
+            "api_version": "V1_1_0",
+            "sector_id": null,
+            "phases": [{ "phase": phase.name(), "duration_secs": secs }],
+            "success": true,
+            "errors": [],
+        }))
+        .expect("valid record")
+    }
+
+    fn records(group: &str, phase: Phase, secs: &[f64]) -> Vec<LifecycleRecord> {
+        secs.iter()
+            .enumerate()
+            .map(|(iteration, secs)| record(group, iteration, phase, *secs))
+            .collect()
+    }
+
+    #[test]
+    fn percentile_is_nearest_rank() {
+        let secs = (1..=100).map(f64::from).collect::<Vec<_>>();
+        assert_eq!(percentile(&secs, 50.0), 50.0);
+        assert_eq!(percentile(&secs, 95.0), 95.0);
+        assert_eq!(percentile(&secs, 99.0), 99.0);
+
+        let secs = [1.0, 2.0, 3.0, 4.0];
+        assert_eq!(percentile(&secs, 50.0), 2.0);
+        assert_eq!(percentile(&secs, 95.0), 4.0);
+        assert_eq!(percentile(&[7.0], 99.0), 7.0);
+    }
+
+    #[test]
+    fn phase_stats_are_per_group() {
+        let mut all = records("fast", Phase::PreCommit1, &[1.0, 3.0]);
+        all.extend(records("slow", Phase::PreCommit1, &[10.0, 10.0, 10.0]));
+
+        let stats = phase_stats(&all);
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].group, "fast");
+        assert_eq!(stats[0].count, 2);
+        assert_eq!(stats[0].mean, 2.0);
+        assert_eq!(stats[0].std_dev, 1.0);
+        assert_eq!(stats[0].p50, 1.0);
+        assert_eq!(stats[1].group, "slow");
+        assert_eq!(stats[1].count, 3);
+        assert_eq!(stats[1].mean, 10.0);
+        assert_eq!(stats[1].std_dev, 0.0);
+    }
+
+    #[test]
+    fn outlier_among_few_runs_is_flagged() {
+        // With itself in the mean, a run out of 10 could never lie more
+        // than 2.85 standard deviations above it.
+        let secs = [10.0, 10.1, 9.9, 10.0, 10.1, 9.9, 10.0, 10.1, 9.9, 30.0];
+        let all = records("seal", Phase::PreCommit2, &secs);
+
+        let outliers = outliers(&all);
+        assert_eq!(outliers.len(), 1);
+        assert_eq!(outliers[0].record.iteration, 9);
+        assert_eq!(outliers[0].phase, Phase::PreCommit2);
+        assert_eq!(outliers[0].secs, 30.0);
+        assert!(outliers[0].sigmas > OUTLIER_SIGMAS);
+    }
+
+    #[test]
+    fn outliers_need_a_spread() {
+        assert!(outliers(&records("seal", Phase::Commit2, &[1.0, 100.0])).is_empty());
+        assert!(outliers(&records("seal", Phase::Commit2, &[5.0, 5.0, 5.0, 5.0])).is_empty());
+    }
+
+    #[test]
+    fn outliers_do_not_mix_groups() {
+        let mut all = records("small", Phase::PreCommit1, &[1.0, 1.1, 0.9, 1.0]);
+        all.extend(records(
+            "large",
+            Phase::PreCommit1,
+            &[100.0, 101.0, 99.0, 100.0],
+        ));
+        assert!(outliers(&all).is_empty());
+    }
+}
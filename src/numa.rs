@@ -0,0 +1,154 @@
+//! Placing workers on NUMA nodes.
+//!
+//! A placed worker is pinned to the cores of its node and binds its memory
+//! policy to that node, so the piece, staging and layer buffers it allocates
+//! or first touches, page cache included, come from local memory.
+
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Result};
+
+use crate::affinity::{self, CoreSets};
+
+const NODES_DIR: &str = "/sys/devices/system/node";
+/// `MPOL_BIND` from `<linux/mempolicy.h>`.
+#[cfg(target_os = "linux")]
+const MPOL_BIND: libc::c_int = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NumaPolicy {
+    /// Worker `i` goes to node `i % n`.
+    RoundRobin,
+    /// Workers are split into `n` consecutive blocks, one per node.
+    Pack,
+    /// Every worker goes to this node.
+    Node(usize),
+}
+
+impl FromStr for NumaPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "round-robin" => Ok(NumaPolicy::RoundRobin),
+            "pack" => Ok(NumaPolicy::Pack),
+            node => match node.parse() {
+                Ok(node) => Ok(NumaPolicy::Node(node)),
+                Err(_) => bail!("unknown numa policy: {}", s),
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct NumaPlacement {
+    /// Node ids and their cores, ordered by id.
+    nodes: Vec<(usize, Vec<usize>)>,
+    policy: NumaPolicy,
+    workers: usize,
+}
+
+impl NumaPlacement {
+    /// Reads the machine's nodes and places `workers` on them.
+    pub fn new(policy: NumaPolicy, workers: usize) -> Result<Self> {
+        let nodes = nodes()?;
+        if nodes.is_empty() {
+            bail!("no numa nodes found under {}", NODES_DIR);
+        }
+        if let NumaPolicy::Node(node) = policy {
+            if !nodes.iter().any(|(id, _)| *id == node) {
+                bail!("numa node {} does not exist", node);
+            }
+        }
+        Ok(NumaPlacement {
+            nodes,
+            policy,
+            workers,
+        })
+    }
+
+    /// The node worker `index` runs on.
+    pub fn node_for(&self, index: usize) -> usize {
+        self.nodes[self.position(index)].0
+    }
+
+    fn position(&self, index: usize) -> usize {
+        let n = self.nodes.len();
+        match self.policy {
+            NumaPolicy::RoundRobin => index % n,
+            NumaPolicy::Pack => (index * n / self.workers.max(1)).min(n - 1),
+            NumaPolicy::Node(node) => self
+                .nodes
+                .iter()
+                .position(|(id, _)| *id == node)
+                .expect("node checked in new"),
+        }
+    }
+
+    /// The cores of every worker's node, for pinning.
+    pub fn core_sets(&self) -> CoreSets {
+        CoreSets::from_sets(
+            (0..self.workers.max(1))
+                .map(|i| self.nodes[self.position(i)].1.clone())
+                .collect(),
+        )
+    }
+}
+
+/// Online nodes that have cores, with their core lists.
+fn nodes() -> Result<Vec<(usize, Vec<usize>)>> {
+    let mut nodes = Vec::new();
+    let entries =
+        fs::read_dir(NODES_DIR).with_context(|| format!("failed to read {}", NODES_DIR))?;
+    for entry in entries {
+        let name = entry?.file_name();
+        let id = match name.to_str().and_then(|name| name.strip_prefix("node")) {
+            Some(id) => match id.parse::<usize>() {
+                Ok(id) => id,
+                Err(_) => continue,
+            },
+            None => continue,
+        };
+        let cpulist = Path::new(NODES_DIR).join(&name).join("cpulist");
+        let cpulist = fs::read_to_string(&cpulist)
+            .with_context(|| format!("failed to read {}", cpulist.display()))?;
+        let cpulist = cpulist.trim();
+        // Memory-only nodes have no cores to run workers on.
+        if cpulist.is_empty() {
+            continue;
+        }
+        nodes.push((id, affinity::parse_list(cpulist)?));
+    }
+    nodes.sort();
+    Ok(nodes)
+}
+
+/// Restricts the calling thread's future allocations to `node`.
+#[cfg(target_os = "linux")]
+pub fn bind_memory(node: usize) -> Result<()> {
+    let bits = 8 * std::mem::size_of::<libc::c_ulong>();
+    let mut mask = vec![0 as libc::c_ulong; node / bits + 1];
+    mask[node / bits] |= 1 << (node % bits);
+    // The kernel ignores the last bit of `maxnode`.
+    let maxnode = mask.len() * bits + 1;
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_set_mempolicy,
+            MPOL_BIND,
+            mask.as_ptr(),
+            maxnode as libc::c_ulong,
+        )
+    };
+    if res != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("failed to bind memory to numa node {}", node));
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn bind_memory(node: usize) -> Result<()> {
+    bail!("binding memory to numa node {} needs Linux", node)
+}
@@ -0,0 +1,206 @@
+//! How a run ended, for CI to tell a reproduced hang from a run that simply
+//! failed.
+//!
+//! Every failed lifecycle and every worker that died is classified, and the
+//! run as a whole takes the most severe classification among them. It is
+//! written to the JSON report and picks the exit code; errors in the setup,
+//! before any worker started, keep the plain exit code 1.
+
+use std::fmt;
+use std::io;
+
+use serde::{Deserialize, Serialize};
+
+use crate::metrics::{LifecycleRecord, PhaseTimeout};
+use crate::process::{ChildFailed, PHASE_TIMEOUT_EXIT_CODE};
+use crate::watchdog::HANG_EXIT_CODE;
+
+/// Exit code of a run in which a phase failed.
+pub const PHASE_FAILURE_EXIT_CODE: i32 = 85;
+/// Exit code of a run in which a proof did not verify.
+pub const VERIFICATION_MISMATCH_EXIT_CODE: i32 = 88;
+/// Exit code of a run that ran out of memory or disk space.
+pub const RESOURCE_EXHAUSTION_EXIT_CODE: i32 = 89;
+/// Exit code of `hang compare` when the second report regressed.
+pub const REGRESSION_EXIT_CODE: i32 = 90;
+
+/// Outcome of a run, from least to most severe.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Classification {
+    Success,
+    PhaseFailure,
+    ResourceExhaustion,
+    VerificationMismatch,
+    /// The watchdog fired or a phase ran past the phase timeout.
+    Hang,
+}
+
+impl Classification {
+    pub fn name(self) -> &'static str {
+        match self {
+            Classification::Success => "success",
+            Classification::PhaseFailure => "phase-failure",
+            Classification::ResourceExhaustion => "resource-exhaustion",
+            Classification::VerificationMismatch => "verification-mismatch",
+            Classification::Hang => "hang",
+        }
+    }
+
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Classification::Success => 0,
+            Classification::PhaseFailure => PHASE_FAILURE_EXIT_CODE,
+            Classification::ResourceExhaustion => RESOURCE_EXHAUSTION_EXIT_CODE,
+            Classification::VerificationMismatch => VERIFICATION_MISMATCH_EXIT_CODE,
+            Classification::Hang => HANG_EXIT_CODE,
+        }
+    }
+
+    /// The classification a `hang` process exited with, if the code is one
+    /// of ours.
+    pub fn from_exit_code(code: i32) -> Option<Self> {
+        match code {
+            0 => Some(Classification::Success),
+            PHASE_FAILURE_EXIT_CODE => Some(Classification::PhaseFailure),
+            RESOURCE_EXHAUSTION_EXIT_CODE => Some(Classification::ResourceExhaustion),
+            VERIFICATION_MISMATCH_EXIT_CODE => Some(Classification::VerificationMismatch),
+            HANG_EXIT_CODE | PHASE_TIMEOUT_EXIT_CODE => Some(Classification::Hang),
+            _ => None,
+        }
+    }
+
+    /// Classifies the error a lifecycle or a worker failed with.
+    pub fn of_error(err: &anyhow::Error) -> Self {
+        if err.is::<PhaseTimeout>() {
+            return Classification::Hang;
+        }
+        if err.is::<VerificationMismatch>() {
+            return Classification::VerificationMismatch;
+        }
+        if let Some(child) = err.downcast_ref::<ChildFailed>() {
+            return child
+                .status
+                .code()
+                .and_then(Classification::from_exit_code)
+                .unwrap_or(Classification::PhaseFailure);
+        }
+        if err.chain().any(is_exhaustion) {
+            return Classification::ResourceExhaustion;
+        }
+        Classification::PhaseFailure
+    }
+
+    /// Classifies a lifecycle from its record, which is all that is left of
+    /// the lifecycles of process workers. A transient failure that was
+    /// retried does not count.
+    pub fn of_record(record: &LifecycleRecord) -> Self {
+        match (record.success, record.failure) {
+            (true, _) => Classification::Success,
+            (false, _) if record.retried => Classification::Success,
+            (false, Some(failure)) => failure,
+            (false, None) if record.timed_out.is_some() => Classification::Hang,
+            (false, None) => Classification::PhaseFailure,
+        }
+    }
+}
+
+impl fmt::Display for Classification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// What a failed lifecycle's error was about, to tell transient failures
+/// from real ones.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorClass {
+    /// An I/O error other than running out of space, such as a flaky temp
+    /// file.
+    Io,
+    /// A cache directory did not pass validation.
+    CacheValidation,
+    /// A proof did not verify or read back data did not match.
+    Proof,
+    Other,
+}
+
+impl ErrorClass {
+    pub fn of_error(err: &anyhow::Error) -> Self {
+        if err.is::<VerificationMismatch>() {
+            return ErrorClass::Proof;
+        }
+        if err.is::<CacheInvalid>() {
+            return ErrorClass::CacheValidation;
+        }
+        if err.is::<PhaseTimeout>() || err.chain().any(is_exhaustion) {
+            return ErrorClass::Other;
+        }
+        if err.chain().any(|cause| cause.is::<io::Error>()) {
+            return ErrorClass::Io;
+        }
+        ErrorClass::Other
+    }
+
+    /// Whether running the lifecycle again may well succeed.
+    pub fn is_transient(self) -> bool {
+        self == ErrorClass::Io
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorClass::Io => "io",
+            ErrorClass::CacheValidation => "cache-validation",
+            ErrorClass::Proof => "proof",
+            ErrorClass::Other => "other",
+        }
+    }
+}
+
+impl fmt::Display for ErrorClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A sector's cache directory was rejected before PC2 or C1.
+#[derive(Debug)]
+pub struct CacheInvalid;
+
+impl fmt::Display for CacheInvalid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("cache validation failed")
+    }
+}
+
+impl std::error::Error for CacheInvalid {}
+
+/// A proof, or data read back from a sealed sector, did not match what was
+/// put in.
+#[derive(Debug)]
+pub struct VerificationMismatch(pub String);
+
+impl fmt::Display for VerificationMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for VerificationMismatch {}
+
+fn is_exhaustion(cause: &(dyn std::error::Error + 'static)) -> bool {
+    if let Some(err) = cause.downcast_ref::<io::Error>() {
+        if matches!(
+            err.raw_os_error(),
+            Some(libc::ENOSPC) | Some(libc::ENOMEM) | Some(libc::EDQUOT)
+        ) {
+            return true;
+        }
+    }
+    // GPU allocation failures only come as messages.
+    let message = cause.to_string().to_lowercase();
+    message.contains("out of memory")
+        || message.contains("no space left on device")
+        || message.contains("cannot allocate memory")
+}
@@ -0,0 +1,142 @@
+//! Forces two phases to run at the same time, one per worker.
+//!
+//! With `--overlap pc2+c2` worker 0 runs lifecycles up to PC2 and worker 1
+//! up to C2, and each waits at the start of its phase until the other got
+//! to its own, so every pass puts exactly that pair side by side. Trying the
+//! pairs one by one narrows down which of them deadlocks much faster than
+//! waiting for free-running threads to line up by chance.
+//!
+//! Only thread workers meet at the gate.
+
+use std::str::FromStr;
+use std::sync::Condvar;
+
+use anyhow::{bail, Result};
+use lazy_static::lazy_static;
+
+use crate::lifecycle::parse_phase;
+use crate::lock_order::{OrderedGuard, OrderedMutex};
+use crate::metrics::Phase;
+use crate::workloads::Workload;
+
+/// What one of the two workers runs up to its gated phase.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Side {
+    pub workload: Workload,
+    /// The seal phase to stop after, `None` for the other workloads.
+    pub stop_after: Option<Phase>,
+    /// The phase held at the gate.
+    pub phase: Phase,
+}
+
+impl FromStr for Side {
+    type Err = anyhow::Error;
+
+    /// Parses a seal phase (`pc1`, `pc2`, `c1`, `c2`, `unseal`) or a
+    /// workload whose main phase overlaps (`window-post`, `winning-post`,
+    /// `aggregate`).
+    fn from_str(s: &str) -> Result<Self> {
+        if let Ok(Some(phase)) = parse_phase(s) {
+            return Ok(Side {
+                workload: Workload::Seal,
+                stop_after: Some(phase),
+                phase,
+            });
+        }
+        let workload = s.parse::<Workload>()?;
+        let phase = match workload {
+            Workload::WindowPost => Phase::WindowPost,
+            Workload::WinningPost => Phase::WinningPost,
+            Workload::Aggregate => Phase::Aggregate,
+            _ => bail!("cannot overlap {}", s),
+        };
+        Ok(Side {
+            workload,
+            stop_after: None,
+            phase,
+        })
+    }
+}
+
+/// The pair of phases `--overlap` runs side by side.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Overlap {
+    pub sides: [Side; 2],
+}
+
+impl FromStr for Overlap {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once('+') {
+            Some((a, b)) => Ok(Overlap {
+                sides: [a.trim().parse()?, b.trim().parse()?],
+            }),
+            None => bail!("an overlap names two phases joined by '+', got {}", s),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Gate {
+    phases: Option<[Phase; 2]>,
+    /// Whether one worker is already waiting for the other.
+    waiting: bool,
+    /// Number of times both workers met.
+    met: usize,
+    /// Set once a worker is done, so the other no longer waits for it.
+    open: bool,
+}
+
+lazy_static! {
+    static ref GATE: OrderedMutex<Gate> = OrderedMutex::new("overlap gate", Gate::default());
+    static ref MET: Condvar = Condvar::new();
+}
+
+/// Holds worker 0 before `phases[0]` and worker 1 before `phases[1]` until
+/// both are there.
+pub fn enable(phases: [Phase; 2]) {
+    lock().phases = Some(phases);
+}
+
+/// Waits for the other worker if `phase` is gated for `worker`.
+pub(crate) fn enter(worker: usize, phase: Phase) {
+    let mut gate = lock();
+    match gate.phases {
+        Some(phases) if phases.get(worker) == Some(&phase) && !gate.open => {}
+        _ => return,
+    }
+    if gate.waiting {
+        gate.waiting = false;
+        gate.met += 1;
+        MET.notify_all();
+        return;
+    }
+    gate.waiting = true;
+    let met = gate.met;
+    while gate.met == met && !gate.open {
+        gate = gate.wait(&MET);
+    }
+}
+
+/// Opens the gate for good once `worker` runs no more phases.
+pub(crate) fn finished(worker: usize) {
+    let mut gate = lock();
+    if gate.phases.is_some() && worker < 2 {
+        gate.open = true;
+        MET.notify_all();
+    }
+}
+
+/// Prints how often the two phases were started together.
+pub fn print_report() {
+    let gate = lock();
+    if let Some([a, b]) = gate.phases {
+        println!();
+        println!("Started {} and {} together {} times", a, b, gate.met);
+    }
+}
+
+fn lock() -> OrderedGuard<'static, Gate> {
+    GATE.lock()
+}
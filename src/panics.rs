@@ -0,0 +1,92 @@
+//! Worker panics, recorded as failed lifecycles.
+//!
+//! A panic inside the proofs library, such as an `expect` on a proof that
+//! could not be generated, would otherwise take its worker thread down
+//! without a record and leave the run waiting for it. Every lifecycle runs
+//! under [`catch`], which turns a panic into a [`WorkerPanic`] error with the
+//! message and location a panic hook saw, so the lifecycle is recorded as a
+//! failure and the worker stops the way a failing lifecycle stops it. Kills
+//! injected with `--chaos` are deliberate and still unwind the worker.
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Once;
+
+use anyhow::Result;
+use tracing::error;
+
+static HOOK: Once = Once::new();
+
+thread_local! {
+    /// The message and location of the latest panic on this thread.
+    static LAST: RefCell<Option<(String, Option<String>)>> = RefCell::new(None);
+}
+
+/// A lifecycle panicked instead of returning an error.
+#[derive(Clone, Debug)]
+pub struct WorkerPanic {
+    pub message: String,
+    pub location: Option<String>,
+}
+
+impl fmt::Display for WorkerPanic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.location {
+            Some(location) => write!(f, "panicked at {}: {}", location, self.message),
+            None => write!(f, "panicked: {}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for WorkerPanic {}
+
+/// Keeps what each panic says for [`catch`], after whatever hook was
+/// installed before.
+fn install_hook() {
+    HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let message = describe(info.payload());
+            let location = info.location().map(|location| location.to_string());
+            LAST.with(|last| *last.borrow_mut() = Some((message, location)));
+            previous(info);
+        }));
+    });
+}
+
+/// Runs `f`, returning a [`WorkerPanic`] if it panics, with the
+/// [`crate::metrics::PhaseError`] of the phase it panicked in as context.
+pub(crate) fn catch<T>(f: impl FnOnce() -> Result<T>) -> Result<T> {
+    install_hook();
+    LAST.with(|last| last.borrow_mut().take());
+    crate::metrics::take_panicked_phase();
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(res) => res,
+        Err(payload) if crate::chaos::killing() => panic::resume_unwind(payload),
+        Err(payload) => {
+            let panic = LAST
+                .with(|last| last.borrow_mut().take())
+                .map(|(message, location)| WorkerPanic { message, location })
+                .unwrap_or_else(|| WorkerPanic {
+                    message: describe(&*payload),
+                    location: None,
+                });
+            error!("{}", panic);
+            let err = anyhow::Error::from(panic);
+            match crate::metrics::take_panicked_phase() {
+                Some(context) => Err(err.context(context)),
+                None => Err(err),
+            }
+        }
+    }
+}
+
+fn describe(payload: &(dyn Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "non-string panic payload".to_string())
+}
@@ -0,0 +1,91 @@
+//! Startup check of the Groth parameter cache.
+//!
+//! Missing parameters are otherwise only noticed by the first proving call,
+//! which then generates them for small sectors, stalling that phase, or fails
+//! deep inside a worker for large ones.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{bail, Context, Result};
+use storage_proofs_core::parameter_cache::parameter_cache_dir;
+
+/// Environment variable the proofs library reads the cache location from.
+pub const PARAMETER_CACHE_ENV: &str = "FIL_PROOFS_PARAMETER_CACHE";
+
+/// Structured reference string used by proof aggregation.
+const SRS_FILE: &str = "v28-fil-inner-product-v1.srs";
+
+/// Points the proofs library, and any child processes, at `dir`. Must run
+/// before the first proving call reads the setting.
+pub fn set_cache_dir(dir: &Path) {
+    env::set_var(PARAMETER_CACHE_ENV, dir);
+}
+
+/// The directory the proofs library loads parameters from.
+pub fn cache_dir() -> PathBuf {
+    parameter_cache_dir()
+}
+
+/// Checks that `dir` holds complete parameters for `sector_size`, and the
+/// SRS if `with_srs` is set.
+pub fn check(dir: &Path, sector_size: u64, with_srs: bool) -> Result<()> {
+    let entries = entries_for(dir, sector_size)?;
+    if entries.is_empty() {
+        bail!(
+            "no parameters for sector size {} in {}",
+            sector_size,
+            dir.display()
+        );
+    }
+    for stem in &entries {
+        for ext in ["params", "vk"].iter() {
+            let path = dir.join(format!("{}.{}", stem, ext));
+            let len = fs::metadata(&path).map(|meta| meta.len()).unwrap_or(0);
+            if len == 0 {
+                bail!("parameter file {} is missing or empty", path.display());
+            }
+        }
+    }
+    if with_srs && !dir.join(SRS_FILE).exists() {
+        bail!("aggregation needs {} in {}", SRS_FILE, dir.display());
+    }
+    Ok(())
+}
+
+/// Stems of the parameter sets in `dir` whose metadata names `sector_size`.
+fn entries_for(dir: &Path, sector_size: u64) -> Result<Vec<String>> {
+    let mut stems = Vec::new();
+    let entries = fs::read_dir(dir).with_context(|| format!("failed to read {}", dir.display()))?;
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("meta") {
+            continue;
+        }
+        let meta: serde_json::Value = serde_json::from_str(&fs::read_to_string(&path)?)
+            .with_context(|| format!("invalid parameter metadata {}", path.display()))?;
+        if meta["sector_size"].as_u64() != Some(sector_size) {
+            continue;
+        }
+        if let Some(stem) = path.file_stem().and_then(|stem| stem.to_str()) {
+            stems.push(stem.to_string());
+        }
+    }
+    stems.sort();
+    Ok(stems)
+}
+
+/// Downloads the parameters for `sector_size` with filecoin-proofs'
+/// `paramfetch`, which has to be on the `PATH`.
+pub fn fetch(sector_size: u64) -> Result<()> {
+    let status = Command::new("paramfetch")
+        .arg(format!("--sector-sizes={}", sector_size))
+        .status()
+        .context("failed to run paramfetch")?;
+    if !status.success() {
+        bail!("paramfetch failed: {}", status);
+    }
+    Ok(())
+}
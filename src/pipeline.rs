@@ -0,0 +1,282 @@
+//! Bounded per-phase concurrency, like lotus's sealing scheduler.
+//!
+//! With `--max-pc1`, `--max-pc2` or `--max-c2`, each worker thread carries
+//! one sector through its lifecycle, and at the start of a limited phase it
+//! queues until one of that phase's slots is free. Sectors are let in first
+//! come, first served, so the threads form a PC1 → PC2 → C1 → C2 pipeline
+//! with a queue in front of every limited phase, the way lotus runs sealing
+//! in production. The thread count sets how many sectors are in flight.
+//!
+//! With `--post-priority`, WindowPoSt takes C2's slots too, both need the
+//! GPU, but it joins the queue ahead of every waiting C2, like lotus's PoSt
+//! priority. Whether a PoSt that jumped the queue can then deadlock against
+//! a C2 holding the GPU lock is what the lane is for; the report counts the
+//! C2s it overtook.
+//!
+//! With `--chaos reassign-pc2:<probability>` workers die as they start PC2,
+//! the way a lotus-worker crashes mid-task. The sector is handed back to the
+//! pipeline first and the next worker starting a seal lifecycle finishes it
+//! from the PC1 checkpoint, verifying its proof; the run fails if a sector
+//! was never finished.
+//!
+//! Only thread workers share the queues.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write;
+use std::sync::Condvar;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use filecoin_proofs::PoRepConfig;
+use lazy_static::lazy_static;
+use tracing::{info, warn};
+
+use crate::artifacts::SectorFiles;
+use crate::lock_order::{self, Held, OrderedGuard, OrderedMutex};
+use crate::metrics::Phase;
+
+/// How often a queued worker tells the watchdog it is still waiting.
+const WAIT_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Default)]
+struct Stage {
+    limit: usize,
+    running: usize,
+    /// Tickets of the workers waiting for a slot, in the order they are let
+    /// in, and whether they are in the priority lane.
+    queue: VecDeque<(u64, bool)>,
+    next_ticket: u64,
+    /// The longest the queue ever got.
+    max_queued: usize,
+    entered: usize,
+    waited: Duration,
+    max_wait: Duration,
+    /// Priority entries, and the waiting entries they overtook.
+    prioritized: usize,
+    overtaken: usize,
+}
+
+/// Sectors whose worker was killed during PC2.
+#[derive(Default)]
+struct Reassignments {
+    waiting: VecDeque<(SectorFiles, PoRepConfig)>,
+    handed_over: usize,
+    finished: usize,
+}
+
+lazy_static! {
+    static ref REASSIGNMENTS: OrderedMutex<Reassignments> =
+        OrderedMutex::new("pipeline reassignments", Reassignments::default());
+    static ref STAGES: OrderedMutex<HashMap<Phase, Stage>> =
+        OrderedMutex::new("pipeline stages", HashMap::new());
+    static ref FREED: Condvar = Condvar::new();
+    static ref POST_PRIORITY: OrderedMutex<bool> =
+        OrderedMutex::new("pipeline post priority", false);
+}
+
+/// Lets at most `limit` workers run each of `limits` phases at a time.
+pub fn enable(limits: &[(Phase, usize)]) {
+    let mut stages = lock();
+    for &(phase, limit) in limits {
+        stages.insert(
+            phase,
+            Stage {
+                limit,
+                ..Stage::default()
+            },
+        );
+    }
+}
+
+/// Puts WindowPoSt in C2's queue, ahead of the waiting C2s. C2 has to be
+/// limited.
+pub fn enable_post_priority() {
+    *POST_PRIORITY.lock() = true;
+}
+
+/// The stage `phase` queues at and whether it takes the priority lane.
+fn stage_of(phase: Phase) -> (Phase, bool) {
+    match phase {
+        Phase::WindowPost if *POST_PRIORITY.lock() => (Phase::Commit2, true),
+        phase => (phase, false),
+    }
+}
+
+/// A slot of a limited phase, given back on drop.
+pub(crate) struct Slot {
+    phase: Phase,
+    _held: Held,
+}
+
+/// Queues for a slot of `phase` if it is limited.
+pub(crate) fn enter(phase: Phase) -> Option<Slot> {
+    let (stage_phase, priority) = stage_of(phase);
+    let mut stages = lock();
+    let stage = stages.get_mut(&stage_phase)?;
+    let ticket = stage.next_ticket;
+    stage.next_ticket += 1;
+    if priority {
+        let at = stage
+            .queue
+            .iter()
+            .position(|(_, prioritized)| !prioritized)
+            .unwrap_or_else(|| stage.queue.len());
+        let overtaken = stage.queue.len() - at;
+        stage.queue.insert(at, (ticket, true));
+        stage.prioritized += 1;
+        stage.overtaken += overtaken;
+        if overtaken > 0 {
+            info!(overtaken, "{} jumps the {} queue", phase, stage_phase);
+        }
+    } else {
+        stage.queue.push_back((ticket, false));
+    }
+    stage.max_queued = stage.max_queued.max(stage.queue.len());
+
+    let start = Instant::now();
+    loop {
+        let stage = stages.get_mut(&stage_phase).expect("stage disappeared");
+        if stage.running < stage.limit && stage.queue.front() == Some(&(ticket, priority)) {
+            stage.queue.pop_front();
+            stage.running += 1;
+            break;
+        }
+        stages = stages.wait_timeout(&FREED, WAIT_INTERVAL).0;
+        // Waiting for a slot is expected, the stuck phase is the one
+        // holding it.
+        crate::watchdog::waiting();
+    }
+
+    let waited = start.elapsed();
+    let stage = stages.get_mut(&stage_phase).expect("stage disappeared");
+    stage.entered += 1;
+    stage.waited += waited;
+    stage.max_wait = stage.max_wait.max(waited);
+    let queued = stage.queue.len();
+    // The next in line may fit too.
+    FREED.notify_all();
+    drop(stages);
+    if waited >= WAIT_INTERVAL {
+        info!(
+            waited_secs = waited.as_secs_f64(),
+            queued, "got a {} slot", phase
+        );
+    }
+    // Taken once the stages are unlocked again, which slot holders lock to
+    // give theirs back.
+    Some(Slot {
+        phase: stage_phase,
+        _held: lock_order::acquire(format!("pipeline {} slot", stage_phase)),
+    })
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        if let Some(stage) = lock().get_mut(&self.phase) {
+            stage.running -= 1;
+        }
+        FREED.notify_all();
+    }
+}
+
+/// Hands the sector in `files`, sealed with `config`, over to another
+/// worker; its PC1 output has to be checkpointed.
+pub(crate) fn hand_over(files: &SectorFiles, config: PoRepConfig) -> Result<()> {
+    let files = files.hand_over()?;
+    warn!("handing {} over to another worker", files.dir().display());
+    let mut reassignments = REASSIGNMENTS.lock();
+    reassignments.waiting.push_back((files, config));
+    reassignments.handed_over += 1;
+    Ok(())
+}
+
+/// A sector another worker handed over, for the caller to finish.
+pub(crate) fn take_over() -> Option<(SectorFiles, PoRepConfig)> {
+    let (files, config) = REASSIGNMENTS.lock().waiting.pop_front()?;
+    info!("taking over {}", files.dir().display());
+    Some((files, config))
+}
+
+/// Called once a sector that was taken over has a verified proof.
+pub(crate) fn reassignment_finished() {
+    REASSIGNMENTS.lock().finished += 1;
+}
+
+/// How many sectors handed over never got a verified proof.
+pub fn unfinished_reassignments() -> usize {
+    let reassignments = REASSIGNMENTS.lock();
+    reassignments.handed_over - reassignments.finished
+}
+
+/// Prints every limited phase with how long sectors queued for it.
+pub fn print_report() {
+    let stages = lock();
+    if stages.is_empty() {
+        return;
+    }
+    let mut phases = stages.keys().copied().collect::<Vec<_>>();
+    phases.sort();
+    println!();
+    println!(
+        "{:<8} {:>6} {:>8} {:>11} {:>14} {:>13}",
+        "phase", "limit", "entered", "max queued", "mean wait (s)", "max wait (s)"
+    );
+    for phase in phases {
+        let stage = &stages[&phase];
+        let mean = match stage.entered {
+            0 => 0.0,
+            entered => stage.waited.as_secs_f64() / entered as f64,
+        };
+        println!(
+            "{:<8} {:>6} {:>8} {:>11} {:>14.1} {:>13.1}",
+            phase.to_string(),
+            stage.limit,
+            stage.entered,
+            stage.max_queued,
+            mean,
+            stage.max_wait.as_secs_f64()
+        );
+        if stage.prioritized > 0 {
+            println!(
+                "    {} WindowPoSt entries in the priority lane overtook {} queued C2s",
+                stage.prioritized, stage.overtaken
+            );
+        }
+    }
+    let reassignments = REASSIGNMENTS.lock();
+    if reassignments.handed_over > 0 {
+        println!(
+            "{} sectors handed over by workers killed in PC2, {} finished with a verified proof",
+            reassignments.handed_over, reassignments.finished
+        );
+    }
+}
+
+/// The slots and queues of every limited phase, for the watchdog's dump.
+pub(crate) fn describe() -> String {
+    let stages = lock();
+    let mut phases = stages.keys().copied().collect::<Vec<_>>();
+    phases.sort();
+    let mut lines = String::new();
+    for phase in phases {
+        let stage = &stages[&phase];
+        let _ = writeln!(
+            lines,
+            "pipeline {}: {}/{} running, {} queued, {} of them prioritized",
+            phase,
+            stage.running,
+            stage.limit,
+            stage.queue.len(),
+            stage
+                .queue
+                .iter()
+                .filter(|(_, prioritized)| *prioritized)
+                .count()
+        );
+    }
+    lines
+}
+
+fn lock() -> OrderedGuard<'static, HashMap<Phase, Stage>> {
+    STAGES.lock()
+}
@@ -0,0 +1,171 @@
+//! The execution plan `--dry-run` prints instead of running.
+//!
+//! Disk and memory figures are rough estimates from the sector size and the
+//! number of SDR layers, meant to tell whether a configuration fits the
+//! machine at all, not to size it precisely.
+
+use crate::sweep::Cell;
+use crate::workloads::{WorkerConfig, Workload};
+
+/// Rough resources a single seal lifecycle holds at its peak.
+#[derive(Clone, Copy, Debug)]
+pub struct Footprint {
+    /// Staged and sealed sector, the SDR layers and the trees.
+    pub disk: u64,
+    /// PC1 keeps the previous layer and the parents cache in memory.
+    pub memory: u64,
+}
+
+pub fn seal_footprint(sector_size: u64) -> Footprint {
+    let layers = crate::layers::total(sector_size) as u64;
+    // tree_d is twice the sector, tree_c and tree_r_last a little over it.
+    let trees = 2 * sector_size + 2 * (sector_size + sector_size / 7);
+    Footprint {
+        disk: 2 * sector_size + layers * sector_size + trees,
+        memory: 2 * sector_size,
+    }
+}
+
+/// Prints every worker with what it will run, followed by the sectors sealed
+/// up front and the estimated peak disk and memory use.
+pub fn print_plan(
+    groups: &[(usize, WorkerConfig)],
+    sector_size: u64,
+    prepared_count: usize,
+    process_workers: bool,
+) {
+    let workers = groups
+        .iter()
+        .flat_map(|(threads, config)| std::iter::repeat(config).take(*threads))
+        .collect::<Vec<_>>();
+    println!(
+        "Plan: {} {}, sector size {}",
+        workers.len(),
+        if process_workers {
+            "worker processes"
+        } else {
+            "worker threads"
+        },
+        sector_size
+    );
+    println!(
+        "{:<8} {:<16} {:<14} {:<8} {:>10} {:<18} {}",
+        "worker", "group", "workload", "phase", "iterations", "api versions", "cores"
+    );
+    for (index, config) in workers.iter().enumerate() {
+        let api_versions = config
+            .worker_api_versions(index)
+            .iter()
+            .map(|(version, _)| format!("{:?}", version))
+            .collect::<Vec<_>>()
+            .join(",");
+        let cores = match &config.pin_cores {
+            Some(sets) => format!("{:?}", sets.for_worker(index)),
+            None => "any".to_string(),
+        };
+        println!(
+            "{:<8} {:<16} {:<14} {:<8} {:>10} {:<18} {}",
+            index,
+            config.group,
+            format!("{:?}", config.workload),
+            config
+                .phase
+                .map_or("all".to_string(), |phase| phase.to_string()),
+            match config.iterations {
+                0 => "forever".to_string(),
+                iterations => iterations.to_string(),
+            },
+            api_versions,
+            cores
+        );
+    }
+
+    let footprint = seal_footprint(sector_size);
+    let sealing = workers
+        .iter()
+        .filter(|config| config.workload == Workload::Seal)
+        .count() as u64;
+    // Batched sectors hold their disk until committed, but run PC1 in turn.
+    let batched = workers
+        .iter()
+        .filter(|config| config.workload == Workload::Seal)
+        .map(|config| config.sectors_per_worker)
+        .sum::<usize>() as u64;
+    let api_versions = workers
+        .iter()
+        .map(|config| config.api_versions.len())
+        .max()
+        .unwrap_or(0);
+    let prepared = if workers
+        .iter()
+        .any(|config| config.workload.needs_sealed_sectors())
+    {
+        let copies = if process_workers { workers.len() } else { 1 };
+        (prepared_count * api_versions * copies) as u64
+    } else {
+        0
+    };
+
+    println!();
+    println!(
+        "Per sealed sector: about {} of disk and {} of memory",
+        human(footprint.disk),
+        human(footprint.memory)
+    );
+    if prepared > 0 {
+        println!(
+            "Sealed up front: {} sectors, about {} of disk{}",
+            prepared,
+            human(prepared * footprint.disk),
+            if process_workers {
+                ", by every worker process"
+            } else {
+                ""
+            }
+        );
+    }
+    println!(
+        "Peak with every worker sealing at once: about {} of disk and {} of memory",
+        human((batched + prepared) * footprint.disk),
+        human(sealing * footprint.memory)
+    );
+}
+
+/// Prints the cells a sweep would run and how long it takes in total.
+pub fn print_sweep(cells: &[Cell], duration_secs: u64) {
+    println!(
+        "Plan: {} sweep cells of {}s each, {}s in total",
+        cells.len(),
+        duration_secs,
+        cells.len() as u64 * duration_secs
+    );
+    println!(
+        "{:>4} {:>8} {:<12} {:<16} {:<14} {:>14}",
+        "cell", "threads", "sector size", "api versions", "workload", "sealing disk"
+    );
+    for (i, cell) in cells.iter().enumerate() {
+        let disk = crate::lifecycle::parse_sector_size(&cell.sector_size)
+            .map(|size| human(cell.threads as u64 * seal_footprint(size).disk))
+            .unwrap_or_default();
+        println!(
+            "{:>4} {:>8} {:<12} {:<16} {:<14} {:>14}",
+            i + 1,
+            cell.threads,
+            cell.sector_size,
+            cell.api_versions,
+            cell.workload,
+            disk
+        );
+    }
+}
+
+fn human(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
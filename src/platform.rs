@@ -0,0 +1,75 @@
+//! The reduced feature set off Linux.
+//!
+//! Thread inspection, core pinning, NUMA binding, tmpfs mounts, cgroups and
+//! the GPU lock monitor all go through procfs, sysfs or Linux syscalls. On
+//! other Unixes, e.g. macOS, the flags that need them are refused up front,
+//! the GPU is turned off and sectors are capped at [`MAX_SECTOR_SIZE`], so
+//! the CPU-only 2KiB lifecycle still runs to debug the orchestration. The
+//! hang diagnostics that only add detail, such as per-thread CPU use in the
+//! watchdog dump, are left out quietly.
+
+use std::env;
+use std::fs;
+
+use anyhow::{bail, Result};
+use filecoin_proofs::SECTOR_SIZE_8_MIB;
+
+/// Whether every feature is available.
+pub const FULL: bool = cfg!(target_os = "linux");
+
+/// The largest sector sealed off Linux, where the proofs run on the CPU.
+pub const MAX_SECTOR_SIZE: u64 = SECTOR_SIZE_8_MIB;
+
+/// Environment variables that keep bellperson and the proofs library off
+/// the GPU.
+const NO_GPU_ENV: [(&str, &str); 3] = [
+    ("BELLMAN_NO_GPU", "1"),
+    ("FIL_PROOFS_USE_GPU_COLUMN_BUILDER", "0"),
+    ("FIL_PROOFS_USE_GPU_TREE_BUILDER", "0"),
+];
+
+/// Refuses `sector_size` and the used ones of `flags` if this platform
+/// cannot run them, and turns the GPU off. Does nothing on Linux; has to
+/// run before the proofs library reads its settings.
+pub fn restrict(sector_size: u64, flags: &[(&str, bool)]) -> Result<()> {
+    if FULL {
+        return Ok(());
+    }
+    let unsupported = flags
+        .iter()
+        .filter(|(_, used)| *used)
+        .map(|(flag, _)| format!("--{}", flag))
+        .collect::<Vec<_>>();
+    if !unsupported.is_empty() {
+        bail!("{} only work on Linux", unsupported.join(", "));
+    }
+    if sector_size > MAX_SECTOR_SIZE {
+        bail!(
+            "sectors above {} bytes are only sealed on Linux, try --fast",
+            MAX_SECTOR_SIZE
+        );
+    }
+    for (var, value) in NO_GPU_ENV.iter() {
+        env::set_var(var, value);
+    }
+    Ok(())
+}
+
+/// The name of this machine, `unknown` if it has none.
+pub fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    let res = unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) };
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    match res {
+        0 => String::from_utf8_lossy(&buf[..len]).into_owned(),
+        _ => "unknown".to_string(),
+    }
+}
+
+/// Tells boots of this machine apart, where the kernel says; pids are only
+/// unique within one.
+pub fn boot_id() -> Option<String> {
+    fs::read_to_string("/proc/sys/kernel/random/boot_id")
+        .ok()
+        .map(|id| id.trim().to_string())
+}
@@ -0,0 +1,121 @@
+//! A postmortem directory for hung or panicked runs.
+//!
+//! With `--postmortem-dir`, the watchdog firing or the first panic collects
+//! everything that is gone once the process exits into a fresh
+//! `postmortem-<unix secs>-<pid>` directory: what happened, the worker
+//! table, native stacks of every thread, the latest log lines and
+//! heartbeats, `/proc/self/maps`, the environment and the open file
+//! descriptors. Collecting is best effort, a file that cannot be written is
+//! skipped.
+
+use std::env;
+use std::fs;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use lazy_static::lazy_static;
+use tracing::{error, info};
+
+lazy_static! {
+    static ref ROOT: Mutex<Option<PathBuf>> = Mutex::new(None);
+}
+
+/// Only the first hang or panic is collected.
+static COLLECTED: AtomicBool = AtomicBool::new(false);
+
+/// Collects a postmortem under `root` on the first hang or panic.
+pub fn enable(root: PathBuf) -> Result<()> {
+    fs::create_dir_all(&root).with_context(|| format!("failed to create {}", root.display()))?;
+    *ROOT.lock().expect("postmortem root poisoned") = Some(root);
+    crate::logging::keep_recent();
+
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        previous(info);
+        collect(
+            &format!("panic: {}", info),
+            "",
+            crate::watchdog::capture_native_stacks().as_deref(),
+        );
+    }));
+    Ok(())
+}
+
+/// Writes the postmortem of a hang or panic, described by `reason`, with the
+/// watchdog's `workers` table and the native `stacks` if there are any.
+pub(crate) fn collect(reason: &str, workers: &str, stacks: Option<&str>) {
+    let root = match ROOT.lock().expect("postmortem root poisoned").clone() {
+        Some(root) => root,
+        None => return,
+    };
+    if COLLECTED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    let dir = root.join(format!("postmortem-{}-{}", secs, std::process::id()));
+    if let Err(err) = fs::create_dir_all(&dir) {
+        error!("postmortem: failed to create {}: {}", dir.display(), err);
+        return;
+    }
+
+    write(&dir, "reason.txt", format!("{}\n", reason));
+    if !workers.is_empty() {
+        write(&dir, "workers.txt", workers.to_string());
+    }
+    if let Some(stacks) = stacks {
+        write(&dir, "stacks.txt", stacks.to_string());
+    }
+    write(&dir, "log.txt", crate::logging::recent().concat());
+    let heartbeats = crate::heartbeat::history();
+    if !heartbeats.is_empty() {
+        write(&dir, "heartbeats.jsonl", lines(heartbeats));
+    }
+    if let Ok(maps) = fs::read_to_string("/proc/self/maps") {
+        write(&dir, "maps.txt", maps);
+    }
+    let mut vars = env::vars_os()
+        .map(|(var, value)| format!("{}={}", var.to_string_lossy(), value.to_string_lossy()))
+        .collect::<Vec<_>>();
+    vars.sort();
+    write(&dir, "environ.txt", lines(vars));
+    if let Some(fds) = open_fds() {
+        write(&dir, "fds.txt", lines(fds));
+    }
+    info!("postmortem written to {}", dir.display());
+}
+
+fn write(dir: &Path, name: &str, contents: String) {
+    let path = dir.join(name);
+    if let Err(err) = fs::write(&path, contents) {
+        error!("postmortem: failed to write {}: {}", path.display(), err);
+    }
+}
+
+fn lines(lines: Vec<String>) -> String {
+    lines.into_iter().map(|line| line + "\n").collect()
+}
+
+/// Every open file descriptor with what it points to.
+fn open_fds() -> Option<Vec<String>> {
+    let mut fds = fs::read_dir("/proc/self/fd")
+        .ok()?
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let fd = path.file_name()?.to_str()?.parse::<u32>().ok()?;
+            let target = fs::read_link(&path).ok()?;
+            Some((fd, target))
+        })
+        .collect::<Vec<_>>();
+    fds.sort();
+    Some(
+        fds.into_iter()
+            .map(|(fd, target)| format!("{} -> {}", fd, target.display()))
+            .collect(),
+    )
+}
@@ -0,0 +1,238 @@
+//! Runs every worker in a child process of its own.
+//!
+//! Children are this binary started again with the parent's arguments plus
+//! `--worker-index`. Each runs a single worker and streams its lifecycle
+//! records back as JSON lines on stdout; anything else it prints, its log
+//! events tagged with the worker span included, is passed through.
+
+use std::env;
+use std::ffi::OsString;
+use std::fmt;
+use std::io::{BufRead, BufReader};
+use std::os::unix::process::CommandExt;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use anyhow::Result;
+use storage_proofs_core::api_version::ApiVersion;
+use tracing::error;
+
+use crate::artifacts;
+use crate::cgroup::{self, WorkerCgroups};
+use crate::env_preset::Toggles;
+use crate::exporter;
+use crate::gpu_devices::GpuDevices;
+use crate::logging;
+use crate::metrics::{LifecycleRecord, PhaseTimeout};
+use crate::watchdog::{self, Watchdog};
+use crate::workloads::{run_worker, SealedSector, WorkerConfig, WorkerEvent};
+
+/// Hidden flag selecting the worker a child process runs.
+pub const WORKER_INDEX_ARG: &str = "worker-index";
+
+/// Hidden flag telling a churning child which lifecycle it runs.
+pub const FIRST_LIFECYCLE_ARG: &str = "first-lifecycle";
+
+/// Marks the stdout lines of a child that carry a lifecycle record.
+const RECORD_PREFIX: &str = "@@record ";
+
+/// Exit code of a child that abandoned a phase after the phase timeout.
+pub const PHASE_TIMEOUT_EXIT_CODE: i32 = 87;
+
+/// A worker process exited with an error; its exit code tells what kind.
+#[derive(Debug)]
+pub struct ChildFailed {
+    pub status: ExitStatus,
+}
+
+impl fmt::Display for ChildFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "worker process {}", self.status)
+    }
+}
+
+impl std::error::Error for ChildFailed {}
+
+/// Spawns the child processes of [`crate::workloads::WorkerMode::Process`].
+#[derive(Clone, Debug)]
+pub struct ProcessWorkers {
+    args: Vec<OsString>,
+    gpu_devices: Option<GpuDevices>,
+    cgroups: Option<Arc<WorkerCgroups>>,
+}
+
+impl ProcessWorkers {
+    /// Children get the arguments of this process followed by `extra_args`.
+    pub fn new<I: IntoIterator<Item = String>>(extra_args: I) -> Self {
+        let args = env::args_os()
+            .skip(1)
+            .chain(extra_args.into_iter().map(OsString::from))
+            .collect();
+        ProcessWorkers {
+            args,
+            gpu_devices: None,
+            cgroups: None,
+        }
+    }
+
+    /// Children get exactly `args`, for workers configured by another
+    /// process's command line.
+    pub fn with_args<I: IntoIterator<Item = String>>(args: I) -> Self {
+        ProcessWorkers {
+            args: args.into_iter().map(OsString::from).collect(),
+            gpu_devices: None,
+            cgroups: None,
+        }
+    }
+
+    /// Restricts every child to the GPUs `devices` assigns to it.
+    pub fn with_gpu_devices(mut self, devices: GpuDevices) -> Self {
+        self.gpu_devices = Some(devices);
+        self
+    }
+
+    /// Starts every child in its cgroup of `cgroups`.
+    pub fn with_cgroups(mut self, cgroups: Arc<WorkerCgroups>) -> Self {
+        self.cgroups = Some(cgroups);
+        self
+    }
+
+    /// Starts worker `index` at `first_lifecycle` with the proofs settings
+    /// of `toggles`, and a thread forwarding its events, returning the
+    /// child's pid along with that thread.
+    pub(crate) fn spawn(
+        &self,
+        index: usize,
+        first_lifecycle: usize,
+        toggles: Toggles,
+        events: Sender<WorkerEvent>,
+    ) -> Result<(u32, JoinHandle<()>)> {
+        let mut command = Command::new(env::current_exe()?);
+        command
+            .args(&self.args)
+            .arg(format!("--{}={}", WORKER_INDEX_ARG, index))
+            .stdout(Stdio::piped());
+        if first_lifecycle > 0 {
+            command.arg(format!("--{}={}", FIRST_LIFECYCLE_ARG, first_lifecycle));
+        }
+        if let Some(devices) = &self.gpu_devices {
+            command.envs(devices.env(index));
+        }
+        command.envs(toggles.env());
+        // A process group of its own keeps the terminal's SIGINT away from the
+        // child, the parent forwards a single SIGTERM instead.
+        let procs = self.cgroups.as_ref().map(|cgroups| cgroups.procs(index));
+        unsafe {
+            command.pre_exec(move || {
+                libc::setpgid(0, 0);
+                match &procs {
+                    Some(procs) => cgroup::join(procs),
+                    None => Ok(()),
+                }
+            });
+        }
+        let mut child = command.spawn()?;
+        let pid = child.id();
+        let stdout = child.stdout.take().expect("child stdout is piped");
+
+        let handle = thread::Builder::new()
+            .name(format!("worker-{}", index))
+            .spawn(move || {
+                for line in BufReader::new(stdout).lines() {
+                    let line = match line {
+                        Ok(line) => line,
+                        Err(_) => break,
+                    };
+                    match line.strip_prefix(RECORD_PREFIX) {
+                        Some(json) => match serde_json::from_str::<LifecycleRecord>(json) {
+                            Ok(record) => {
+                                exporter::lifecycle_finished(&record.group, record.success);
+                                let _ = events.send(WorkerEvent::Lifecycle(record));
+                            }
+                            Err(err) => error!(worker = index, "invalid record: {}", err),
+                        },
+                        None => println!("{}", line),
+                    }
+                }
+
+                let res = match child.wait() {
+                    Ok(status) if status.success() => Ok(()),
+                    Ok(status) if status.code() == Some(PHASE_TIMEOUT_EXIT_CODE) => {
+                        let _ = events.send(WorkerEvent::TimedOut(index));
+                        return;
+                    }
+                    Ok(status) => Err(ChildFailed { status }.into()),
+                    Err(err) => Err(err.into()),
+                };
+                let _ = events.send(WorkerEvent::Finished(index, res));
+            })?;
+        Ok((pid, handle))
+    }
+}
+
+/// Asks the children to stop after their current lifecycle.
+pub(crate) fn terminate(pids: &[u32]) {
+    signal(pids, libc::SIGTERM);
+}
+
+/// Kills children that did not stop in time.
+pub(crate) fn kill(pids: &[u32]) {
+    signal(pids, libc::SIGKILL);
+}
+
+fn signal(pids: &[u32], signal: libc::c_int) {
+    for pid in pids {
+        unsafe {
+            libc::kill(*pid as libc::pid_t, signal);
+        }
+    }
+}
+
+/// Child side: runs worker `index` with its own watchdog and prints its
+/// records for the parent. A phase timeout ends the child with
+/// [`PHASE_TIMEOUT_EXIT_CODE`], leaving retries to the parent.
+pub fn run_child(
+    index: usize,
+    config: &WorkerConfig,
+    sealed_sectors: Arc<Vec<(ApiVersion, Vec<SealedSector>)>>,
+    hang_timeout: Duration,
+) -> Result<()> {
+    logging::open_worker_log(index, true)?;
+    let watchdog = Watchdog::new(1, hang_timeout);
+    if hang_timeout > Duration::from_secs(0) {
+        watchdog.clone().spawn();
+    }
+
+    let (events, rx) = mpsc::channel();
+    let printer = thread::spawn(move || {
+        for event in rx {
+            if let WorkerEvent::Lifecycle(record) = event {
+                let json = serde_json::to_string(&record).expect("failed to encode record");
+                println!("{}{}", RECORD_PREFIX, json);
+            }
+        }
+    });
+
+    let config = WorkerConfig {
+        retries: 0,
+        ..config.clone()
+    };
+    watchdog.attach(0);
+    let res = run_worker(index, &config, &sealed_sectors, &events);
+    watchdog::detach();
+
+    drop(events);
+    printer.join().expect("record printer panicked");
+    match res {
+        // The abandoned phase may still hold locks, only exiting frees them.
+        Err(err) if err.is::<PhaseTimeout>() => {
+            error!(worker = index, "{}", err);
+            artifacts::finish_run(false);
+            std::process::exit(PHASE_TIMEOUT_EXIT_CODE);
+        }
+        res => res,
+    }
+}
@@ -0,0 +1,129 @@
+//! A flamegraph of the process once the watchdog found it hung.
+//!
+//! `perf` samples the whole process for [`PROFILE_WINDOW`], and its stacks
+//! are folded into an SVG by inferno, or by the FlameGraph scripts as a
+//! fallback. Without either, the raw `perf.data` is kept for `perf report`.
+
+use std::ffi::OsString;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use lazy_static::lazy_static;
+use tracing::{error, info};
+
+/// How long the hung process is sampled for.
+const PROFILE_WINDOW: Duration = Duration::from_secs(10);
+const SAMPLE_FREQUENCY: &str = "99";
+
+/// Stack folding and rendering tools, tried in order.
+const RENDERERS: [(&str, &str); 2] = [
+    ("inferno-collapse-perf", "inferno-flamegraph"),
+    ("stackcollapse-perf.pl", "flamegraph.pl"),
+];
+
+lazy_static! {
+    static ref OUTPUT: Mutex<Option<PathBuf>> = Mutex::new(None);
+}
+
+/// Writes a flamegraph to `path` when the watchdog fires.
+pub fn enable(path: PathBuf) {
+    *OUTPUT.lock().expect("profile output poisoned") = Some(path);
+}
+
+/// Profiles this process and writes the flamegraph, if enabled.
+pub(crate) fn capture() {
+    let path = match OUTPUT.lock().expect("profile output poisoned").clone() {
+        Some(path) => path,
+        None => return,
+    };
+    error!(
+        "watchdog: profiling for {:?} before exiting",
+        PROFILE_WINDOW
+    );
+    match flamegraph(&path) {
+        Ok(()) => info!("watchdog: wrote a flamegraph to {}", path.display()),
+        Err(err) => error!("watchdog: no flamegraph: {:#}", err),
+    }
+}
+
+fn flamegraph(path: &Path) -> Result<()> {
+    let mut data = OsString::from(path.as_os_str());
+    data.push(".perf.data");
+    let data = PathBuf::from(data);
+
+    let pid = std::process::id().to_string();
+    let window = PROFILE_WINDOW.as_secs().to_string();
+    let output = Command::new("perf")
+        .args(&["record", "-F", SAMPLE_FREQUENCY, "-g", "-p", &pid, "-o"])
+        .arg(&data)
+        .args(&["--", "sleep", &window])
+        .output()
+        .context("could not run perf")?;
+    if !output.status.success() {
+        bail!(
+            "perf record failed, check kernel.perf_event_paranoid: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    let script = Command::new("perf")
+        .args(&["script", "-i"])
+        .arg(&data)
+        .output()
+        .context("could not run perf script")?;
+    if !script.status.success() {
+        bail!(
+            "perf script failed: {}",
+            String::from_utf8_lossy(&script.stderr).trim()
+        );
+    }
+
+    for (collapse, render) in RENDERERS.iter() {
+        let folded = match pipe(collapse, &script.stdout) {
+            Ok(folded) => folded,
+            Err(_) => continue,
+        };
+        let svg = pipe(render, &folded)?;
+        fs::write(path, svg).with_context(|| format!("failed to write {}", path.display()))?;
+        let _ = fs::remove_file(&data);
+        return Ok(());
+    }
+    bail!(
+        "neither inferno nor the FlameGraph scripts are installed, the samples are in {}",
+        data.display()
+    )
+}
+
+/// Runs `tool` with `input` on its stdin and returns its stdout.
+fn pipe(tool: &str, input: &[u8]) -> Result<Vec<u8>> {
+    let mut child = Command::new(tool)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("could not run {}", tool))?;
+    // Fed from a thread of its own, so a tool that writes before it read
+    // everything cannot block on a full pipe. One that dies early only
+    // closes the pipe; its status tells.
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let input = input.to_vec();
+    let writer = thread::spawn(move || {
+        let _ = stdin.write_all(&input);
+    });
+    let output = child.wait_with_output()?;
+    let _ = writer.join();
+    if !output.status.success() {
+        bail!(
+            "{} failed: {}",
+            tool,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(output.stdout)
+}
@@ -0,0 +1,230 @@
+//! Records the order in which workers enter phases and get the GPU lock, and
+//! replays a recorded order on a later run.
+//!
+//! A replay holds every worker at the start of a phase until the recording
+//! says it is its turn, so runs with the same configuration and seed go
+//! through phases in the same interleaving. The GPU lock is taken inside
+//! bellperson, where no barrier can reach; its order is only compared with
+//! the recording, and only for acquisitions the sampler of
+//! [`crate::gpu_lock`] saw a thread wait for. Once the run takes a step the
+//! recording does not have, the replay reports where and lets workers run
+//! freely.
+//!
+//! Only thread workers are recorded and replayed.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Condvar;
+
+use anyhow::{Context, Result};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::lock_order::{OrderedGuard, OrderedMutex};
+use crate::metrics::Phase;
+
+/// One step of a schedule, a line of JSON in its file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event {
+    /// `worker` started `phase`.
+    Phase { worker: usize, phase: Phase },
+    /// `worker` got the GPU lock during `phase` after waiting for it.
+    GpuLock { worker: usize, phase: Phase },
+}
+
+enum State {
+    Off,
+    Record(File),
+    Replay(Replay),
+}
+
+struct Replay {
+    phases: Vec<(usize, Phase)>,
+    next_phase: usize,
+    locks: Vec<(usize, Phase)>,
+    next_lock: usize,
+    lock_mismatches: usize,
+    /// The phase each held worker is waiting to start.
+    waiting: HashMap<usize, Phase>,
+    finished: HashSet<usize>,
+    diverged: Option<String>,
+}
+
+lazy_static! {
+    static ref STATE: OrderedMutex<State> = OrderedMutex::new("schedule", State::Off);
+    static ref TURN: Condvar = Condvar::new();
+}
+
+/// Writes the schedule of this run to `path`.
+pub fn record(path: &Path) -> Result<()> {
+    let file =
+        File::create(path).with_context(|| format!("failed to create {}", path.display()))?;
+    *lock() = State::Record(file);
+    Ok(())
+}
+
+/// Makes this run follow the schedule recorded in `path`.
+pub fn replay(path: &Path) -> Result<()> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let mut phases = Vec::new();
+    let mut locks = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let event = serde_json::from_str(line)
+            .with_context(|| format!("{}:{}: invalid event", path.display(), i + 1))?;
+        match event {
+            Event::Phase { worker, phase } => phases.push((worker, phase)),
+            Event::GpuLock { worker, phase } => locks.push((worker, phase)),
+        }
+    }
+    info!(
+        "Replaying {} phase starts and {} GPU lock acquisitions from {}",
+        phases.len(),
+        locks.len(),
+        path.display()
+    );
+    *lock() = State::Replay(Replay {
+        phases,
+        next_phase: 0,
+        locks,
+        next_lock: 0,
+        lock_mismatches: 0,
+        waiting: HashMap::new(),
+        finished: HashSet::new(),
+        diverged: None,
+    });
+    Ok(())
+}
+
+/// Records that `worker` starts `phase`, or holds it until its turn.
+pub(crate) fn enter_phase(worker: usize, phase: Phase) {
+    let mut state = lock();
+    loop {
+        let replay = match &mut *state {
+            State::Off => return,
+            State::Record(file) => {
+                write(file, Event::Phase { worker, phase });
+                return;
+            }
+            State::Replay(replay) if replay.diverged.is_some() => return,
+            State::Replay(replay) => replay,
+        };
+        replay.waiting.remove(&worker);
+        let reason = match replay.phases.get(replay.next_phase) {
+            Some(&(next, next_phase)) if next == worker && next_phase == phase => {
+                replay.next_phase += 1;
+                TURN.notify_all();
+                return;
+            }
+            None => Some("the recording ends".to_string()),
+            Some(&(next, next_phase)) if next == worker => Some(format!(
+                "worker {} started {} instead of {}",
+                worker, phase, next_phase
+            )),
+            Some(&(next, next_phase)) if replay.finished.contains(&next) => Some(format!(
+                "worker {} finished before starting {}",
+                next, next_phase
+            )),
+            Some(&(next, next_phase)) => match replay.waiting.get(&next) {
+                Some(waiting) => Some(format!(
+                    "worker {} started {} instead of {}",
+                    next, waiting, next_phase
+                )),
+                None => None,
+            },
+        };
+        if let Some(reason) = reason {
+            diverge(replay, reason);
+            return;
+        }
+        replay.waiting.insert(worker, phase);
+        state = state.wait(&TURN);
+    }
+}
+
+/// Records that `worker` got the GPU lock during `phase`, or compares that
+/// with the recording.
+pub(crate) fn gpu_lock_acquired(worker: usize, phase: Phase) {
+    match &mut *lock() {
+        State::Off => {}
+        State::Record(file) => write(file, Event::GpuLock { worker, phase }),
+        State::Replay(replay) => {
+            let expected = replay.locks.get(replay.next_lock).copied();
+            replay.next_lock += 1;
+            if expected != Some((worker, phase)) {
+                replay.lock_mismatches += 1;
+                warn!(
+                    worker,
+                    "replay: GPU lock acquisition {} went to worker {} in {}, recorded {}",
+                    replay.next_lock,
+                    worker,
+                    phase,
+                    match expected {
+                        Some((worker, phase)) => format!("worker {} in {}", worker, phase),
+                        None => "nothing".to_string(),
+                    }
+                );
+            }
+        }
+    }
+}
+
+/// Tells a replay that `worker` starts no more phases.
+pub(crate) fn finished(worker: usize) {
+    if let State::Replay(replay) = &mut *lock() {
+        replay.finished.insert(worker);
+        // A worker held for this one would wait forever.
+        if let Some(&(next, next_phase)) = replay.phases.get(replay.next_phase) {
+            if next == worker && replay.diverged.is_none() {
+                diverge(
+                    replay,
+                    format!("worker {} finished before starting {}", worker, next_phase),
+                );
+            }
+        }
+    }
+}
+
+/// Prints how much of the recording a replay followed.
+pub fn print_report() {
+    if let State::Replay(replay) = &*lock() {
+        println!();
+        println!(
+            "Replay: {} of {} phase starts in the recorded order{}",
+            replay.next_phase,
+            replay.phases.len(),
+            match &replay.diverged {
+                Some(reason) => format!(", diverged because {}", reason),
+                None => String::new(),
+            }
+        );
+        println!(
+            "Replay: {} of {} contended GPU lock acquisitions differed from the recording",
+            replay.lock_mismatches,
+            replay.next_lock.max(replay.locks.len())
+        );
+    }
+}
+
+fn diverge(replay: &mut Replay, reason: String) {
+    warn!(
+        "replay: {} at phase start {}, running unconstrained from here",
+        reason,
+        replay.next_phase + 1
+    );
+    replay.diverged = Some(reason);
+    TURN.notify_all();
+}
+
+fn write(file: &mut File, event: Event) {
+    let line = serde_json::to_string(&event).expect("failed to encode event");
+    let _ = file.write_all(format!("{}\n", line).as_bytes());
+}
+
+fn lock() -> OrderedGuard<'static, State> {
+    STATE.lock()
+}
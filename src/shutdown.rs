@@ -0,0 +1,47 @@
+//! SIGINT/SIGTERM handling.
+//!
+//! The first signal only raises a flag that workers poll between lifecycles;
+//! a second one terminates the process immediately.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Result;
+use lazy_static::lazy_static;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::flag;
+
+/// Exit code used when a second signal forces the process down.
+pub const FORCED_EXIT_CODE: i32 = 130;
+
+lazy_static! {
+    static ref REQUESTED: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
+}
+
+pub fn install() -> Result<()> {
+    for signal in [SIGINT, SIGTERM].iter() {
+        // Registered first so it only fires once the flag is already set.
+        flag::register_conditional_shutdown(*signal, FORCED_EXIT_CODE, REQUESTED.clone())?;
+        flag::register(*signal, REQUESTED.clone())?;
+    }
+    Ok(())
+}
+
+/// Requests a shutdown once `duration` has passed, as a first signal would.
+pub fn request_after(duration: Duration) {
+    let requested = REQUESTED.clone();
+    thread::Builder::new()
+        .name("deadline".into())
+        .spawn(move || {
+            thread::sleep(duration);
+            requested.store(true, Ordering::SeqCst);
+        })
+        .expect("failed to spawn deadline thread");
+}
+
+/// Whether a shutdown has been requested.
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
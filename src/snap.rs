@@ -0,0 +1,120 @@
+//! SnapDeals: encoding new data into a committed capacity sector that was
+//! sealed before, then proving and verifying that empty sector update.
+//!
+//! Only filecoin-proofs 11 has the update proofs, so this module is built
+//! with the `proofs-v11` feature alone.
+
+use std::fs;
+
+use anyhow::{ensure, Result};
+use filecoin_proofs::{
+    compute_comm_d, encode_into, generate_empty_sector_update_proof,
+    verify_empty_sector_update_proof, DefaultTreeHasher, MerkleTreeTrait, PoRepConfig,
+};
+use rand::Rng;
+use rand_xorshift::XorShiftRng;
+use storage_proofs_core::api_version::ApiVersion;
+
+use crate::artifacts::{ArtifactStore, SectorFiles, TempStore};
+use crate::lifecycle::{add_pieces, generate_piece_file, init_logger, piece_sizes, porep_config};
+use crate::metrics::{self, Phase};
+use crate::outcome::VerificationMismatch;
+use crate::piece_gen::PieceData;
+use crate::workloads::SealedSector;
+
+/// Fills a fresh copy of `sector`, a CC sector sealed up front, with
+/// `pieces` pieces of new data and proves the update. The sector key stays
+/// untouched, so every worker can update the same sector at once.
+pub fn snap_lifecycle<Tree: 'static + MerkleTreeTrait<Hasher = DefaultTreeHasher>>(
+    sector_size: u64,
+    porep_id: &[u8; 32],
+    api_version: ApiVersion,
+    sector: &SealedSector,
+    pieces: usize,
+    piece_data: PieceData,
+    rng: &mut XorShiftRng,
+) -> Result<()> {
+    init_logger();
+
+    let config = porep_config(sector_size, *porep_id, api_version);
+    let mut files = TempStore.sector("snap")?;
+    let res = update::<Tree>(config, sector, &files, pieces, piece_data, rng);
+    if res.is_err() {
+        files.failed();
+    }
+    res
+}
+
+/// Encodes new pieces into `files` on top of `sector`, then proves and
+/// verifies the update.
+fn update<Tree: 'static + MerkleTreeTrait<Hasher = DefaultTreeHasher>>(
+    config: PoRepConfig,
+    sector: &SealedSector,
+    files: &SectorFiles,
+    pieces: usize,
+    piece_data: PieceData,
+    rng: &mut XorShiftRng,
+) -> Result<()> {
+    let sector_size = u64::from(config.sector_size);
+    let mut piece_file = generate_piece_file(
+        &TempStore,
+        &files.piece(),
+        sector_size,
+        piece_data,
+        rng.gen(),
+    )?;
+    let piece_infos = add_pieces(
+        &TempStore,
+        &files.staged(),
+        &mut piece_file,
+        &piece_sizes(sector_size, pieces)?,
+        piece_data,
+    )?;
+    drop(piece_file);
+    fs::remove_file(files.piece())?;
+
+    // encode_into writes the new replica in place.
+    TempStore.create(&files.sealed())?.set_len(sector_size)?;
+    let encoded = metrics::time(Phase::SnapEncode, || {
+        encode_into::<Tree>(
+            config,
+            &files.sealed(),
+            &files.cache(),
+            &sector.files.sealed(),
+            &sector.files.cache(),
+            &files.staged(),
+            &piece_infos,
+        )
+    })?;
+    ensure!(
+        encoded.comm_d_new == compute_comm_d(config.sector_size, &piece_infos)?,
+        VerificationMismatch("the updated sector commits to other pieces".to_string())
+    );
+
+    let proof = metrics::time(Phase::SnapProve, || {
+        generate_empty_sector_update_proof::<Tree>(
+            config,
+            sector.comm_r,
+            encoded.comm_r_new,
+            encoded.comm_d_new,
+            &sector.files.sealed(),
+            &sector.files.cache(),
+            &files.sealed(),
+            &files.cache(),
+        )
+    })?;
+    let valid = metrics::time(Phase::Verify, || {
+        verify_empty_sector_update_proof::<Tree>(
+            config,
+            &proof.0,
+            sector.comm_r,
+            encoded.comm_r_new,
+            encoded.comm_d_new,
+        )
+    })?;
+    ensure!(
+        valid,
+        VerificationMismatch("failed to verify empty sector update proof".to_string())
+    );
+    Ok(())
+}
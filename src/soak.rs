@@ -0,0 +1,124 @@
+//! Soak runs, which loop lifecycles until a wall-clock deadline instead of
+//! for a fixed number of iterations.
+//!
+//! Hangs show up about once every few hours, so a soak run is mostly judged
+//! by how many lifecycles it got through and how many of them hung.
+
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+
+use crate::outcome::Classification;
+use crate::workloads::RunOutcome;
+
+/// Parses a duration such as `90`, `90s`, `30m`, `6h` or `2d`; a bare number
+/// counts seconds. Zero is rejected, as it would end the soak before it
+/// started.
+pub fn parse_duration(value: &str) -> Result<Duration> {
+    let value = value.trim();
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => value.split_at(i),
+        None => (value, "s"),
+    };
+    let number = number
+        .parse::<u64>()
+        .with_context(|| format!("invalid duration: {}", value))?;
+    let unit_secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => bail!("invalid duration: {}", value),
+    };
+    let secs = match number.checked_mul(unit_secs) {
+        Some(0) => bail!("duration must be longer than 0: {}", value),
+        Some(secs) => secs,
+        None => bail!("duration too long: {}", value),
+    };
+    Ok(Duration::from_secs(secs))
+}
+
+/// Prints the lifecycle throughput of a soak run that went on for `elapsed`,
+/// followed by every hang event: phases abandoned after the phase timeout
+/// and process workers their watchdog ended.
+pub fn print_summary(outcome: &RunOutcome, elapsed: Duration) {
+    let lifecycles = outcome.records.len();
+    let succeeded = outcome.records.iter().filter(|r| r.success).count();
+    let hours = elapsed.as_secs_f64() / 3600.0;
+    let per_hour = if hours > 0.0 {
+        succeeded as f64 / hours
+    } else {
+        0.0
+    };
+
+    println!();
+    println!(
+        "Soak ran for {}: {} lifecycles, {} succeeded, {:.1} per hour",
+        format_duration(elapsed),
+        lifecycles,
+        succeeded,
+        per_hour
+    );
+
+    let timeouts = outcome
+        .records
+        .iter()
+        .filter_map(|r| r.timed_out.map(|phase| (r, phase)))
+        .collect::<Vec<_>>();
+    let hung = outcome
+        .failed_workers
+        .iter()
+        .filter(|(_, classification)| *classification == Classification::Hang)
+        .map(|(worker, _)| *worker)
+        .collect::<Vec<_>>();
+    println!("{} hang events", timeouts.len() + hung.len());
+    for (record, phase) in timeouts {
+        println!(
+            "  thread {}: {} timed out in iteration {}",
+            record.thread, phase, record.iteration
+        );
+    }
+    for worker in hung {
+        println!("  thread {}: hung", worker);
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!("{}h {:02}m {:02}s", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_parse() {
+        assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("90s").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("30m").unwrap(), Duration::from_secs(30 * 60));
+        assert_eq!(
+            parse_duration(" 6h ").unwrap(),
+            Duration::from_secs(6 * 3600)
+        );
+        assert_eq!(
+            parse_duration("2d").unwrap(),
+            Duration::from_secs(2 * 86400)
+        );
+    }
+
+    #[test]
+    fn bad_durations_are_rejected() {
+        for value in &["", "h", "-5m", "1.5h", "10w", "10 m"] {
+            assert!(parse_duration(value).is_err(), "{:?} parsed", value);
+        }
+    }
+
+    #[test]
+    fn zero_and_overflowing_durations_are_rejected() {
+        assert!(parse_duration("0").is_err());
+        assert!(parse_duration("0d").is_err());
+        assert!(parse_duration(&format!("{}d", u64::MAX / 60)).is_err());
+        assert!(parse_duration(&format!("{}", u64::MAX)).is_ok());
+    }
+}
@@ -0,0 +1,192 @@
+//! Detects PC1 and PC2 runs that stopped writing to their cache directory.
+//!
+//! The watchdog only sees phase boundaries, so a phase that legitimately
+//! takes hours looks the same as one that hung at its start. While a worker
+//! is in PC1 or PC2, its cache directory is watched with inotify and the
+//! worker is flagged once no file in it was created, written or closed for
+//! the stall window. The flag is a warning and shows up in the heartbeat
+//! file; it does not end the run. Every write counts as progress for the
+//! watchdog, so a long phase that keeps writing is not taken for a hang.
+//!
+//! Files written through memory maps only count once they are closed, and
+//! PC1 writes each layer when it is done, so the window must be longer than
+//! a layer takes.
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use tracing::{warn, Span};
+
+use crate::metrics::Phase;
+
+lazy_static! {
+    static ref WINDOW: Mutex<Option<Duration>> = Mutex::new(None);
+}
+
+/// Flags PC1 and PC2 runs whose cache directory saw no write for `window`.
+pub fn enable(window: Duration) {
+    *WINDOW.lock().expect("stall window poisoned") = Some(window);
+}
+
+/// Watches a cache directory for as long as it lives, if detection is
+/// enabled.
+pub struct StallWatch {
+    stop: Arc<AtomicBool>,
+    watcher: Option<JoinHandle<()>>,
+}
+
+impl StallWatch {
+    /// Watches `cache` on behalf of the current worker, which runs `phase`.
+    pub fn start(cache: PathBuf, phase: Phase) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let window = *WINDOW.lock().expect("stall window poisoned");
+        let watcher = window.and_then(|window| {
+            let stop = stop.clone();
+            let worker = crate::watchdog::current();
+            let span = Span::current();
+            thread::Builder::new()
+                .name("cache-stall".into())
+                .spawn(move || {
+                    let _span = span.entered();
+                    let flag = |stalled| {
+                        if let Some((watchdog, index)) = &worker {
+                            watchdog.set_cache_stalled(*index, stalled);
+                        }
+                    };
+                    let wrote = || {
+                        if let Some((watchdog, index)) = &worker {
+                            watchdog.progressed(*index);
+                        }
+                    };
+                    if let Err(err) = imp::watch(&cache, window, phase, &stop, flag, wrote) {
+                        warn!("cache stall detection is off for this {}: {:#}", phase, err);
+                    }
+                })
+                .ok()
+        });
+        StallWatch { stop, watcher }
+    }
+}
+
+impl Drop for StallWatch {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(watcher) = self.watcher.take() {
+            let _ = watcher.join();
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use std::ffi::CString;
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::{Duration, Instant};
+
+    use anyhow::{Context, Result};
+    use tracing::{info, warn};
+
+    use crate::metrics::Phase;
+
+    /// How often the stop flag is checked while no event arrives.
+    const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+    pub fn watch<F: Fn(Option<Instant>), W: Fn()>(
+        cache: &Path,
+        window: Duration,
+        phase: Phase,
+        stop: &AtomicBool,
+        flag: F,
+        wrote: W,
+    ) -> Result<()> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error()).context("inotify_init1 failed");
+        }
+        let res = watch_fd(fd, cache, window, phase, stop, flag, wrote);
+        unsafe { libc::close(fd) };
+        res
+    }
+
+    fn watch_fd<F: Fn(Option<Instant>), W: Fn()>(
+        fd: i32,
+        cache: &Path,
+        window: Duration,
+        phase: Phase,
+        stop: &AtomicBool,
+        flag: F,
+        wrote: W,
+    ) -> Result<()> {
+        let path = CString::new(cache.as_os_str().as_bytes())?;
+        let mask = libc::IN_CREATE | libc::IN_MODIFY | libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO;
+        if unsafe { libc::inotify_add_watch(fd, path.as_ptr(), mask) } < 0 {
+            return Err(io::Error::last_os_error())
+                .with_context(|| format!("failed to watch {}", cache.display()));
+        }
+
+        let mut last_write = Instant::now();
+        let mut stalled = false;
+        let mut events = [0u8; 4096];
+        while !stop.load(Ordering::SeqCst) {
+            let mut pollfd = libc::pollfd {
+                fd,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let ready = unsafe { libc::poll(&mut pollfd, 1, POLL_INTERVAL.as_millis() as i32) };
+            if ready > 0 {
+                // Only the time of the last event matters, not what it was.
+                while unsafe { libc::read(fd, events.as_mut_ptr() as *mut _, events.len()) } > 0 {}
+                if stalled {
+                    info!(
+                        quiet_secs = last_write.elapsed().as_secs_f64(),
+                        "{} writes to its cache directory again", phase
+                    );
+                    stalled = false;
+                    flag(None);
+                }
+                wrote();
+                last_write = Instant::now();
+            } else if !stalled && last_write.elapsed() > window {
+                warn!(
+                    cache = %cache.display(),
+                    "{} wrote nothing to its cache directory for more than {:?}",
+                    phase,
+                    window
+                );
+                stalled = true;
+                flag(Some(last_write));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod imp {
+    use std::path::Path;
+    use std::sync::atomic::AtomicBool;
+    use std::time::{Duration, Instant};
+
+    use anyhow::{bail, Result};
+
+    use crate::metrics::Phase;
+
+    pub fn watch<F: Fn(Option<Instant>), W: Fn()>(
+        _cache: &Path,
+        _window: Duration,
+        _phase: Phase,
+        _stop: &AtomicBool,
+        _flag: F,
+        _wrote: W,
+    ) -> Result<()> {
+        bail!("it needs inotify")
+    }
+}
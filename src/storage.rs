@@ -0,0 +1,235 @@
+//! Constrained storage for the sector files.
+//!
+//! `--tmpfs-size` puts every sector on a size-limited tmpfs so runs hit a
+//! nearly full disk, and `--io-read-bps`/`--io-write-bps` move the process
+//! into a cgroup whose `io.max` throttles the device sectors are written to.
+//! `--storage-latency-ms` keeps the staged and sealed files and the cache on
+//! a filesystem whose every I/O a device-mapper `delay` target holds back,
+//! the way sectors on NFS or ceph are. All of them need root.
+
+use std::fs;
+#[cfg(target_os = "linux")]
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use tempfile::tempdir;
+
+pub(crate) const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// Parses a byte amount such as `512M` or `2G`; suffixes are powers of 1024.
+pub fn parse_bytes(value: &str) -> Result<u64> {
+    let value = value.trim();
+    let (digits, shift) = match value.char_indices().last() {
+        Some((i, 'K')) | Some((i, 'k')) => (&value[..i], 10),
+        Some((i, 'M')) | Some((i, 'm')) => (&value[..i], 20),
+        Some((i, 'G')) | Some((i, 'g')) => (&value[..i], 30),
+        _ => (value, 0),
+    };
+    let amount: u64 = digits
+        .parse()
+        .with_context(|| format!("invalid byte amount {:?}", value))?;
+    Ok(amount << shift)
+}
+
+/// A tmpfs mounted for the duration of the run.
+pub struct Tmpfs {
+    dir: PathBuf,
+}
+
+impl Tmpfs {
+    /// Mounts a tmpfs of `size` bytes on a fresh temporary directory.
+    pub fn mount(size: u64) -> Result<Self> {
+        let dir = tempdir()
+            .context("failed to create tmpfs mount point")?
+            .into_path();
+        let status = Command::new("mount")
+            .args(&["-t", "tmpfs", "-o"])
+            .arg(format!("size={}", size))
+            .arg("tmpfs")
+            .arg(&dir)
+            .status()
+            .context("failed to run mount")?;
+        if !status.success() {
+            let _ = fs::remove_dir(&dir);
+            bail!("mounting a tmpfs on {} failed: {}", dir.display(), status);
+        }
+        Ok(Tmpfs { dir })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+}
+
+impl Drop for Tmpfs {
+    fn drop(&mut self) {
+        let _ = Command::new("umount").arg(&self.dir).status();
+        let _ = fs::remove_dir(&self.dir);
+    }
+}
+
+/// A cgroup throttling the I/O of this process and its children.
+pub struct IoLimit {
+    dir: PathBuf,
+}
+
+impl IoLimit {
+    /// Limits reads and writes to the whole disk holding `path` to the given
+    /// bytes per second, `None` meaning unlimited.
+    pub fn apply(path: &Path, read_bps: Option<u64>, write_bps: Option<u64>) -> Result<Self> {
+        let device = disk_of(path)?;
+        fs::write(Path::new(CGROUP_ROOT).join("cgroup.subtree_control"), "+io")
+            .context("failed to enable the io controller")?;
+        let dir = Path::new(CGROUP_ROOT).join(format!("hang-{}", std::process::id()));
+        fs::create_dir(&dir).with_context(|| format!("failed to create {}", dir.display()))?;
+        let limit = IoLimit { dir };
+
+        let bps = |limit: Option<u64>| limit.map_or("max".to_string(), |bps| bps.to_string());
+        fs::write(
+            limit.dir.join("io.max"),
+            format!("{} rbps={} wbps={}", device, bps(read_bps), bps(write_bps)),
+        )
+        .context("failed to set io.max")?;
+        fs::write(
+            limit.dir.join("cgroup.procs"),
+            std::process::id().to_string(),
+        )
+        .context("failed to join the io cgroup")?;
+        Ok(limit)
+    }
+
+    /// The `major:minor` of the throttled disk.
+    pub fn device(&self) -> Result<String> {
+        let max = fs::read_to_string(self.dir.join("io.max"))?;
+        Ok(max
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .to_string())
+    }
+}
+
+impl Drop for IoLimit {
+    fn drop(&mut self) {
+        // A cgroup can only be removed once it is empty.
+        let _ = fs::write(
+            Path::new(CGROUP_ROOT).join("cgroup.procs"),
+            std::process::id().to_string(),
+        );
+        let _ = fs::remove_dir(&self.dir);
+    }
+}
+
+/// An ext4 filesystem on a device-mapper `delay` target over a sparse loop
+/// device, mounted for the duration of the run.
+pub struct DelayedDisk {
+    dir: PathBuf,
+    image: PathBuf,
+    loop_device: Option<String>,
+    target: Option<String>,
+    mounted: bool,
+}
+
+impl DelayedDisk {
+    /// Mounts a filesystem of `size` bytes whose reads and writes each take
+    /// `latency` longer.
+    pub fn mount(size: u64, latency: Duration) -> Result<Self> {
+        let dir = tempdir()
+            .context("failed to create delayed disk mount point")?
+            .into_path();
+        let mut disk = DelayedDisk {
+            image: dir.with_extension("img"),
+            dir,
+            loop_device: None,
+            target: None,
+            mounted: false,
+        };
+        fs::File::create(&disk.image)
+            .and_then(|image| image.set_len(size))
+            .with_context(|| format!("failed to create {}", disk.image.display()))?;
+        let loop_device = run(Command::new("losetup")
+            .args(&["--find", "--show"])
+            .arg(&disk.image))?;
+        disk.loop_device = Some(loop_device.clone());
+
+        let target = format!("hang-delay-{}", std::process::id());
+        run(Command::new("dmsetup")
+            .args(&["create", &target, "--table"])
+            .arg(format!(
+                "0 {} delay {} 0 {}",
+                size / 512,
+                loop_device,
+                latency.as_millis()
+            )))?;
+        disk.target = Some(target.clone());
+        let device = format!("/dev/mapper/{}", target);
+        run(Command::new("mkfs.ext4").args(&["-q", &device]))?;
+        run(Command::new("mount").arg(&device).arg(&disk.dir))?;
+        disk.mounted = true;
+        Ok(disk)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+}
+
+impl Drop for DelayedDisk {
+    fn drop(&mut self) {
+        if self.mounted {
+            let _ = Command::new("umount").arg(&self.dir).status();
+        }
+        if let Some(target) = &self.target {
+            let _ = Command::new("dmsetup").args(&["remove", target]).status();
+        }
+        if let Some(loop_device) = &self.loop_device {
+            let _ = Command::new("losetup").args(&["-d", loop_device]).status();
+        }
+        let _ = fs::remove_file(&self.image);
+        let _ = fs::remove_dir(&self.dir);
+    }
+}
+
+/// Runs `command`, returning its trimmed output.
+fn run(command: &mut Command) -> Result<String> {
+    let name = format!("{:?}", command);
+    let output = command
+        .output()
+        .with_context(|| format!("failed to run {}", name))?;
+    if !output.status.success() {
+        bail!(
+            "{} failed: {}: {}",
+            name,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// The `major:minor` of the disk `path` lives on; `io.max` does not take
+/// partitions.
+#[cfg(target_os = "linux")]
+fn disk_of(path: &Path) -> Result<String> {
+    let dev = fs::metadata(path)
+        .with_context(|| format!("failed to stat {}", path.display()))?
+        .dev();
+    let (major, minor) = unsafe { (libc::major(dev), libc::minor(dev)) };
+    let sysfs = PathBuf::from(format!("/sys/dev/block/{}:{}", major, minor));
+    if !sysfs.exists() {
+        bail!("{} is not on a block device", path.display());
+    }
+    if sysfs.join("partition").exists() {
+        let disk = fs::read_to_string(sysfs.join("..").join("dev"))?;
+        return Ok(disk.trim().to_string());
+    }
+    Ok(format!("{}:{}", major, minor))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn disk_of(path: &Path) -> Result<String> {
+    bail!("throttling the disk of {} needs Linux", path.display())
+}
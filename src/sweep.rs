@@ -0,0 +1,297 @@
+//! Runs every combination of a configuration matrix in turn.
+//!
+//! Each cell is this binary started again with the parent's arguments, the
+//! cell's settings and `--iterations=0`, and is stopped with SIGTERM once its
+//! duration is up. Its records come back through `--output-json` and are
+//! boiled down to a single summary row.
+
+use std::env;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::BufWriter;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Command, ExitStatus};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use serde::Serialize;
+use tempfile::NamedTempFile;
+
+use crate::config::SweepConfig;
+use crate::environment::Environment;
+use crate::lifecycle::{parse_api_version, parse_sector_size};
+use crate::metrics::{self, LifecycleRecord};
+use crate::outcome::Classification;
+use crate::process;
+use crate::shutdown;
+use crate::workloads::Workload;
+
+/// Flag starting a sweep.
+pub const SWEEP_ARG: &str = "sweep";
+
+/// How often a running cell is checked on.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Extra time a stopped cell gets on top of its shutdown timeout before it is
+/// killed.
+const KILL_GRACE: Duration = Duration::from_secs(10);
+
+/// One combination of the matrix.
+#[derive(Clone, Debug, Serialize)]
+pub struct Cell {
+    pub threads: usize,
+    pub sector_size: String,
+    /// Comma separated, as passed to `--api-versions`.
+    pub api_versions: String,
+    pub workload: String,
+}
+
+impl Cell {
+    fn args(&self) -> Vec<String> {
+        vec![
+            format!("--num-threads={}", self.threads),
+            format!("--sector-size={}", self.sector_size),
+            format!("--api-versions={}", self.api_versions),
+            format!("--workload={}", self.workload),
+        ]
+    }
+}
+
+/// Every combination of `sweep`, in the order threads, sector sizes, API
+/// versions and workloads; an empty list keeps the value of `base`.
+pub fn cells(sweep: &SweepConfig, base: &Cell) -> Result<Vec<Cell>> {
+    if sweep.duration == 0 {
+        bail!("the sweep duration must be at least 1 second");
+    }
+    let or_base = |values: &[String], base: &str| match values {
+        [] => vec![base.to_string()],
+        values => values.to_vec(),
+    };
+    let threads = match sweep.threads.as_slice() {
+        [] => vec![base.threads],
+        threads => threads.to_vec(),
+    };
+    let sector_sizes = or_base(&sweep.sector_sizes, &base.sector_size);
+    let api_versions = or_base(&sweep.api_versions, &base.api_versions);
+    let workloads = or_base(&sweep.workloads, &base.workload);
+
+    // Fail before the first cell rather than in the middle of the sweep.
+    for size in &sector_sizes {
+        parse_sector_size(size)?;
+    }
+    for versions in &api_versions {
+        for version in versions.split(',') {
+            parse_api_version(version)?;
+        }
+    }
+    for workload in &workloads {
+        workload.parse::<Workload>()?;
+    }
+    if threads.contains(&0) {
+        bail!("a sweep cell needs at least 1 thread");
+    }
+
+    let mut cells = Vec::new();
+    for threads in &threads {
+        for sector_size in &sector_sizes {
+            for api_versions in &api_versions {
+                for workload in &workloads {
+                    cells.push(Cell {
+                        threads: *threads,
+                        sector_size: sector_size.clone(),
+                        api_versions: api_versions.clone(),
+                        workload: workload.clone(),
+                    });
+                }
+            }
+        }
+    }
+    Ok(cells)
+}
+
+/// The outcome of one cell.
+#[derive(Clone, Debug, Serialize)]
+pub struct CellSummary {
+    #[serde(flatten)]
+    pub cell: Cell,
+    pub lifecycles: usize,
+    pub failed: usize,
+    /// Mean wall-clock time of the successful lifecycles.
+    pub mean_lifecycle_secs: Option<f64>,
+    /// `ok`, the [`Classification`] the run exited with, `killed` if the
+    /// cell ignored the stop request, or how the process failed otherwise.
+    pub outcome: String,
+}
+
+/// Starts the cells of a sweep with the arguments of this process, minus
+/// `--sweep` and `--output-json`, followed by `extra_args`.
+pub struct Sweep {
+    args: Vec<OsString>,
+    duration: Duration,
+    shutdown_timeout: Duration,
+}
+
+impl Sweep {
+    pub fn new<I: IntoIterator<Item = String>>(
+        extra_args: I,
+        duration: Duration,
+        shutdown_timeout: Duration,
+    ) -> Self {
+        let mut args = Vec::new();
+        let mut parent_args = env::args_os().skip(1);
+        while let Some(arg) = parent_args.next() {
+            let text = arg.to_string_lossy();
+            if text == format!("--{}", SWEEP_ARG) || text.starts_with("--output-json=") {
+                continue;
+            }
+            if text == "--output-json" {
+                parent_args.next();
+                continue;
+            }
+            args.push(arg);
+        }
+        args.extend(extra_args.into_iter().map(OsString::from));
+        Sweep {
+            args,
+            duration,
+            shutdown_timeout,
+        }
+    }
+
+    /// Runs `cell` for the sweep duration and summarizes its records.
+    pub fn run(&self, cell: &Cell) -> Result<CellSummary> {
+        let output = NamedTempFile::new()?;
+        let mut command = Command::new(env::current_exe()?);
+        command
+            .args(&self.args)
+            .args(cell.args())
+            .arg("--iterations=0")
+            .arg(format!("--output-json={}", output.path().display()));
+        // As with process workers, a SIGINT reaches the cell only through us,
+        // a second one would force it to exit.
+        unsafe {
+            command.pre_exec(|| {
+                libc::setpgid(0, 0);
+                Ok(())
+            });
+        }
+        let mut child = command.spawn()?;
+        let pids = [child.id()];
+
+        let started = Instant::now();
+        let mut stop_deadline = None;
+        let mut killed = false;
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            match stop_deadline {
+                None if started.elapsed() >= self.duration || shutdown::requested() => {
+                    process::terminate(&pids);
+                    stop_deadline = Some(Instant::now() + self.shutdown_timeout + KILL_GRACE);
+                }
+                Some(deadline) if !killed && Instant::now() >= deadline => {
+                    process::kill(&pids);
+                    killed = true;
+                }
+                _ => {}
+            }
+            thread::sleep(POLL_INTERVAL);
+        };
+
+        let records = if killed {
+            vec![]
+        } else {
+            metrics::read_json(output.path()).unwrap_or_default()
+        };
+        Ok(summarize(cell, &records, outcome(status, killed)))
+    }
+}
+
+fn outcome(status: ExitStatus, killed: bool) -> String {
+    if killed {
+        return "killed".to_string();
+    }
+    match status.code().and_then(Classification::from_exit_code) {
+        Some(Classification::Success) => "ok".to_string(),
+        Some(classification) => classification.to_string(),
+        None => status.to_string(),
+    }
+}
+
+fn summarize(cell: &Cell, records: &[LifecycleRecord], outcome: String) -> CellSummary {
+    let durations = records
+        .iter()
+        .filter(|r| r.success)
+        .map(|r| r.phases.iter().map(|t| t.duration).sum::<Duration>())
+        .collect::<Vec<_>>();
+    let mean_lifecycle_secs = match durations.len() {
+        0 => None,
+        n => Some(durations.iter().sum::<Duration>().as_secs_f64() / n as f64),
+    };
+    CellSummary {
+        cell: cell.clone(),
+        lifecycles: records.len(),
+        failed: records.iter().filter(|r| !r.success).count(),
+        mean_lifecycle_secs,
+        outcome,
+    }
+}
+
+/// Prints one row per cell.
+pub fn print_summaries(summaries: &[CellSummary]) {
+    println!(
+        "{:>8} {:<12} {:<16} {:<14} {:>10} {:>8} {:>12}  {}",
+        "threads",
+        "sector size",
+        "api versions",
+        "workload",
+        "lifecycles",
+        "failed",
+        "mean (s)",
+        "outcome"
+    );
+    for summary in summaries {
+        let mean = match summary.mean_lifecycle_secs {
+            Some(secs) => format!("{:.3}", secs),
+            None => "-".to_string(),
+        };
+        println!(
+            "{:>8} {:<12} {:<16} {:<14} {:>10} {:>8} {:>12}  {}",
+            summary.cell.threads,
+            summary.cell.sector_size,
+            summary.cell.api_versions,
+            summary.cell.workload,
+            summary.lifecycles,
+            summary.failed,
+            mean,
+            summary.outcome
+        );
+    }
+}
+
+/// Writes the environment and the cell summaries to `path` as a single JSON
+/// document.
+pub fn write_json<P: AsRef<Path>>(
+    path: P,
+    environment: &Environment,
+    summaries: &[CellSummary],
+) -> Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    serde_json::to_writer_pretty(
+        file,
+        &SweepReport {
+            environment,
+            cells: summaries,
+        },
+    )?;
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct SweepReport<'a> {
+    environment: &'a Environment,
+    cells: &'a [CellSummary],
+}
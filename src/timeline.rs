@@ -0,0 +1,71 @@
+//! Phase completions as CSV, written as the run goes.
+//!
+//! With `--timeline` every phase that ends appends a row with the Unix time,
+//! the worker, the phase, its duration and whether it succeeded. Each row is
+//! a single write to the file, so whatever was written survives a run that
+//! has to be SIGKILLed after a hang, and the phases missing at the end show
+//! where the workers got stuck. Process workers append to the parent's file.
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use lazy_static::lazy_static;
+
+use crate::metrics::Phase;
+
+const HEADER: &str = "timestamp,worker,phase,duration_secs,result\n";
+
+lazy_static! {
+    static ref FILE: Mutex<Option<File>> = Mutex::new(None);
+}
+
+/// Starts a new timeline at `path`.
+pub fn start(path: &Path) -> Result<()> {
+    let mut file =
+        File::create(path).with_context(|| format!("failed to create {}", path.display()))?;
+    file.write_all(HEADER.as_bytes())?;
+    *FILE.lock().expect("timeline file poisoned") = Some(file);
+    Ok(())
+}
+
+/// Adds the rows of this process, a process worker, to the timeline its
+/// parent started at `path`.
+pub fn join(path: &Path) -> Result<()> {
+    let file = OpenOptions::new()
+        .append(true)
+        .open(path)
+        .with_context(|| format!("failed to open {}", path.display()))?;
+    *FILE.lock().expect("timeline file poisoned") = Some(file);
+    Ok(())
+}
+
+/// Appends the row of a `phase` of `worker` that ended after `duration`.
+pub(crate) fn phase_finished(
+    worker: Option<usize>,
+    phase: Phase,
+    duration: Duration,
+    succeeded: bool,
+) {
+    let mut file = FILE.lock().expect("timeline file poisoned");
+    let file = match file.as_mut() {
+        Some(file) => file,
+        None => return,
+    };
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let row = format!(
+        "{:.3},{},{},{:.3},{}\n",
+        now.as_secs_f64(),
+        worker.map(|worker| worker.to_string()).unwrap_or_default(),
+        phase,
+        duration.as_secs_f64(),
+        if succeeded { "ok" } else { "error" }
+    );
+    // A single write per row keeps the rows of concurrent processes whole.
+    let _ = file.write_all(row.as_bytes());
+}
@@ -0,0 +1,165 @@
+//! Run timeline in the Chrome trace event format.
+//!
+//! With `--trace` every worker gets a track of its own showing its phases,
+//! with the stretches it spent blocked on the GPU lock nested inside them,
+//! next to a counter of the threads running at the time, which is mostly the
+//! rayon pools at work; rayon offers no hooks to trace its jobs directly. The
+//! file opens in `chrome://tracing` or ui.perfetto.dev.
+//!
+//! Events are appended as they happen, and the format allows the array to be
+//! left open, so a run killed by the watchdog still shows where every worker
+//! was stuck: its last phase has no end. Process workers append to the
+//! parent's file on tracks of their own.
+
+use std::cell::Cell;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Mutex, Once};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use lazy_static::lazy_static;
+use serde_json::{json, Value};
+
+const COUNTER_INTERVAL: Duration = Duration::from_millis(100);
+
+lazy_static! {
+    static ref FILE: Mutex<Option<File>> = Mutex::new(None);
+}
+
+static COUNTER: Once = Once::new();
+
+thread_local! {
+    static WORKER: Cell<Option<usize>> = Cell::new(None);
+}
+
+/// Starts a new trace at `path`.
+pub fn start(path: &Path) -> Result<()> {
+    let mut file =
+        File::create(path).with_context(|| format!("failed to create {}", path.display()))?;
+    file.write_all(b"[\n")?;
+    install(file, "hang");
+    Ok(())
+}
+
+/// Adds the events of this process, a process worker, to the trace its
+/// parent started at `path`.
+pub fn join(path: &Path, index: usize) -> Result<()> {
+    let file = OpenOptions::new()
+        .append(true)
+        .open(path)
+        .with_context(|| format!("failed to open {}", path.display()))?;
+    install(file, &format!("hang worker {}", index));
+    Ok(())
+}
+
+fn install(file: File, process_name: &str) {
+    *FILE.lock().expect("trace file poisoned") = Some(file);
+    write(json!({
+        "name": "process_name",
+        "ph": "M",
+        "args": { "name": process_name },
+    }));
+    COUNTER.call_once(|| {
+        thread::Builder::new()
+            .name("trace-counter".into())
+            .spawn(count_running_threads)
+            .expect("failed to spawn trace counter");
+    });
+}
+
+/// Names the track of worker `index` and puts the current thread on it.
+pub(crate) fn name_worker(index: usize, group: &str) {
+    set_worker(index);
+    write(json!({
+        "name": "thread_name",
+        "ph": "M",
+        "tid": index,
+        "args": { "name": format!("worker {} ({})", index, group) },
+    }));
+}
+
+/// Puts the current thread on the track of worker `index`.
+pub(crate) fn set_worker(index: usize) {
+    WORKER.with(|worker| worker.set(Some(index)));
+}
+
+/// The worker whose track the current thread is on.
+pub(crate) fn worker() -> Option<usize> {
+    WORKER.with(Cell::get)
+}
+
+/// Opens a slice called `name` on the track of `worker`.
+pub(crate) fn begin(worker: usize, category: &str, name: &str) {
+    write(json!({ "name": name, "cat": category, "ph": "B", "tid": worker }));
+}
+
+/// Closes the innermost open slice on the track of `worker`.
+pub(crate) fn end(worker: usize, category: &str, name: &str) {
+    write(json!({ "name": name, "cat": category, "ph": "E", "tid": worker }));
+}
+
+/// Appends `event`, stamped with the process and a clock shared with the
+/// other processes, as a line of its own.
+fn write(mut event: Value) {
+    let mut file = FILE.lock().expect("trace file poisoned");
+    let file = match file.as_mut() {
+        Some(file) => file,
+        None => return,
+    };
+    event["pid"] = json!(std::process::id());
+    event["ts"] = json!(now_micros());
+    if event.get("tid").is_none() {
+        event["tid"] = json!(0);
+    }
+    // A single write per event keeps the lines of concurrent processes whole.
+    let _ = file.write_all(format!("{},\n", event).as_bytes());
+}
+
+fn now_micros() -> u64 {
+    let mut now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe {
+        libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now);
+    }
+    now.tv_sec as u64 * 1_000_000 + now.tv_nsec as u64 / 1_000
+}
+
+fn count_running_threads() {
+    let mut last = None;
+    loop {
+        thread::sleep(COUNTER_INTERVAL);
+        let running = match running_threads() {
+            Some(running) => running,
+            None => return,
+        };
+        if last != Some(running) {
+            write(json!({
+                "name": "running threads",
+                "ph": "C",
+                "args": { "threads": running },
+            }));
+            last = Some(running);
+        }
+    }
+}
+
+/// Counts the threads of this process that are on a CPU or ready to run.
+fn running_threads() -> Option<usize> {
+    let tasks = std::fs::read_dir("/proc/self/task").ok()?;
+    Some(
+        tasks
+            .filter_map(|task| {
+                let stat = std::fs::read_to_string(task.ok()?.path().join("stat")).ok()?;
+                // The state follows the command name, which may contain spaces.
+                let state = stat.rsplit(')').next()?.split_whitespace().next()?;
+                Some(state == "R")
+            })
+            .filter(|running| *running)
+            .count(),
+    )
+}
@@ -0,0 +1,367 @@
+//! Detects worker threads that stop making progress.
+//!
+//! Workers attach themselves with [`Watchdog::attach`]; every phase boundary
+//! seen by [`crate::metrics::time`] then counts as a heartbeat, as do a new
+//! PC1 layer and, with cache stall detection on, writes to the cache. If a worker
+//! goes longer than the configured timeout without one, the state of every
+//! worker is dumped and the process exits with [`HANG_EXIT_CODE`].
+
+use std::cell::RefCell;
+use std::fmt::Write;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use tracing::{error, warn};
+
+use crate::metrics::Phase;
+
+/// Process exit code used when a hang has been detected.
+pub const HANG_EXIT_CODE: i32 = 86;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How long the dump measures the CPU use of hung workers over.
+const CPU_WINDOW: Duration = Duration::from_millis(500);
+
+#[derive(Clone)]
+struct WorkerState {
+    /// Bumped by every attach, so a thread that was replaced can no longer
+    /// report for the worker.
+    generation: usize,
+    phase: Option<Phase>,
+    phase_started: Instant,
+    last_progress: Instant,
+    /// The cache directory and layer count of a running PC1.
+    layers: Option<(PathBuf, usize)>,
+    /// Since when the cache directory of the running phase saw no write,
+    /// once that exceeded the stall window.
+    cache_stalled: Option<Instant>,
+    /// Kernel id of the thread attached as the worker.
+    tid: Option<i64>,
+    finished: bool,
+}
+
+pub struct Watchdog {
+    timeout: Duration,
+    workers: Mutex<Vec<WorkerState>>,
+}
+
+thread_local! {
+    static HANDLE: RefCell<Option<(Arc<Watchdog>, usize, usize)>> = RefCell::new(None);
+}
+
+impl Watchdog {
+    pub fn new(num_workers: usize, timeout: Duration) -> Arc<Self> {
+        let now = Instant::now();
+        let workers = (0..num_workers)
+            .map(|_| WorkerState {
+                generation: 0,
+                phase: None,
+                phase_started: now,
+                last_progress: now,
+                layers: None,
+                cache_stalled: None,
+                tid: None,
+                finished: false,
+            })
+            .collect();
+
+        Arc::new(Watchdog {
+            timeout,
+            workers: Mutex::new(workers),
+        })
+    }
+
+    /// Registers the calling thread as worker `index`, taking over from any
+    /// thread attached as that worker before.
+    pub fn attach(self: Arc<Self>, index: usize) {
+        let mut generation = 0;
+        self.update(index, |state, now| {
+            state.generation += 1;
+            state.phase = None;
+            state.layers = None;
+            state.cache_stalled = None;
+            state.tid = Some(crate::gpu_lock::current_tid());
+            state.last_progress = now;
+            generation = state.generation;
+        });
+        HANDLE.with(|handle| *handle.borrow_mut() = Some((self, index, generation)));
+    }
+
+    /// The phase worker `index` is in and for how long it has been.
+    pub fn phase(&self, index: usize) -> Option<(Phase, Duration)> {
+        let workers = self.workers.lock().expect("watchdog poisoned");
+        let state = &workers[index];
+        state
+            .phase
+            .map(|phase| (phase, state.phase_started.elapsed()))
+    }
+
+    /// The SDR layer worker `index` is labelling and the layer count, while
+    /// it runs PC1.
+    pub fn layer(&self, index: usize) -> Option<(usize, usize)> {
+        let layers = self.workers.lock().expect("watchdog poisoned")[index]
+            .layers
+            .clone();
+        layers.map(|(cache, total)| (crate::layers::current(&cache, total), total))
+    }
+
+    /// How long the cache directory of worker `index` has gone without a
+    /// write, once [`crate::stall`] flagged it.
+    pub fn cache_stalled(&self, index: usize) -> Option<Duration> {
+        let workers = self.workers.lock().expect("watchdog poisoned");
+        workers[index].cache_stalled.map(|since| since.elapsed())
+    }
+
+    pub(crate) fn set_cache_stalled(&self, index: usize, since: Option<Instant>) {
+        self.update(index, |state, _| state.cache_stalled = since);
+    }
+
+    /// Heartbeat from a thread watching worker `index`'s phase: it labelled
+    /// another PC1 layer or wrote to its cache directory, which a phase
+    /// taking hours does long before it ends.
+    pub(crate) fn progressed(&self, index: usize) {
+        self.update(index, |state, now| {
+            if state.phase.is_some() {
+                state.last_progress = now;
+            }
+        });
+    }
+
+    /// How long ago worker `index` last made progress, `None` once it is
+    /// done.
+    pub fn since_progress(&self, index: usize) -> Option<Duration> {
+        let workers = self.workers.lock().expect("watchdog poisoned");
+        let state = &workers[index];
+        if state.finished {
+            None
+        } else {
+            Some(state.last_progress.elapsed())
+        }
+    }
+
+    /// Starts the monitoring thread.
+    pub fn spawn(self: Arc<Self>) -> JoinHandle<()> {
+        thread::Builder::new()
+            .name("watchdog".into())
+            .spawn(move || self.monitor())
+            .expect("failed to spawn watchdog thread")
+    }
+
+    fn monitor(&self) {
+        loop {
+            thread::sleep(POLL_INTERVAL);
+
+            let workers = self.workers.lock().expect("watchdog poisoned");
+            if workers.iter().all(|state| state.finished) {
+                return;
+            }
+
+            let hung = workers
+                .iter()
+                .enumerate()
+                .filter(|(_, state)| {
+                    !state.finished && state.last_progress.elapsed() > self.timeout
+                })
+                .map(|(index, _)| index)
+                .collect::<Vec<_>>();
+            if hung.is_empty() {
+                continue;
+            }
+            // The dumps below take seconds; workers that are not hung must
+            // not wait for them in enter, leave or waiting.
+            let states = workers.to_vec();
+            drop(workers);
+
+            error!(
+                workers = ?hung,
+                gpu_lock_blocked = crate::gpu_lock::blocked_now(),
+                rayon_starved_secs = crate::canary::starved_for().map(|waited| waited.as_secs()),
+                "watchdog: no progress for more than {:?}",
+                self.timeout
+            );
+            // The state and stacks go to stderr as a whole, they are dumps
+            // rather than events.
+            let state = describe(&states)
+                + &crate::pipeline::describe()
+                + &crate::canary::describe()
+                + &crate::lock_order::describe();
+            eprint!("{}", state);
+            let stacks = capture_native_stacks();
+            match &stacks {
+                Some(stacks) => eprintln!("{}", stacks),
+                None => error!("watchdog: neither eu-stack nor gdb could capture stacks"),
+            }
+            crate::postmortem::collect(
+                &format!("watchdog: no progress for more than {:?}", self.timeout),
+                &state,
+                stacks.as_deref(),
+            );
+            crate::profile::capture();
+            // Nothing unwinds past the exit, so the sector files are only
+            // cleaned up here.
+            crate::artifacts::finish_run(false);
+            std::process::exit(HANG_EXIT_CODE);
+        }
+    }
+
+    fn update<F: FnOnce(&mut WorkerState, Instant)>(&self, index: usize, f: F) {
+        let mut workers = self.workers.lock().expect("watchdog poisoned");
+        f(&mut workers[index], Instant::now());
+    }
+}
+
+/// A table of every worker's state.
+fn describe(workers: &[WorkerState]) -> String {
+    // A hung thread spinning at 100% and one asleep at 0% are different bugs.
+    let tids = workers
+        .iter()
+        .map(|state| state.tid.filter(|_| !state.finished).unwrap_or(-1))
+        .collect::<Vec<_>>();
+    let cpu = crate::cpu::threads_pct(&tids, CPU_WINDOW);
+    let mut table = String::new();
+    let _ = writeln!(
+        table,
+        "{:<8} {:<14} {:>16} {:>20} {:>8} {:>18} {:>7}",
+        "worker",
+        "phase",
+        "in phase (s)",
+        "since progress (s)",
+        "layer",
+        "cache quiet (s)",
+        "cpu %"
+    );
+    for ((index, state), cpu) in workers.iter().enumerate().zip(cpu) {
+        let phase = match (state.finished, state.phase) {
+            (true, _) => "finished".to_string(),
+            (false, Some(phase)) => phase.to_string(),
+            (false, None) => "idle".to_string(),
+        };
+        let layer = match &state.layers {
+            Some((cache, total)) => format!("{}/{}", crate::layers::current(cache, *total), total),
+            None => "-".to_string(),
+        };
+        let cache_quiet = state.cache_stalled.map_or("-".to_string(), |since| {
+            format!("{:.1}", since.elapsed().as_secs_f64())
+        });
+        let cpu = cpu.map_or("-".to_string(), |pct| format!("{:.0}", pct));
+        let _ = writeln!(
+            table,
+            "{:<8} {:<14} {:>16.1} {:>20.1} {:>8} {:>18} {:>7}",
+            index,
+            phase,
+            state.phase_started.elapsed().as_secs_f64(),
+            state.last_progress.elapsed().as_secs_f64(),
+            layer,
+            cache_quiet,
+            cpu
+        );
+    }
+    table
+}
+
+/// Collects native backtraces of every thread in this process by pointing
+/// `eu-stack`, or `gdb` as a fallback, at our own pid.
+pub fn capture_native_stacks() -> Option<String> {
+    allow_ptrace_from_children();
+
+    let pid = std::process::id().to_string();
+    let tools: [(&str, Vec<&str>); 2] = [
+        ("eu-stack", vec!["-p", &pid]),
+        (
+            "gdb",
+            vec!["-p", &pid, "-batch", "-ex", "thread apply all bt"],
+        ),
+    ];
+
+    for (tool, args) in tools.iter() {
+        match Command::new(tool).args(args).output() {
+            Ok(output) if !output.stdout.is_empty() => {
+                return Some(String::from_utf8_lossy(&output.stdout).into_owned());
+            }
+            Ok(output) => warn!(
+                "watchdog: {} failed: {}",
+                tool,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+            Err(err) => warn!("watchdog: could not run {}: {}", tool, err),
+        }
+    }
+    None
+}
+
+/// With Yama's `ptrace_scope=1` a child may not attach to its parent unless
+/// the parent opts in.
+#[cfg(target_os = "linux")]
+fn allow_ptrace_from_children() {
+    unsafe {
+        libc::prctl(libc::PR_SET_PTRACER, libc::PR_SET_PTRACER_ANY, 0, 0, 0);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn allow_ptrace_from_children() {}
+
+/// Runs `f` unless the current thread is not, or no longer, attached.
+fn with_handle<F: FnOnce(&mut WorkerState, Instant)>(f: F) {
+    HANDLE.with(|handle| {
+        if let Some((watchdog, index, generation)) = handle.borrow().as_ref() {
+            watchdog.update(*index, |state, now| {
+                if state.generation == *generation {
+                    f(state, now);
+                }
+            });
+        }
+    });
+}
+
+/// The watchdog and worker index the current thread is attached as.
+pub fn current() -> Option<(Arc<Watchdog>, usize)> {
+    HANDLE.with(|handle| {
+        handle
+            .borrow()
+            .as_ref()
+            .map(|(watchdog, index, _)| (watchdog.clone(), *index))
+    })
+}
+
+/// Heartbeat: the current worker started `phase`.
+pub fn enter(phase: Phase) {
+    with_handle(|state, now| {
+        state.phase = Some(phase);
+        state.phase_started = now;
+        state.last_progress = now;
+    });
+}
+
+/// The current worker's PC1 writes its layers to `cache`.
+pub fn watch_layers(cache: PathBuf, total: usize) {
+    with_handle(|state, _| state.layers = Some((cache, total)));
+}
+
+/// Heartbeat: the current worker is queued for a phase slot.
+pub fn waiting() {
+    with_handle(|state, now| state.last_progress = now);
+}
+
+/// Heartbeat: the current worker finished its phase.
+pub fn leave() {
+    with_handle(|state, now| {
+        state.phase = None;
+        state.layers = None;
+        state.cache_stalled = None;
+        state.phase_started = now;
+        state.last_progress = now;
+    });
+}
+
+/// Marks the current worker as done and detaches it from the watchdog.
+pub fn detach() {
+    HANDLE.with(|handle| {
+        if let Some((watchdog, index, _)) = handle.borrow_mut().take() {
+            watchdog.update(index, |state, _| state.finished = true);
+        }
+    });
+}
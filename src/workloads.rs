@@ -0,0 +1,1446 @@
+//! The workloads worker threads run, and the threads themselves.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use filecoin_proofs::{
+    aggregate_seal_commit_proofs, clear_cache, generate_window_post, generate_winning_post,
+    generate_winning_post_sector_challenge, get_seal_inputs, seal_commit_phase1,
+    seal_commit_phase2, validate_cache_for_commit, verify_aggregate_seal_commit_proofs,
+    verify_window_post, verify_winning_post, Commitment, MerkleTreeTrait, PoRepConfig, PoStConfig,
+    PoStType, PrivateReplicaInfo, ProverId, PublicReplicaInfo, SealCommitOutput,
+    SealPreCommitOutput, SectorSize, WINDOW_POST_CHALLENGE_COUNT, WINDOW_POST_SECTOR_COUNT,
+    WINNING_POST_CHALLENGE_COUNT, WINNING_POST_SECTOR_COUNT,
+};
+use rand::{Rng, SeedableRng};
+use rand_xorshift::XorShiftRng;
+use rayon::ThreadPool;
+use storage_proofs_core::{api_version::ApiVersion, sector::SectorId};
+use tracing::{debug, info, info_span, warn, Span};
+
+use crate::affinity::{self, CoreSets};
+use crate::artifacts::{
+    self, ArtifactStore, DirStore, SectorFiles, SharedCache, SharedStore, TempStore, C2_OUTPUT,
+    PC2_OUTPUT, SECTOR_META,
+};
+use crate::barrier;
+use crate::compat::Fr;
+use crate::env_preset::Toggles;
+use crate::fuzz;
+use crate::handoff::{C2Queue, QueueDrained};
+use crate::heartbeat::{self, Heartbeat};
+use crate::lifecycle::{
+    batch_seal_lifecycle, c2_lifecycle, init_logger, porep_config, random_prover_id,
+    seal_lifecycle, unseal_and_check, verify_lifecycle, PhaseRange, SectorMeta, UnsealRanges,
+};
+use crate::logging;
+use crate::metrics::{self, LifecycleRecord, Phase, PhaseTimeout};
+use crate::numa::{self, NumaPlacement};
+use crate::outcome::{Classification, ErrorClass, VerificationMismatch};
+use crate::overlap;
+use crate::panics;
+use crate::piece_gen::{splitmix64, PieceData};
+use crate::pipeline;
+use crate::process::{self, ProcessWorkers};
+use crate::schedule;
+use crate::shutdown;
+#[cfg(feature = "proofs-v11")]
+use crate::snap::snap_lifecycle;
+use crate::trace;
+use crate::watchdog::{self, Watchdog};
+
+const TEST_SEED: [u8; 16] = [
+    0x59, 0x62, 0xbe, 0x5d, 0x76, 0x3d, 0x31, 0x8d, 0x17, 0xdb, 0x37, 0x32, 0x54, 0x06, 0xbc, 0xe5,
+];
+
+const POST_ROUNDS: usize = 2;
+const UNSEAL_ROUNDS: usize = 4;
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(500);
+const PHASE_TIMEOUT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// The longest a worker backs off before retrying a transient failure.
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(300);
+/// Keeps the start jitter apart from the inputs of the worker's lifecycles.
+const JITTER_SEED_SALT: u64 = 0x6a69_7474_6572;
+
+/// What each worker thread spends its time doing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Workload {
+    Seal,
+    WindowPost,
+    WinningPost,
+    Aggregate,
+    /// Verifies the proofs seal runs kept under the cache root.
+    Verify,
+    /// Unseals ranges of one shared sector from every worker at once, as
+    /// concurrent retrievals do.
+    Unseal,
+    /// Runs C2 on the C1 outputs seal workers queued with `--c2-queue`.
+    C2,
+    /// Encodes new data into CC sectors sealed up front and proves the
+    /// empty sector update, as SnapDeals do.
+    #[cfg(feature = "proofs-v11")]
+    Snap,
+}
+
+impl Workload {
+    pub fn needs_sealed_sectors(self) -> bool {
+        match self {
+            Workload::WindowPost
+            | Workload::WinningPost
+            | Workload::Aggregate
+            | Workload::Unseal => true,
+            #[cfg(feature = "proofs-v11")]
+            Workload::Snap => true,
+            _ => false,
+        }
+    }
+
+    /// Whether the sectors sealed up front must be committed capacity
+    /// sectors, the only ones SnapDeals update.
+    pub fn needs_cc_sectors(self) -> bool {
+        match self {
+            #[cfg(feature = "proofs-v11")]
+            Workload::Snap => true,
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Workload {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "seal" => Ok(Workload::Seal),
+            "window-post" => Ok(Workload::WindowPost),
+            "winning-post" => Ok(Workload::WinningPost),
+            "aggregate" => Ok(Workload::Aggregate),
+            "verify" => Ok(Workload::Verify),
+            "unseal" => Ok(Workload::Unseal),
+            "c2" => Ok(Workload::C2),
+            #[cfg(feature = "proofs-v11")]
+            "snap" => Ok(Workload::Snap),
+            // encode_into and the empty sector update proofs only exist from
+            // filecoin-proofs 11 on.
+            #[cfg(not(feature = "proofs-v11"))]
+            "snap" => bail!("the snap workload needs the proofs-v11 feature"),
+            _ => bail!("unknown workload: {}", s),
+        }
+    }
+}
+
+/// A sector sealed up front and shared by the PoSt and aggregation workloads.
+pub struct SealedSector {
+    pub prover_id: ProverId,
+    pub sector_id: SectorId,
+    pub comm_r: Commitment,
+    pub files: SectorFiles,
+    /// Only present when an aggregation workload needs it.
+    pub proof: Option<CommitProof>,
+}
+
+/// A sector's C2 proof together with the public inputs it verifies against.
+pub struct CommitProof {
+    pub seed: [u8; 32],
+    pub output: SealCommitOutput,
+    pub inputs: Vec<Vec<Fr>>,
+}
+
+/// Seals `count` sectors filled with `piece_data` up to PC2, leaving the
+/// cache in the state PoSt expects. With `with_proofs` each sector also gets
+/// a C2 proof for the aggregation workload. They belong to `prover_id`, or to
+/// a fixed test prover.
+pub fn seal_sectors<Tree: 'static + MerkleTreeTrait>(
+    sector_size: u64,
+    porep_id: &[u8; 32],
+    api_version: ApiVersion,
+    count: usize,
+    with_proofs: bool,
+    prover_id: Option<ProverId>,
+    piece_data: PieceData,
+) -> Result<Vec<SealedSector>> {
+    let rng = &mut XorShiftRng::from_seed(TEST_SEED);
+    let test_prover_id = random_prover_id(rng);
+    let prover_id = prover_id.unwrap_or(test_prover_id);
+    let config = porep_config(sector_size, *porep_id, api_version);
+
+    (0..count)
+        .map(|_| -> Result<SealedSector> {
+            let files = TempStore.sector("prepared")?;
+            let range = PhaseRange {
+                resume_from: None,
+                stop_after: Some(Phase::PreCommit2),
+            };
+            seal_lifecycle::<Tree, _>(
+                &TempStore,
+                rng,
+                prover_id,
+                &files,
+                sector_size,
+                porep_id,
+                api_version,
+                1,
+                piece_data,
+                UnsealRanges::Skip,
+                range,
+                false,
+            )?;
+
+            validate_cache_for_commit::<_, _, Tree>(files.cache(), files.sealed())?;
+
+            let meta = files
+                .load::<SectorMeta>(SECTOR_META)?
+                .expect("sector meta was just saved");
+            let pre_commit_output = files
+                .load::<SealPreCommitOutput>(PC2_OUTPUT)?
+                .expect("PC2 output was just saved");
+            let comm_r = pre_commit_output.comm_r;
+            let proof = if with_proofs {
+                Some(commit_proof::<Tree>(
+                    config,
+                    &files,
+                    &meta,
+                    pre_commit_output,
+                )?)
+            } else {
+                None
+            };
+
+            clear_cache::<Tree>(&files.cache())?;
+            Ok(SealedSector {
+                prover_id,
+                sector_id: meta.sector_id,
+                comm_r,
+                files,
+                proof,
+            })
+        })
+        .collect()
+}
+
+/// Runs C1 and C2 for a sector sealed by `seal_sectors` and collects what
+/// aggregate verification needs.
+fn commit_proof<Tree: 'static + MerkleTreeTrait>(
+    config: PoRepConfig,
+    files: &SectorFiles,
+    meta: &SectorMeta,
+    pre_commit_output: SealPreCommitOutput,
+) -> Result<CommitProof> {
+    let comm_d = pre_commit_output.comm_d;
+    let comm_r = pre_commit_output.comm_r;
+
+    let phase1_output = metrics::time(Phase::Commit1, || {
+        seal_commit_phase1::<_, Tree>(
+            config,
+            files.cache(),
+            files.sealed(),
+            meta.prover_id,
+            meta.sector_id,
+            meta.ticket,
+            meta.seed,
+            pre_commit_output,
+            &meta.piece_infos,
+        )
+    })?;
+    let output = metrics::time(Phase::Commit2, || {
+        seal_commit_phase2(config, phase1_output, meta.prover_id, meta.sector_id)
+    })?;
+    let inputs = get_seal_inputs::<Tree>(
+        config,
+        comm_r,
+        comm_d,
+        meta.prover_id,
+        meta.sector_id,
+        meta.ticket,
+        meta.seed,
+    )?;
+
+    Ok(CommitProof {
+        seed: meta.seed,
+        output,
+        inputs,
+    })
+}
+
+pub fn aggregate_lifecycle<Tree: 'static + MerkleTreeTrait>(
+    sector_size: u64,
+    porep_id: &[u8; 32],
+    api_version: ApiVersion,
+    sectors: &[SealedSector],
+) -> Result<()> {
+    init_logger();
+
+    let config = porep_config(sector_size, *porep_id, api_version);
+
+    let proofs = sectors
+        .iter()
+        .map(|sector| {
+            sector
+                .proof
+                .as_ref()
+                .expect("sector sealed without a commit proof")
+        })
+        .collect::<Vec<_>>();
+    let comm_rs = sectors
+        .iter()
+        .map(|sector| sector.comm_r)
+        .collect::<Vec<_>>();
+    let seeds = proofs.iter().map(|proof| proof.seed).collect::<Vec<_>>();
+    let commit_outputs = proofs
+        .iter()
+        .map(|proof| proof.output.clone())
+        .collect::<Vec<_>>();
+    let inputs = proofs
+        .iter()
+        .flat_map(|proof| proof.inputs.iter().cloned())
+        .collect::<Vec<_>>();
+
+    let aggregate = metrics::time(Phase::Aggregate, || {
+        aggregate_seal_commit_proofs::<Tree>(config, &comm_rs, &seeds, &commit_outputs)
+    })?;
+    let valid = metrics::time(Phase::Verify, || {
+        verify_aggregate_seal_commit_proofs::<Tree>(config, aggregate, &comm_rs, &seeds, inputs)
+    })?;
+    ensure!(
+        valid,
+        VerificationMismatch("failed to verify aggregate seal proof".to_string())
+    );
+    Ok(())
+}
+
+pub fn window_post_lifecycle<Tree: 'static + MerkleTreeTrait>(
+    sector_size: u64,
+    api_version: ApiVersion,
+    sectors: &[SealedSector],
+    rng: &mut XorShiftRng,
+) -> Result<()> {
+    init_logger();
+
+    let prover_id = prover_of(sectors)?;
+
+    let config = PoStConfig {
+        sector_size: SectorSize(sector_size),
+        challenge_count: WINDOW_POST_CHALLENGE_COUNT,
+        sector_count: *WINDOW_POST_SECTOR_COUNT
+            .read()
+            .expect("WINDOW_POST_SECTOR_COUNT poisoned")
+            .get(&sector_size)
+            .expect("unknown sector size"),
+        typ: PoStType::Window,
+        priority: false,
+        api_version,
+    };
+
+    let mut priv_replicas = BTreeMap::new();
+    let mut pub_replicas = BTreeMap::new();
+    for sector in sectors {
+        priv_replicas.insert(
+            sector.sector_id,
+            PrivateReplicaInfo::<Tree>::new(
+                sector.files.sealed(),
+                sector.comm_r,
+                sector.files.cache(),
+            )?,
+        );
+        pub_replicas.insert(sector.sector_id, PublicReplicaInfo::new(sector.comm_r)?);
+    }
+
+    for _ in 0..POST_ROUNDS {
+        let randomness = rng.gen::<[u8; 32]>();
+        let proof = metrics::time(Phase::WindowPost, || {
+            generate_window_post::<Tree>(&config, &randomness, &priv_replicas, prover_id)
+        })?;
+        let valid = metrics::time(Phase::Verify, || {
+            verify_window_post::<Tree>(&config, &randomness, &pub_replicas, prover_id, &proof)
+        })?;
+        ensure!(
+            valid,
+            VerificationMismatch("failed to verify window post".to_string())
+        );
+    }
+    Ok(())
+}
+
+pub fn winning_post_lifecycle<Tree: 'static + MerkleTreeTrait>(
+    sector_size: u64,
+    api_version: ApiVersion,
+    sectors: &[SealedSector],
+    rng: &mut XorShiftRng,
+) -> Result<()> {
+    init_logger();
+
+    let prover_id = prover_of(sectors)?;
+
+    let config = PoStConfig {
+        sector_size: SectorSize(sector_size),
+        challenge_count: WINNING_POST_CHALLENGE_COUNT,
+        sector_count: WINNING_POST_SECTOR_COUNT,
+        typ: PoStType::Winning,
+        priority: true,
+        api_version,
+    };
+
+    for _ in 0..POST_ROUNDS {
+        let randomness = rng.gen::<[u8; 32]>();
+        let challenged_sectors = generate_winning_post_sector_challenge::<Tree>(
+            &config,
+            &randomness,
+            sectors.len() as u64,
+            prover_id,
+        )?;
+
+        let mut priv_replicas = Vec::with_capacity(challenged_sectors.len());
+        let mut pub_replicas = Vec::with_capacity(challenged_sectors.len());
+        for index in challenged_sectors {
+            let sector = &sectors[index as usize];
+            priv_replicas.push((
+                sector.sector_id,
+                PrivateReplicaInfo::<Tree>::new(
+                    sector.files.sealed(),
+                    sector.comm_r,
+                    sector.files.cache(),
+                )?,
+            ));
+            pub_replicas.push((sector.sector_id, PublicReplicaInfo::new(sector.comm_r)?));
+        }
+
+        let proof = metrics::time(Phase::WinningPost, || {
+            generate_winning_post::<Tree>(&config, &randomness, &priv_replicas, prover_id)
+        })?;
+        let valid = metrics::time(Phase::Verify, || {
+            verify_winning_post::<Tree>(&config, &randomness, &pub_replicas, prover_id, &proof)
+        })?;
+        ensure!(
+            valid,
+            VerificationMismatch("failed to verify winning post".to_string())
+        );
+    }
+    Ok(())
+}
+
+/// Unseals `UNSEAL_ROUNDS` ranges of `sector` and checks them against its
+/// piece data. Every worker of the workload reads the same sector, each
+/// with ranges of its own unless `ranges` unseals all of it.
+pub fn unseal_lifecycle<Tree: 'static + MerkleTreeTrait>(
+    sector_size: u64,
+    porep_id: &[u8; 32],
+    api_version: ApiVersion,
+    sector: &SealedSector,
+    ranges: UnsealRanges,
+    rng: &mut XorShiftRng,
+) -> Result<()> {
+    init_logger();
+
+    let config = porep_config(sector_size, *porep_id, api_version);
+    let meta = sector
+        .files
+        .load::<SectorMeta>(SECTOR_META)?
+        .expect("sealed sectors keep their meta");
+    let comm_d = sector
+        .files
+        .load::<SealPreCommitOutput>(PC2_OUTPUT)?
+        .expect("sealed sectors keep their PC2 output")
+        .comm_d;
+    let ranges = match ranges {
+        UnsealRanges::Full => UnsealRanges::Full,
+        _ => UnsealRanges::Random,
+    };
+    for _ in 0..UNSEAL_ROUNDS {
+        unseal_and_check::<Tree, _>(
+            &TempStore,
+            config,
+            &sector.files,
+            &meta,
+            comm_d,
+            ranges,
+            rng,
+        )?;
+    }
+    Ok(())
+}
+
+/// The prover the shared sectors were sealed for.
+fn prover_of(sectors: &[SealedSector]) -> Result<ProverId> {
+    sectors
+        .first()
+        .map(|sector| sector.prover_id)
+        .ok_or_else(|| anyhow!("no sectors were sealed up front"))
+}
+
+/// Derives the RNG of one lifecycle from the run seed, so every thread,
+/// iteration and API version of the iteration, the `version`-th the thread
+/// runs, gets different inputs that a rerun with the same seed reproduces.
+fn lifecycle_rng(seed: u64, thread: usize, iteration: usize, version: usize) -> XorShiftRng {
+    let lo = splitmix64(splitmix64(splitmix64(seed) ^ thread as u64) ^ iteration as u64);
+    let lo = splitmix64(lo ^ version as u64);
+    let hi = splitmix64(lo);
+    let mut bytes = [0u8; 16];
+    bytes[..8].copy_from_slice(&lo.to_le_bytes());
+    bytes[8..].copy_from_slice(&hi.to_le_bytes());
+    XorShiftRng::from_seed(bytes)
+}
+
+/// Settings shared by every worker thread of a group.
+#[derive(Clone, Debug)]
+pub struct WorkerConfig {
+    pub group: String,
+    pub workload: Workload,
+    pub sector_size: u64,
+    pub phase: Option<Phase>,
+    /// Every pass runs one lifecycle per entry, in this order, unless
+    /// `api_version_mix` spreads them over the workers.
+    pub api_versions: Vec<(ApiVersion, [u8; 32])>,
+    pub api_version_mix: ApiVersionMix,
+    /// Number of passes over `api_versions`, 0 meaning run until killed.
+    pub iterations: usize,
+    /// Number of pieces each sealed sector is filled with.
+    pub pieces: usize,
+    /// Number of sectors a seal lifecycle takes through PC2 before
+    /// committing any of them.
+    pub sectors_per_worker: usize,
+    /// Number of pre-sealed sectors each aggregate proof covers.
+    pub aggregate_sectors: usize,
+    /// What sealed sectors are filled with, zeros making committed capacity
+    /// sectors.
+    pub piece_data: PieceData,
+    /// Stop seal lifecycles after PC2 and clear the cache, leaving out
+    /// everything that needs Groth parameters.
+    pub skip_proofs: bool,
+    /// Point every worker at the same cache or sector directory.
+    pub shared_cache: Option<SharedCache>,
+    pub unseal_ranges: UnsealRanges,
+    /// Delay between the starts of consecutive workers.
+    pub stagger: Duration,
+    /// Random extra start delay of up to this much, derived from `seed`.
+    pub jitter: Duration,
+    /// Run seed every lifecycle's RNG is derived from.
+    pub seed: u64,
+    /// Stable location for sector files instead of a temporary directory.
+    pub cache_root: Option<PathBuf>,
+    /// Keep sector files under `cache_root` for later runs to reuse.
+    pub keep_artifacts: bool,
+    /// Phase to restart from using the checkpoints under `cache_root`.
+    pub resume_from: Option<Phase>,
+    /// Cores each worker is bound to.
+    pub pin_cores: Option<CoreSets>,
+    /// Give each pinned worker its own rayon pool on its cores.
+    pub pin_rayon: bool,
+    /// Size of a rayon pool of each worker's own instead of the global one.
+    pub rayon_threads: Option<usize>,
+    /// Proofs settings turned on or off for the worker.
+    pub toggles: Toggles,
+    /// Longest a phase may run before its lifecycle is abandoned.
+    pub phase_timeout: Option<Duration>,
+    /// How often an abandoned lifecycle is started over; process workers
+    /// are restarted instead.
+    pub retries: usize,
+    /// How often a lifecycle that failed with a transient error is started
+    /// over.
+    pub transient_retries: usize,
+    /// Wait before the first such retry, doubled for every further one.
+    pub retry_backoff: Duration,
+    /// NUMA nodes whose memory workers allocate from; their cores are in
+    /// `pin_cores`.
+    pub numa: Option<NumaPlacement>,
+    /// End the worker after every lifecycle and start a fresh one for the
+    /// next, like a lotus-worker that keeps restarting.
+    pub churn: bool,
+    /// Lifecycles of earlier incarnations of a churning worker, counted over
+    /// all passes and API versions.
+    pub first_lifecycle: usize,
+    /// Prover identities seal lifecycles use, worker `i` the `i % len`-th;
+    /// empty for a random one per lifecycle.
+    pub provers: Vec<ProverId>,
+    /// Where seal lifecycles queue their C1 outputs, stopping after C1, and
+    /// the C2 workload takes them from.
+    pub c2_queue: Option<C2Queue>,
+}
+
+impl WorkerConfig {
+    /// The API versions worker `index` runs, according to `api_version_mix`.
+    pub fn worker_api_versions(&self, index: usize) -> &[(ApiVersion, [u8; 32])] {
+        match self.api_version_mix {
+            ApiVersionMix::Within => &self.api_versions[..],
+            ApiVersionMix::Across => {
+                let i = index % self.api_versions.len();
+                &self.api_versions[i..=i]
+            }
+        }
+    }
+
+    /// Lifecycles worker `index` runs over all its passes, `usize::MAX` if it
+    /// runs until stopped.
+    pub fn lifecycles(&self, index: usize) -> usize {
+        match self.iterations {
+            0 => usize::MAX,
+            iterations => iterations * self.worker_api_versions(index).len(),
+        }
+    }
+}
+
+/// Where lifecycles of different API versions meet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ApiVersionMix {
+    /// Each worker cycles through all API versions.
+    Within,
+    /// Worker `i` only runs version `i` modulo their number, so versions
+    /// only mix between workers.
+    Across,
+}
+
+impl FromStr for ApiVersionMix {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "within" => Ok(ApiVersionMix::Within),
+            "across" => Ok(ApiVersionMix::Across),
+            _ => bail!("unknown api version mix: {}", s),
+        }
+    }
+}
+
+/// How workers are isolated from each other.
+#[derive(Clone, Debug)]
+pub enum WorkerMode {
+    /// Threads of this process, sharing the proofs library and its GPU locks.
+    Thread,
+    /// Child processes, like separate lotus-worker instances.
+    Process(ProcessWorkers),
+}
+
+/// Everything [`run_groups`] collected.
+#[derive(Clone, Debug)]
+pub struct RunOutcome {
+    pub records: Vec<LifecycleRecord>,
+    /// The most severe failure of any lifecycle or worker.
+    pub classification: Classification,
+    /// Workers that ended with an error, with how it was classified; phase
+    /// timeouts are left to the `timed_out` of their records.
+    pub failed_workers: Vec<(usize, Classification)>,
+}
+
+/// What workers report back to the main thread.
+pub(crate) enum WorkerEvent {
+    Lifecycle(LifecycleRecord),
+    Finished(usize, Result<()>),
+    /// A process worker exited after a phase timeout.
+    TimedOut(usize),
+}
+
+/// Runs `config.iterations` passes with one lifecycle per API version the
+/// worker gets from `config.api_version_mix`, sending a record for each of
+/// them. Stops at the first failing
+/// lifecycle, or before starting a new one once shutdown was requested. A
+/// lifecycle abandoned after a phase timeout is retried up to
+/// `config.retries` times first, and one that failed with a transient error
+/// up to `config.transient_retries` times, backing off in between. A
+/// churning worker starts at
+/// `config.first_lifecycle` and returns after that single lifecycle.
+///
+/// Worker `index` starts `index` times `config.stagger` late, plus up to
+/// `config.jitter`, and is then bound to its NUMA node and cores if
+/// `config.numa` or `config.pin_cores` are set. Lifecycles run in a rayon
+/// pool of the worker's own if `config.rayon_threads` or `config.pin_rayon`
+/// ask for one.
+pub(crate) fn run_worker(
+    index: usize,
+    config: &WorkerConfig,
+    sealed_sectors: &Arc<Vec<(ApiVersion, Vec<SealedSector>)>>,
+    events: &Sender<WorkerEvent>,
+) -> Result<()> {
+    let _span = info_span!("worker", index, group = %config.group).entered();
+    trace::name_worker(index, &config.group);
+    let jitter = config.jitter.as_millis() as u64;
+    let jitter =
+        lifecycle_rng(config.seed ^ JITTER_SEED_SALT, index, 0, 0).gen_range(0, jitter + 1);
+    thread::sleep(config.stagger * index as u32 + Duration::from_millis(jitter));
+
+    if let Some(numa) = &config.numa {
+        numa::bind_memory(numa.node_for(index))?;
+    }
+    let cores = match &config.pin_cores {
+        Some(sets) => {
+            let cores = sets.for_worker(index);
+            affinity::pin_current(cores)?;
+            Some(cores)
+        }
+        None => None,
+    };
+    let pool_threads = match (config.rayon_threads, cores) {
+        (Some(threads), _) => Some(threads),
+        (None, Some(cores)) if config.pin_rayon => Some(cores.len()),
+        _ => None,
+    };
+    let pool = match pool_threads {
+        Some(threads) => Some(Arc::new(affinity::pool(threads)?)),
+        None => None,
+    };
+
+    let api_versions = config.worker_api_versions(index);
+    let mut lifecycle = config.first_lifecycle;
+    while lifecycle < config.lifecycles(index) {
+        let iteration = lifecycle / api_versions.len();
+        let (api_version, porep_id) = &api_versions[lifecycle % api_versions.len()];
+        let correlation = correlation_id(config.seed, index, lifecycle);
+        let _span = info_span!(
+            "iteration",
+            iteration,
+            api_version = ?api_version,
+            correlation = %correlation
+        )
+        .entered();
+        let schedule = fuzz::begin(config.seed, index, iteration);
+        let pool = match schedule {
+            Some(schedule) => {
+                info!(
+                    "fuzz schedule {}: {:?} start delay, {} rayon threads",
+                    schedule.seed, schedule.start_delay, schedule.rayon_threads
+                );
+                back_off(schedule.start_delay);
+                Some(Arc::new(affinity::pool(schedule.rayon_threads)?))
+            }
+            None => pool.clone(),
+        };
+        let mut retries = config.retries;
+        let mut transient_retries = 0;
+        loop {
+            if shutdown::requested() {
+                return Ok(());
+            }
+
+            let (res, mut record) = run_attempt(
+                index,
+                iteration,
+                config,
+                *api_version,
+                *porep_id,
+                sealed_sectors,
+                &pool,
+            );
+            // A C2 worker is done once no seal worker is left to queue jobs.
+            if matches!(&res, Err(err) if err.is::<QueueDrained>()) {
+                return Ok(());
+            }
+            record.retried = match &res {
+                Err(err) => {
+                    transient_retries < config.transient_retries
+                        && ErrorClass::of_error(err).is_transient()
+                }
+                Ok(()) => false,
+            };
+            record.correlation = correlation.clone();
+            record.fuzz_schedule = schedule.map(|schedule| schedule.seed);
+            let retried = record.retried;
+            let _ = events.send(WorkerEvent::Lifecycle(record));
+            match res {
+                Err(err) if retried => {
+                    let backoff = retry_backoff(config.retry_backoff, transient_retries);
+                    transient_retries += 1;
+                    warn!(
+                        "{:#}, retrying in {:?} ({}/{})",
+                        err, backoff, transient_retries, config.transient_retries
+                    );
+                    back_off(backoff);
+                }
+                Err(err) if retries > 0 && err.is::<PhaseTimeout>() => {
+                    warn!("{}, retrying", err);
+                    retries -= 1;
+                }
+                res => {
+                    res?;
+                    break;
+                }
+            }
+        }
+        lifecycle += 1;
+        if config.churn {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Tells lifecycle `lifecycle` of worker `index` apart in the logs of
+/// every worker and in reports: the low half of the run's seed, the worker
+/// and the lifecycle, e.g. `5eed0001-w3-l12`.
+fn correlation_id(seed: u64, index: usize, lifecycle: usize) -> String {
+    format!("{:08x}-w{}-l{}", seed as u32, index, lifecycle)
+}
+
+/// `base` doubled for every earlier transient retry of the lifecycle.
+fn retry_backoff(base: Duration, retry: usize) -> Duration {
+    base.checked_mul(1 << retry.min(16))
+        .unwrap_or(MAX_RETRY_BACKOFF)
+        .min(MAX_RETRY_BACKOFF)
+}
+
+/// Waits `delay` before a retry, or until shutdown is requested.
+fn back_off(delay: Duration) {
+    let deadline = Instant::now() + delay;
+    while !shutdown::requested() {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left == Duration::default() {
+            break;
+        }
+        thread::sleep(left.min(Duration::from_secs(1)));
+        // Backing off is not a hang.
+        watchdog::waiting();
+    }
+}
+
+/// Runs one lifecycle and closes its record. With a phase timeout the
+/// lifecycle gets a thread of its own, which is abandoned, still running,
+/// once one of its phases overruns.
+fn run_attempt(
+    index: usize,
+    iteration: usize,
+    config: &WorkerConfig,
+    api_version: ApiVersion,
+    porep_id: [u8; 32],
+    sealed_sectors: &Arc<Vec<(ApiVersion, Vec<SealedSector>)>>,
+    pool: &Option<Arc<ThreadPool>>,
+) -> (Result<()>, LifecycleRecord) {
+    let timeout = match config.phase_timeout {
+        Some(timeout) => timeout,
+        None => {
+            return attempt(
+                index,
+                iteration,
+                config,
+                api_version,
+                &porep_id,
+                sealed_sectors,
+                pool,
+            )
+        }
+    };
+    let (watchdog, slot) = watchdog::current().expect("workers are attached to a watchdog");
+
+    let (tx, rx) = mpsc::channel();
+    let handle = {
+        let watchdog = watchdog.clone();
+        let config = config.clone();
+        let sealed_sectors = sealed_sectors.clone();
+        let pool = pool.clone();
+        let span = Span::current();
+        thread::Builder::new()
+            .name(format!("lifecycle-{}", index))
+            .spawn(move || {
+                let _span = span.entered();
+                trace::set_worker(index);
+                watchdog.attach(slot);
+                let _ = tx.send(attempt(
+                    index,
+                    iteration,
+                    &config,
+                    api_version,
+                    &porep_id,
+                    &sealed_sectors,
+                    &pool,
+                ));
+            })
+            .expect("failed to spawn lifecycle thread")
+    };
+    loop {
+        match rx.recv_timeout(PHASE_TIMEOUT_POLL_INTERVAL) {
+            Ok(outcome) => return outcome,
+            Err(RecvTimeoutError::Timeout) => match watchdog.phase(slot) {
+                Some((phase, elapsed)) if elapsed > timeout => {
+                    // Take the worker's watchdog slot back from the stuck thread.
+                    watchdog.attach(slot);
+                    let res: Result<()> = Err(PhaseTimeout { phase, timeout }.into());
+                    let record =
+                        metrics::finish(&config.group, index, iteration, api_version, &res);
+                    return (res, record);
+                }
+                _ => {}
+            },
+            Err(RecvTimeoutError::Disconnected) => match handle.join() {
+                Err(panic) => panic::resume_unwind(panic),
+                Ok(()) => unreachable!("lifecycle thread exited without an outcome"),
+            },
+        }
+    }
+}
+
+fn attempt(
+    index: usize,
+    iteration: usize,
+    config: &WorkerConfig,
+    api_version: ApiVersion,
+    porep_id: &[u8; 32],
+    sealed_sectors: &[(ApiVersion, Vec<SealedSector>)],
+    pool: &Option<Arc<ThreadPool>>,
+) -> (Result<()>, LifecycleRecord) {
+    // Pool threads do not inherit the caller's span or trace track.
+    let span = Span::current();
+    let run = || {
+        trace::set_worker(index);
+        metrics::set_iteration(iteration);
+        span.in_scope(|| {
+            panics::catch(|| {
+                run_lifecycle(
+                    index,
+                    iteration,
+                    config,
+                    api_version,
+                    porep_id,
+                    sealed_sectors,
+                )
+            })
+        })
+    };
+    let res = match pool {
+        Some(pool) => pool.install(run),
+        None => run(),
+    };
+    let record = metrics::finish(&config.group, index, iteration, api_version, &res);
+    (res, record)
+}
+
+/// The cache clearing that ends a lifecycle run with `--skip-proofs`.
+fn clear_caches<Tree: 'static + MerkleTreeTrait>(batch: &[SectorFiles]) -> Result<()> {
+    batch
+        .iter()
+        .try_for_each(|files| clear_cache::<Tree>(&files.cache()))
+}
+
+pub fn run_lifecycle(
+    index: usize,
+    iteration: usize,
+    config: &WorkerConfig,
+    api_version: ApiVersion,
+    porep_id: &[u8; 32],
+    sealed_sectors: &[(ApiVersion, Vec<SealedSector>)],
+) -> Result<()> {
+    let sector_size = config.sector_size;
+    let version = config
+        .worker_api_versions(index)
+        .iter()
+        .position(|(version, _)| *version == api_version)
+        .unwrap_or_default();
+    let rng = &mut lifecycle_rng(config.seed, index, iteration, version);
+    let sectors = || {
+        sealed_sectors
+            .iter()
+            .find(|(version, _)| *version == api_version)
+            .map(|(_, sectors)| sectors.as_slice())
+            .expect("no sectors sealed for api version")
+    };
+
+    match config.workload {
+        Workload::Seal => {
+            let store: Box<dyn ArtifactStore> = match (&config.cache_root, config.shared_cache) {
+                (Some(root), _) => Box::new(DirStore::new(
+                    sectors_dir(root, sector_size, api_version),
+                    config.keep_artifacts,
+                )),
+                (None, Some(mode)) => Box::new(SharedStore(mode)),
+                (None, None) => Box::new(TempStore),
+            };
+            if let Some((mut files, porep_config)) = pipeline::take_over() {
+                // Resuming at PC2 reads the prover the sector was
+                // pre-committed for from its meta, so none is drawn here.
+                let prover_id = [0; 32];
+                let res = with_shape!(
+                    u64::from(porep_config.sector_size),
+                    seal_lifecycle::<_, dyn ArtifactStore>,
+                    &*store,
+                    rng,
+                    prover_id,
+                    &files,
+                    u64::from(porep_config.sector_size),
+                    &porep_config.porep_id,
+                    porep_config.api_version,
+                    config.pieces,
+                    config.piece_data,
+                    config.unseal_ranges,
+                    PhaseRange {
+                        resume_from: Some(Phase::PreCommit2),
+                        stop_after: None,
+                    },
+                    false,
+                );
+                if res.is_err() {
+                    files.failed();
+                }
+                res.context("failed to finish a sector taken over")?;
+                pipeline::reassignment_finished();
+            }
+            let mut batch = (0..config.sectors_per_worker)
+                // The first sector keeps the directory of unbatched runs.
+                .map(|sector| {
+                    store.sector(&match sector {
+                        0 => format!("worker-{}", index),
+                        sector => format!("worker-{}-{}", index, sector),
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            // A single phase reuses whatever earlier outputs were kept.
+            let range = if config.skip_proofs {
+                PhaseRange {
+                    resume_from: config.resume_from,
+                    stop_after: Some(Phase::PreCommit2),
+                }
+            } else {
+                PhaseRange {
+                    resume_from: config.resume_from.or(config.phase),
+                    stop_after: config
+                        .phase
+                        .or_else(|| config.c2_queue.as_ref().map(|_| Phase::Commit1)),
+                }
+            };
+            let prover_id = match config.provers.len() {
+                0 => random_prover_id(rng),
+                provers => config.provers[index % provers],
+            };
+            let res = with_shape!(
+                sector_size,
+                batch_seal_lifecycle::<_, dyn ArtifactStore>,
+                &*store,
+                rng,
+                prover_id,
+                &batch,
+                sector_size,
+                porep_id,
+                api_version,
+                config.pieces,
+                config.piece_data,
+                config.unseal_ranges,
+                range,
+                config.keep_artifacts,
+            );
+            let res = match res {
+                Ok(()) if config.skip_proofs && !config.keep_artifacts => {
+                    with_shape!(sector_size, clear_caches, &batch)
+                }
+                Ok(()) if range.stop_after == Some(Phase::Commit1) => match &config.c2_queue {
+                    Some(queue) => {
+                        let porep_config = porep_config(sector_size, *porep_id, api_version);
+                        batch
+                            .iter()
+                            .try_for_each(|files| queue.push(files, porep_config))
+                    }
+                    None => Ok(()),
+                },
+                res => res,
+            };
+            if res.is_err() {
+                batch.iter_mut().for_each(SectorFiles::failed);
+            }
+            res
+        }
+        Workload::C2 => {
+            let queue = config
+                .c2_queue
+                .as_ref()
+                .ok_or_else(|| anyhow!("the c2 workload needs a --c2-queue"))?;
+            let (mut files, job) = queue.pop()?.ok_or(QueueDrained)?;
+            let res = with_shape!(job.sector_size, c2_lifecycle, &files, job.config()?);
+            if res.is_err() {
+                queue.keep_failed(&mut files);
+            }
+            res
+        }
+        Workload::WinningPost => with_shape!(
+            sector_size,
+            winning_post_lifecycle,
+            sector_size,
+            api_version,
+            sectors(),
+            rng,
+        ),
+        Workload::WindowPost => with_shape!(
+            sector_size,
+            window_post_lifecycle,
+            sector_size,
+            api_version,
+            sectors(),
+            rng,
+        ),
+        Workload::Aggregate => with_shape!(
+            sector_size,
+            aggregate_lifecycle,
+            sector_size,
+            porep_id,
+            api_version,
+            &sectors()[..config.aggregate_sectors],
+        ),
+        Workload::Unseal => with_shape!(
+            sector_size,
+            unseal_lifecycle,
+            sector_size,
+            porep_id,
+            api_version,
+            &sectors()[0],
+            config.unseal_ranges,
+            rng,
+        ),
+        #[cfg(feature = "proofs-v11")]
+        Workload::Snap => {
+            let sectors = sectors();
+            with_shape!(
+                sector_size,
+                snap_lifecycle,
+                sector_size,
+                porep_id,
+                api_version,
+                &sectors[rng.gen_range(0, sectors.len())],
+                config.pieces,
+                config.piece_data,
+                rng,
+            )
+        }
+        Workload::Verify => {
+            let root = config
+                .cache_root
+                .as_ref()
+                .ok_or_else(|| anyhow!("the verify workload needs a cache root"))?;
+            let dirs = proven_sectors(&sectors_dir(root, sector_size, api_version))?;
+            if dirs.is_empty() {
+                bail!(
+                    "no proofs under {}, seal some with --keep-artifacts first",
+                    root.display()
+                );
+            }
+            let files = SectorFiles::persistent(dirs[rng.gen_range(0, dirs.len())].clone(), true)?;
+            with_shape!(
+                sector_size,
+                verify_lifecycle,
+                &files,
+                sector_size,
+                porep_id,
+                api_version,
+            )
+        }
+    }
+}
+
+/// Where seal workers keep their sectors under `root`.
+fn sectors_dir(root: &Path, sector_size: u64, api_version: ApiVersion) -> PathBuf {
+    root.join(sector_size.to_string())
+        .join(format!("{:?}", api_version))
+}
+
+/// The sector directories below `dir` holding a finished proof.
+fn proven_sectors(dir: &Path) -> Result<Vec<PathBuf>> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut dirs = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if artifacts::is_saved(&path, C2_OUTPUT) {
+            dirs.push(path);
+        }
+    }
+    dirs.sort();
+    Ok(dirs)
+}
+
+/// Seals `count` sectors per API version for the PoSt, aggregation and
+/// snap workloads to share.
+pub fn prepare_sectors(
+    sector_size: u64,
+    api_versions: &[(ApiVersion, [u8; 32])],
+    count: usize,
+    with_proofs: bool,
+    prover_id: Option<ProverId>,
+    piece_data: PieceData,
+) -> Result<Vec<(ApiVersion, Vec<SealedSector>)>> {
+    api_versions
+        .iter()
+        .map(
+            |(api_version, porep_id)| -> Result<(ApiVersion, Vec<SealedSector>)> {
+                let sectors = with_shape!(
+                    sector_size,
+                    seal_sectors,
+                    sector_size,
+                    porep_id,
+                    *api_version,
+                    count,
+                    with_proofs,
+                    prover_id,
+                    piece_data,
+                )?;
+                Ok((*api_version, sectors))
+            },
+        )
+        .collect()
+}
+
+/// Spawns `threads` workers for every group and collects their lifecycle
+/// records until all of them are done, classifying how the run ended.
+///
+/// In thread mode a watchdog aborts the process when a worker makes no
+/// progress for `hang_timeout`, unless it is zero; child processes run their
+/// own. Process workers that exit after a phase timeout are restarted up to
+/// their `retries` times, and churning workers after every lifecycle until
+/// they ran all of theirs. Once a shutdown was requested workers get
+/// `shutdown_timeout` to finish their in-flight phases before the records
+/// collected so far are returned.
+pub fn run_groups(
+    groups: &[(usize, WorkerConfig)],
+    sealed_sectors: Arc<Vec<(ApiVersion, Vec<SealedSector>)>>,
+    hang_timeout: Duration,
+    shutdown_timeout: Duration,
+    mode: &WorkerMode,
+) -> Result<RunOutcome> {
+    let configs = groups
+        .iter()
+        .flat_map(|(threads, config)| std::iter::repeat(config).take(*threads))
+        .collect::<Vec<_>>();
+    let num_threads = configs.len();
+    // Registered before any worker starts, so no C2 worker takes the queue
+    // for drained while the seal workers are still starting up.
+    let mut producers = configs
+        .iter()
+        .enumerate()
+        .map(|(i, config)| match &config.c2_queue {
+            Some(queue) if config.workload == Workload::Seal => queue.producer(i).map(Some),
+            _ => Ok(None),
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let (events, rx) = mpsc::channel();
+    let (mut handlers, mut children, watchdog) = match mode {
+        WorkerMode::Thread => {
+            let watchdog = Watchdog::new(num_threads, hang_timeout);
+            if hang_timeout > Duration::from_secs(0) {
+                watchdog.clone().spawn();
+            }
+
+            let handlers = configs
+                .iter()
+                .enumerate()
+                .map(|(i, config)| {
+                    spawn_thread(
+                        i,
+                        (*config).clone(),
+                        sealed_sectors.clone(),
+                        watchdog.clone(),
+                        events.clone(),
+                    )
+                })
+                .collect::<Vec<_>>();
+            (handlers, Vec::new(), Some(watchdog))
+        }
+        WorkerMode::Process(workers) => {
+            let mut handlers = Vec::with_capacity(num_threads);
+            let mut children = Vec::with_capacity(num_threads);
+            for i in 0..num_threads {
+                let (pid, handle) = workers.spawn(i, 0, configs[i].toggles, events.clone())?;
+                children.push(pid);
+                handlers.push(handle);
+            }
+            (handlers, children, None)
+        }
+    };
+    let heartbeat = match heartbeat::config() {
+        Some((path, interval)) => Some(Heartbeat::start(path, interval, groups, watchdog.clone())?),
+        None => None,
+    };
+    // Churning thread workers are respawned on the same watchdog slot.
+    let churn_watchdog = watchdog.clone();
+    #[cfg(feature = "tui")]
+    let dashboard = if crate::dashboard::enabled() {
+        Some(crate::dashboard::Dashboard::start(groups, watchdog)?)
+    } else {
+        None
+    };
+    #[cfg(not(feature = "tui"))]
+    drop(watchdog);
+    // Process workers that hit the phase timeout are restarted, churning
+    // workers of either kind after every lifecycle.
+    let churn = configs.iter().any(|config| config.churn);
+    let restart_events = match mode {
+        WorkerMode::Process(_) => Some(events.clone()),
+        WorkerMode::Thread if churn => Some(events.clone()),
+        WorkerMode::Thread => None,
+    };
+    drop(events);
+    let mut restarts = vec![0; num_threads];
+    let mut next_lifecycle = vec![0; num_threads];
+
+    let mut records = Vec::new();
+    let mut classification = Classification::Success;
+    let mut failed_workers = Vec::new();
+    let mut finished = 0;
+    let mut shutdown_deadline = None;
+    while finished < num_threads {
+        match rx.recv_timeout(SHUTDOWN_POLL_INTERVAL) {
+            Ok(WorkerEvent::Lifecycle(record)) => {
+                #[cfg(feature = "tui")]
+                if let Some(dashboard) = &dashboard {
+                    dashboard.lifecycle(&record);
+                }
+                if let Some(heartbeat) = &heartbeat {
+                    heartbeat.lifecycle(&record);
+                }
+                classification = classification.max(Classification::of_record(&record));
+                records.push(record);
+            }
+            Ok(WorkerEvent::Finished(i, Ok(())))
+                if configs[i].churn
+                    && !shutdown::requested()
+                    && next_lifecycle[i] + 1 < configs[i].lifecycles(i) =>
+            {
+                next_lifecycle[i] += 1;
+                debug!(thread = i, "respawning for lifecycle {}", next_lifecycle[i]);
+                let events = restart_events
+                    .clone()
+                    .expect("churning workers keep a sender");
+                match mode {
+                    WorkerMode::Thread => {
+                        let config = WorkerConfig {
+                            first_lifecycle: next_lifecycle[i],
+                            ..configs[i].clone()
+                        };
+                        let watchdog = churn_watchdog.clone().expect("thread mode has a watchdog");
+                        handlers.push(spawn_thread(
+                            i,
+                            config,
+                            sealed_sectors.clone(),
+                            watchdog,
+                            events,
+                        ));
+                    }
+                    WorkerMode::Process(workers) => {
+                        let (pid, handle) =
+                            workers.spawn(i, next_lifecycle[i], configs[i].toggles, events)?;
+                        children[i] = pid;
+                        handlers.push(handle);
+                    }
+                }
+            }
+            Ok(WorkerEvent::Finished(i, res)) => {
+                #[cfg(feature = "tui")]
+                if let Some(dashboard) = &dashboard {
+                    dashboard.finished(i);
+                }
+                if let Some(heartbeat) = &heartbeat {
+                    heartbeat.finished(i);
+                }
+                schedule::finished(i);
+                overlap::finished(i);
+                barrier::finished(i);
+                producers[i] = None;
+                info!(thread = i, "got result: {:?}", res);
+                match &res {
+                    Err(err) if !err.is::<PhaseTimeout>() => {
+                        failed_workers.push((i, Classification::of_error(err)));
+                        classification = classification.max(Classification::of_error(err));
+                    }
+                    _ => {}
+                }
+                finished += 1;
+            }
+            Ok(WorkerEvent::TimedOut(i)) => {
+                let retries = configs[i].retries;
+                match (mode, &restart_events) {
+                    (WorkerMode::Process(workers), Some(events))
+                        if restarts[i] < retries && !shutdown::requested() =>
+                    {
+                        restarts[i] += 1;
+                        warn!(
+                            thread = i,
+                            "hit the phase timeout, restarting it ({}/{})", restarts[i], retries
+                        );
+                        let (pid, handle) = workers.spawn(
+                            i,
+                            next_lifecycle[i],
+                            configs[i].toggles,
+                            events.clone(),
+                        )?;
+                        children[i] = pid;
+                        handlers.push(handle);
+                    }
+                    _ => {
+                        #[cfg(feature = "tui")]
+                        if let Some(dashboard) = &dashboard {
+                            dashboard.finished(i);
+                        }
+                        if let Some(heartbeat) = &heartbeat {
+                            heartbeat.finished(i);
+                        }
+                        schedule::finished(i);
+                        overlap::finished(i);
+                        barrier::finished(i);
+                        producers[i] = None;
+                        info!(thread = i, "got result: phase timeout");
+                        classification = Classification::Hang;
+                        finished += 1;
+                    }
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                if !shutdown::requested() {
+                    continue;
+                }
+                let deadline = *shutdown_deadline.get_or_insert_with(|| {
+                    info!(
+                        "Shutdown requested, waiting up to {}s for in-flight phases",
+                        shutdown_timeout.as_secs()
+                    );
+                    process::terminate(&children);
+                    Instant::now() + shutdown_timeout
+                });
+                if Instant::now() >= deadline {
+                    warn!(
+                        "Gave up waiting for {} threads, reporting partial results",
+                        num_threads - finished
+                    );
+                    process::kill(&children);
+                    break;
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                warn!(
+                    "{} threads died without reporting a result",
+                    num_threads - finished
+                );
+                break;
+            }
+        }
+    }
+
+    // Propagate worker panics, unless we gave up on stuck workers.
+    if finished == num_threads {
+        for h in handlers {
+            h.join().unwrap();
+        }
+    }
+
+    Ok(RunOutcome {
+        records,
+        classification,
+        failed_workers,
+    })
+}
+
+fn spawn_thread(
+    index: usize,
+    config: WorkerConfig,
+    sealed_sectors: Arc<Vec<(ApiVersion, Vec<SealedSector>)>>,
+    watchdog: Arc<Watchdog>,
+    events: Sender<WorkerEvent>,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        watchdog.attach(index);
+        if let Err(err) = logging::open_worker_log(index, false) {
+            warn!(thread = index, "{:#}", err);
+        }
+        let res = run_worker(index, &config, &sealed_sectors, &events);
+        watchdog::detach();
+        let _ = events.send(WorkerEvent::Finished(index, res));
+    })
+}